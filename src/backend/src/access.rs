@@ -0,0 +1,175 @@
+use crate::access_mode::admit_read;
+use crate::maintenance::admit_update;
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, is_controller};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+pub(crate) type PrincipalKey = Blob<29>;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum Role {
+    Admin,
+    Moderator,
+    Coordinator,
+    Responder,
+    VerifiedOrg,
+    // Leads response teams for a partner organization
+    OrgLead,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct RoleSet {
+    roles: Vec<Role>,
+}
+
+// Implementing Storable and BoundedStorable traits for RoleSet
+impl Storable for RoleSet {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for RoleSet {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static ROLE_STORAGE: RefCell<StableBTreeMap<PrincipalKey, RoleSet, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8)))
+    ));
+}
+
+pub(crate) fn principal_key(principal: &Principal) -> PrincipalKey {
+    Blob::try_from(principal.as_slice()).expect("principal is longer than 29 bytes")
+}
+
+// Controllers of the canister are always treated as admins
+pub(crate) fn roles_of(principal: &Principal) -> Vec<Role> {
+    let mut roles = ROLE_STORAGE
+        .with(|s| s.borrow().get(&principal_key(principal)))
+        .unwrap_or_default()
+        .roles;
+    if is_controller(principal) && !roles.contains(&Role::Admin) {
+        roles.push(Role::Admin);
+    }
+    roles
+}
+
+// Admins implicitly hold every role
+pub(crate) fn has_role(principal: &Principal, role: Role) -> bool {
+    let roles = roles_of(principal);
+    roles.contains(&Role::Admin) || roles.contains(&role)
+}
+
+// Moderators, coordinators and admins, who see every update whatever its review or visibility
+pub(crate) fn is_staff(principal: &Principal) -> bool {
+    has_role(principal, Role::Moderator) || has_role(principal, Role::Coordinator)
+}
+
+// Principals granted any of the roles or admin; controllers are not listed
+pub(crate) fn holders_of(roles: &[Role]) -> Vec<Principal> {
+    ROLE_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, set)| set.roles.iter().any(|role| *role == Role::Admin || roles.contains(role)))
+            .map(|(key, _)| Principal::from_slice(key.as_slice()))
+            .collect()
+    })
+}
+
+// Returns the caller when it holds at least one of the given roles
+pub(crate) fn require_any_role(allowed: &[Role]) -> Result<Principal, Error> {
+    let caller = caller();
+    if allowed.iter().any(|role| has_role(&caller, *role)) {
+        Ok(caller)
+    } else {
+        Err(Error::unauthorized(format!(
+            "caller {} must hold one of the roles {:?}",
+            caller, allowed
+        )))
+    }
+}
+
+pub(crate) fn require_role(role: Role) -> Result<Principal, Error> {
+    require_any_role(&[role])
+}
+
+// Adds the role to the principal and returns their explicit roles
+pub(crate) fn add_role(principal: &Principal, role: Role) -> Vec<Role> {
+    let key = principal_key(principal);
+    let roles = ROLE_STORAGE.with(|s| {
+        let mut storage = s.borrow_mut();
+        let mut set = storage.get(&key).unwrap_or_default();
+        if !set.roles.contains(&role) {
+            set.roles.push(role);
+        }
+        storage.insert(key, set.clone());
+        set.roles
+    });
+    // Responders are vetted by an admin or a credential check before being granted the role
+    if role == Role::Responder {
+        crate::leaderboard::award_badge(principal, crate::leaderboard::Badge::VerifiedResponder);
+    }
+    roles
+}
+
+// Removes the role from the principal and returns their remaining explicit roles
+pub(crate) fn remove_role(principal: &Principal, role: Role) -> Result<Vec<Role>, Error> {
+    let key = principal_key(principal);
+    ROLE_STORAGE.with(|s| {
+        let mut storage = s.borrow_mut();
+        match storage.get(&key) {
+            Some(mut set) if set.roles.contains(&role) => {
+                set.roles.retain(|r| *r != role);
+                if set.roles.is_empty() {
+                    storage.remove(&key);
+                } else {
+                    storage.insert(key, set.clone());
+                }
+                Ok(set.roles)
+            }
+            _ => Err(Error::not_found(format!(
+                "principal {} does not hold the role {:?}",
+                principal, role
+            ))),
+        }
+    })
+}
+
+// 2.10.1 grant_role Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn grant_role(principal: Principal, role: Role) -> Result<Vec<Role>, Error> {
+    crate::call_stats::count_call("grant_role");
+    require_role(Role::Admin)?;
+    Ok(add_role(&principal, role))
+}
+
+// 2.10.2 revoke_role Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn revoke_role(principal: Principal, role: Role) -> Result<Vec<Role>, Error> {
+    crate::call_stats::count_call("revoke_role");
+    require_role(Role::Admin)?;
+    remove_role(&principal, role)
+}
+
+// 2.10.3 get_roles Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_roles(principal: Principal) -> Vec<Role> {
+    roles_of(&principal)
+}
+
+// 2.10.4 get_my_roles Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_my_roles() -> Vec<Role> {
+    roles_of(&caller())
+}
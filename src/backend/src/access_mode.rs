@@ -0,0 +1,71 @@
+// Who may read the dataset. Public deployments serve every query to anyone;
+// agencies that keep their data private can require an identity, or membership
+// through a role or a tenant. The mode is enforced by the guard on every query
+// method and on the HTTP gateway, and is set at install or by admins later.
+use crate::access::{require_role, roles_of, Role};
+use crate::maintenance::admit_update;
+use crate::settings::{get_settings, update_settings};
+use crate::tenants::tenant_of;
+use crate::{Error, ErrorCode};
+use candid::Principal;
+use ic_cdk::api::caller;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+pub(crate) enum AccessMode {
+    #[default]
+    PublicRead,
+    // Any caller except the anonymous principal
+    AuthenticatedRead,
+    // Callers holding a role or belonging to a tenant
+    MembersOnly,
+}
+
+pub(crate) fn access_mode() -> AccessMode {
+    get_settings().access_mode.unwrap_or_default()
+}
+
+pub(crate) fn may_read(principal: &Principal) -> bool {
+    match access_mode() {
+        AccessMode::PublicRead => true,
+        AccessMode::AuthenticatedRead => *principal != Principal::anonymous(),
+        AccessMode::MembersOnly => !roles_of(principal).is_empty() || tenant_of(principal).is_some(),
+    }
+}
+
+// Guard for every query method
+pub(crate) fn admit_read() -> Result<(), String> {
+    let caller = caller();
+    if may_read(&caller) {
+        return Ok(());
+    }
+    Err(format!(
+        "{:?}: reading requires {} in {:?} mode",
+        ErrorCode::Unauthorized,
+        match access_mode() {
+            AccessMode::MembersOnly => "a role or tenant membership",
+            _ => "an authenticated identity",
+        },
+        access_mode()
+    ))
+}
+
+// 2.70.1 set_access_mode Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn set_access_mode(mode: AccessMode) -> Result<AccessMode, Error> {
+    crate::call_stats::count_call("set_access_mode");
+    require_role(Role::Admin)?;
+    update_settings(|s| s.access_mode = Some(mode))?;
+    crate::logs::log(
+        crate::logs::LogLevel::Warn,
+        Some("set_access_mode"),
+        &format!("access mode set to {:?}", mode),
+    );
+    Ok(mode)
+}
+
+// 2.70.2 get_access_mode Function:
+// Open in every mode, so clients can tell that they need to sign in.
+#[ic_cdk::query]
+fn get_access_mode() -> AccessMode {
+    access_mode()
+}
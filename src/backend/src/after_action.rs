@@ -0,0 +1,342 @@
+// After-action reports: one document per crisis update that brings together its
+// timeline, statistics, SLA compliance, the resources requested for it and the
+// donations raised, for debriefs and donor reporting. Coordinators generate the
+// full report as JSON through the chunked export protocol. The HTTP gateway
+// also serves it as JSON, Markdown or HTML at /after-action/<id>.<format> for
+// updates stored in this canister, with a long timeline cut to its latest events.
+use crate::access::{require_any_role, Role};
+use crate::assignments::Assignee;
+use crate::calendar::format_utc;
+use crate::donations::DONATION_STORAGE;
+use crate::exports::{start_export_session, ExportInfo, ExportKind};
+use crate::jobs::NANOS_PER_SECOND;
+use crate::maintenance::admit_update;
+use crate::resources::{ResourceRequestStatus, ResourceType, RESOURCE_REQUEST_STORAGE};
+use crate::sanitize::escape_html;
+use crate::sla::UpdateSla;
+use crate::timeline::{TimelineEvent, TimelineEventKind};
+use crate::{CrisisPriority, CrisisStatus, CrisisUpdate, Error};
+use candid::Principal;
+use ic_cdk::api::time;
+
+pub(crate) const AFTER_ACTION_PATH_PREFIX: &str = "/after-action/";
+// Keeps gateway responses well inside the HTTP response limit
+const MAX_GATEWAY_TIMELINE_EVENTS: usize = 500;
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct AfterActionStatistics {
+    timeline_events: u64,
+    status_changes: u64,
+    escalations: u64,
+    assignments: u64,
+    links_attached: u64,
+    alerts_issued: u64,
+    resource_requests: u64,
+    // From the report to the first response and to resolution, when reached
+    response_time_secs: Option<u64>,
+    resolution_time_secs: Option<u64>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ResourceUsage {
+    request_id: u64,
+    resource_type: ResourceType,
+    description: String,
+    quantity: u64,
+    fulfilled_quantity: u64,
+    status: ResourceRequestStatus,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct DonationSummary {
+    donation_count: u64,
+    total_raised: u128,
+    total_disbursed: u128,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct AfterActionReport {
+    crisis_update_id: u64,
+    title: String,
+    location: String,
+    status: CrisisStatus,
+    priority: CrisisPriority,
+    reported_at: u64,
+    generated_at: u64,
+    statistics: AfterActionStatistics,
+    sla: Option<UpdateSla>,
+    resources: Vec<ResourceUsage>,
+    donations: DonationSummary,
+    // Oldest first
+    timeline: Vec<TimelineEvent>,
+    // Earliest events left out of the timeline; 0 in exported reports
+    omitted_timeline_events: u64,
+}
+
+fn statistics(events: &[TimelineEvent], sla: Option<&UpdateSla>, resource_requests: u64) -> AfterActionStatistics {
+    let mut statistics = AfterActionStatistics {
+        timeline_events: events.len() as u64,
+        resource_requests,
+        ..Default::default()
+    };
+    for event in events {
+        match event.kind {
+            TimelineEventKind::StatusChanged { .. } => statistics.status_changes += 1,
+            TimelineEventKind::Escalated { .. } => statistics.escalations += 1,
+            TimelineEventKind::Assigned { .. } => statistics.assignments += 1,
+            TimelineEventKind::LinkAttached { .. } => statistics.links_attached += 1,
+            TimelineEventKind::AlertIssued { .. } => statistics.alerts_issued += 1,
+            _ => {}
+        }
+    }
+    if let Some(sla) = sla {
+        let secs_since_opened = |at: u64| at.saturating_sub(sla.opened_at) / NANOS_PER_SECOND;
+        statistics.response_time_secs = sla.first_response_at.map(secs_since_opened);
+        statistics.resolution_time_secs = sla.resolved_at.map(secs_since_opened);
+    }
+    statistics
+}
+
+fn resources_for(crisis_update_id: u64) -> Vec<ResourceUsage> {
+    RESOURCE_REQUEST_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, request)| request)
+            .filter(|request| request.crisis_update_id == crisis_update_id)
+            .map(|request| ResourceUsage {
+                request_id: request.id,
+                resource_type: request.resource_type,
+                description: request.description,
+                quantity: request.quantity,
+                fulfilled_quantity: request.fulfilled_quantity,
+                status: request.status,
+            })
+            .collect()
+    })
+}
+
+fn donations_for(crisis_update_id: u64) -> DonationSummary {
+    let donation_count = DONATION_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, donation)| donation.crisis_id == crisis_update_id)
+            .count() as u64
+    });
+    DonationSummary {
+        donation_count,
+        total_raised: crate::donations::total_raised(crisis_update_id),
+        total_disbursed: crate::fundraising::total_disbursed(crisis_update_id),
+    }
+}
+
+// Keeps at most max_events of the latest timeline events when given
+fn compile(update: &CrisisUpdate, now: u64, max_events: Option<usize>) -> AfterActionReport {
+    let mut timeline = crate::timeline::collect_events(update);
+    let sla = crate::sla::sla_of(update.id, now);
+    let resources = resources_for(update.id);
+    let statistics = statistics(&timeline, sla.as_ref(), resources.len() as u64);
+    let omitted = max_events.map_or(0, |max| timeline.len().saturating_sub(max));
+    timeline.drain(..omitted);
+    AfterActionReport {
+        crisis_update_id: update.id,
+        title: update.title.clone(),
+        location: update.location.clone(),
+        status: update.status,
+        priority: update.priority,
+        reported_at: update.created_at,
+        generated_at: now,
+        statistics,
+        sla,
+        resources,
+        donations: donations_for(update.id),
+        timeline,
+        omitted_timeline_events: omitted as u64,
+    }
+}
+
+fn assignee_label(assignee: &Assignee) -> String {
+    match assignee {
+        Assignee::Responder(principal) => format!("responder {}", principal),
+        Assignee::Team(id) => format!("team {}", id),
+    }
+}
+
+fn describe(kind: &TimelineEventKind) -> String {
+    match kind {
+        TimelineEventKind::Reported { title } => format!("Reported: {}", title),
+        TimelineEventKind::StatusChanged { from, to } => format!("Status changed from {:?} to {:?}", from, to),
+        TimelineEventKind::PriorityChanged { from, to } => format!("Priority changed from {:?} to {:?}", from, to),
+        TimelineEventKind::Reviewed { approved: true } => "Approved by a moderator".to_string(),
+        TimelineEventKind::Reviewed { approved: false } => "Rejected by a moderator".to_string(),
+        TimelineEventKind::Escalated {
+            from,
+            to,
+            rule_name,
+            reason,
+        } => format!(
+            "Escalated from {:?} to {:?} by rule \"{}\": {}",
+            from, to, rule_name, reason
+        ),
+        TimelineEventKind::Assigned { assignee } => format!("Assigned to {}", assignee_label(assignee)),
+        TimelineEventKind::Unassigned { assignee } => format!("Unassigned from {}", assignee_label(assignee)),
+        TimelineEventKind::LinkAttached { url } => format!("Link attached: {}", url),
+        TimelineEventKind::ResourceRequested {
+            request_id,
+            resource_type,
+            quantity,
+        } => format!("Requested {} x {:?} (request {})", quantity, resource_type, request_id),
+        TimelineEventKind::AlertIssued {
+            alert_id,
+            title,
+            severity,
+        } => format!("{:?} alert issued: {} (alert {})", severity, title, alert_id),
+        TimelineEventKind::QuickUpdatePosted { quick_update_id, text } => {
+            format!("Quick update: {} (quick update {})", text, quick_update_id)
+        }
+    }
+}
+
+fn optional_secs(secs: Option<u64>) -> String {
+    secs.map_or_else(|| "not reached".to_string(), |secs| format!("{} s", secs))
+}
+
+// The report as titled sections of plain-text lines, shared by the Markdown and HTML renderings
+fn sections(report: &AfterActionReport) -> Vec<(&'static str, Vec<String>)> {
+    let stats = &report.statistics;
+    let mut sections = vec![
+        (
+            "Summary",
+            vec![
+                format!("Location: {}", report.location),
+                format!("Status: {:?}", report.status),
+                format!("Priority: {:?}", report.priority),
+                format!("Reported: {}", format_utc(report.reported_at)),
+                format!("Report generated: {}", format_utc(report.generated_at)),
+            ],
+        ),
+        (
+            "Statistics",
+            vec![
+                format!("Timeline events: {}", stats.timeline_events),
+                format!("Status changes: {}", stats.status_changes),
+                format!("Escalations: {}", stats.escalations),
+                format!("Assignments: {}", stats.assignments),
+                format!("Links attached: {}", stats.links_attached),
+                format!("Alerts issued: {}", stats.alerts_issued),
+                format!("Resource requests: {}", stats.resource_requests),
+                format!("Time to first response: {}", optional_secs(stats.response_time_secs)),
+                format!("Time to resolution: {}", optional_secs(stats.resolution_time_secs)),
+            ],
+        ),
+    ];
+    sections.push((
+        "SLA compliance",
+        match &report.sla {
+            Some(sla) => vec![
+                format!("Response: {:?}", sla.response_state),
+                format!("Resolution: {:?}", sla.resolution_state),
+                format!("Overall: {:?}", sla.state),
+            ],
+            None => vec!["No SLA record".to_string()],
+        },
+    ));
+    sections.push((
+        "Resources",
+        if report.resources.is_empty() {
+            vec!["No resource requests".to_string()]
+        } else {
+            report
+                .resources
+                .iter()
+                .map(|r| {
+                    format!(
+                        "Request {}: {:?}, {} of {} fulfilled ({:?}) {}",
+                        r.request_id, r.resource_type, r.fulfilled_quantity, r.quantity, r.status, r.description
+                    )
+                })
+                .collect()
+        },
+    ));
+    sections.push((
+        "Donations",
+        vec![
+            format!("Donations: {}", report.donations.donation_count),
+            format!("Raised: {}", report.donations.total_raised),
+            format!("Disbursed: {}", report.donations.total_disbursed),
+        ],
+    ));
+    let mut timeline: Vec<String> = Vec::new();
+    if report.omitted_timeline_events > 0 {
+        timeline.push(format!(
+            "{} earlier events are left out; generate the full report for all of them",
+            report.omitted_timeline_events
+        ));
+    }
+    timeline.extend(
+        report
+            .timeline
+            .iter()
+            .map(|event| format!("{}: {}", format_utc(event.at), describe(&event.kind))),
+    );
+    sections.push(("Timeline", timeline));
+    sections
+}
+
+fn render_markdown(report: &AfterActionReport) -> String {
+    let mut doc = format!("# After-action report: {}\n", report.title);
+    for (heading, lines) in sections(report) {
+        doc.push_str(&format!("\n## {}\n\n", heading));
+        for line in lines {
+            doc.push_str(&format!("- {}\n", line.replace('\n', " ")));
+        }
+    }
+    doc
+}
+
+fn render_html(report: &AfterActionReport) -> String {
+    let title = escape_html(&format!("After-action report: {}", report.title));
+    let mut doc = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head><body>\n<h1>{0}</h1>\n",
+        title
+    );
+    for (heading, lines) in sections(report) {
+        doc.push_str(&format!("<h2>{}</h2>\n<ul>\n", heading));
+        for line in lines {
+            doc.push_str(&format!("<li>{}</li>\n", escape_html(&line)));
+        }
+        doc.push_str("</ul>\n");
+    }
+    doc.push_str("</body></html>\n");
+    doc
+}
+
+// Renders the report named by the rest of a gateway path, e.g. "42.md", as its
+// content type and body; None when there is no such report or format
+pub(crate) fn render_for_gateway(path: &str, viewer: &Principal) -> Option<(&'static str, String)> {
+    let (id, format) = path.rsplit_once('.')?;
+    let update = crate::_get_crisis_update(&id.parse().ok()?).filter(|u| crate::moderation::visible_to(u, viewer))?;
+    let report = compile(&update, time(), Some(MAX_GATEWAY_TIMELINE_EVENTS));
+    match format {
+        "json" => Some(("application/json", serde_json::to_string(&report).ok()?)),
+        "md" => Some(("text/markdown; charset=utf-8", render_markdown(&report))),
+        "html" => Some(("text/html; charset=utf-8", render_html(&report))),
+        _ => None,
+    }
+}
+
+// 2.79.1 generate_after_action_report Function:
+// Opens an export session with the full report as JSON; fetch it with get_export_chunk.
+#[ic_cdk::update(guard = "admit_update")]
+async fn generate_after_action_report(crisis_id: u64) -> Result<ExportInfo, Error> {
+    crate::call_stats::count_call("generate_after_action_report");
+    let caller = require_any_role(&[Role::Coordinator, Role::Moderator])?;
+    let Some(update) = crate::_find_crisis_update(crisis_id).await? else {
+        return Err(
+            Error::not_found(format!("a crisis update with id={} not found", crisis_id)).with_detail("id", crisis_id),
+        );
+    };
+    let report = compile(&update, time(), None);
+    let data = serde_json::to_vec(&report)
+        .map_err(|err| Error::internal(format!("cannot encode the after-action report: {}", err)))?;
+    start_export_session(caller, ExportKind::AfterActionReport, data)
+}
@@ -0,0 +1,363 @@
+// Cross-posting of public crisis updates to a global aggregator canister, so
+// regional deployments can feed a national situational picture. Like
+// replication, the change feed is the publishing queue: the heartbeat sends the
+// records after the aggregator's cursor in batches. Only updates anyone could
+// see are sent, stripped of authorship, contact details, signatures and
+// metadata; an update that is deleted or stops being public is withdrawn.
+// The aggregator assigns its own ids, which are kept per local update. Failed
+// calls are retried with exponential backoff, and a new aggregator starts with
+// a full resync of every stored update.
+//
+// The aggregator canister is expected to expose:
+//   aggregator_apply : (vec AggregatorEntry) -> (vec AggregatorReceipt);
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::changes::{changes_after, current_seq, pruned_through_seq, ChangeKind};
+use crate::geo::Coordinates;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::logs::{log, LogLevel};
+use crate::maintenance::admit_update;
+use crate::regions::Region;
+use crate::settings::{get_settings, update_settings};
+use crate::visibility::Visibility;
+use crate::{CrisisPriority, CrisisStatus, CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const PUBLISH_BATCH_SIZE: usize = 50;
+pub(crate) const AGGREGATOR_INTERVAL_SECS: u64 = 10;
+const RETRY_BASE_SECS: u64 = 10;
+const MAX_RETRY_DELAY_SECS: u64 = 60 * 60;
+
+// The public part of a crisis update, as sent to the aggregator
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AggregatedUpdate {
+    title: String,
+    description: String,
+    location: String,
+    coordinates: Option<Coordinates>,
+    region: Option<Region>,
+    status: CrisisStatus,
+    priority: CrisisPriority,
+    created_at: u64,
+    updated_at: Option<u64>,
+    occurred_at: Option<u64>,
+    expires_at: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AggregatorEntry {
+    // The id of the update in this canister
+    source_id: u64,
+    // The aggregator's id, once it has assigned one
+    aggregator_id: Option<u64>,
+    // None withdraws the update
+    update: Option<AggregatedUpdate>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AggregatorReceipt {
+    source_id: u64,
+    // None once the update has been withdrawn
+    aggregator_id: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct AggregatorState {
+    // Every change up to this sequence number has reached the aggregator
+    published_through_seq: u64,
+    // Set while a full resync runs: the feed position it started at and the last id sent
+    resync: Option<(u64, Option<u64>)>,
+    last_publish_at: Option<u64>,
+    last_error: Option<String>,
+    consecutive_failures: u32,
+    // No attempt is made before this time after a failure
+    retry_at: Option<u64>,
+}
+
+// Implementing Storable trait for AggregatorState
+impl Storable for AggregatorState {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct AggregatorStatus {
+    aggregator_canister: Option<Principal>,
+    published_through_seq: u64,
+    current_seq: u64,
+    // Feed records not yet sent to the aggregator
+    lag_changes: u64,
+    resync_in_progress: bool,
+    // Local updates the aggregator currently holds
+    published_updates: u64,
+    last_publish_at: Option<u64>,
+    last_error: Option<String>,
+    consecutive_failures: u32,
+    retry_at: Option<u64>,
+}
+
+thread_local! {
+    static AGGREGATOR_STATE: RefCell<Cell<AggregatorState, Memory>> = RefCell::new(
+        Cell::init(CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(115))), AggregatorState::default())
+            .expect("Cannot create the aggregator state cell")
+    );
+
+    // local crisis update id -> aggregator id
+    static AGGREGATOR_IDS: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(116)))
+    ));
+
+    static PUBLISH_IN_PROGRESS: RefCell<bool> = const { RefCell::new(false) };
+}
+
+fn state() -> AggregatorState {
+    AGGREGATOR_STATE.with(|s| s.borrow().get().clone())
+}
+
+fn save_state(state: AggregatorState) -> Result<(), Error> {
+    AGGREGATOR_STATE.with(|s| {
+        s.borrow_mut()
+            .set(state)
+            .map(|_| ())
+            .map_err(|err| Error::internal(format!("cannot persist the aggregator state: {:?}", err)))
+    })
+}
+
+struct PublishRunGuard;
+
+impl PublishRunGuard {
+    fn acquire() -> Result<Self, Error> {
+        if PUBLISH_IN_PROGRESS.with(|r| r.replace(true)) {
+            return Err(Error::conflict("an aggregator push is already in progress"));
+        }
+        Ok(PublishRunGuard)
+    }
+}
+
+impl Drop for PublishRunGuard {
+    fn drop(&mut self) {
+        PUBLISH_IN_PROGRESS.with(|r| *r.borrow_mut() = false);
+    }
+}
+
+// True when there is anything to publish and no retry is pending; checked by the heartbeat
+pub(crate) fn publish_pending(now: u64) -> bool {
+    if get_settings().aggregator_canister.is_none() || PUBLISH_IN_PROGRESS.with(|r| *r.borrow()) {
+        return false;
+    }
+    let state = state();
+    state.retry_at.is_none_or(|at| now >= at) && (state.resync.is_some() || state.published_through_seq < current_seq())
+}
+
+fn is_public(update: &CrisisUpdate) -> bool {
+    update.visibility == Visibility::Public && crate::moderation::visible_to(update, &Principal::anonymous())
+}
+
+// Built from the public redaction, so the aggregator sees what an anonymous reader would
+fn aggregated(update: &CrisisUpdate) -> AggregatedUpdate {
+    let update = crate::redaction::redact_update(update.clone(), &Principal::anonymous());
+    AggregatedUpdate {
+        title: update.title,
+        description: update.description,
+        location: update.location,
+        coordinates: update.coordinates,
+        region: update.region,
+        status: update.status,
+        priority: update.priority,
+        created_at: update.created_at,
+        updated_at: update.updated_at,
+        occurred_at: update.occurred_at,
+        expires_at: update.expires_at,
+    }
+}
+
+// The entry for an update's current state; None when the aggregator has nothing to learn
+fn entry_for(source_id: u64, update: Option<&CrisisUpdate>) -> Option<AggregatorEntry> {
+    let aggregator_id = AGGREGATOR_IDS.with(|s| s.borrow().get(&source_id));
+    let update = update.filter(|update| is_public(update)).map(aggregated);
+    if update.is_none() && aggregator_id.is_none() {
+        return None;
+    }
+    Some(AggregatorEntry {
+        source_id,
+        aggregator_id,
+        update,
+    })
+}
+
+async fn send_entries(aggregator: Principal, entries: Vec<AggregatorEntry>) -> Result<(), Error> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let (receipts,): (Vec<AggregatorReceipt>,) = ic_cdk::call(aggregator, "aggregator_apply", (entries,))
+        .await
+        .map_err(|(code, msg)| {
+            Error::external_call_failed(format!(
+                "aggregator {} rejected aggregator_apply: {:?} {}",
+                aggregator, code, msg
+            ))
+        })?;
+    AGGREGATOR_IDS.with(|s| {
+        let mut s = s.borrow_mut();
+        for receipt in receipts {
+            match receipt.aggregator_id {
+                Some(id) => s.insert(receipt.source_id, id),
+                None => s.remove(&receipt.source_id),
+            };
+        }
+    });
+    Ok(())
+}
+
+// Sends the next batch of a full resync; returns the new resync cursor
+async fn publish_resync_batch(aggregator: Principal, last_id: Option<u64>) -> Result<Option<Option<u64>>, Error> {
+    let start = last_id.map_or(0, |id| id.saturating_add(1));
+    let batch: Vec<CrisisUpdate> = CRISIS_STORAGE.with(|s| {
+        s.borrow()
+            .range(start..)
+            .take(PUBLISH_BATCH_SIZE)
+            .map(|(_, update)| update)
+            .collect()
+    });
+    let Some(next_last) = batch.last().map(|update| update.id) else {
+        return Ok(None);
+    };
+    let entries = batch
+        .iter()
+        .filter_map(|update| entry_for(update.id, Some(update)))
+        .collect();
+    send_entries(aggregator, entries).await?;
+    Ok(Some(Some(next_last)))
+}
+
+// Sends the next feed records after the cursor; returns the new cursor
+async fn publish_change_batch(aggregator: Principal, since: u64) -> Result<u64, Error> {
+    let records = changes_after(since, PUBLISH_BATCH_SIZE);
+    let Some(last_seq) = records.last().map(|(seq, _)| *seq) else {
+        return Ok(since);
+    };
+    let mut entries: Vec<AggregatorEntry> = Vec::with_capacity(records.len());
+    for (_, record) in records {
+        // Only the latest state of an update edited several times in the batch is sent
+        entries.retain(|entry| entry.source_id != record.crisis_update_id);
+        let update = match record.kind {
+            ChangeKind::Deleted => None,
+            ChangeKind::Created | ChangeKind::Updated => crate::_find_crisis_update(record.crisis_update_id).await?,
+        };
+        entries.extend(entry_for(record.crisis_update_id, update.as_ref()));
+    }
+    send_entries(aggregator, entries).await?;
+    Ok(last_seq)
+}
+
+async fn publish_once(aggregator: Principal) -> Result<(), Error> {
+    let mut state = state();
+    if state.resync.is_none() && state.published_through_seq < pruned_through_seq() {
+        // Changes the aggregator never saw have been pruned from the feed
+        state.resync = Some((current_seq(), None));
+    }
+    match state.resync {
+        Some((started_at_seq, last_id)) => match publish_resync_batch(aggregator, last_id).await? {
+            Some(cursor) => state.resync = Some((started_at_seq, cursor)),
+            None => {
+                // Changes made during the resync are replayed from where it started
+                state.resync = None;
+                state.published_through_seq = started_at_seq;
+            }
+        },
+        None => state.published_through_seq = publish_change_batch(aggregator, state.published_through_seq).await?,
+    }
+    state.last_publish_at = Some(time());
+    state.last_error = None;
+    state.consecutive_failures = 0;
+    state.retry_at = None;
+    save_state(state)
+}
+
+fn retry_delay_secs(failures: u32) -> u64 {
+    RETRY_BASE_SECS
+        .saturating_mul(1u64 << failures.saturating_sub(1).min(16))
+        .min(MAX_RETRY_DELAY_SECS)
+}
+
+// Publishes one batch to the aggregator; run from the heartbeat
+pub(crate) async fn run_publishing() {
+    let Some(aggregator) = get_settings().aggregator_canister else {
+        return;
+    };
+    let Ok(_guard) = PublishRunGuard::acquire() else {
+        return;
+    };
+    if let Err(err) = publish_once(aggregator).await {
+        log(
+            LogLevel::Error,
+            Some("aggregator"),
+            &format!("publishing to aggregator {} failed: {:?}", aggregator, err),
+        );
+        let mut state = state();
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+        state.retry_at = Some(time().saturating_add(retry_delay_secs(state.consecutive_failures) * NANOS_PER_SECOND));
+        state.last_error = Some(err.msg);
+        if let Err(err) = save_state(state) {
+            log(LogLevel::Error, Some("aggregator"), &format!("{:?}", err));
+        }
+    }
+}
+
+// 2.84.1 set_aggregator_canister Function:
+// A new aggregator starts with a full resync; None stops publishing.
+#[ic_cdk::update(guard = "admit_update")]
+fn set_aggregator_canister(aggregator: Option<Principal>) -> Result<(), Error> {
+    crate::call_stats::count_call("set_aggregator_canister");
+    require_role(Role::Admin)?;
+    update_settings(|s| s.aggregator_canister = aggregator)?;
+    // Ids assigned by a previous aggregator mean nothing to the next one
+    AGGREGATOR_IDS.with(|s| {
+        let mut s = s.borrow_mut();
+        let ids: Vec<u64> = s.iter().map(|(id, _)| id).collect();
+        for id in ids {
+            s.remove(&id);
+        }
+    });
+    save_state(AggregatorState {
+        resync: aggregator.map(|_| (current_seq(), None)),
+        ..AggregatorState::default()
+    })
+}
+
+// 2.84.2 get_aggregator_status Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_aggregator_status() -> Result<AggregatorStatus, Error> {
+    require_role(Role::Admin)?;
+    let state = state();
+    let current_seq = current_seq();
+    Ok(AggregatorStatus {
+        aggregator_canister: get_settings().aggregator_canister,
+        published_through_seq: state.published_through_seq,
+        current_seq,
+        lag_changes: current_seq.saturating_sub(state.published_through_seq),
+        resync_in_progress: state.resync.is_some(),
+        published_updates: AGGREGATOR_IDS.with(|s| s.borrow().len()),
+        last_publish_at: state.last_publish_at,
+        last_error: state.last_error,
+        consecutive_failures: state.consecutive_failures,
+        retry_at: state.retry_at,
+    })
+}
+
+// 2.84.3 get_aggregator_id Function:
+// The aggregator's id for a published update; None when it has not been published.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_aggregator_id(crisis_update_id: u64) -> Option<u64> {
+    AGGREGATOR_IDS.with(|s| s.borrow().get(&crisis_update_id))
+}
@@ -0,0 +1,393 @@
+// Official mass alerts, distinct from crisis updates reported by the public.
+// Only verified organizations publish them. Publishing notifies everyone
+// subscribed to an affected region, and `list_active_alerts` keeps an alert
+// at the top of public feeds until it expires or is withdrawn. Recipients
+// acknowledge alerts so managers can see how far an order has reached. An alert
+// with a geofence only notifies subscribers whose subscribed locations are known
+// to lie inside it, and `get_my_active_alerts` matches it against a position.
+use crate::access::{has_role, principal_key, require_role, PrincipalKey, Role};
+use crate::access_mode::admit_read;
+use crate::geo::{Coordinates, Geofence};
+use crate::ids::IdCounter;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::maintenance::admit_update;
+use crate::normalize::{normalize_line, normalize_multiline};
+use crate::notifications::{subscribers_of, subscribers_within, try_notify, NotificationKind};
+use crate::sanitize::sanitize_text;
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_TITLE_CHARS: usize = 150;
+const MAX_BODY_CHARS: usize = 2000;
+const MAX_AFFECTED_REGIONS: usize = 20;
+const MAX_REGION_CHARS: usize = 100;
+const MAX_ALERT_DURATION_SECS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub(crate) enum AlertSeverity {
+    Advisory,
+    Watch,
+    Warning,
+    Emergency,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AlertPayload {
+    title: String,
+    body: String,
+    severity: AlertSeverity,
+    // Location names, matched like crisis update locations
+    affected_regions: Vec<String>,
+    geofence: Option<Geofence>,
+    expires_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Alert {
+    pub(crate) id: u64,
+    pub(crate) title: String,
+    body: String,
+    pub(crate) severity: AlertSeverity,
+    affected_regions: Vec<String>,
+    geofence: Option<Geofence>,
+    pub(crate) issued_by: Principal,
+    pub(crate) issued_at: u64,
+    expires_at: u64,
+    withdrawn_at: Option<u64>,
+    // Subscribers notified when the alert was published
+    notified: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct AlertAckStats {
+    alert_id: u64,
+    notified: u64,
+    acknowledged: u64,
+    // Acknowledgments from principals that were notified
+    acknowledged_by_notified: u64,
+    // Users with a registered profile, for reach across the whole user base
+    registered_users: u64,
+    last_acknowledged_at: Option<u64>,
+}
+
+impl Alert {
+    fn is_active(&self, now: u64) -> bool {
+        self.withdrawn_at.is_none() && self.expires_at > now
+    }
+
+    fn covers(&self, location: &str) -> bool {
+        self.affected_regions
+            .iter()
+            .any(|region| crate::areas::same_place(region, location))
+    }
+}
+
+// Implementing Storable and BoundedStorable traits for Alert
+impl Storable for Alert {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Alert {
+    const MAX_SIZE: u32 = 24 * 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static ALERT_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(70, "alerts"));
+
+    static ALERT_STORAGE: RefCell<StableBTreeMap<u64, Alert, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(71)))
+    ));
+
+    // (alert id, principal) -> acknowledged at
+    static ALERT_ACKS: RefCell<StableBTreeMap<(u64, PrincipalKey), u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(72)))
+    ));
+
+    // (alert id, principal) for every subscriber the alert was sent to
+    static ALERT_RECIPIENTS: RefCell<StableBTreeMap<(u64, PrincipalKey), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(73)))
+    ));
+}
+
+// (alert id, acknowledged at) for every alert the principal acknowledged
+pub(crate) fn acknowledgments_of(principal: &Principal) -> Vec<(u64, u64)> {
+    let key = principal_key(principal);
+    ALERT_ACKS.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|((_, k), _)| *k == key)
+            .map(|((alert_id, _), at)| (alert_id, at))
+            .collect()
+    })
+}
+
+// Forgets that the principal received or acknowledged alerts; returns the acknowledgments removed
+pub(crate) fn remove_alert_records_of(principal: &Principal) -> u64 {
+    let key = principal_key(principal);
+    let recipients: Vec<(u64, PrincipalKey)> =
+        ALERT_RECIPIENTS.with(|s| s.borrow().iter().map(|(k, _)| k).filter(|k| k.1 == key).collect());
+    ALERT_RECIPIENTS.with(|s| {
+        let mut s = s.borrow_mut();
+        for k in &recipients {
+            s.remove(k);
+        }
+    });
+    let acks: Vec<(u64, PrincipalKey)> =
+        ALERT_ACKS.with(|s| s.borrow().iter().map(|(k, _)| k).filter(|k| k.1 == key).collect());
+    ALERT_ACKS.with(|s| {
+        let mut s = s.borrow_mut();
+        for k in &acks {
+            s.remove(k);
+        }
+    });
+    acks.len() as u64
+}
+
+fn check_length(field: &str, text: &str, max: usize) -> Result<(), Error> {
+    if text.is_empty() || text.chars().count() > max {
+        return Err(Error::validation(format!(
+            "{} must be between 1 and {} characters",
+            field, max
+        )));
+    }
+    Ok(())
+}
+
+fn validate_alert(payload: &mut AlertPayload, now: u64) -> Result<(), Error> {
+    payload.title = normalize_line(&sanitize_text("title", &payload.title, false)?);
+    payload.body = normalize_multiline(&sanitize_text("body", &payload.body, true)?);
+    check_length("title", &payload.title, MAX_TITLE_CHARS)?;
+    check_length("body", &payload.body, MAX_BODY_CHARS)?;
+    let mut regions: Vec<String> = Vec::new();
+    for region in &payload.affected_regions {
+        let region = normalize_line(&sanitize_text("affected region", region, false)?);
+        check_length("affected regions", &region, MAX_REGION_CHARS)?;
+        if !regions.iter().any(|r| crate::areas::same_place(r, &region)) {
+            regions.push(region);
+        }
+    }
+    if regions.is_empty() || regions.len() > MAX_AFFECTED_REGIONS {
+        return Err(Error::validation(format!(
+            "an alert must name between 1 and {} affected regions",
+            MAX_AFFECTED_REGIONS
+        )));
+    }
+    payload.affected_regions = regions;
+    if let Some(geofence) = &payload.geofence {
+        geofence.validate()?;
+    }
+    let latest = now.saturating_add(MAX_ALERT_DURATION_SECS * NANOS_PER_SECOND);
+    if payload.expires_at <= now || payload.expires_at > latest {
+        return Err(Error::validation(format!(
+            "expires_at must be in the future and at most {} days away",
+            MAX_ALERT_DURATION_SECS / (24 * 60 * 60)
+        ))
+        .with_detail("expires_at", payload.expires_at));
+    }
+    Ok(())
+}
+
+fn find_alert(id: u64) -> Result<Alert, Error> {
+    ALERT_STORAGE
+        .with(|s| s.borrow().get(&id))
+        .ok_or_else(|| Error::not_found(format!("an alert with id={} not found", id)).with_detail("id", id))
+}
+
+// Notifies subscribers of the affected regions; returns how many were notified
+fn broadcast(alert: &Alert) -> u64 {
+    let title: String = alert.title.chars().take(200).collect();
+    let message = format!(
+        "{:?} alert for {}: {}",
+        alert.severity,
+        alert.affected_regions.join(", "),
+        title
+    );
+    let mut notified = 0;
+    let recipients = match &alert.geofence {
+        Some(geofence) => subscribers_within(geofence),
+        None => subscribers_of(&alert.affected_regions),
+    };
+    for recipient in recipients {
+        if recipient != alert.issued_by {
+            try_notify(recipient, NotificationKind::MassAlert, message.clone(), Some(alert.id));
+            ALERT_RECIPIENTS.with(|s| s.borrow_mut().insert((alert.id, principal_key(&recipient)), ()));
+            notified += 1;
+        }
+    }
+    notified
+}
+
+// Alerts, active or not, naming any of the places as an affected region
+pub(crate) fn alerts_covering(places: &[String]) -> Vec<Alert> {
+    ALERT_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, alert)| alert)
+            .filter(|alert| places.iter().any(|place| alert.covers(place)))
+            .collect()
+    })
+}
+
+// 2.54.1 publish_alert Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn publish_alert(mut payload: AlertPayload) -> Result<Alert, Error> {
+    crate::call_stats::count_call("publish_alert");
+    let issuer = require_role(Role::VerifiedOrg)?;
+    let now = time();
+    validate_alert(&mut payload, now)?;
+    let mut alert = Alert {
+        id: crate::ids::next_id(&ALERT_ID_COUNTER)?,
+        title: payload.title,
+        body: payload.body,
+        severity: payload.severity,
+        affected_regions: payload.affected_regions,
+        geofence: payload.geofence,
+        issued_by: issuer,
+        issued_at: now,
+        expires_at: payload.expires_at,
+        withdrawn_at: None,
+        notified: 0,
+    };
+    crate::ensure_storable(&alert, "alert")?;
+    alert.notified = broadcast(&alert);
+    ALERT_STORAGE.with(|s| s.borrow_mut().insert(alert.id, alert.clone()));
+    Ok(alert)
+}
+
+// 2.54.2 withdraw_alert Function:
+// Ends an alert early; only its issuer or an admin may withdraw it.
+#[ic_cdk::update(guard = "admit_update")]
+fn withdraw_alert(id: u64) -> Result<Alert, Error> {
+    crate::call_stats::count_call("withdraw_alert");
+    let caller = require_role(Role::VerifiedOrg)?;
+    let mut alert = find_alert(id)?;
+    if alert.issued_by != caller && !has_role(&caller, Role::Admin) {
+        return Err(Error::unauthorized(format!(
+            "caller did not issue the alert with id={}",
+            id
+        )));
+    }
+    if alert.withdrawn_at.is_some() {
+        return Err(Error::conflict(format!(
+            "the alert with id={} is already withdrawn",
+            id
+        )));
+    }
+    alert.withdrawn_at = Some(time());
+    ALERT_STORAGE.with(|s| s.borrow_mut().insert(id, alert.clone()));
+    Ok(alert)
+}
+
+// 2.54.3 get_alert Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_alert(id: u64) -> Result<Alert, Error> {
+    find_alert(id)
+}
+
+// 2.54.4 list_active_alerts Function:
+// Most severe first, then newest; all active alerts when no location is given.
+#[ic_cdk::query(guard = "admit_read")]
+fn list_active_alerts(location: Option<String>) -> Vec<Alert> {
+    let now = time();
+    let mut alerts: Vec<Alert> = ALERT_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, alert)| alert)
+            .filter(|alert| alert.is_active(now) && location.as_ref().is_none_or(|l| alert.covers(l)))
+            .collect()
+    });
+    alerts.sort_by_key(|alert| std::cmp::Reverse((alert.severity, alert.issued_at)));
+    alerts
+}
+
+// 2.54.5 acknowledge_alert Function:
+// Records that the caller has seen the alert; acknowledging again keeps the first time.
+#[ic_cdk::update(guard = "admit_update")]
+fn acknowledge_alert(id: u64) -> Result<u64, Error> {
+    crate::call_stats::count_call("acknowledge_alert");
+    let caller = caller();
+    if caller == Principal::anonymous() {
+        return Err(Error::unauthorized("anonymous callers cannot acknowledge alerts"));
+    }
+    find_alert(id)?;
+    let key = (id, principal_key(&caller));
+    Ok(ALERT_ACKS.with(|s| {
+        let mut s = s.borrow_mut();
+        match s.get(&key) {
+            Some(at) => at,
+            None => {
+                let now = time();
+                s.insert(key, now);
+                now
+            }
+        }
+    }))
+}
+
+// 2.54.6 get_alert_ack_stats Function:
+// Available to the issuing organization and to coordinators.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_alert_ack_stats(id: u64) -> Result<AlertAckStats, Error> {
+    let caller = caller();
+    let alert = find_alert(id)?;
+    if alert.issued_by != caller && !has_role(&caller, Role::Coordinator) {
+        return Err(Error::unauthorized(format!(
+            "caller may not see acknowledgments of the alert with id={}",
+            id
+        )));
+    }
+    let min_key = (id, PrincipalKey::default());
+    let acks: Vec<(PrincipalKey, u64)> = ALERT_ACKS.with(|s| {
+        s.borrow()
+            .range(min_key..)
+            .take_while(|((alert_id, _), _)| *alert_id == id)
+            .map(|((_, principal), at)| (principal, at))
+            .collect()
+    });
+    let acknowledged_by_notified = ALERT_RECIPIENTS.with(|s| {
+        let s = s.borrow();
+        acks.iter()
+            .filter(|(principal, _)| s.contains_key(&(id, *principal)))
+            .count() as u64
+    });
+    Ok(AlertAckStats {
+        alert_id: id,
+        notified: alert.notified,
+        acknowledged: acks.len() as u64,
+        acknowledged_by_notified,
+        registered_users: crate::profiles::profile_count(),
+        last_acknowledged_at: acks.iter().map(|(_, at)| *at).max(),
+    })
+}
+
+// 2.54.7 get_my_active_alerts Function:
+// Active geofenced alerts whose geofence contains the caller's position, most severe first.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_my_active_alerts(latitude: f64, longitude: f64) -> Result<Vec<Alert>, Error> {
+    let position = Coordinates { latitude, longitude };
+    position.validate()?;
+    let now = time();
+    let mut alerts: Vec<Alert> = ALERT_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, alert)| alert)
+            .filter(|alert| alert.is_active(now) && alert.geofence.as_ref().is_some_and(|g| g.contains(&position)))
+            .collect()
+    });
+    alerts.sort_by_key(|alert| std::cmp::Reverse((alert.severity, alert.issued_at)));
+    Ok(alerts)
+}
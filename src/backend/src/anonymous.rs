@@ -0,0 +1,166 @@
+// Anonymous reporting for reporters who fear retaliation. The report stores no
+// principal: only a salted hash of the submitter and a salted hash of a random
+// claim token that is returned once and lets the reporter edit the report
+// later. The salt is drawn from the IC's randomness on first use and never
+// leaves the canister. Anonymous reports always wait for moderator review.
+use crate::maintenance::admit_update;
+use crate::moderation::ReviewStatus;
+use crate::{CrisisPriority, CrisisStatus, CrisisUpdate, CrisisUpdatePayload, Error, Memory, CRISIS_MEMORY_MANAGER};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::Cell;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AnonymousAuthorship {
+    // salted sha256 of the submitting principal
+    author_hash: Vec<u8>,
+    // salted sha256 of the claim token
+    claim_hash: Vec<u8>,
+}
+
+impl AnonymousAuthorship {
+    // Marks a report as anonymous without revealing its hashes
+    pub(crate) fn masked() -> Self {
+        AnonymousAuthorship {
+            author_hash: Vec::new(),
+            claim_hash: Vec::new(),
+        }
+    }
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct AnonymousSubmission {
+    update: CrisisUpdate,
+    // Shown only in this reply; needed to edit the report later
+    claim_token: String,
+}
+
+thread_local! {
+    static ANONYMITY_SALT: RefCell<Cell<Vec<u8>, Memory>> = RefCell::new(
+        Cell::init(CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(56))), Vec::new())
+            .expect("Cannot create the anonymity salt cell")
+    );
+}
+
+async fn random_bytes() -> Result<Vec<u8>, Error> {
+    raw_rand()
+        .await
+        .map(|(bytes,)| bytes)
+        .map_err(|(code, msg)| Error::external_call_failed(format!("raw_rand failed: {:?} {}", code, msg)))
+}
+
+async fn salt() -> Result<Vec<u8>, Error> {
+    let current = ANONYMITY_SALT.with(|s| s.borrow().get().clone());
+    if !current.is_empty() {
+        return Ok(current);
+    }
+    let fresh = random_bytes().await?;
+    // Another call may have set the salt while this one awaited randomness
+    ANONYMITY_SALT.with(|s| {
+        let existing = s.borrow().get().clone();
+        if !existing.is_empty() {
+            return Ok(existing);
+        }
+        s.borrow_mut()
+            .set(fresh.clone())
+            .map_err(|err| Error::internal(format!("cannot persist the anonymity salt: {:?}", err)))?;
+        Ok(fresh)
+    })
+}
+
+fn salted_hash(salt: &[u8], domain: &str, value: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(domain.as_bytes());
+    hasher.update(value);
+    hasher.finalize().to_vec()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// 2.38.1 add_anonymous_crisis_update Function:
+#[ic_cdk::update(guard = "admit_update")]
+async fn add_anonymous_crisis_update(mut payload: CrisisUpdatePayload) -> Result<AnonymousSubmission, Error> {
+    crate::call_stats::count_call("add_anonymous_crisis_update");
+    if payload.signature.is_some() {
+        return Err(Error::validation(
+            "anonymous reports cannot be signed; a signature would identify the author",
+        ));
+    }
+    let now = time();
+    crate::_check_input(&mut payload, now)?;
+    let salt = salt().await?;
+    let claim_token = to_hex(&random_bytes().await?);
+    let id = crate::ids::next_id(&crate::CRISIS_ID_COUNTER)?;
+    let update = CrisisUpdate {
+        id,
+        title: payload.title,
+        description: payload.description,
+        location: payload.location,
+        affected_areas: payload.affected_areas,
+        created_at: now,
+        updated_at: None,
+        occurred_at: payload.occurred_at,
+        status: CrisisStatus::Active,
+        priority: CrisisPriority::Normal,
+        author: None,
+        author_name: None,
+        signature: None,
+        review: Some(ReviewStatus::Pending),
+        anonymous: Some(AnonymousAuthorship {
+            author_hash: salted_hash(&salt, "author", caller().as_slice()),
+            claim_hash: salted_hash(&salt, "claim", claim_token.as_bytes()),
+        }),
+        metadata: payload.metadata,
+        coordinates: payload.coordinates,
+        region: payload.region,
+        visibility: payload.visibility.unwrap_or_default(),
+        tenant: None,
+        api_key_id: None,
+        contact: payload.contact,
+        expires_at: payload.expires_at,
+    };
+    crate::store_new_crisis_update(&update).await?;
+    let update = crate::escalation::evaluate_escalation(update).await;
+    Ok(AnonymousSubmission { update, claim_token })
+}
+
+// 2.38.2 update_anonymous_crisis_update Function:
+// An edit sends the report back to the moderation queue.
+#[ic_cdk::update(guard = "admit_update")]
+async fn update_anonymous_crisis_update(
+    id: u64,
+    claim_token: String,
+    mut payload: CrisisUpdatePayload,
+) -> Result<CrisisUpdate, Error> {
+    crate::call_stats::count_call("update_anonymous_crisis_update");
+    let now = time();
+    crate::_check_input(&mut payload, now)?;
+    let Some(mut update) = crate::_find_crisis_update(id).await? else {
+        return Err(Error::not_found(format!(
+            "couldn't update a crisis update with id={}. update not found",
+            id
+        )));
+    };
+    let salt = salt().await?;
+    let claimed = update
+        .anonymous
+        .as_ref()
+        .is_some_and(|a| a.claim_hash == salted_hash(&salt, "claim", claim_token.as_bytes()));
+    if !claimed {
+        return Err(Error::unauthorized(format!(
+            "the claim token does not match the crisis update with id={}",
+            id
+        )));
+    }
+    payload.signature = None;
+    crate::apply_edit(&mut update, payload, now)?;
+    update.review = Some(ReviewStatus::Pending);
+    crate::store_edited_crisis_update(&update).await?;
+    Ok(update)
+}
@@ -0,0 +1,262 @@
+// API keys for reporters that cannot hold an Internet Identity, such as flood
+// sensors and scripts. An admin issues a key once; only its sha256 is stored.
+// Each key is limited to the places it may report from and to an hourly
+// number of submissions, and can be revoked at any time.
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::ids::IdCounter;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::maintenance::admit_update;
+use crate::normalize::normalize_line;
+use crate::response::{fit, QueryResult};
+use crate::{
+    CrisisPriority, CrisisStatus, CrisisUpdate, CrisisUpdatePayload, Error, Memory, CRISIS_MEMORY_MANAGER,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+const KEY_PREFIX: &str = "cuk_";
+const MAX_LABEL_CHARS: usize = 100;
+const MAX_ALLOWED_LOCATIONS: usize = 20;
+const MAX_SUBMISSIONS_PER_HOUR: u32 = 600;
+const RATE_WINDOW_SECS: u64 = 60 * 60;
+
+type KeyHash = Blob<32>;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ApiKeyScope {
+    // Locations the key may report from; empty allows any
+    allowed_locations: Vec<String>,
+    max_submissions_per_hour: u32,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ApiKeyPayload {
+    label: String,
+    scope: ApiKeyScope,
+    // Tenant the key's reports belong to
+    tenant_id: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ApiKey {
+    id: u64,
+    label: String,
+    scope: ApiKeyScope,
+    tenant_id: Option<u64>,
+    issued_by: Principal,
+    issued_at: u64,
+    revoked_at: Option<u64>,
+    last_used_at: Option<u64>,
+    // Start and count of the current rate limit window
+    window_started_at: u64,
+    window_submissions: u32,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct IssuedApiKey {
+    key: ApiKey,
+    // Shown only in this reply
+    secret: String,
+}
+
+// Implementing Storable and BoundedStorable traits for ApiKey
+impl Storable for ApiKey {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ApiKey {
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static API_KEY_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(80, "API keys"));
+
+    static API_KEY_STORAGE: RefCell<StableBTreeMap<u64, ApiKey, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(81)))
+    ));
+
+    // sha256 of the secret -> key id
+    static API_KEY_HASHES: RefCell<StableBTreeMap<KeyHash, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(82)))
+    ));
+}
+
+fn hash_secret(secret: &str) -> KeyHash {
+    Blob::try_from(Sha256::digest(secret.as_bytes()).as_slice()).expect("sha256 digests are 32 bytes")
+}
+
+fn validate_payload(payload: &mut ApiKeyPayload) -> Result<(), Error> {
+    payload.label = normalize_line(&payload.label);
+    if payload.label.is_empty() || payload.label.chars().count() > MAX_LABEL_CHARS {
+        return Err(Error::validation(format!(
+            "key labels must be between 1 and {} characters",
+            MAX_LABEL_CHARS
+        )));
+    }
+    let scope = &mut payload.scope;
+    if scope.allowed_locations.len() > MAX_ALLOWED_LOCATIONS {
+        return Err(Error::validation(format!(
+            "a key can be limited to at most {} locations",
+            MAX_ALLOWED_LOCATIONS
+        )));
+    }
+    for location in scope.allowed_locations.iter_mut() {
+        *location = normalize_line(location);
+        if location.is_empty() {
+            return Err(Error::validation("allowed locations must not be empty"));
+        }
+    }
+    if !(1..=MAX_SUBMISSIONS_PER_HOUR).contains(&scope.max_submissions_per_hour) {
+        return Err(Error::validation(format!(
+            "max_submissions_per_hour must be between 1 and {}",
+            MAX_SUBMISSIONS_PER_HOUR
+        ))
+        .with_detail("max_submissions_per_hour", scope.max_submissions_per_hour));
+    }
+    if let Some(tenant_id) = payload.tenant_id {
+        crate::tenants::find_tenant(tenant_id)?;
+    }
+    Ok(())
+}
+
+// Resolves the secret to an active key and counts the submission against its limit
+fn authorize_submission(secret: &str, location: &str, now: u64) -> Result<ApiKey, Error> {
+    let id = API_KEY_HASHES
+        .with(|s| s.borrow().get(&hash_secret(secret)))
+        .ok_or_else(|| Error::unauthorized("unknown API key"))?;
+    let mut key = API_KEY_STORAGE
+        .with(|s| s.borrow().get(&id))
+        .ok_or_else(|| Error::internal(format!("API key {} has a hash but no record", id)))?;
+    if key.revoked_at.is_some() {
+        return Err(Error::unauthorized("the API key has been revoked").with_detail("id", id));
+    }
+    let allowed = &key.scope.allowed_locations;
+    if !allowed.is_empty() && !allowed.iter().any(|place| crate::areas::same_place(place, location)) {
+        return Err(Error::unauthorized(format!("the API key may not report from {}", location)).with_detail("id", id));
+    }
+    if now.saturating_sub(key.window_started_at) >= RATE_WINDOW_SECS * NANOS_PER_SECOND {
+        key.window_started_at = now;
+        key.window_submissions = 0;
+    }
+    if key.window_submissions >= key.scope.max_submissions_per_hour {
+        return Err(Error::rate_limited(format!(
+            "the API key may submit at most {} updates per hour",
+            key.scope.max_submissions_per_hour
+        ))
+        .with_detail("id", id));
+    }
+    key.window_submissions += 1;
+    key.last_used_at = Some(now);
+    API_KEY_STORAGE.with(|s| s.borrow_mut().insert(id, key.clone()));
+    Ok(key)
+}
+
+// 2.58.1 issue_api_key Function:
+#[ic_cdk::update(guard = "admit_update")]
+async fn issue_api_key(mut payload: ApiKeyPayload) -> Result<IssuedApiKey, Error> {
+    crate::call_stats::count_call("issue_api_key");
+    require_role(Role::Admin)?;
+    validate_payload(&mut payload)?;
+    let (bytes,) = raw_rand()
+        .await
+        .map_err(|(code, msg)| Error::external_call_failed(format!("raw_rand failed: {:?} {}", code, msg)))?;
+    let secret: String = std::iter::once(KEY_PREFIX.to_string())
+        .chain(bytes.iter().map(|b| format!("{:02x}", b)))
+        .collect();
+    let now = time();
+    let key = ApiKey {
+        id: crate::ids::next_id(&API_KEY_ID_COUNTER)?,
+        label: payload.label,
+        scope: payload.scope,
+        tenant_id: payload.tenant_id,
+        issued_by: caller(),
+        issued_at: now,
+        revoked_at: None,
+        last_used_at: None,
+        window_started_at: now,
+        window_submissions: 0,
+    };
+    API_KEY_STORAGE.with(|s| s.borrow_mut().insert(key.id, key.clone()));
+    API_KEY_HASHES.with(|s| s.borrow_mut().insert(hash_secret(&secret), key.id));
+    Ok(IssuedApiKey { key, secret })
+}
+
+// 2.58.2 list_api_keys Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn list_api_keys() -> Result<QueryResult<ApiKey>, Error> {
+    require_role(Role::Admin)?;
+    fit(API_KEY_STORAGE.with(|s| s.borrow().iter().map(|(_, key)| key).collect()))
+}
+
+// 2.58.3 revoke_api_key Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn revoke_api_key(id: u64) -> Result<ApiKey, Error> {
+    crate::call_stats::count_call("revoke_api_key");
+    require_role(Role::Admin)?;
+    let mut key = API_KEY_STORAGE
+        .with(|s| s.borrow().get(&id))
+        .ok_or_else(|| Error::not_found(format!("an API key with id={} not found", id)).with_detail("id", id))?;
+    if key.revoked_at.is_none() {
+        key.revoked_at = Some(time());
+        API_KEY_STORAGE.with(|s| s.borrow_mut().insert(id, key.clone()));
+    }
+    Ok(key)
+}
+
+// 2.58.4 add_crisis_update_with_key Function:
+// Reports have no author principal; `api_key_id` records which key sent them.
+// Keys are issued by admins, so moderation treats their reports like the
+// issuing admin's.
+#[ic_cdk::update(guard = "admit_update")]
+async fn add_crisis_update_with_key(key: String, mut payload: CrisisUpdatePayload) -> Result<CrisisUpdate, Error> {
+    crate::call_stats::count_call("add_crisis_update_with_key");
+    if payload.signature.is_some() {
+        return Err(Error::validation("reports submitted with an API key cannot be signed"));
+    }
+    let now = time();
+    crate::_check_input(&mut payload, now)?;
+    let api_key = authorize_submission(&key, &payload.location, now)?;
+    let update = CrisisUpdate {
+        id: crate::ids::next_id(&crate::CRISIS_ID_COUNTER)?,
+        title: payload.title,
+        description: payload.description,
+        location: payload.location,
+        affected_areas: payload.affected_areas,
+        created_at: now,
+        updated_at: None,
+        occurred_at: payload.occurred_at,
+        status: CrisisStatus::Active,
+        priority: CrisisPriority::Normal,
+        author: None,
+        author_name: None,
+        signature: None,
+        review: crate::moderation::initial_review(&api_key.issued_by),
+        anonymous: None,
+        metadata: payload.metadata,
+        coordinates: payload.coordinates,
+        region: payload.region,
+        visibility: payload.visibility.unwrap_or_default(),
+        tenant: api_key.tenant_id,
+        api_key_id: Some(api_key.id),
+        contact: payload.contact,
+        expires_at: payload.expires_at,
+    };
+    crate::store_new_crisis_update(&update).await?;
+    Ok(crate::escalation::evaluate_escalation(update).await)
+}
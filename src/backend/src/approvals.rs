@@ -0,0 +1,278 @@
+// Two-admin approval for destructive actions. One admin proposes a restore,
+// mass delete or purge, and it runs only when a different admin approves it
+// within the approval window, so a single compromised or mistaken account
+// cannot wipe crisis data. Proposals are kept with their outcome for review.
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::ids::IdCounter;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::maintenance::admit_update;
+use crate::normalize::normalize_line;
+use crate::response::{fit, QueryResult};
+use crate::snapshots::{check_restore, find_snapshot, remove_local_update, restore_snapshot, RestoreReport};
+use crate::{CrisisStatus, Error, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const APPROVAL_WINDOW_SECS: u64 = 30 * 60;
+const MAX_DELETE_IDS: usize = 1000;
+const MAX_REASON_CHARS: usize = 500;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) enum DestructiveAction {
+    RestoreSnapshot { snapshot_id: u64 },
+    // Local updates only; ids held on shards or not found are skipped
+    DeleteCrisisUpdates { ids: Vec<u64> },
+    // Deletes local updates with the status, last modified before the time
+    PurgeCrisisUpdates { status: CrisisStatus, modified_before: u64 },
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) enum ActionOutcome {
+    Restored(RestoreReport),
+    Deleted { removed: u64, skipped: u64 },
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq)]
+pub(crate) enum ProposalState {
+    Pending,
+    Executed,
+    // Approved, but the action returned an error
+    Failed { error: String },
+    Rejected,
+    // Not approved within the window; never stored, derived when read
+    Expired,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Proposal {
+    id: u64,
+    action: DestructiveAction,
+    reason: String,
+    proposed_by: Principal,
+    proposed_at: u64,
+    expires_at: u64,
+    state: ProposalState,
+    // The admin who approved or rejected the proposal
+    decided_by: Option<Principal>,
+    decided_at: Option<u64>,
+    outcome: Option<ActionOutcome>,
+}
+
+impl Proposal {
+    fn with_current_state(mut self, now: u64) -> Self {
+        if self.state == ProposalState::Pending && now >= self.expires_at {
+            self.state = ProposalState::Expired;
+        }
+        self
+    }
+}
+
+// Implementing Storable and BoundedStorable traits for Proposal
+impl Storable for Proposal {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Proposal {
+    const MAX_SIZE: u32 = 16384;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static PROPOSAL_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(88, "proposals"));
+
+    static PROPOSALS: RefCell<StableBTreeMap<u64, Proposal, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(89)))
+    ));
+}
+
+fn find_proposal(id: u64) -> Result<Proposal, Error> {
+    PROPOSALS
+        .with(|s| s.borrow().get(&id))
+        .map(|proposal| proposal.with_current_state(time()))
+        .ok_or_else(|| Error::not_found(format!("couldn't find a proposal with id={}", id)).with_detail("id", id))
+}
+
+fn validate_action(action: &mut DestructiveAction) -> Result<(), Error> {
+    match action {
+        DestructiveAction::RestoreSnapshot { snapshot_id } => {
+            find_snapshot(*snapshot_id)?;
+        }
+        DestructiveAction::DeleteCrisisUpdates { ids } => {
+            ids.sort_unstable();
+            ids.dedup();
+            if ids.is_empty() || ids.len() > MAX_DELETE_IDS {
+                return Err(Error::validation(format!(
+                    "a mass delete must name between 1 and {} crisis updates",
+                    MAX_DELETE_IDS
+                )));
+            }
+        }
+        DestructiveAction::PurgeCrisisUpdates { status, .. } => {
+            if status.is_open() {
+                return Err(Error::validation("open crisis updates cannot be purged"));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn delete_local(ids: &[u64], admin: Principal) -> Result<ActionOutcome, Error> {
+    let mut removed = 0;
+    for id in ids {
+        if CRISIS_STORAGE.with(|s| s.borrow().contains_key(id)) {
+            remove_local_update(*id, admin)?;
+            removed += 1;
+        }
+    }
+    Ok(ActionOutcome::Deleted {
+        removed,
+        skipped: ids.len() as u64 - removed,
+    })
+}
+
+// Everything that could refuse the action, checked before it changes anything.
+// Deletes have nothing to refuse: missing ids are skipped.
+fn check_action(action: &DestructiveAction) -> Result<(), Error> {
+    match action {
+        DestructiveAction::RestoreSnapshot { snapshot_id } => check_restore(*snapshot_id),
+        DestructiveAction::DeleteCrisisUpdates { .. } | DestructiveAction::PurgeCrisisUpdates { .. } => Ok(()),
+    }
+}
+
+fn execute(action: &DestructiveAction, admin: Principal) -> Result<ActionOutcome, Error> {
+    match action {
+        DestructiveAction::RestoreSnapshot { snapshot_id } => {
+            restore_snapshot(*snapshot_id, admin).map(ActionOutcome::Restored)
+        }
+        DestructiveAction::DeleteCrisisUpdates { ids } => delete_local(ids, admin),
+        DestructiveAction::PurgeCrisisUpdates {
+            status,
+            modified_before,
+        } => {
+            let ids: Vec<u64> = CRISIS_STORAGE.with(|s| {
+                s.borrow()
+                    .iter()
+                    .filter(|(_, update)| update.status == *status && update.last_modified() < *modified_before)
+                    .map(|(id, _)| id)
+                    .collect()
+            });
+            delete_local(&ids, admin)
+        }
+    }
+}
+
+// 2.63.1 propose_destructive_action Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn propose_destructive_action(mut action: DestructiveAction, reason: String) -> Result<Proposal, Error> {
+    crate::call_stats::count_call("propose_destructive_action");
+    let admin = require_role(Role::Admin)?;
+    let reason = normalize_line(&reason);
+    if reason.is_empty() || reason.chars().count() > MAX_REASON_CHARS {
+        return Err(Error::validation(format!(
+            "reasons must be between 1 and {} characters",
+            MAX_REASON_CHARS
+        )));
+    }
+    validate_action(&mut action)?;
+    let now = time();
+    let proposal = Proposal {
+        id: crate::ids::next_id(&PROPOSAL_ID_COUNTER)?,
+        action,
+        reason,
+        proposed_by: admin,
+        proposed_at: now,
+        expires_at: now.saturating_add(APPROVAL_WINDOW_SECS * NANOS_PER_SECOND),
+        state: ProposalState::Pending,
+        decided_by: None,
+        decided_at: None,
+        outcome: None,
+    };
+    PROPOSALS.with(|s| s.borrow_mut().insert(proposal.id, proposal.clone()));
+    Ok(proposal)
+}
+
+// 2.63.2 approve_proposal Function:
+// Runs the action. An action refused by its checks is recorded on the proposal
+// rather than returned, so the attempt stays on record. One that fails partway
+// traps instead, so none of its changes are kept and the proposal stays pending.
+#[ic_cdk::update(guard = "admit_update")]
+fn approve_proposal(id: u64) -> Result<Proposal, Error> {
+    crate::call_stats::count_call("approve_proposal");
+    let admin = require_role(Role::Admin)?;
+    let mut proposal = find_proposal(id)?;
+    if proposal.state != ProposalState::Pending {
+        return Err(Error::conflict(format!("proposal {} is no longer pending", id)).with_detail("id", id));
+    }
+    if proposal.proposed_by == admin {
+        return Err(Error::unauthorized("a proposal must be approved by a different admin"));
+    }
+    proposal.decided_by = Some(admin);
+    proposal.decided_at = Some(time());
+    match check_action(&proposal.action) {
+        Ok(()) => match execute(&proposal.action, admin) {
+            Ok(outcome) => {
+                proposal.state = ProposalState::Executed;
+                proposal.outcome = Some(outcome);
+            }
+            Err(err) => ic_cdk::trap(&format!(
+                "proposal {} failed partway and was rolled back: {}",
+                id, err.msg
+            )),
+        },
+        Err(err) => proposal.state = ProposalState::Failed { error: err.msg },
+    }
+    PROPOSALS.with(|s| s.borrow_mut().insert(id, proposal.clone()));
+    Ok(proposal)
+}
+
+// 2.63.3 reject_proposal Function:
+// Any admin may reject, including the proposer withdrawing their own proposal.
+#[ic_cdk::update(guard = "admit_update")]
+fn reject_proposal(id: u64) -> Result<Proposal, Error> {
+    crate::call_stats::count_call("reject_proposal");
+    let admin = require_role(Role::Admin)?;
+    let mut proposal = find_proposal(id)?;
+    if proposal.state != ProposalState::Pending {
+        return Err(Error::conflict(format!("proposal {} is no longer pending", id)).with_detail("id", id));
+    }
+    proposal.state = ProposalState::Rejected;
+    proposal.decided_by = Some(admin);
+    proposal.decided_at = Some(time());
+    PROPOSALS.with(|s| s.borrow_mut().insert(id, proposal.clone()));
+    Ok(proposal)
+}
+
+// 2.63.4 list_proposals Function:
+// Newest first.
+#[ic_cdk::query(guard = "admit_read")]
+fn list_proposals(pending_only: bool) -> Result<QueryResult<Proposal>, Error> {
+    require_role(Role::Admin)?;
+    let now = time();
+    let mut proposals: Vec<Proposal> = PROPOSALS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, proposal)| proposal.with_current_state(now))
+            .filter(|proposal| !pending_only || proposal.state == ProposalState::Pending)
+            .collect()
+    });
+    proposals.reverse();
+    fit(proposals)
+}
+
+// 2.63.5 get_proposal Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_proposal(id: u64) -> Result<Proposal, Error> {
+    require_role(Role::Admin)?;
+    find_proposal(id)
+}
@@ -0,0 +1,228 @@
+// Moves resolved and archived crisis updates to a separate archive canister once
+// the active storage grows past the configured threshold.
+//
+// The archive canister is expected to expose:
+//   archive_crisis_updates : (vec CrisisUpdate) -> ();
+//   get_archived_crisis_update : (nat64) -> (opt CrisisUpdate) query;
+use crate::access::{principal_key, require_role, PrincipalKey, Role};
+use crate::access_mode::admit_read;
+use crate::maintenance::admit_update;
+use crate::settings::{get_settings, update_settings, Settings};
+use crate::{CrisisStatus, CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use candid::Principal;
+use ic_cdk::api::id;
+use ic_cdk::api::management_canister::main::{
+    create_canister, install_code, CanisterInstallMode, CanisterSettings, CreateCanisterArgument, InstallCodeArgument,
+};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{Cell, StableBTreeMap};
+use std::cell::RefCell;
+
+const ARCHIVE_BATCH_SIZE: usize = 100;
+// Cycles attached when the backend has to create the archive canister itself
+const ARCHIVE_CREATION_CYCLES: u128 = 2_000_000_000_000;
+pub(crate) const ARCHIVE_CHECK_INTERVAL_SECS: u64 = 10 * 60;
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ArchiveReport {
+    archive_canister: Option<Principal>,
+    archived_count: u64,
+    active_count: u64,
+    archived_total: u64,
+}
+
+thread_local! {
+    static ARCHIVE_WASM: RefCell<Cell<Vec<u8>, Memory>> = RefCell::new(
+        Cell::init(CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32))), Vec::new())
+            .expect("Cannot create the archive wasm cell")
+    );
+
+    // Which archive canister holds each migrated update
+    static ARCHIVE_INDEX: RefCell<StableBTreeMap<u64, PrincipalKey, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(33)))
+    ));
+
+    static ARCHIVING_IN_PROGRESS: RefCell<bool> = const { RefCell::new(false) };
+}
+
+pub(crate) fn archived_location(id: u64) -> Option<Principal> {
+    ARCHIVE_INDEX
+        .with(|index| index.borrow().get(&id))
+        .map(|key| Principal::from_slice(key.as_slice()))
+}
+
+pub(crate) fn archived_count() -> u64 {
+    ARCHIVE_INDEX.with(|index| index.borrow().len())
+}
+
+// Looks an update up in the archive canister that holds it, if any
+pub(crate) async fn get_archived_crisis_update(id: u64) -> Result<Option<CrisisUpdate>, Error> {
+    let Some(archive) = archived_location(id) else {
+        return Ok(None);
+    };
+    let (update,): (Option<CrisisUpdate>,) = ic_cdk::call(archive, "get_archived_crisis_update", (id,))
+        .await
+        .map_err(|(code, msg)| {
+            Error::external_call_failed(format!(
+                "archive canister {} rejected the lookup: {:?} {}",
+                archive, code, msg
+            ))
+        })?;
+    Ok(update)
+}
+
+fn is_archivable(update: &CrisisUpdate) -> bool {
+    matches!(update.status, CrisisStatus::Resolved | CrisisStatus::Archived)
+}
+
+pub(crate) fn archive_needed() -> bool {
+    match get_settings().archive_threshold {
+        Some(threshold) => CRISIS_STORAGE.with(|s| s.borrow().len()) > threshold,
+        None => false,
+    }
+}
+
+// Returns the configured archive canister, creating it from the uploaded wasm if needed
+async fn ensure_archive_canister() -> Result<Principal, Error> {
+    if let Some(archive) = get_settings().archive_canister {
+        return Ok(archive);
+    }
+    let wasm_module = ARCHIVE_WASM.with(|w| w.borrow().get().clone());
+    if wasm_module.is_empty() {
+        return Err(Error::validation(
+            "no archive canister is configured and no archive wasm has been uploaded",
+        ));
+    }
+    let create_arg = CreateCanisterArgument {
+        settings: Some(CanisterSettings {
+            controllers: Some(vec![id()]),
+            ..Default::default()
+        }),
+    };
+    let (record,) = create_canister(create_arg, ARCHIVE_CREATION_CYCLES)
+        .await
+        .map_err(|(code, msg)| {
+            Error::external_call_failed(format!("failed to create the archive canister: {:?} {}", code, msg))
+        })?;
+    install_code(InstallCodeArgument {
+        mode: CanisterInstallMode::Install,
+        canister_id: record.canister_id,
+        wasm_module,
+        arg: Vec::new(),
+    })
+    .await
+    .map_err(|(code, msg)| {
+        Error::external_call_failed(format!("failed to install the archive canister: {:?} {}", code, msg))
+    })?;
+    update_settings(|s| s.archive_canister = Some(record.canister_id))?;
+    Ok(record.canister_id)
+}
+
+// Releases the in-progress flag however the run ends
+struct ArchiveRunGuard;
+
+impl ArchiveRunGuard {
+    fn acquire() -> Result<Self, Error> {
+        if ARCHIVING_IN_PROGRESS.with(|a| a.replace(true)) {
+            return Err(Error::conflict("an archive run is already in progress"));
+        }
+        Ok(ArchiveRunGuard)
+    }
+}
+
+impl Drop for ArchiveRunGuard {
+    fn drop(&mut self) {
+        ARCHIVING_IN_PROGRESS.with(|a| *a.borrow_mut() = false);
+    }
+}
+
+// Migrates archivable updates in batches until none are left or a call fails
+pub(crate) async fn run_archive() -> Result<ArchiveReport, Error> {
+    let _guard = ArchiveRunGuard::acquire()?;
+    let archive = ensure_archive_canister().await?;
+    let mut migrated = 0;
+    loop {
+        let batch: Vec<CrisisUpdate> = CRISIS_STORAGE.with(|s| {
+            s.borrow()
+                .iter()
+                .map(|(_, update)| update)
+                .filter(is_archivable)
+                .take(ARCHIVE_BATCH_SIZE)
+                .collect()
+        });
+        if batch.is_empty() {
+            break;
+        }
+        let ids: Vec<u64> = batch.iter().map(|u| u.id).collect();
+        let _: () = ic_cdk::call(archive, "archive_crisis_updates", (batch,))
+            .await
+            .map_err(|(code, msg)| {
+                Error::external_call_failed(format!(
+                    "archive canister {} rejected the batch: {:?} {}",
+                    archive, code, msg
+                ))
+            })?;
+        let key = principal_key(&archive);
+        for id in ids {
+            // Skip updates that were reactivated while the batch was in flight
+            let still_archivable = CRISIS_STORAGE
+                .with(|s| s.borrow().get(&id))
+                .is_some_and(|u| is_archivable(&u));
+            if !still_archivable {
+                continue;
+            }
+            crate::snapshots::remove_local_copy(id);
+            ARCHIVE_INDEX.with(|index| index.borrow_mut().insert(id, key));
+            migrated += 1;
+        }
+    }
+    Ok(ArchiveReport {
+        archive_canister: Some(archive),
+        archived_count: migrated,
+        active_count: CRISIS_STORAGE.with(|s| s.borrow().len()),
+        archived_total: archived_count(),
+    })
+}
+
+// 2.23.1 set_archive_config Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn set_archive_config(archive_canister: Option<Principal>, threshold: Option<u64>) -> Result<Settings, Error> {
+    crate::call_stats::count_call("set_archive_config");
+    require_role(Role::Admin)?;
+    update_settings(|s| {
+        s.archive_canister = archive_canister;
+        s.archive_threshold = threshold;
+    })
+}
+
+// 2.23.2 upload_archive_wasm Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn upload_archive_wasm(wasm_module: Vec<u8>) -> Result<u64, Error> {
+    crate::call_stats::count_call("upload_archive_wasm");
+    require_role(Role::Admin)?;
+    let size = wasm_module.len() as u64;
+    ARCHIVE_WASM
+        .with(|w| w.borrow_mut().set(wasm_module))
+        .map_err(|err| Error::internal(format!("cannot persist the archive wasm: {:?}", err)))?;
+    Ok(size)
+}
+
+// 2.23.3 run_archive_now Function:
+#[ic_cdk::update(guard = "admit_update")]
+async fn run_archive_now() -> Result<ArchiveReport, Error> {
+    crate::call_stats::count_call("run_archive_now");
+    require_role(Role::Admin)?;
+    run_archive().await
+}
+
+// 2.23.4 get_archive_status Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_archive_status() -> ArchiveReport {
+    ArchiveReport {
+        archive_canister: get_settings().archive_canister,
+        archived_count: 0,
+        active_count: CRISIS_STORAGE.with(|s| s.borrow().len()),
+        archived_total: archived_count(),
+    }
+}
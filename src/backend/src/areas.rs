@@ -0,0 +1,224 @@
+// Areas affected by an update beyond its primary location: a flood covers many
+// neighbourhoods. The primary location and every affected area are indexed by
+// their normalized name, so a location search finds the update under any of them.
+use crate::access::{require_role, Role};
+use crate::geo::{validate_coordinates, Coordinates};
+use crate::maintenance::admit_update;
+use crate::normalize::normalize_line;
+use crate::{CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+const MAX_AFFECTED_AREAS: usize = 20;
+const MAX_AREA_NAME_CHARS: usize = 100;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Debug)]
+pub(crate) struct Location {
+    pub(crate) name: String,
+    coordinates: Option<Coordinates>,
+}
+
+pub(crate) type LocationKey = Blob<32>;
+
+thread_local! {
+    // (hash of the normalized location name, crisis update id)
+    static LOCATION_INDEX: RefCell<StableBTreeMap<(LocationKey, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(59)))
+    ));
+
+    // (crisis update id, location name hash), to drop an update's old entries on edit
+    static LOCATIONS_BY_UPDATE: RefCell<StableBTreeMap<(u64, LocationKey), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(60)))
+    ));
+}
+
+// Names are compared case-insensitively after normalization
+fn comparable(name: &str) -> String {
+    normalize_line(name).to_lowercase()
+}
+
+pub(crate) fn location_key(name: &str) -> LocationKey {
+    let digest = Sha256::digest(comparable(name).as_bytes());
+    Blob::try_from(digest.as_slice()).expect("sha256 digests are 32 bytes")
+}
+
+// Sanitizes and normalizes area names and drops repeats of a name
+pub(crate) fn validate_affected_areas(areas: &mut Vec<Location>) -> Result<(), Error> {
+    if areas.len() > MAX_AFFECTED_AREAS {
+        return Err(Error::validation(format!(
+            "an update can list at most {} affected areas",
+            MAX_AFFECTED_AREAS
+        )));
+    }
+    for area in areas.iter_mut() {
+        area.name = normalize_line(&crate::sanitize::sanitize_text("affected area", &area.name, false)?);
+        if area.name.is_empty() || area.name.chars().count() > MAX_AREA_NAME_CHARS {
+            return Err(Error::validation(format!(
+                "affected area names must be 1-{} characters",
+                MAX_AREA_NAME_CHARS
+            )));
+        }
+        validate_coordinates(&area.coordinates)?;
+    }
+    let mut seen = Vec::new();
+    areas.retain(|area| {
+        let name = comparable(&area.name);
+        let first = !seen.contains(&name);
+        seen.push(name);
+        first
+    });
+    Ok(())
+}
+
+// True when the name is the update's primary location or one of its affected areas
+// True when both names refer to the same place once normalized
+pub(crate) fn same_place(a: &str, b: &str) -> bool {
+    comparable(a) == comparable(b)
+}
+
+pub(crate) fn covers(update: &CrisisUpdate, name: &str) -> bool {
+    let name = comparable(name);
+    comparable(&update.location) == name || update.affected_areas.iter().any(|area| comparable(&area.name) == name)
+}
+
+pub(crate) fn unindex_update(id: u64) {
+    let keys: Vec<LocationKey> = LOCATIONS_BY_UPDATE.with(|s| {
+        s.borrow()
+            .range((id, Blob::default())..)
+            .take_while(|((update_id, _), _)| *update_id == id)
+            .map(|((_, key), _)| key)
+            .collect()
+    });
+    for key in keys {
+        LOCATION_INDEX.with(|s| s.borrow_mut().remove(&(key, id)));
+        LOCATIONS_BY_UPDATE.with(|s| s.borrow_mut().remove(&(id, key)));
+    }
+}
+
+fn indexed_names(update: &CrisisUpdate) -> BTreeSet<LocationKey> {
+    std::iter::once(&update.location)
+        .chain(update.affected_areas.iter().map(|area| &area.name))
+        .filter(|name| !name.trim().is_empty())
+        .map(|name| location_key(name))
+        .collect()
+}
+
+// Re-indexes an update under its current primary location and affected areas
+pub(crate) fn index_update(update: &CrisisUpdate) {
+    unindex_update(update.id);
+    for key in indexed_names(update) {
+        LOCATION_INDEX.with(|s| s.borrow_mut().insert((key, update.id), ()));
+        LOCATIONS_BY_UPDATE.with(|s| s.borrow_mut().insert((update.id, key), ()));
+    }
+}
+
+// Whether both index maps hold exactly the update's current names
+pub(crate) fn is_indexed(update: &CrisisUpdate) -> bool {
+    let expected = indexed_names(update);
+    let indexed: BTreeSet<LocationKey> = LOCATIONS_BY_UPDATE.with(|s| {
+        s.borrow()
+            .range((update.id, Blob::default())..)
+            .take_while(|((update_id, _), _)| *update_id == update.id)
+            .map(|((_, key), _)| key)
+            .collect()
+    });
+    expected == indexed
+        && LOCATION_INDEX.with(|s| {
+            let s = s.borrow();
+            expected.iter().all(|key| s.contains_key(&(*key, update.id)))
+        })
+}
+
+// Reverse entries of updates that no longer exist, and forward entries without
+// a reverse entry, which cannot be found through the update
+type OrphanedEntries = (Vec<(u64, LocationKey)>, Vec<(LocationKey, u64)>);
+
+fn orphaned_index_entries(is_live: &dyn Fn(u64) -> bool) -> OrphanedEntries {
+    let stale: Vec<(u64, LocationKey)> = LOCATIONS_BY_UPDATE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|(id, _)| !is_live(*id))
+            .collect()
+    });
+    let unreachable: Vec<(LocationKey, u64)> = LOCATION_INDEX.with(|index| {
+        LOCATIONS_BY_UPDATE.with(|by_update| {
+            let by_update = by_update.borrow();
+            index
+                .borrow()
+                .iter()
+                .map(|(key, _)| key)
+                .filter(|(key, id)| !by_update.contains_key(&(*id, *key)))
+                .collect()
+        })
+    });
+    (stale, unreachable)
+}
+
+pub(crate) fn count_orphaned_index_entries(is_live: &dyn Fn(u64) -> bool) -> u64 {
+    let (stale, unreachable) = orphaned_index_entries(is_live);
+    (stale.len() + unreachable.len()) as u64
+}
+
+// Drops index entries of updates that no longer exist; returns how many were removed
+pub(crate) fn remove_orphaned_index_entries(is_live: &dyn Fn(u64) -> bool) -> u64 {
+    let (stale, unreachable) = orphaned_index_entries(is_live);
+    for (id, key) in &stale {
+        LOCATION_INDEX.with(|s| s.borrow_mut().remove(&(*key, *id)));
+        LOCATIONS_BY_UPDATE.with(|s| s.borrow_mut().remove(&(*id, *key)));
+    }
+    LOCATION_INDEX.with(|s| {
+        let mut s = s.borrow_mut();
+        for key in &unreachable {
+            s.remove(key);
+        }
+    });
+    (stale.len() + unreachable.len()) as u64
+}
+
+// Coordinates recorded for the named place on any indexed update
+pub(crate) fn coordinates_of(name: &str) -> Option<Coordinates> {
+    updates_at(name).into_iter().find_map(|id| {
+        let update = crate::_get_crisis_update(&id)?;
+        if same_place(&update.location, name) {
+            if let Some(coordinates) = update.coordinates {
+                return Some(coordinates);
+            }
+        }
+        update
+            .affected_areas
+            .iter()
+            .find(|area| same_place(&area.name, name))
+            .and_then(|area| area.coordinates)
+    })
+}
+
+// Ids of the updates indexed under the name
+pub(crate) fn updates_at(name: &str) -> Vec<u64> {
+    let key = location_key(name);
+    LOCATION_INDEX.with(|s| {
+        s.borrow()
+            .range((key, 0)..=(key, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    })
+}
+
+// 2.45.1 rebuild_location_index Function:
+// Indexes updates stored before the index existed; returns how many were indexed.
+#[ic_cdk::update(guard = "admit_update")]
+fn rebuild_location_index() -> Result<u64, Error> {
+    crate::call_stats::count_call("rebuild_location_index");
+    require_role(Role::Admin)?;
+    let updates: Vec<CrisisUpdate> = CRISIS_STORAGE.with(|s| s.borrow().iter().map(|(_, update)| update).collect());
+    for update in &updates {
+        index_update(update);
+    }
+    Ok(updates.len() as u64)
+}
@@ -0,0 +1,418 @@
+use crate::access::{has_role, require_role, Role};
+use crate::access_mode::admit_read;
+use crate::ids::IdCounter;
+use crate::maintenance::admit_update;
+use crate::notifications::{try_notify, NotificationKind};
+use crate::response::{fit, QueryResult};
+use crate::logs::{log, LogLevel};
+use crate::teams::find_team;
+use crate::{CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum Assignee {
+    Responder(Principal),
+    Team(u64),
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Assignment {
+    pub(crate) id: u64,
+    pub(crate) crisis_update_id: u64,
+    pub(crate) assignee: Assignee,
+    pub(crate) assigned_by: Principal,
+    pub(crate) assigned_at: u64,
+    // Set when the assignment is withdrawn; kept for the dispatch history
+    pub(crate) unassigned_at: Option<u64>,
+}
+
+// Implementing Storable and BoundedStorable traits for Assignment
+impl Storable for Assignment {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Assignment {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Assignment {
+    fn is_active(&self) -> bool {
+        self.unassigned_at.is_none()
+    }
+}
+
+// Audit entry for a bulk hand-over from one responder, e.g. at a shift change
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Reassignment {
+    id: u64,
+    from: Principal,
+    to: Assignee,
+    reassigned_by: Principal,
+    reassigned_at: u64,
+    // Updates whose assignment moved, and tasks that moved
+    crisis_update_ids: Vec<u64>,
+    task_ids: Vec<u64>,
+}
+
+// Implementing Storable and BoundedStorable traits for Reassignment
+impl Storable for Reassignment {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Reassignment {
+    const MAX_SIZE: u32 = 16 * 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static ASSIGNMENT_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(39, "assignments"));
+
+    static ASSIGNMENT_STORAGE: RefCell<StableBTreeMap<u64, Assignment, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(40)))
+    ));
+
+    static REASSIGNMENT_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(128, "reassignments"));
+
+    static REASSIGNMENTS: RefCell<StableBTreeMap<u64, Reassignment, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(129)))
+    ));
+}
+
+// Helper method to perform insert for Assignment
+fn do_insert_assignment(assignment: &Assignment) {
+    ASSIGNMENT_STORAGE.with(|service| service.borrow_mut().insert(assignment.id, assignment.clone()));
+}
+
+fn active_assignment(crisis_update_id: u64, assignee: &Assignee) -> Option<Assignment> {
+    ASSIGNMENT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, a)| a)
+            .find(|a| a.is_active() && a.crisis_update_id == crisis_update_id && a.assignee == *assignee)
+    })
+}
+
+// Everyone who should hear about an assignment
+fn assignee_principals(assignee: &Assignee) -> Vec<Principal> {
+    match assignee {
+        Assignee::Responder(principal) => vec![*principal],
+        Assignee::Team(team_id) => find_team(*team_id).map(|team| team.members).unwrap_or_default(),
+    }
+}
+
+fn is_assigned_to(assignee: &Assignee, principal: &Principal) -> bool {
+    assignee_principals(assignee).contains(principal)
+}
+
+// Principals actively assigned to an update, directly or through a team
+pub(crate) fn assigned_principals(crisis_update_id: u64) -> Vec<Principal> {
+    assignments_for_update(crisis_update_id)
+        .iter()
+        .flat_map(|a| assignee_principals(&a.assignee))
+        .collect()
+}
+
+// Active assignments covering the given update
+pub(crate) fn assignments_for_update(crisis_update_id: u64) -> Vec<Assignment> {
+    ASSIGNMENT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, a)| a)
+            .filter(|a| a.is_active() && a.crisis_update_id == crisis_update_id)
+            .collect()
+    })
+}
+
+// Every assignment of the update, including withdrawn ones
+pub(crate) fn assignment_history(crisis_update_id: u64) -> Vec<Assignment> {
+    ASSIGNMENT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, a)| a)
+            .filter(|a| a.crisis_update_id == crisis_update_id)
+            .collect()
+    })
+}
+
+// Drops assignments to updates that no longer exist; returns how many were removed
+pub(crate) fn remove_orphaned_assignments(is_live: &dyn Fn(u64) -> bool) -> u64 {
+    let ids: Vec<u64> = ASSIGNMENT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, a)| !is_live(a.crisis_update_id))
+            .map(|(id, _)| id)
+            .collect()
+    });
+    ASSIGNMENT_STORAGE.with(|service| {
+        let mut service = service.borrow_mut();
+        for id in &ids {
+            service.remove(id);
+        }
+    });
+    ids.len() as u64
+}
+
+// Every (team id, crisis update id) pair a team has been assigned, past or present
+pub(crate) fn team_assignments() -> Vec<(u64, u64)> {
+    let mut pairs: Vec<(u64, u64)> = ASSIGNMENT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter_map(|(_, a)| match a.assignee {
+                Assignee::Team(team_id) => Some((team_id, a.crisis_update_id)),
+                Assignee::Responder(_) => None,
+            })
+            .collect()
+    });
+    pairs.sort_unstable();
+    pairs.dedup();
+    pairs
+}
+
+// 2.28.1 assign_responder Function:
+#[ic_cdk::update(guard = "admit_update")]
+async fn assign_responder(crisis_update_id: u64, assignee: Assignee) -> Result<Assignment, Error> {
+    crate::call_stats::count_call("assign_responder");
+    let assigned_by = require_role(Role::Coordinator)?;
+    let Some(update) = crate::_find_crisis_update(crisis_update_id).await? else {
+        return Err(Error::not_found(format!(
+            "a crisis update with id={} not found",
+            crisis_update_id
+        )));
+    };
+    match assignee {
+        Assignee::Responder(principal) if !has_role(&principal, Role::Responder) => {
+            return Err(Error::validation(format!("principal {} is not a responder", principal)));
+        }
+        Assignee::Team(team_id) if find_team(team_id).is_none() => {
+            return Err(Error::not_found(format!("a team with id={} not found", team_id)));
+        }
+        _ => {}
+    }
+    if active_assignment(crisis_update_id, &assignee).is_some() {
+        return Err(Error::conflict(format!(
+            "the crisis update with id={} is already assigned to {:?}",
+            crisis_update_id, assignee
+        )));
+    }
+    let id = crate::ids::next_id(&ASSIGNMENT_ID_COUNTER)?;
+    let assignment = Assignment {
+        id,
+        crisis_update_id,
+        assignee,
+        assigned_by,
+        assigned_at: time(),
+        unassigned_at: None,
+    };
+    do_insert_assignment(&assignment);
+    crate::sla::record_first_response(crisis_update_id, assignment.assigned_at);
+    // Keep the message well inside the notification size bound
+    let summary: String = update.title.chars().take(200).collect();
+    for recipient in assignee_principals(&assignee) {
+        try_notify(
+            recipient,
+            NotificationKind::AssignedToUpdate,
+            format!("You have been assigned to crisis update #{}: {}", update.id, summary),
+            Some(crisis_update_id),
+        );
+    }
+    Ok(assignment)
+}
+
+// 2.28.2 unassign_responder Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn unassign_responder(crisis_update_id: u64, assignee: Assignee) -> Result<Assignment, Error> {
+    crate::call_stats::count_call("unassign_responder");
+    require_role(Role::Coordinator)?;
+    match active_assignment(crisis_update_id, &assignee) {
+        Some(mut assignment) => {
+            assignment.unassigned_at = Some(time());
+            do_insert_assignment(&assignment);
+            Ok(assignment)
+        }
+        None => Err(Error::not_found(format!(
+            "couldn't unassign {:?} from a crisis update with id={}. assignment not found",
+            assignee, crisis_update_id
+        ))),
+    }
+}
+
+// 2.28.3 get_assignments_for_update Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_assignments_for_update(crisis_update_id: u64) -> Result<QueryResult<Assignment>, Error> {
+    fit(assignments_for_update(crisis_update_id))
+}
+
+// 2.28.4 get_updates_assigned_to_me Function:
+#[ic_cdk::query(composite = true, guard = "admit_read")]
+async fn get_updates_assigned_to_me() -> Result<QueryResult<CrisisUpdate>, Error> {
+    let me = caller();
+    let mut ids: Vec<u64> = ASSIGNMENT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, a)| a)
+            .filter(|a| a.is_active() && is_assigned_to(&a.assignee, &me))
+            .map(|a| a.crisis_update_id)
+            .collect()
+    });
+    ids.sort_unstable();
+    ids.dedup();
+    let mut updates = Vec::with_capacity(ids.len());
+    for id in ids {
+        updates.extend(crate::_find_crisis_update(id).await?);
+    }
+    crate::fit_updates(updates)
+}
+
+// 2.28.5 reassign_all Function:
+// Moves every active assignment and open task held by one responder to another
+// responder or a team in a single step, e.g. at a shift change or when someone
+// loses contact. Nothing moves if any part fails.
+#[ic_cdk::update(guard = "admit_update")]
+fn reassign_all(from: Principal, to: Assignee) -> Result<Reassignment, Error> {
+    crate::call_stats::count_call("reassign_all");
+    let reassigned_by = require_role(Role::Coordinator)?;
+    match to {
+        Assignee::Responder(principal) if principal == from => {
+            return Err(Error::validation("cannot reassign a responder's work to themselves"));
+        }
+        Assignee::Responder(principal) if !has_role(&principal, Role::Responder) => {
+            return Err(Error::validation(format!("principal {} is not a responder", principal)));
+        }
+        Assignee::Team(team_id) if find_team(team_id).is_none() => {
+            return Err(Error::not_found(format!("a team with id={} not found", team_id)));
+        }
+        _ => {}
+    }
+    let assignments: Vec<Assignment> = ASSIGNMENT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, a)| a)
+            .filter(|a| a.is_active() && a.assignee == Assignee::Responder(from))
+            .collect()
+    });
+    let tasks = crate::volunteers::open_tasks_of(&from);
+    if assignments.is_empty() && tasks.is_empty() {
+        return Err(Error::not_found(format!(
+            "principal {} has no active assignments or open tasks",
+            from
+        )));
+    }
+    if let Assignee::Responder(principal) = to {
+        if !tasks.is_empty() && !crate::volunteers::is_registered_volunteer(&principal) {
+            return Err(Error::validation(format!(
+                "principal {} is not a registered volunteer and cannot take over tasks",
+                principal
+            )));
+        }
+    }
+    let now = time();
+    let mut entry = Reassignment {
+        id: crate::ids::next_id(&REASSIGNMENT_ID_COUNTER)?,
+        from,
+        to,
+        reassigned_by,
+        reassigned_at: now,
+        crisis_update_ids: assignments.iter().map(|a| a.crisis_update_id).collect(),
+        task_ids: tasks.iter().map(|task| task.id).collect(),
+    };
+    entry.crisis_update_ids.sort_unstable();
+    entry.crisis_update_ids.dedup();
+    crate::ensure_storable(&entry, "reassignment")?;
+    // New assignment ids are taken up front so a failure leaves everything as it was
+    let mut new_ids = Vec::new();
+    for assignment in &assignments {
+        if active_assignment(assignment.crisis_update_id, &to).is_none() {
+            new_ids.push((assignment.crisis_update_id, crate::ids::next_id(&ASSIGNMENT_ID_COUNTER)?));
+        }
+    }
+    for mut assignment in assignments {
+        assignment.unassigned_at = Some(now);
+        do_insert_assignment(&assignment);
+    }
+    for (crisis_update_id, id) in new_ids {
+        do_insert_assignment(&Assignment {
+            id,
+            crisis_update_id,
+            assignee: to,
+            assigned_by: reassigned_by,
+            assigned_at: now,
+            unassigned_at: None,
+        });
+    }
+    for mut task in tasks {
+        match to {
+            Assignee::Responder(principal) => task.assigned_to = Some(principal),
+            Assignee::Team(team_id) => {
+                task.assigned_to = None;
+                task.assigned_team = Some(team_id);
+            }
+        }
+        task.updated_at = Some(now);
+        crate::volunteers::do_insert_task(&task);
+    }
+    REASSIGNMENTS.with(|s| s.borrow_mut().insert(entry.id, entry.clone()));
+    let message = format!(
+        "You have taken over {} crisis update assignments and {} tasks from {}",
+        entry.crisis_update_ids.len(),
+        entry.task_ids.len(),
+        from
+    );
+    for recipient in assignee_principals(&to) {
+        try_notify(recipient, NotificationKind::AssignedToUpdate, message.clone(), None);
+    }
+    log(
+        LogLevel::Info,
+        Some("reassign_all"),
+        &format!(
+            "{} moved {} assignments and {} tasks from {} to {:?}",
+            reassigned_by,
+            entry.crisis_update_ids.len(),
+            entry.task_ids.len(),
+            from,
+            to
+        ),
+    );
+    Ok(entry)
+}
+
+// 2.28.6 list_reassignments Function:
+// Newest first; pass a principal to see only hand-overs from them.
+#[ic_cdk::query(guard = "admit_read")]
+fn list_reassignments(from: Option<Principal>) -> Result<QueryResult<Reassignment>, Error> {
+    require_role(Role::Coordinator)?;
+    let mut entries: Vec<Reassignment> = REASSIGNMENTS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, entry)| entry)
+            .filter(|entry| from.is_none_or(|from| entry.from == from))
+            .collect()
+    });
+    entries.reverse();
+    fit(entries)
+}
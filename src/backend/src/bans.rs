@@ -0,0 +1,168 @@
+// Principals barred from changing anything. Every update method's guard runs
+// `reject_banned`, so a banned principal can still read but not
+// write. Moderators ban by hand; a reporter whose updates are rejected or
+// flagged repeatedly within a day is banned automatically for a day.
+use crate::access::{has_role, principal_key, require_role, PrincipalKey, Role};
+use crate::access_mode::admit_read;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::maintenance::admit_update;
+use crate::normalize::normalize_line;
+use crate::response::{fit, QueryResult};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_REASON_CHARS: usize = 500;
+const STRIKE_WINDOW_SECS: u64 = 24 * 60 * 60;
+const STRIKES_BEFORE_BAN: u64 = 3;
+const AUTOMATIC_BAN_SECS: u64 = 24 * 60 * 60;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Ban {
+    principal: Principal,
+    reason: String,
+    // None for bans issued by the canister itself
+    banned_by: Option<Principal>,
+    banned_at: u64,
+    // None for a permanent ban
+    until: Option<u64>,
+}
+
+// Implementing Storable and BoundedStorable traits for Ban
+impl Storable for Ban {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Ban {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static BAN_STORAGE: RefCell<StableBTreeMap<PrincipalKey, Ban, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(83)))
+    ));
+
+    // principal -> (first strike in the current window, strikes in the window)
+    static STRIKES: RefCell<StableBTreeMap<PrincipalKey, (u64, u64), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(84)))
+    ));
+}
+
+fn active_ban(principal: &Principal, now: u64) -> Option<Ban> {
+    BAN_STORAGE
+        .with(|s| s.borrow().get(&principal_key(principal)))
+        .filter(|ban| ban.until.is_none_or(|until| until > now))
+}
+
+// Guard for every update method
+pub(crate) fn reject_banned() -> Result<(), String> {
+    match active_ban(&caller(), time()) {
+        Some(ban) => Err(format!("the caller is banned: {}", ban.reason)),
+        None => Ok(()),
+    }
+}
+
+// Counts a rejected or flagged report against its author, banning them for a
+// day once the strikes in the current window reach the threshold
+pub(crate) fn record_strike(author: &Principal, now: u64) {
+    let key = principal_key(author);
+    let window = STRIKE_WINDOW_SECS * NANOS_PER_SECOND;
+    let (started_at, count) = STRIKES
+        .with(|s| s.borrow().get(&key))
+        .filter(|(started_at, _)| now.saturating_sub(*started_at) < window)
+        .unwrap_or((now, 0));
+    let count = count + 1;
+    if count < STRIKES_BEFORE_BAN {
+        STRIKES.with(|s| s.borrow_mut().insert(key, (started_at, count)));
+        return;
+    }
+    STRIKES.with(|s| s.borrow_mut().remove(&key));
+    if active_ban(author, now).is_some() {
+        return;
+    }
+    let ban = Ban {
+        principal: *author,
+        reason: format!("{} rejected or flagged reports within a day", count),
+        banned_by: None,
+        banned_at: now,
+        until: Some(now.saturating_add(AUTOMATIC_BAN_SECS * NANOS_PER_SECOND)),
+    };
+    BAN_STORAGE.with(|s| s.borrow_mut().insert(key, ban));
+}
+
+// Forgets the strikes counted against the principal; a ban in force is kept
+pub(crate) fn remove_strikes(principal: &Principal) {
+    STRIKES.with(|s| s.borrow_mut().remove(&principal_key(principal)));
+}
+
+// 2.59.1 ban_principal Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn ban_principal(principal: Principal, reason: String, until: Option<u64>) -> Result<Ban, Error> {
+    crate::call_stats::count_call("ban_principal");
+    let moderator = require_role(Role::Moderator)?;
+    if principal == moderator {
+        return Err(Error::validation("moderators cannot ban themselves"));
+    }
+    // Controllers count as admins, and admins hold every role
+    if has_role(&principal, Role::Moderator) {
+        return Err(Error::unauthorized("moderators, admins and controllers cannot be banned")
+            .with_detail("principal", principal));
+    }
+    let reason = normalize_line(&reason);
+    if reason.is_empty() || reason.chars().count() > MAX_REASON_CHARS {
+        return Err(Error::validation(format!(
+            "ban reasons must be between 1 and {} characters",
+            MAX_REASON_CHARS
+        )));
+    }
+    let now = time();
+    if let Some(t) = until.filter(|t| *t <= now) {
+        return Err(Error::validation("until must be in the future").with_detail("until", t));
+    }
+    let ban = Ban {
+        principal,
+        reason,
+        banned_by: Some(moderator),
+        banned_at: now,
+        until,
+    };
+    BAN_STORAGE.with(|s| s.borrow_mut().insert(principal_key(&principal), ban.clone()));
+    Ok(ban)
+}
+
+// 2.59.2 unban_principal Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn unban_principal(principal: Principal) -> Result<Option<Ban>, Error> {
+    crate::call_stats::count_call("unban_principal");
+    require_role(Role::Moderator)?;
+    let key = principal_key(&principal);
+    STRIKES.with(|s| s.borrow_mut().remove(&key));
+    Ok(BAN_STORAGE.with(|s| s.borrow_mut().remove(&key)))
+}
+
+// 2.59.3 list_banned_principals Function:
+// Bans still in force; expired ones are kept until the principal is unbanned or banned again.
+#[ic_cdk::query(guard = "admit_read")]
+fn list_banned_principals() -> Result<QueryResult<Ban>, Error> {
+    require_role(Role::Moderator)?;
+    let now = time();
+    fit(BAN_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, ban)| ban)
+            .filter(|ban| ban.until.is_none_or(|until| until > now))
+            .collect()
+    }))
+}
@@ -0,0 +1,140 @@
+// Heap copies of the crisis updates queries ask for most: the most recently
+// modified ones and the pinned ones, so hot lookups skip decoding from stable
+// memory. The cache holds every local update modified at or after `since`,
+// plus the pinned updates. Writes keep it current; anything it cannot account
+// for cheaply drops it instead. Queries cannot keep state, so after an upgrade
+// or a drop the heartbeat rebuilds it and reads fall back to stable memory
+// until then.
+use crate::{CrisisUpdate, CRISIS_STORAGE};
+use candid::Principal;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+pub(crate) const CACHE_CHECK_INTERVAL_SECS: u64 = 5;
+const RECENT_CAPACITY: usize = 200;
+
+struct HotCache {
+    entries: BTreeMap<u64, CrisisUpdate>,
+    // (last modified, id) of the cached updates modified at or after `since`
+    recent: BTreeSet<(u64, u64)>,
+    since: u64,
+    pinned: BTreeSet<u64>,
+}
+
+impl HotCache {
+    // Evicts the oldest recent entries once there are twice as many as needed
+    fn trim(&mut self) {
+        if self.recent.len() <= 2 * RECENT_CAPACITY {
+            return;
+        }
+        let Some(&(since, _)) = self.recent.iter().rev().nth(RECENT_CAPACITY - 1) else {
+            return;
+        };
+        let evicted: Vec<(u64, u64)> = self.recent.range(..(since, 0)).copied().collect();
+        for key in evicted {
+            self.recent.remove(&key);
+            if !self.pinned.contains(&key.1) {
+                self.entries.remove(&key.1);
+            }
+        }
+        self.since = since;
+    }
+}
+
+thread_local! {
+    static HOT_CACHE: RefCell<Option<HotCache>> = const { RefCell::new(None) };
+}
+
+pub(crate) fn is_built() -> bool {
+    HOT_CACHE.with(|c| c.borrow().is_some())
+}
+
+pub(crate) fn rebuild() {
+    let pinned: BTreeSet<u64> = crate::pins::pinned_ids().into_iter().collect();
+    let mut keys: Vec<(u64, u64)> = CRISIS_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(id, update)| (update.last_modified(), id))
+            .collect()
+    });
+    keys.sort_unstable();
+    let since = match keys.len().checked_sub(RECENT_CAPACITY) {
+        Some(start) => keys[start].0,
+        None => 0,
+    };
+    let recent: BTreeSet<(u64, u64)> = keys.into_iter().filter(|(at, _)| *at >= since).collect();
+    let entries = CRISIS_STORAGE.with(|s| {
+        let s = s.borrow();
+        recent
+            .iter()
+            .map(|(_, id)| *id)
+            .chain(pinned.iter().copied())
+            .filter_map(|id| s.get(&id).map(|update| (id, update)))
+            .collect()
+    });
+    HOT_CACHE.with(|c| {
+        *c.borrow_mut() = Some(HotCache {
+            entries,
+            recent,
+            since,
+            pinned,
+        })
+    });
+}
+
+pub(crate) fn clear() {
+    HOT_CACHE.with(|c| *c.borrow_mut() = None);
+}
+
+pub(crate) fn cached(id: u64) -> Option<CrisisUpdate> {
+    HOT_CACHE.with(|c| c.borrow().as_ref().and_then(|cache| cache.entries.get(&id).cloned()))
+}
+
+// Called after every local insert
+pub(crate) fn record(update: &CrisisUpdate) {
+    HOT_CACHE.with(|c| {
+        let mut c = c.borrow_mut();
+        let Some(cache) = c.as_mut() else {
+            return;
+        };
+        if let Some(previous) = cache.entries.remove(&update.id) {
+            cache.recent.remove(&(previous.last_modified(), update.id));
+        }
+        let key = (update.last_modified(), update.id);
+        let recent = key.0 >= cache.since;
+        if recent {
+            cache.recent.insert(key);
+        }
+        if recent || cache.pinned.contains(&update.id) {
+            cache.entries.insert(update.id, update.clone());
+        }
+        cache.trim();
+    });
+}
+
+// Called after every local removal
+pub(crate) fn forget(id: u64) {
+    HOT_CACHE.with(|c| {
+        if let Some(cache) = c.borrow_mut().as_mut() {
+            if let Some(previous) = cache.entries.remove(&id) {
+                cache.recent.remove(&(previous.last_modified(), id));
+            }
+        }
+    });
+}
+
+// The most recently modified unexpired update the viewer may see; None when the
+// cache is not built or holds nothing visible, in which case the caller scans storage
+pub(crate) fn latest_visible(viewer: &Principal, now: u64) -> Option<CrisisUpdate> {
+    HOT_CACHE.with(|c| {
+        let c = c.borrow();
+        let cache = c.as_ref()?;
+        cache
+            .recent
+            .iter()
+            .rev()
+            .filter_map(|(_, id)| cache.entries.get(id))
+            .find(|update| !update.is_expired(now) && crate::moderation::visible_to(update, viewer))
+            .cloned()
+    })
+}
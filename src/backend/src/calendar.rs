@@ -0,0 +1,134 @@
+// iCalendar (RFC 5545) feed of crises and relief activities, served over the
+// HTTP gateway so coordination teams can subscribe from their calendar apps.
+use crate::geo::Coordinates;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::{CrisisStatus, CRISIS_STORAGE};
+use ic_cdk::api::time;
+
+pub(crate) const CALENDAR_PATH: &str = "/calendar.ics";
+// Keeps the feed well inside the HTTP gateway's response limit
+const MAX_FEED_EVENTS: usize = 1000;
+const MAX_LINE_OCTETS: usize = 75;
+
+pub(crate) struct CalendarEvent {
+    // Unique and stable across feed refreshes
+    pub(crate) uid: String,
+    pub(crate) start: u64,
+    pub(crate) stamp: u64,
+    pub(crate) summary: String,
+    pub(crate) location: String,
+    pub(crate) description: String,
+    pub(crate) coordinates: Option<Coordinates>,
+    pub(crate) cancelled: bool,
+}
+
+// Formats nanoseconds since the epoch as a UTC date-time, e.g. 20240131T094500Z
+pub(crate) fn format_utc(nanos: u64) -> String {
+    let secs = nanos / NANOS_PER_SECOND;
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+    // Civil-from-days conversion for the proleptic Gregorian calendar
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+// Folds a content line so no line exceeds 75 octets, without splitting a character
+fn push_line(feed: &mut String, line: &str) {
+    let mut octets = 0;
+    for c in line.chars() {
+        if octets + c.len_utf8() > MAX_LINE_OCTETS {
+            feed.push_str("\r\n ");
+            octets = 1;
+        }
+        feed.push(c);
+        octets += c.len_utf8();
+    }
+    feed.push_str("\r\n");
+}
+
+fn crisis_events() -> Vec<CalendarEvent> {
+    CRISIS_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, update)| update)
+            .filter(|update| update.status != CrisisStatus::Archived && crate::moderation::visible_to_caller(update))
+            .map(crate::redaction::redact_update_for_caller)
+            .map(|update| CalendarEvent {
+                uid: format!("crisis-update-{}", update.id),
+                start: update.occurred_at.unwrap_or(update.created_at),
+                stamp: update.last_modified(),
+                summary: format!("[{:?}] {}", update.priority, update.title),
+                location: update.location.clone(),
+                description: update.description.clone(),
+                coordinates: update.coordinates,
+                cancelled: false,
+            })
+            .collect()
+    })
+}
+
+pub(crate) fn render_calendar() -> String {
+    let mut events = crisis_events();
+    events.extend(crate::shelters::shelter_calendar_events());
+    // The most recent events are kept when the feed is too long
+    events.sort_by_key(|event| std::cmp::Reverse(event.start));
+    events.truncate(MAX_FEED_EVENTS);
+
+    let now = format_utc(time());
+    let mut feed = String::new();
+    for line in [
+        "BEGIN:VCALENDAR",
+        "VERSION:2.0",
+        "PRODID:-//crisis-update//calendar feed//EN",
+        "CALSCALE:GREGORIAN",
+        "X-WR-CALNAME:Crisis updates",
+    ] {
+        push_line(&mut feed, line);
+    }
+    for event in events {
+        push_line(&mut feed, "BEGIN:VEVENT");
+        push_line(&mut feed, &format!("UID:{}@crisis-update", event.uid));
+        push_line(&mut feed, &format!("DTSTAMP:{}", now));
+        push_line(&mut feed, &format!("LAST-MODIFIED:{}", format_utc(event.stamp)));
+        push_line(&mut feed, &format!("DTSTART:{}", format_utc(event.start)));
+        push_line(&mut feed, &format!("SUMMARY:{}", escape_text(&event.summary)));
+        if !event.location.is_empty() {
+            push_line(&mut feed, &format!("LOCATION:{}", escape_text(&event.location)));
+        }
+        if !event.description.is_empty() {
+            push_line(&mut feed, &format!("DESCRIPTION:{}", escape_text(&event.description)));
+        }
+        if let Some(c) = event.coordinates {
+            push_line(&mut feed, &format!("GEO:{:.6};{:.6}", c.latitude, c.longitude));
+        }
+        let status = if event.cancelled { "CANCELLED" } else { "CONFIRMED" };
+        push_line(&mut feed, &format!("STATUS:{}", status));
+        push_line(&mut feed, "END:VEVENT");
+    }
+    push_line(&mut feed, "END:VCALENDAR");
+    feed
+}
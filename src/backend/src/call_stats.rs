@@ -0,0 +1,165 @@
+// Call statistics per method and per caller, kept in stable memory so they
+// survive upgrades, for spotting abuse and understanding load during an
+// emergency. Every update method counts itself once its guard has admitted the
+// call; queries cannot be counted, since state changed during a query is
+// discarded when it returns. Per-caller counts are kept by day for 30 days,
+// plus a running total.
+use crate::access::{principal_key, require_role, PrincipalKey, Role};
+use crate::access_mode::admit_read;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::leaderboard::LeaderboardWindow;
+use crate::response::{fit, QueryResult};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::Principal;
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+const DAY_NANOS: u64 = 24 * 60 * 60 * NANOS_PER_SECOND;
+const DAYS_KEPT: u64 = 30;
+const MAX_TOP_CALLERS: usize = 50;
+
+// Method names are ASCII identifiers well under 64 bytes
+type MethodKey = Blob<64>;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct MethodStats {
+    method: String,
+    calls: u64,
+    last_called_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CallerStats {
+    principal: Principal,
+    calls: u64,
+}
+
+thread_local! {
+    // method -> (calls since counting began, last call)
+    static METHOD_CALLS: RefCell<StableBTreeMap<MethodKey, (u64, u64), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(112)))
+    ));
+
+    // (day since the epoch, caller) -> calls that day
+    static DAILY_CALLER_CALLS: RefCell<StableBTreeMap<(u64, PrincipalKey), u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(113)))
+    ));
+
+    // caller -> calls since counting began
+    static TOTAL_CALLER_CALLS: RefCell<StableBTreeMap<PrincipalKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(114)))
+    ));
+}
+
+fn method_key(method: &str) -> MethodKey {
+    Blob::try_from(method.as_bytes()).expect("method name is longer than 64 bytes")
+}
+
+// Counts a call of the method by the current caller
+pub(crate) fn count_call(method: &'static str) {
+    let now = time();
+    let caller = principal_key(&caller());
+    let today = now / DAY_NANOS;
+    METHOD_CALLS.with(|s| {
+        let mut s = s.borrow_mut();
+        let key = method_key(method);
+        let (calls, _) = s.get(&key).unwrap_or_default();
+        s.insert(key, (calls.saturating_add(1), now));
+    });
+    DAILY_CALLER_CALLS.with(|s| {
+        let mut s = s.borrow_mut();
+        let oldest_kept = today.saturating_sub(DAYS_KEPT - 1);
+        let expired: Vec<(u64, PrincipalKey)> = s
+            .range(..(oldest_kept, PrincipalKey::default()))
+            .map(|(key, _)| key)
+            .collect();
+        for key in expired {
+            s.remove(&key);
+        }
+        let calls = s.get(&(today, caller)).unwrap_or_default();
+        s.insert((today, caller), calls.saturating_add(1));
+    });
+    TOTAL_CALLER_CALLS.with(|s| {
+        let mut s = s.borrow_mut();
+        let calls = s.get(&caller).unwrap_or_default();
+        s.insert(caller, calls.saturating_add(1));
+    });
+}
+
+// Drops the principal's per-caller counts; the per-method totals stay
+pub(crate) fn remove_caller_counts(principal: &Principal) {
+    let caller = principal_key(principal);
+    DAILY_CALLER_CALLS.with(|s| {
+        let mut s = s.borrow_mut();
+        let keys: Vec<(u64, PrincipalKey)> = s.iter().map(|(key, _)| key).filter(|(_, c)| *c == caller).collect();
+        for key in keys {
+            s.remove(&key);
+        }
+    });
+    TOTAL_CALLER_CALLS.with(|s| s.borrow_mut().remove(&caller));
+}
+
+// Calls per method since counting began, in method name order
+pub(crate) fn method_totals() -> Vec<(String, u64)> {
+    METHOD_CALLS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(key, (calls, _))| (String::from_utf8_lossy(key.as_slice()).into_owned(), calls))
+            .collect()
+    })
+}
+
+// 2.82.1 get_method_stats Function:
+// Update methods by number of calls, most called first.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_method_stats() -> Result<QueryResult<MethodStats>, Error> {
+    require_role(Role::Admin)?;
+    let mut stats: Vec<MethodStats> = METHOD_CALLS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(key, (calls, last_called_at))| MethodStats {
+                method: String::from_utf8_lossy(key.as_slice()).into_owned(),
+                calls,
+                last_called_at,
+            })
+            .collect()
+    });
+    stats.sort_by(|a, b| b.calls.cmp(&a.calls).then_with(|| a.method.cmp(&b.method)));
+    fit(stats)
+}
+
+// 2.82.2 get_top_callers Function:
+// The principals making the most update calls within the window, most first.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_top_callers(window: LeaderboardWindow) -> Result<Vec<CallerStats>, Error> {
+    require_role(Role::Admin)?;
+    let counts: BTreeMap<PrincipalKey, u64> = if window == LeaderboardWindow::AllTime {
+        TOTAL_CALLER_CALLS.with(|s| s.borrow().iter().collect())
+    } else {
+        let first_day = window.cutoff(time()) / DAY_NANOS;
+        let mut counts = BTreeMap::new();
+        DAILY_CALLER_CALLS.with(|s| {
+            for ((_, caller), calls) in s.borrow().range((first_day, PrincipalKey::default())..) {
+                *counts.entry(caller).or_insert(0) += calls;
+            }
+        });
+        counts
+    };
+    let mut callers: Vec<CallerStats> = counts
+        .into_iter()
+        .map(|(key, calls)| CallerStats {
+            principal: Principal::from_slice(key.as_slice()),
+            calls,
+        })
+        .collect();
+    callers.sort_by_key(|c| std::cmp::Reverse(c.calls));
+    callers.truncate(MAX_TOP_CALLERS);
+    Ok(callers)
+}
@@ -0,0 +1,267 @@
+// Casualty and displacement figures per crisis update. Every submission is kept
+// as a revision with its source, and only figures a coordinator has verified
+// (someone other than the person who submitted them) reach public queries, so
+// unconfirmed numbers circulating on the ground are not repeated as fact.
+// Public queries show the latest verified revision per update and totals
+// across updates, never who reported a figure; the full revision history is
+// limited to responders and coordinators.
+use crate::access::{require_any_role, require_role, Role};
+use crate::access_mode::admit_read;
+use crate::ids::IdCounter;
+use crate::maintenance::admit_update;
+use crate::normalize::normalize_line;
+use crate::response::{fit, QueryResult};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_SOURCE_CHARS: usize = 200;
+const MAX_REASON_CHARS: usize = 500;
+
+const REPORTERS: [Role; 3] = [Role::Responder, Role::VerifiedOrg, Role::Coordinator];
+const HISTORY_VIEWERS: [Role; 2] = [Role::Responder, Role::Coordinator];
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+pub(crate) struct CasualtyFigures {
+    deaths: u64,
+    injured: u64,
+    missing: u64,
+    displaced: u64,
+}
+
+impl CasualtyFigures {
+    fn add(&mut self, other: &CasualtyFigures) {
+        self.deaths = self.deaths.saturating_add(other.deaths);
+        self.injured = self.injured.saturating_add(other.injured);
+        self.missing = self.missing.saturating_add(other.missing);
+        self.displaced = self.displaced.saturating_add(other.displaced);
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum FigureVerification {
+    Pending,
+    Verified { by: Principal, at: u64 },
+    Rejected { by: Principal, at: u64, reason: String },
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CasualtyRevision {
+    id: u64,
+    crisis_update_id: u64,
+    figures: CasualtyFigures,
+    // Who the figures come from, e.g. "County health department"
+    source: String,
+    reported_by: Principal,
+    reported_at: u64,
+    verification: FigureVerification,
+}
+
+// Implementing Storable and BoundedStorable traits for CasualtyRevision
+impl Storable for CasualtyRevision {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for CasualtyRevision {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// What public queries show of a verified revision
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct VerifiedCasualtyFigures {
+    crisis_update_id: u64,
+    figures: CasualtyFigures,
+    source: String,
+    verified_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct CasualtyTotals {
+    figures: CasualtyFigures,
+    // Crisis updates with verified figures
+    crisis_updates: u64,
+}
+
+thread_local! {
+    static REVISION_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(102, "casualty revisions"));
+
+    // (crisis update id, revision id)
+    static REVISION_STORAGE: RefCell<StableBTreeMap<(u64, u64), CasualtyRevision, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(103)))
+    ));
+}
+
+fn revisions_of(crisis_update_id: u64) -> Vec<CasualtyRevision> {
+    REVISION_STORAGE.with(|s| {
+        s.borrow()
+            .range((crisis_update_id, 0)..=(crisis_update_id, u64::MAX))
+            .map(|(_, revision)| revision)
+            .collect()
+    })
+}
+
+fn find_revision(crisis_update_id: u64, id: u64) -> Result<CasualtyRevision, Error> {
+    REVISION_STORAGE
+        .with(|s| s.borrow().get(&(crisis_update_id, id)))
+        .ok_or_else(|| {
+            Error::not_found(format!("a casualty revision with id={} not found", id))
+                .with_detail("crisis_update_id", crisis_update_id)
+                .with_detail("id", id)
+        })
+}
+
+fn public_view(revision: CasualtyRevision) -> Option<VerifiedCasualtyFigures> {
+    match revision.verification {
+        FigureVerification::Verified { at, .. } => Some(VerifiedCasualtyFigures {
+            crisis_update_id: revision.crisis_update_id,
+            figures: revision.figures,
+            source: revision.source,
+            verified_at: at,
+        }),
+        _ => None,
+    }
+}
+
+// The most recently submitted revision that has been verified
+fn latest_verified(crisis_update_id: u64) -> Option<VerifiedCasualtyFigures> {
+    revisions_of(crisis_update_id).into_iter().rev().find_map(public_view)
+}
+
+pub(crate) fn remove_orphaned_revisions(is_live: &dyn Fn(u64) -> bool) -> u64 {
+    let keys: Vec<(u64, u64)> = REVISION_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|(crisis_update_id, _)| !is_live(*crisis_update_id))
+            .collect()
+    });
+    REVISION_STORAGE.with(|s| {
+        let mut s = s.borrow_mut();
+        for key in &keys {
+            s.remove(key);
+        }
+    });
+    keys.len() as u64
+}
+
+// 2.75.1 submit_casualty_figures Function:
+// Adds a revision awaiting verification; earlier revisions are kept.
+#[ic_cdk::update(guard = "admit_update")]
+async fn submit_casualty_figures(
+    crisis_update_id: u64,
+    figures: CasualtyFigures,
+    source: String,
+) -> Result<CasualtyRevision, Error> {
+    crate::call_stats::count_call("submit_casualty_figures");
+    let reported_by = require_any_role(&REPORTERS)?;
+    let source = normalize_line(&source);
+    if source.is_empty() || source.chars().count() > MAX_SOURCE_CHARS {
+        return Err(Error::validation(format!(
+            "sources must be between 1 and {} characters",
+            MAX_SOURCE_CHARS
+        )));
+    }
+    if crate::_find_crisis_update(crisis_update_id).await?.is_none() {
+        return Err(
+            Error::not_found(format!("a crisis update with id={} not found", crisis_update_id))
+                .with_detail("crisis_update_id", crisis_update_id),
+        );
+    }
+    let revision = CasualtyRevision {
+        id: crate::ids::next_id(&REVISION_ID_COUNTER)?,
+        crisis_update_id,
+        figures,
+        source,
+        reported_by,
+        reported_at: time(),
+        verification: FigureVerification::Pending,
+    };
+    crate::ensure_storable(&revision, "casualty revision")?;
+    REVISION_STORAGE.with(|s| s.borrow_mut().insert((crisis_update_id, revision.id), revision.clone()));
+    Ok(revision)
+}
+
+// 2.75.2 verify_casualty_figures Function:
+// Approves or rejects a pending revision; a rejection needs a reason.
+#[ic_cdk::update(guard = "admit_update")]
+fn verify_casualty_figures(
+    crisis_update_id: u64,
+    id: u64,
+    approve: bool,
+    reason: Option<String>,
+) -> Result<CasualtyRevision, Error> {
+    crate::call_stats::count_call("verify_casualty_figures");
+    let verifier = require_role(Role::Coordinator)?;
+    let mut revision = find_revision(crisis_update_id, id)?;
+    if revision.verification != FigureVerification::Pending {
+        return Err(Error::conflict(format!(
+            "casualty revision with id={} was already reviewed",
+            id
+        )));
+    }
+    if revision.reported_by == verifier {
+        return Err(Error::unauthorized(
+            "figures must be verified by someone other than their reporter",
+        ));
+    }
+    let now = time();
+    revision.verification = if approve {
+        FigureVerification::Verified { by: verifier, at: now }
+    } else {
+        let reason = reason.map(|r| normalize_line(&r)).unwrap_or_default();
+        if reason.is_empty() || reason.chars().count() > MAX_REASON_CHARS {
+            return Err(Error::validation(format!(
+                "rejections need a reason of at most {} characters",
+                MAX_REASON_CHARS
+            )));
+        }
+        FigureVerification::Rejected {
+            by: verifier,
+            at: now,
+            reason,
+        }
+    };
+    crate::ensure_storable(&revision, "casualty revision")?;
+    REVISION_STORAGE.with(|s| s.borrow_mut().insert((crisis_update_id, id), revision.clone()));
+    Ok(revision)
+}
+
+// 2.75.3 get_casualty_history Function:
+// Every revision for the update, oldest first, whatever its verification state.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_casualty_history(crisis_update_id: u64) -> Result<QueryResult<CasualtyRevision>, Error> {
+    require_any_role(&HISTORY_VIEWERS)?;
+    fit(revisions_of(crisis_update_id))
+}
+
+// 2.75.4 get_casualty_figures Function:
+// The latest verified figures for the update; None until a revision is verified.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_casualty_figures(crisis_update_id: u64) -> Option<VerifiedCasualtyFigures> {
+    latest_verified(crisis_update_id)
+}
+
+// 2.75.5 get_casualty_totals Function:
+// Sums the latest verified figures of every update.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_casualty_totals() -> CasualtyTotals {
+    let mut ids: Vec<u64> = REVISION_STORAGE.with(|s| s.borrow().iter().map(|((id, _), _)| id).collect());
+    ids.dedup();
+    let mut totals = CasualtyTotals::default();
+    for verified in ids.into_iter().filter_map(latest_verified) {
+        totals.figures.add(&verified.figures);
+        totals.crisis_updates += 1;
+    }
+    totals
+}
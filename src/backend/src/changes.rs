@@ -0,0 +1,343 @@
+// Change feed for incremental sync. Every crisis update mutation is appended
+// under a monotonically increasing sequence number; clients remember the last
+// sequence they saw and ask for everything after it.
+//
+// Only the newest record of each update is kept: the current version is what
+// a client syncs to, so a newer record makes the older ones redundant.
+//
+// Deletions leave a tombstone for the configured retention window. Once it
+// lapses, the tombstone and the feed records of the deleted update are pruned,
+// and clients whose cursor predates the pruning are told to resync in full.
+use crate::access::{is_staff, require_role, Role};
+use crate::access_mode::admit_read;
+use crate::ids::IdCounter;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::logs::{log, LogLevel};
+use crate::maintenance::admit_update;
+use crate::settings::{get_settings, update_settings, Settings};
+use crate::{CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::collections::BTreeMap;
+use std::{borrow::Cow, cell::RefCell};
+
+const DEFAULT_TOMBSTONE_RETENTION_SECS: u64 = 30 * 24 * 60 * 60;
+pub(crate) const TOMBSTONE_EXPIRY_INTERVAL_SECS: u64 = 60 * 60;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ChangeRecord {
+    pub(crate) crisis_update_id: u64,
+    pub(crate) kind: ChangeKind,
+    pub(crate) timestamp: u64,
+}
+
+// Implementing Storable and BoundedStorable traits for ChangeRecord
+impl Storable for ChangeRecord {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ChangeRecord {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Tombstone {
+    crisis_update_id: u64,
+    deleted_at: u64,
+    deleted_by: Principal,
+}
+
+// Implementing Storable and BoundedStorable traits for Tombstone
+impl Storable for Tombstone {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Tombstone {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// A tombstone as the feed serves it; `deleted_by` is shown to staff only
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct TombstoneInfo {
+    crisis_update_id: u64,
+    deleted_at: u64,
+    deleted_by: Option<Principal>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ChangeEvent {
+    seq: u64,
+    crisis_update_id: u64,
+    kind: ChangeKind,
+    timestamp: u64,
+    // Current version of the update; None once it has been deleted
+    update: Option<CrisisUpdate>,
+    tombstone: Option<TombstoneInfo>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ChangeFeedPage {
+    events: Vec<ChangeEvent>,
+    // Pass back as `since` to continue; equals the request's `since` when nothing is new
+    last_seq: u64,
+    has_more: bool,
+    // Deletions after `since` have been pruned; discard local state and sync from zero
+    resync_required: bool,
+}
+
+thread_local! {
+    static CHANGE_SEQ_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(46, "the change feed"));
+
+    static CHANGE_FEED: RefCell<StableBTreeMap<u64, ChangeRecord, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(47)))
+    ));
+
+    static TOMBSTONES: RefCell<StableBTreeMap<u64, Tombstone, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(48)))
+    ));
+
+    // crisis update id -> sequence number of its newest feed record
+    static LATEST_CHANGE_SEQ: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(133)))
+    ));
+
+    // Highest sequence number removed from the feed by tombstone expiry
+    static PRUNED_THROUGH_SEQ: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(49))), 0)
+            .expect("Cannot create the change feed pruning watermark")
+    );
+}
+
+// Appends a mutation to the feed and returns its sequence number (starting at 1)
+pub(crate) fn record_change(crisis_update_id: u64, kind: ChangeKind) -> Result<u64, Error> {
+    let seq = crate::ids::next_id(&CHANGE_SEQ_COUNTER)? + 1;
+    let record = ChangeRecord {
+        crisis_update_id,
+        kind,
+        timestamp: time(),
+    };
+    CHANGE_FEED.with(|feed| feed.borrow_mut().insert(seq, record));
+    if let Some(superseded) = LATEST_CHANGE_SEQ.with(|l| l.borrow_mut().insert(crisis_update_id, seq)) {
+        CHANGE_FEED.with(|feed| feed.borrow_mut().remove(&superseded));
+    }
+    if kind != ChangeKind::Deleted {
+        // The update is back, e.g. restored from a snapshot
+        TOMBSTONES.with(|t| t.borrow_mut().remove(&crisis_update_id));
+    }
+    crate::realtime::publish(seq, crisis_update_id, kind);
+    let event = match kind {
+        ChangeKind::Created => crate::webhooks::WebhookEvent::Created,
+        ChangeKind::Updated => crate::webhooks::WebhookEvent::Updated,
+        ChangeKind::Deleted => crate::webhooks::WebhookEvent::Deleted,
+    };
+    crate::webhooks::enqueue(event, crisis_update_id);
+    Ok(seq)
+}
+
+// Records a deletion in the feed and leaves a tombstone for it
+pub(crate) fn record_deletion(crisis_update_id: u64, deleted_by: Principal) -> Result<u64, Error> {
+    let tombstone = Tombstone {
+        crisis_update_id,
+        deleted_at: time(),
+        deleted_by,
+    };
+    TOMBSTONES.with(|t| t.borrow_mut().insert(crisis_update_id, tombstone));
+    record_change(crisis_update_id, ChangeKind::Deleted)
+}
+
+fn tombstone_retention_secs() -> u64 {
+    get_settings()
+        .tombstone_retention_secs
+        .unwrap_or(DEFAULT_TOMBSTONE_RETENTION_SECS)
+}
+
+// Drops tombstones past the retention window along with the feed records of their updates
+pub(crate) fn expire_tombstones(now: u64) {
+    let cutoff = now.saturating_sub(tombstone_retention_secs().saturating_mul(NANOS_PER_SECOND));
+    let expired: Vec<u64> = TOMBSTONES.with(|t| {
+        t.borrow()
+            .iter()
+            .filter(|(_, tombstone)| tombstone.deleted_at < cutoff)
+            .map(|(id, _)| id)
+            .collect()
+    });
+    if expired.is_empty() {
+        return;
+    }
+    let pruned: Vec<u64> = CHANGE_FEED.with(|feed| {
+        feed.borrow()
+            .iter()
+            .filter(|(_, record)| expired.contains(&record.crisis_update_id))
+            .map(|(seq, _)| seq)
+            .collect()
+    });
+    CHANGE_FEED.with(|feed| {
+        let mut feed = feed.borrow_mut();
+        for seq in &pruned {
+            feed.remove(seq);
+        }
+    });
+    TOMBSTONES.with(|t| {
+        let mut t = t.borrow_mut();
+        for id in &expired {
+            t.remove(id);
+        }
+    });
+    LATEST_CHANGE_SEQ.with(|l| {
+        let mut l = l.borrow_mut();
+        for id in &expired {
+            l.remove(id);
+        }
+    });
+    if let Some(max_pruned) = pruned.iter().max().copied() {
+        PRUNED_THROUGH_SEQ.with(|watermark| {
+            let current = *watermark.borrow().get();
+            if max_pruned > current {
+                if let Err(err) = watermark.borrow_mut().set(max_pruned) {
+                    log(
+                        LogLevel::Error,
+                        Some("expire_tombstones"),
+                        &format!("cannot persist the change feed pruning watermark: {:?}", err),
+                    );
+                }
+            }
+        });
+    }
+}
+
+// Drops the records superseded before the feed kept only the newest record
+// of each update, and fills the index of newest records. Does nothing once
+// the index is filled.
+pub(crate) fn compact_change_feed() -> u64 {
+    if !LATEST_CHANGE_SEQ.with(|l| l.borrow().is_empty()) {
+        return 0;
+    }
+    let mut latest: BTreeMap<u64, u64> = BTreeMap::new();
+    let mut superseded = Vec::new();
+    CHANGE_FEED.with(|feed| {
+        for (seq, record) in feed.borrow().iter() {
+            if let Some(previous) = latest.insert(record.crisis_update_id, seq) {
+                superseded.push(previous);
+            }
+        }
+    });
+    CHANGE_FEED.with(|feed| {
+        let mut feed = feed.borrow_mut();
+        for seq in &superseded {
+            feed.remove(seq);
+        }
+    });
+    LATEST_CHANGE_SEQ.with(|l| {
+        let mut l = l.borrow_mut();
+        for (id, seq) in latest {
+            l.insert(id, seq);
+        }
+    });
+    superseded.len() as u64
+}
+
+pub(crate) fn current_seq() -> u64 {
+    CHANGE_SEQ_COUNTER.with(|counter| counter.borrow().peek().unwrap_or_default())
+}
+
+pub(crate) fn pruned_through_seq() -> u64 {
+    PRUNED_THROUGH_SEQ.with(|watermark| *watermark.borrow().get())
+}
+
+// Up to `limit` feed records after `since`, oldest first
+pub(crate) fn changes_after(since: u64, limit: usize) -> Vec<(u64, ChangeRecord)> {
+    CHANGE_FEED.with(|feed| feed.borrow().range(since.saturating_add(1)..).take(limit).collect())
+}
+
+// 2.31.1 get_changes_since Function:
+// Skips events of updates hidden from the caller; last_seq still advances past them.
+#[ic_cdk::query(composite = true, guard = "admit_read")]
+async fn get_changes_since(since: u64, limit: u64) -> Result<ChangeFeedPage, Error> {
+    let limit = crate::query_limits::page_size(limit) as usize;
+    let mut records: Vec<(u64, ChangeRecord)> =
+        CHANGE_FEED.with(|feed| feed.borrow().range(since.saturating_add(1)..).take(limit + 1).collect());
+    let resync_required = since < pruned_through_seq();
+    let has_more = records.len() > limit;
+    records.truncate(limit);
+    let last_seq = records.last().map_or(since, |(seq, _)| *seq);
+    let staff = is_staff(&ic_cdk::caller());
+    let mut events = Vec::with_capacity(records.len());
+    for (seq, record) in records {
+        let (update, tombstone) = match record.kind {
+            ChangeKind::Deleted => {
+                let tombstone = TOMBSTONES.with(|t| t.borrow().get(&record.crisis_update_id));
+                let tombstone = tombstone.map(|t| TombstoneInfo {
+                    crisis_update_id: t.crisis_update_id,
+                    deleted_at: t.deleted_at,
+                    deleted_by: staff.then_some(t.deleted_by),
+                });
+                (None, tombstone)
+            }
+            ChangeKind::Created | ChangeKind::Updated => {
+                let update = crate::_find_crisis_update(record.crisis_update_id).await?;
+                // Events of updates the caller may not see are left out altogether
+                if update.as_ref().is_some_and(|u| !crate::moderation::visible_to_caller(u)) {
+                    continue;
+                }
+                (update.map(crate::redaction::redact_update_for_caller), None)
+            }
+        };
+        events.push(ChangeEvent {
+            seq,
+            crisis_update_id: record.crisis_update_id,
+            kind: record.kind,
+            timestamp: record.timestamp,
+            update,
+            tombstone,
+        });
+    }
+    Ok(ChangeFeedPage {
+        events,
+        last_seq,
+        has_more,
+        resync_required,
+    })
+}
+
+// 2.31.2 get_current_change_seq Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_current_change_seq() -> u64 {
+    current_seq()
+}
+
+// 2.31.3 set_tombstone_retention Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn set_tombstone_retention(retention_secs: Option<u64>) -> Result<Settings, Error> {
+    crate::call_stats::count_call("set_tombstone_retention");
+    require_role(Role::Admin)?;
+    if retention_secs == Some(0) {
+        return Err(Error::validation("tombstone retention must be greater than zero"));
+    }
+    update_settings(|s| s.tombstone_retention_secs = retention_secs)
+}
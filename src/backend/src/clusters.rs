@@ -0,0 +1,98 @@
+// Server-side clustering of crisis updates for map views. Updates with
+// coordinates inside the requested box are bucketed into a grid whose cells
+// shrink as the zoom level grows, so a frontend draws one marker per cell
+// instead of thousands of points.
+use crate::access_mode::admit_read;
+use crate::geo::{BoundingBox, Coordinates};
+use crate::response::{fit, QueryResult};
+use crate::{CrisisPriority, Error, CRISIS_STORAGE};
+use std::collections::BTreeMap;
+
+const MAX_ZOOM: u8 = 20;
+// Grid cells across one map tile at any zoom level
+const CELLS_PER_TILE: f64 = 8.0;
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct UpdateCluster {
+    // Mean position of the updates in the cell
+    centroid: Coordinates,
+    count: u64,
+    // The most common priority in the cell; ties go to the higher priority
+    dominant_priority: CrisisPriority,
+    // Set when the cell holds a single update, so it can be drawn directly
+    update_id: Option<u64>,
+}
+
+#[derive(Default)]
+struct CellTotals {
+    latitude_sum: f64,
+    longitude_sum: f64,
+    count: u64,
+    per_priority: [u64; 4],
+    first_id: u64,
+}
+
+// Side of a grid cell in degrees; a tile spans 360 / 2^zoom degrees
+pub(crate) fn cell_size_degrees(zoom: u8) -> f64 {
+    360.0 / f64::from(1u32 << zoom) / CELLS_PER_TILE
+}
+
+pub(crate) fn cell_of(point: &Coordinates, cell_size: f64) -> (i64, i64) {
+    (
+        ((point.latitude + 90.0) / cell_size).floor() as i64,
+        ((point.longitude + 180.0) / cell_size).floor() as i64,
+    )
+}
+
+pub(crate) fn validate_zoom(zoom: u8) -> Result<(), Error> {
+    if zoom > MAX_ZOOM {
+        return Err(Error::validation(format!("zoom must be at most {}", MAX_ZOOM)).with_detail("zoom", zoom));
+    }
+    Ok(())
+}
+
+// 2.42.1 get_crisis_update_clusters Function:
+// Only updates with coordinates that the caller may see are counted, at the
+// precision the caller may see them.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_crisis_update_clusters(bbox: BoundingBox, zoom: u8) -> Result<QueryResult<UpdateCluster>, Error> {
+    bbox.validate()?;
+    validate_zoom(zoom)?;
+    let cell_size = cell_size_degrees(zoom);
+    let mut cells: BTreeMap<(i64, i64), CellTotals> = BTreeMap::new();
+    CRISIS_STORAGE.with(|service| {
+        for (_, update) in service.borrow().iter() {
+            if update.coordinates.is_none() || !crate::moderation::visible_to_caller(&update) {
+                continue;
+            }
+            // Redaction fuzzes the position for callers who may not see it exactly
+            let update = crate::redaction::redact_update_for_caller(update);
+            let Some(point) = update.coordinates.filter(|c| bbox.contains(c)) else {
+                continue;
+            };
+            let cell = cells.entry(cell_of(&point, cell_size)).or_default();
+            if cell.count == 0 {
+                cell.first_id = update.id;
+            }
+            cell.latitude_sum += point.latitude;
+            cell.longitude_sum += point.longitude;
+            cell.count += 1;
+            cell.per_priority[update.priority as usize] += 1;
+        }
+    });
+    fit(cells
+        .into_values()
+        .map(|cell| UpdateCluster {
+            centroid: Coordinates {
+                latitude: cell.latitude_sum / cell.count as f64,
+                longitude: cell.longitude_sum / cell.count as f64,
+            },
+            count: cell.count,
+            dominant_priority: CrisisPriority::ALL
+                .into_iter()
+                .max_by_key(|p| cell.per_priority[*p as usize])
+                .unwrap_or_default(),
+            update_id: (cell.count == 1).then_some(cell.first_id),
+        })
+        .collect())
+}
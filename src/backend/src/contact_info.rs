@@ -0,0 +1,145 @@
+// Follow-up contacts reporters and requesters may attach, so responders can
+// reach them. Phone numbers are stored in E.164 form and emails are checked for
+// plausible syntax. Contacts are hidden from query responses unless the viewer
+// is a responder (or an admin) or the person who submitted them.
+use crate::access::{has_role, Role};
+use crate::resources::ResourceRequest;
+use crate::{CrisisUpdate, Error};
+use candid::Principal;
+use ic_cdk::api::caller;
+
+const MAX_EMAIL_CHARS: usize = 254;
+const MAX_EMAIL_LOCAL_CHARS: usize = 64;
+const MIN_PHONE_DIGITS: usize = 8;
+const MAX_PHONE_DIGITS: usize = 15;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub(crate) struct ContactInfo {
+    // E.164, e.g. +254700123456; spaces, dashes and brackets are removed on submission
+    phone: Option<String>,
+    email: Option<String>,
+}
+
+fn normalize_phone(phone: &str) -> Result<String, Error> {
+    let phone: String = phone
+        .chars()
+        .filter(|c| !c.is_whitespace() && !matches!(c, '-' | '(' | ')' | '.'))
+        .collect();
+    let digits = phone.strip_prefix('+').unwrap_or_default();
+    let valid = digits.chars().all(|c| c.is_ascii_digit())
+        && (MIN_PHONE_DIGITS..=MAX_PHONE_DIGITS).contains(&digits.len())
+        && !digits.starts_with('0');
+    if !valid {
+        return Err(
+            Error::validation("phone numbers must be in E.164 format, such as +254700123456")
+                .with_detail("phone", phone),
+        );
+    }
+    Ok(phone)
+}
+
+fn valid_domain(domain: &str) -> bool {
+    let labels: Vec<&str> = domain.split('.').collect();
+    labels.len() >= 2
+        && labels.iter().all(|label| {
+            (1..=63).contains(&label.len())
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        })
+        && labels
+            .last()
+            .is_some_and(|tld| tld.len() >= 2 && tld.chars().all(|c| c.is_ascii_alphabetic()))
+}
+
+fn normalize_email(email: &str) -> Result<String, Error> {
+    let email = email.trim();
+    let invalid = || Error::validation("email addresses must look like name@example.org").with_detail("email", email);
+    let Some((local, domain)) = email.rsplit_once('@') else {
+        return Err(invalid());
+    };
+    let local_valid = (1..=MAX_EMAIL_LOCAL_CHARS).contains(&local.len())
+        && local
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+/=?^_`{|}~.-".contains(c))
+        && !local.starts_with('.')
+        && !local.ends_with('.')
+        && !local.contains("..");
+    if email.len() > MAX_EMAIL_CHARS || !local_valid || !valid_domain(domain) {
+        return Err(invalid());
+    }
+    Ok(format!("{}@{}", local, domain.to_ascii_lowercase()))
+}
+
+// Normalizes the given channels; a contact with neither becomes None
+pub(crate) fn validate_contact(contact: &mut Option<ContactInfo>) -> Result<(), Error> {
+    let Some(info) = contact.as_mut() else {
+        return Ok(());
+    };
+    info.phone = info.phone.as_deref().map(normalize_phone).transpose()?;
+    info.email = info.email.as_deref().map(normalize_email).transpose()?;
+    if info.phone.is_none() && info.email.is_none() {
+        *contact = None;
+    }
+    Ok(())
+}
+
+fn may_see(owner: Option<&Principal>, viewer: &Principal) -> bool {
+    owner == Some(viewer) || has_role(viewer, Role::Responder)
+}
+
+pub(crate) fn redact_update(mut update: CrisisUpdate, viewer: &Principal) -> CrisisUpdate {
+    if update.contact.is_some() && !may_see(update.author.as_ref(), viewer) {
+        update.contact = None;
+    }
+    update
+}
+
+pub(crate) fn redact_request_for_caller(mut request: ResourceRequest) -> ResourceRequest {
+    if request.contact.is_some() && !may_see(Some(&request.requester), &caller()) {
+        request.contact = None;
+    }
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phones_are_stored_in_e164_form() {
+        assert_eq!(normalize_phone("+254 (700) 123-456").unwrap(), "+254700123456");
+        for invalid in ["0700123456", "+0700123456", "+2547", "+2547001234567890", "+254 700 12a 456"] {
+            assert!(normalize_phone(invalid).is_err(), "{} was accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn emails_keep_the_local_part_and_lower_case_the_domain() {
+        assert_eq!(normalize_email(" Jane.Doe+relief@Example.ORG ").unwrap(), "Jane.Doe+relief@example.org");
+        for invalid in [
+            "jane",
+            "@example.org",
+            "jane@localhost",
+            "jane..doe@example.org",
+            "jane@-example.org",
+            "jane@example.4",
+        ] {
+            assert!(normalize_email(invalid).is_err(), "{} was accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn a_contact_without_channels_becomes_none() {
+        let mut contact = Some(ContactInfo::default());
+        validate_contact(&mut contact).unwrap();
+        assert!(contact.is_none());
+
+        let mut contact = Some(ContactInfo {
+            phone: Some("+254-700-123456".to_string()),
+            email: None,
+        });
+        validate_contact(&mut contact).unwrap();
+        assert_eq!(contact.unwrap().phone.as_deref(), Some("+254700123456"));
+    }
+}
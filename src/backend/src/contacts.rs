@@ -0,0 +1,172 @@
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::ids::IdCounter;
+use crate::maintenance::admit_update;
+use crate::response::{fit, QueryResult};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_REGION_CHARS: usize = 100;
+const MAX_NAME_CHARS: usize = 100;
+const MAX_PHONE_CHARS: usize = 32;
+const MAX_NOTES_CHARS: usize = 500;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub(crate) enum EmergencyService {
+    Police,
+    Fire,
+    #[default]
+    Medical,
+    Utilities,
+    Other,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct EmergencyContact {
+    id: u64,
+    region: String,
+    service: EmergencyService,
+    name: String,
+    phone: String,
+    notes: String,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+// Implementing Storable and BoundedStorable traits for EmergencyContact
+impl Storable for EmergencyContact {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for EmergencyContact {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct EmergencyContactPayload {
+    region: String,
+    service: EmergencyService,
+    name: String,
+    phone: String,
+    notes: String,
+}
+
+thread_local! {
+    static CONTACT_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(19, "emergency contacts"));
+
+    static CONTACT_STORAGE: RefCell<StableBTreeMap<u64, EmergencyContact, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20)))
+    ));
+}
+
+fn validate_contact_payload(payload: &EmergencyContactPayload) -> Result<(), Error> {
+    if payload.region.trim().is_empty() || payload.phone.trim().is_empty() {
+        return Err(Error::validation("region and phone are required"));
+    }
+    for (field, value, max) in [
+        ("region", &payload.region, MAX_REGION_CHARS),
+        ("name", &payload.name, MAX_NAME_CHARS),
+        ("phone", &payload.phone, MAX_PHONE_CHARS),
+        ("notes", &payload.notes, MAX_NOTES_CHARS),
+    ] {
+        if value.chars().count() > max {
+            return Err(Error::validation(format!("{} must be at most {} characters", field, max))
+                .with_detail("field", field));
+        }
+    }
+    Ok(())
+}
+
+// 2.17.1 get_emergency_contact Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_emergency_contact(id: u64) -> Result<EmergencyContact, Error> {
+    match CONTACT_STORAGE.with(|s| s.borrow().get(&id)) {
+        Some(contact) => Ok(contact),
+        None => Err(Error::not_found(format!(
+            "an emergency contact with id={} not found",
+            id
+        ))),
+    }
+}
+
+// 2.17.2 add_emergency_contact Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn add_emergency_contact(payload: EmergencyContactPayload) -> Result<EmergencyContact, Error> {
+    crate::call_stats::count_call("add_emergency_contact");
+    require_role(Role::Admin)?;
+    validate_contact_payload(&payload)?;
+    let id = crate::ids::next_id(&CONTACT_ID_COUNTER)?;
+    let contact = EmergencyContact {
+        id,
+        region: payload.region,
+        service: payload.service,
+        name: payload.name,
+        phone: payload.phone,
+        notes: payload.notes,
+        created_at: time(),
+        updated_at: None,
+    };
+    crate::ensure_storable(&contact, "emergency contact")?;
+    CONTACT_STORAGE.with(|s| s.borrow_mut().insert(id, contact.clone()));
+    Ok(contact)
+}
+
+// 2.17.3 update_emergency_contact Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn update_emergency_contact(id: u64, payload: EmergencyContactPayload) -> Result<EmergencyContact, Error> {
+    crate::call_stats::count_call("update_emergency_contact");
+    require_role(Role::Admin)?;
+    validate_contact_payload(&payload)?;
+    let mut contact = get_emergency_contact(id)?;
+    contact.region = payload.region;
+    contact.service = payload.service;
+    contact.name = payload.name;
+    contact.phone = payload.phone;
+    contact.notes = payload.notes;
+    contact.updated_at = Some(time());
+    crate::ensure_storable(&contact, "emergency contact")?;
+    CONTACT_STORAGE.with(|s| s.borrow_mut().insert(id, contact.clone()));
+    Ok(contact)
+}
+
+// 2.17.4 delete_emergency_contact Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn delete_emergency_contact(id: u64) -> Result<EmergencyContact, Error> {
+    crate::call_stats::count_call("delete_emergency_contact");
+    require_role(Role::Admin)?;
+    match CONTACT_STORAGE.with(|service| service.borrow_mut().remove(&id)) {
+        Some(contact) => Ok(contact),
+        None => Err(Error::not_found(format!(
+            "couldn't delete an emergency contact with id={}. contact not found.",
+            id
+        ))),
+    }
+}
+
+// 2.17.5 get_emergency_contacts Function:
+// A contact applies when its region names the location or appears within it,
+// so "Nairobi" contacts are returned for "Westlands, Nairobi".
+#[ic_cdk::query(guard = "admit_read")]
+fn get_emergency_contacts(location: String) -> Result<QueryResult<EmergencyContact>, Error> {
+    let location = location.to_lowercase();
+    fit(CONTACT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, contact)| location.contains(&contact.region.to_lowercase()))
+            .map(|(_, contact)| contact)
+            .collect()
+    }))
+}
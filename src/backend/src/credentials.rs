@@ -0,0 +1,332 @@
+// Responder and verified-organisation roles backed by an external credentials
+// canister, so those roles need not rest on an admin's manual grant alone. A
+// caller asks for a role, the credentials canister confirms their credential,
+// and the role is granted with a cached verification that expires. The
+// heartbeat re-checks verifications as they expire and revokes the role when
+// the credential no longer holds, or when the credentials canister has been
+// unreachable for longer than a grace period. Roles granted by an admin are
+// never revoked here.
+//
+// The credentials canister must expose
+//   verify_credential : (principal, Role) -> (CredentialCheck)
+use crate::access::{add_role, principal_key, remove_role, require_role, roles_of, PrincipalKey, Role};
+use crate::access_mode::admit_read;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::logs::LogLevel;
+use crate::maintenance::admit_update;
+use crate::response::{fit, QueryResult};
+use crate::settings::{get_settings, update_settings, Settings};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+pub(crate) const CREDENTIAL_CHECK_INTERVAL_SECS: u64 = 15 * 60;
+// Verifications are re-checked at least this often, whatever the credential's own expiry
+const MAX_CACHE_SECS: u64 = 24 * 60 * 60;
+const UNREACHABLE_GRACE_SECS: u64 = 3 * 24 * 60 * 60;
+const RECHECK_BATCH_SIZE: usize = 20;
+
+const CREDENTIALED_ROLES: [Role; 2] = [Role::Responder, Role::VerifiedOrg];
+
+// The credentials canister's answer for a principal and role
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CredentialCheck {
+    valid: bool,
+    // When the credential itself lapses; None if it does not
+    expires_at: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CredentialVerification {
+    principal: Principal,
+    role: Role,
+    verified_at: u64,
+    expires_at: u64,
+    // False when the principal already held the role, so it is kept on revocation
+    granted_role: bool,
+    // Set while re-checks fail to reach the credentials canister
+    last_error: Option<String>,
+    // Earliest time of the next re-check after a failed one, backing off as failures go on
+    retry_at: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct CredentialRecord {
+    verifications: Vec<CredentialVerification>,
+}
+
+// Implementing Storable and BoundedStorable traits for CredentialRecord
+impl Storable for CredentialRecord {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for CredentialRecord {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct CredentialRecheckReport {
+    checked: u64,
+    renewed: u64,
+    revoked: u64,
+    // Credentials canister unreachable; retried on the next run
+    failed: u64,
+}
+
+thread_local! {
+    static CREDENTIAL_STORAGE: RefCell<StableBTreeMap<PrincipalKey, CredentialRecord, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(109)))
+    ));
+
+    static RECHECK_IN_PROGRESS: RefCell<bool> = const { RefCell::new(false) };
+}
+
+struct RecheckRunGuard;
+
+impl RecheckRunGuard {
+    fn acquire() -> Result<Self, Error> {
+        if RECHECK_IN_PROGRESS.with(|r| r.replace(true)) {
+            return Err(Error::conflict("a credential re-check is already in progress"));
+        }
+        Ok(RecheckRunGuard)
+    }
+}
+
+impl Drop for RecheckRunGuard {
+    fn drop(&mut self) {
+        RECHECK_IN_PROGRESS.with(|r| *r.borrow_mut() = false);
+    }
+}
+
+fn credentials_canister() -> Result<Principal, Error> {
+    get_settings()
+        .credentials_canister
+        .ok_or_else(|| Error::validation("no credentials canister is configured"))
+}
+
+async fn verify_credential(canister: Principal, principal: Principal, role: Role) -> Result<CredentialCheck, Error> {
+    let (check,): (CredentialCheck,) = ic_cdk::call(canister, "verify_credential", (principal, role))
+        .await
+        .map_err(|(code, msg)| {
+            Error::external_call_failed(format!(
+                "credentials canister {} rejected verify_credential: {:?} {}",
+                canister, code, msg
+            ))
+        })?;
+    Ok(check)
+}
+
+fn verification_expiry(check: &CredentialCheck, now: u64) -> u64 {
+    let cache_limit = now.saturating_add(MAX_CACHE_SECS * NANOS_PER_SECOND);
+    check.expires_at.map_or(cache_limit, |at| at.min(cache_limit))
+}
+
+fn verifications_of(principal: &Principal) -> Vec<CredentialVerification> {
+    CREDENTIAL_STORAGE
+        .with(|s| s.borrow().get(&principal_key(principal)))
+        .unwrap_or_default()
+        .verifications
+}
+
+// Replaces the principal's verification for the role, or drops it when None
+fn store_verification(principal: Principal, role: Role, verification: Option<CredentialVerification>) {
+    let key = principal_key(&principal);
+    CREDENTIAL_STORAGE.with(|s| {
+        let mut s = s.borrow_mut();
+        let mut record = s.get(&key).unwrap_or_default();
+        record.verifications.retain(|v| v.role != role);
+        record.verifications.extend(verification);
+        if record.verifications.is_empty() {
+            s.remove(&key);
+        } else {
+            s.insert(key, record);
+        }
+    });
+}
+
+fn revoke(verification: &CredentialVerification, reason: &str) {
+    store_verification(verification.principal, verification.role, None);
+    if verification.granted_role && remove_role(&verification.principal, verification.role).is_err() {
+        // An admin already revoked the role
+        return;
+    }
+    crate::logs::log(
+        LogLevel::Warn,
+        Some("recheck_credentials"),
+        &format!(
+            "credential verification of {:?} for {} ended: {}",
+            verification.role, verification.principal, reason
+        ),
+    );
+}
+
+// Waits as long as the canister has been unreachable, between the check
+// interval and a day, but never past the end of the grace period
+fn next_retry_at(unreachable_since: u64, now: u64) -> u64 {
+    let delay = now.saturating_sub(unreachable_since).clamp(
+        CREDENTIAL_CHECK_INTERVAL_SECS * NANOS_PER_SECOND,
+        MAX_CACHE_SECS * NANOS_PER_SECOND,
+    );
+    let grace_ends_at = unreachable_since.saturating_add(UNREACHABLE_GRACE_SECS * NANOS_PER_SECOND);
+    now.saturating_add(delay).min(grace_ends_at.saturating_add(1))
+}
+
+// Re-checks up to a batch of expired verifications against the credentials canister
+pub(crate) async fn recheck_credentials(now: u64) -> Result<CredentialRecheckReport, Error> {
+    let mut report = CredentialRecheckReport::default();
+    let Some(canister) = get_settings().credentials_canister else {
+        return Ok(report);
+    };
+    let _guard = RecheckRunGuard::acquire()?;
+    let due: Vec<CredentialVerification> = CREDENTIAL_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .flat_map(|(_, record)| record.verifications)
+            .filter(|v| v.expires_at <= now && v.retry_at.is_none_or(|at| at <= now))
+            .take(RECHECK_BATCH_SIZE)
+            .collect()
+    });
+    for mut verification in due {
+        report.checked += 1;
+        match verify_credential(canister, verification.principal, verification.role).await {
+            Ok(check) if check.valid => {
+                let now = time();
+                verification.verified_at = now;
+                verification.expires_at = verification_expiry(&check, now);
+                verification.last_error = None;
+                verification.retry_at = None;
+                store_verification(verification.principal, verification.role, Some(verification));
+                report.renewed += 1;
+            }
+            Ok(_) => {
+                revoke(&verification, "the credential is no longer valid");
+                report.revoked += 1;
+            }
+            Err(err) => {
+                report.failed += 1;
+                let unreachable_since = verification.expires_at;
+                if time().saturating_sub(unreachable_since) > UNREACHABLE_GRACE_SECS * NANOS_PER_SECOND {
+                    revoke(&verification, "the credentials canister stayed unreachable");
+                    report.revoked += 1;
+                } else {
+                    verification.last_error = Some(err.msg);
+                    verification.retry_at = Some(next_retry_at(verification.expires_at, time()));
+                    store_verification(verification.principal, verification.role, Some(verification));
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+// 2.78.1 set_credentials_canister Function:
+// None stops credential checks; existing verifications keep their roles until it is set again.
+#[ic_cdk::update(guard = "admit_update")]
+fn set_credentials_canister(canister: Option<Principal>) -> Result<Settings, Error> {
+    crate::call_stats::count_call("set_credentials_canister");
+    require_role(Role::Admin)?;
+    update_settings(|s| s.credentials_canister = canister)
+}
+
+// 2.78.2 request_credentialed_role Function:
+// Grants the caller Responder or VerifiedOrg once the credentials canister confirms their credential.
+#[ic_cdk::update(guard = "admit_update")]
+async fn request_credentialed_role(role: Role) -> Result<CredentialVerification, Error> {
+    crate::call_stats::count_call("request_credentialed_role");
+    let principal = caller();
+    if principal == Principal::anonymous() {
+        return Err(Error::unauthorized("the anonymous principal cannot hold credentials"));
+    }
+    if !CREDENTIALED_ROLES.contains(&role) {
+        return Err(Error::validation(format!(
+            "only the roles {:?} can be granted by credential",
+            CREDENTIALED_ROLES
+        )));
+    }
+    let canister = credentials_canister()?;
+    let check = verify_credential(canister, principal, role).await?;
+    if !check.valid {
+        return Err(Error::unauthorized(format!(
+            "the credentials canister did not confirm a {:?} credential for the caller",
+            role
+        )));
+    }
+    let now = time();
+    // Kept from an earlier verification, since the role would otherwise count as admin-granted
+    let granted_role = verifications_of(&principal)
+        .iter()
+        .find(|v| v.role == role)
+        .map_or(!roles_of(&principal).contains(&role), |v| v.granted_role);
+    let verification = CredentialVerification {
+        principal,
+        role,
+        verified_at: now,
+        expires_at: verification_expiry(&check, now),
+        granted_role,
+        last_error: None,
+        retry_at: None,
+    };
+    add_role(&principal, role);
+    store_verification(principal, role, Some(verification.clone()));
+    Ok(verification)
+}
+
+// 2.78.3 get_my_credential_verifications Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_my_credential_verifications() -> Vec<CredentialVerification> {
+    verifications_of(&caller())
+}
+
+// 2.78.4 list_credential_verifications Function:
+// Soonest to expire first.
+#[ic_cdk::query(guard = "admit_read")]
+fn list_credential_verifications() -> Result<QueryResult<CredentialVerification>, Error> {
+    require_role(Role::Admin)?;
+    let mut verifications: Vec<CredentialVerification> =
+        CREDENTIAL_STORAGE.with(|s| s.borrow().iter().flat_map(|(_, record)| record.verifications).collect());
+    verifications.sort_by_key(|v| v.expires_at);
+    fit(verifications)
+}
+
+// 2.78.5 recheck_credentials_now Function:
+// Re-checks a batch of expired verifications without waiting for the heartbeat.
+#[ic_cdk::update(guard = "admit_update")]
+async fn recheck_credentials_now() -> Result<CredentialRecheckReport, Error> {
+    crate::call_stats::count_call("recheck_credentials_now");
+    require_role(Role::Admin)?;
+    credentials_canister()?;
+    recheck_credentials(time()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: u64 = 60 * NANOS_PER_SECOND;
+    const HOUR: u64 = 60 * MINUTE;
+
+    #[test]
+    fn retries_back_off_with_the_outage() {
+        let since = 1_000 * HOUR;
+        assert_eq!(next_retry_at(since, since), since + 15 * MINUTE);
+        assert_eq!(next_retry_at(since, since + 2 * HOUR), since + 4 * HOUR);
+        assert_eq!(next_retry_at(since, since + 30 * HOUR), since + 54 * HOUR);
+    }
+
+    #[test]
+    fn retries_stop_at_the_end_of_the_grace_period() {
+        let since = 1_000 * HOUR;
+        assert_eq!(next_retry_at(since, since + 70 * HOUR), since + 72 * HOUR + 1);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::logs::{log, LogLevel};
+use crate::maintenance::admit_update;
+use crate::response::{fit, QueryResult};
+use crate::settings::{get_settings, update_settings, Settings};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::Principal;
+use ic_cdk::api::{canister_balance128, id, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const DEFAULT_CHECK_INTERVAL_SECS: u64 = 60 * 60;
+const MIN_CHECK_INTERVAL_SECS: u64 = 60;
+const MAX_HISTORY_ENTRIES: u64 = 2_000;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CyclesAlertTarget {
+    pub(crate) canister_id: Principal,
+    pub(crate) method: String,
+}
+
+// Argument sent to the alert target when the balance drops below the threshold
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct CyclesAlert {
+    canister_id: Principal,
+    balance: u128,
+    threshold: u128,
+    timestamp: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct CyclesBalanceEntry {
+    timestamp: u64,
+    balance: u128,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct CyclesStatus {
+    balance: u128,
+    threshold: Option<u128>,
+    below_threshold: bool,
+    alert_raised_at: Option<u64>,
+}
+
+thread_local! {
+    static CYCLES_HISTORY: RefCell<StableBTreeMap<u64, u128, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31)))
+    ));
+
+    // Set while the balance stays below the threshold so the alert fires once per dip
+    static ALERT_RAISED_AT: RefCell<Option<u64>> = const { RefCell::new(None) };
+}
+
+pub(crate) fn check_interval_secs() -> u64 {
+    get_settings()
+        .cycles_check_interval_secs
+        .unwrap_or(DEFAULT_CHECK_INTERVAL_SECS)
+}
+
+fn record_balance(now: u64, balance: u128) {
+    CYCLES_HISTORY.with(|h| {
+        let mut history = h.borrow_mut();
+        history.insert(now, balance);
+        while history.len() > MAX_HISTORY_ENTRIES {
+            match history.first_key_value() {
+                Some((oldest, _)) => history.remove(&oldest),
+                None => break,
+            };
+        }
+    });
+}
+
+// Records the current balance and alerts the configured target on a threshold crossing
+pub(crate) fn check_cycles_balance(now: u64) {
+    let balance = canister_balance128();
+    record_balance(now, balance);
+    let settings = get_settings();
+    let Some(threshold) = settings.cycles_alert_threshold else {
+        return;
+    };
+    if balance >= threshold {
+        ALERT_RAISED_AT.with(|a| *a.borrow_mut() = None);
+        return;
+    }
+    if ALERT_RAISED_AT.with(|a| a.borrow().is_some()) {
+        return;
+    }
+    ALERT_RAISED_AT.with(|a| *a.borrow_mut() = Some(now));
+    log(
+        LogLevel::Warn,
+        Some("cycles_monitor"),
+        &format!("cycles balance {} is below the alert threshold {}", balance, threshold),
+    );
+    if let Some(target) = settings.cycles_alert_target {
+        let alert = CyclesAlert {
+            canister_id: id(),
+            balance,
+            threshold,
+            timestamp: now,
+        };
+        if let Err(code) = ic_cdk::notify(target.canister_id, &target.method, (alert,)) {
+            log(
+                LogLevel::Error,
+                Some("cycles_monitor"),
+                &format!("failed to notify cycles alert target: {:?}", code),
+            );
+        }
+    }
+}
+
+// 2.22.1 set_cycles_alert_config Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn set_cycles_alert_config(
+    threshold: Option<u128>,
+    target: Option<CyclesAlertTarget>,
+    check_interval_secs: Option<u64>,
+) -> Result<Settings, Error> {
+    crate::call_stats::count_call("set_cycles_alert_config");
+    require_role(Role::Admin)?;
+    if check_interval_secs.is_some_and(|s| s < MIN_CHECK_INTERVAL_SECS) {
+        return Err(Error::validation(format!(
+            "check interval must be at least {} seconds",
+            MIN_CHECK_INTERVAL_SECS
+        )));
+    }
+    ALERT_RAISED_AT.with(|a| *a.borrow_mut() = None);
+    update_settings(|s| {
+        s.cycles_alert_threshold = threshold;
+        s.cycles_alert_target = target;
+        s.cycles_check_interval_secs = check_interval_secs;
+    })
+}
+
+// 2.22.2 get_cycles_status Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_cycles_status() -> Result<CyclesStatus, Error> {
+    require_role(Role::Admin)?;
+    let balance = canister_balance128();
+    let threshold = get_settings().cycles_alert_threshold;
+    Ok(CyclesStatus {
+        balance,
+        threshold,
+        below_threshold: threshold.is_some_and(|t| balance < t),
+        alert_raised_at: ALERT_RAISED_AT.with(|a| *a.borrow()),
+    })
+}
+
+// 2.22.3 get_cycles_history Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_cycles_history(since: Option<u64>) -> Result<QueryResult<CyclesBalanceEntry>, Error> {
+    require_role(Role::Admin)?;
+    fit(CYCLES_HISTORY.with(|h| {
+        h.borrow()
+            .range(since.unwrap_or(0)..)
+            .map(|(timestamp, balance)| CyclesBalanceEntry { timestamp, balance })
+            .collect()
+    }))
+}
+
+// 2.22.4 record_cycles_balance Function:
+// Lets admins take a sample immediately instead of waiting for the next check.
+#[ic_cdk::update(guard = "admit_update")]
+fn record_cycles_balance() -> Result<u128, Error> {
+    crate::call_stats::count_call("record_cycles_balance");
+    require_role(Role::Admin)?;
+    check_cycles_balance(time());
+    Ok(canister_balance128())
+}
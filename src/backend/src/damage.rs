@@ -0,0 +1,288 @@
+// Structured damage assessments filed by field assessors against a crisis
+// update, one per assessed structure or site. Photos are https links on the
+// admin allowlist, as for link attachments. Summaries per region feed recovery
+// planning; an assessment without a region of its own takes the update's.
+use crate::access::{has_role, require_any_role, Role};
+use crate::access_mode::admit_read;
+use crate::ids::IdCounter;
+use crate::maintenance::admit_update;
+use crate::normalize::normalize_multiline;
+use crate::regions::{validate_region, Region};
+use crate::response::{fit, QueryResult};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::collections::BTreeMap;
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_PHOTOS: usize = 5;
+const MAX_NOTES_CHARS: usize = 1000;
+
+const ASSESSORS: [Role; 2] = [Role::Responder, Role::VerifiedOrg];
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+pub(crate) enum StructureType {
+    #[default]
+    Residential,
+    Commercial,
+    School,
+    HealthFacility,
+    Road,
+    Bridge,
+    Utility,
+    Agricultural,
+    Other,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+pub(crate) enum DamageLevel {
+    #[default]
+    Minor,
+    Major,
+    Destroyed,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DamageAssessment {
+    id: u64,
+    crisis_update_id: u64,
+    assessor: Principal,
+    structure_type: StructureType,
+    damage_level: DamageLevel,
+    // Estimated people living or working in the structure
+    affected_people: u64,
+    photo_urls: Vec<String>,
+    notes: String,
+    region: Option<Region>,
+    assessed_at: u64,
+    updated_at: Option<u64>,
+}
+
+// Implementing Storable and BoundedStorable traits for DamageAssessment
+impl Storable for DamageAssessment {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for DamageAssessment {
+    const MAX_SIZE: u32 = 8192;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct DamageAssessmentPayload {
+    crisis_update_id: u64,
+    structure_type: StructureType,
+    damage_level: DamageLevel,
+    affected_people: u64,
+    photo_urls: Vec<String>,
+    notes: String,
+    // Defaults to the region of the crisis update
+    region: Option<Region>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct RegionDamageSummary {
+    // Region label, e.g. "Mombasa, Coast, KE"; None for assessments without a region
+    region: Option<String>,
+    assessments: u64,
+    destroyed: u64,
+    // Minor and major damage
+    damaged: u64,
+    affected_people: u64,
+}
+
+thread_local! {
+    static ASSESSMENT_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(100, "damage assessments"));
+
+    static ASSESSMENT_STORAGE: RefCell<StableBTreeMap<u64, DamageAssessment, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(101)))
+    ));
+}
+
+// Checks the payload and fills in the region from the crisis update
+async fn validate_payload(payload: &mut DamageAssessmentPayload) -> Result<(), Error> {
+    if payload.photo_urls.len() > MAX_PHOTOS {
+        return Err(Error::validation(format!(
+            "an assessment can have at most {} photos",
+            MAX_PHOTOS
+        )));
+    }
+    payload.photo_urls = payload
+        .photo_urls
+        .iter()
+        .map(|url| crate::links::validate_allowed_url(url).map(|(url, _)| url))
+        .collect::<Result<_, _>>()?;
+    payload.notes = normalize_multiline(&payload.notes);
+    if payload.notes.chars().count() > MAX_NOTES_CHARS {
+        return Err(Error::validation(format!(
+            "notes must be at most {} characters",
+            MAX_NOTES_CHARS
+        )));
+    }
+    validate_region(&mut payload.region)?;
+    let Some(update) = crate::_find_crisis_update(payload.crisis_update_id).await? else {
+        return Err(Error::not_found(format!(
+            "a crisis update with id={} not found",
+            payload.crisis_update_id
+        ))
+        .with_detail("crisis_update_id", payload.crisis_update_id));
+    };
+    if payload.region.is_none() {
+        payload.region = update.region;
+    }
+    Ok(())
+}
+
+fn find_assessment(id: u64) -> Result<DamageAssessment, Error> {
+    ASSESSMENT_STORAGE
+        .with(|s| s.borrow().get(&id))
+        .ok_or_else(|| Error::not_found(format!("a damage assessment with id={} not found", id)).with_detail("id", id))
+}
+
+// Loads an assessment the caller filed, or any assessment for coordinators
+fn find_own_assessment(id: u64) -> Result<DamageAssessment, Error> {
+    let assessment = find_assessment(id)?;
+    let caller = caller();
+    if assessment.assessor != caller && !has_role(&caller, Role::Coordinator) {
+        return Err(Error::unauthorized(format!(
+            "caller did not file the damage assessment with id={}",
+            id
+        )));
+    }
+    Ok(assessment)
+}
+
+fn store_assessment(assessment: &DamageAssessment) -> Result<(), Error> {
+    crate::ensure_storable(assessment, "damage assessment")?;
+    ASSESSMENT_STORAGE.with(|s| s.borrow_mut().insert(assessment.id, assessment.clone()));
+    Ok(())
+}
+
+pub(crate) fn remove_orphaned_assessments(is_live: &dyn Fn(u64) -> bool) -> u64 {
+    let ids: Vec<u64> = ASSESSMENT_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, assessment)| !is_live(assessment.crisis_update_id))
+            .map(|(id, _)| id)
+            .collect()
+    });
+    ASSESSMENT_STORAGE.with(|s| {
+        let mut s = s.borrow_mut();
+        for id in &ids {
+            s.remove(id);
+        }
+    });
+    ids.len() as u64
+}
+
+// 2.74.1 add_damage_assessment Function:
+#[ic_cdk::update(guard = "admit_update")]
+async fn add_damage_assessment(mut payload: DamageAssessmentPayload) -> Result<DamageAssessment, Error> {
+    crate::call_stats::count_call("add_damage_assessment");
+    let assessor = require_any_role(&ASSESSORS)?;
+    validate_payload(&mut payload).await?;
+    let assessment = DamageAssessment {
+        id: crate::ids::next_id(&ASSESSMENT_ID_COUNTER)?,
+        crisis_update_id: payload.crisis_update_id,
+        assessor,
+        structure_type: payload.structure_type,
+        damage_level: payload.damage_level,
+        affected_people: payload.affected_people,
+        photo_urls: payload.photo_urls,
+        notes: payload.notes,
+        region: payload.region,
+        assessed_at: time(),
+        updated_at: None,
+    };
+    store_assessment(&assessment)?;
+    Ok(assessment)
+}
+
+// 2.74.2 update_damage_assessment Function:
+// The assessor who filed it or a coordinator may revise an assessment.
+#[ic_cdk::update(guard = "admit_update")]
+async fn update_damage_assessment(id: u64, mut payload: DamageAssessmentPayload) -> Result<DamageAssessment, Error> {
+    crate::call_stats::count_call("update_damage_assessment");
+    find_own_assessment(id)?;
+    validate_payload(&mut payload).await?;
+    // Loaded again, since it may have changed while the crisis update was fetched
+    let mut assessment = find_own_assessment(id)?;
+    assessment.crisis_update_id = payload.crisis_update_id;
+    assessment.structure_type = payload.structure_type;
+    assessment.damage_level = payload.damage_level;
+    assessment.affected_people = payload.affected_people;
+    assessment.photo_urls = payload.photo_urls;
+    assessment.notes = payload.notes;
+    assessment.region = payload.region;
+    assessment.updated_at = Some(time());
+    store_assessment(&assessment)?;
+    Ok(assessment)
+}
+
+// 2.74.3 delete_damage_assessment Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn delete_damage_assessment(id: u64) -> Result<DamageAssessment, Error> {
+    crate::call_stats::count_call("delete_damage_assessment");
+    let assessment = find_own_assessment(id)?;
+    ASSESSMENT_STORAGE.with(|s| s.borrow_mut().remove(&id));
+    Ok(assessment)
+}
+
+// 2.74.4 get_damage_assessment Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_damage_assessment(id: u64) -> Result<DamageAssessment, Error> {
+    find_assessment(id)
+}
+
+// 2.74.5 list_damage_assessments Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn list_damage_assessments(crisis_update_id: u64) -> Result<QueryResult<DamageAssessment>, Error> {
+    fit(ASSESSMENT_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, assessment)| assessment)
+            .filter(|assessment| assessment.crisis_update_id == crisis_update_id)
+            .collect()
+    }))
+}
+
+// 2.74.6 get_damage_summary Function:
+// Totals per region, optionally for one country (ISO 3166-1 alpha-2), most destroyed first.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_damage_summary(country: Option<String>) -> Result<QueryResult<RegionDamageSummary>, Error> {
+    let country = country.map(|c| c.trim().to_ascii_uppercase());
+    let mut summaries: BTreeMap<Option<String>, RegionDamageSummary> = BTreeMap::new();
+    ASSESSMENT_STORAGE.with(|s| {
+        for (_, assessment) in s.borrow().iter() {
+            if country
+                .as_ref()
+                .is_some_and(|c| assessment.region.as_ref().is_none_or(|r| r.country() != c))
+            {
+                continue;
+            }
+            let label = assessment.region.as_ref().map(Region::label);
+            let summary = summaries.entry(label.clone()).or_insert_with(|| RegionDamageSummary {
+                region: label,
+                ..Default::default()
+            });
+            summary.assessments += 1;
+            summary.affected_people = summary.affected_people.saturating_add(assessment.affected_people);
+            match assessment.damage_level {
+                DamageLevel::Destroyed => summary.destroyed += 1,
+                DamageLevel::Minor | DamageLevel::Major => summary.damaged += 1,
+            }
+        }
+    });
+    let mut summaries: Vec<RegionDamageSummary> = summaries.into_values().collect();
+    summaries.sort_by(|a, b| b.destroyed.cmp(&a.destroyed).then(b.damaged.cmp(&a.damaged)));
+    fit(summaries)
+}
@@ -0,0 +1,108 @@
+// Duplicate detection for resubmitted reports. A hash of the normalized title,
+// description, location, author and day maps to the update it produced, so a
+// flaky client retrying an add gets the original update back.
+use crate::jobs::NANOS_PER_SECOND;
+use crate::{Memory, CRISIS_MEMORY_MANAGER};
+use candid::Principal;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+const DEDUP_TTL_SECS: u64 = 24 * 60 * 60;
+pub(crate) const DEDUP_EXPIRY_INTERVAL_SECS: u64 = 60 * 60;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+type ContentHash = Blob<32>;
+
+thread_local! {
+    // content hash -> (crisis update id, recorded at)
+    static CONTENT_HASHES: RefCell<StableBTreeMap<ContentHash, (u64, u64), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(50)))
+    ));
+}
+
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+pub(crate) fn content_hash(
+    title: &str,
+    description: &str,
+    location: &str,
+    author: &Principal,
+    now: u64,
+) -> ContentHash {
+    let mut hasher = Sha256::new();
+    for part in [normalize(title), normalize(description), normalize(location)] {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part.as_bytes());
+    }
+    hasher.update(author.as_slice());
+    hasher.update((now / NANOS_PER_SECOND / SECONDS_PER_DAY).to_be_bytes());
+    Blob::try_from(hasher.finalize().as_slice()).expect("sha256 digests are 32 bytes")
+}
+
+// The update previously recorded under this hash, if still within the TTL
+pub(crate) fn find_duplicate(hash: &ContentHash, now: u64) -> Option<u64> {
+    CONTENT_HASHES
+        .with(|h| h.borrow().get(hash))
+        .filter(|(_, recorded_at)| now.saturating_sub(*recorded_at) < DEDUP_TTL_SECS * NANOS_PER_SECOND)
+        .map(|(id, _)| id)
+}
+
+pub(crate) fn record_content_hash(hash: ContentHash, crisis_update_id: u64, now: u64) {
+    CONTENT_HASHES.with(|h| h.borrow_mut().insert(hash, (crisis_update_id, now)));
+}
+
+pub(crate) fn expire_content_hashes(now: u64) {
+    let cutoff = now.saturating_sub(DEDUP_TTL_SECS * NANOS_PER_SECOND);
+    let expired: Vec<ContentHash> = CONTENT_HASHES.with(|h| {
+        h.borrow()
+            .iter()
+            .filter(|(_, (_, recorded_at))| *recorded_at < cutoff)
+            .map(|(hash, _)| hash)
+            .collect()
+    });
+    CONTENT_HASHES.with(|h| {
+        let mut h = h.borrow_mut();
+        for hash in &expired {
+            h.remove(hash);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = SECONDS_PER_DAY * NANOS_PER_SECOND;
+
+    #[test]
+    fn hashes_ignore_case_and_spacing() {
+        let author = Principal::from_slice(&[1]);
+        let hash = content_hash("Bridge  collapsed", "River St", "Kisumu", &author, DAY + 5);
+        assert_eq!(hash, content_hash(" bridge collapsed", "river st ", "KISUMU", &author, DAY + 10));
+        assert_ne!(hash, content_hash("Bridge collapsed", "River St", "Kisumu", &Principal::anonymous(), DAY + 5));
+        // A report on the next UTC day is a new report
+        assert_ne!(hash, content_hash("Bridge collapsed", "River St", "Kisumu", &author, 2 * DAY));
+        // Field boundaries count, so text cannot move between fields
+        assert_ne!(
+            content_hash("ab", "c", "d", &author, 0),
+            content_hash("a", "bc", "d", &author, 0)
+        );
+    }
+
+    #[test]
+    fn duplicates_are_found_within_the_ttl() {
+        let hash = content_hash("Flooding", "Water over the road", "Bridge St", &Principal::anonymous(), 0);
+        assert_eq!(find_duplicate(&hash, 0), None);
+        record_content_hash(hash, 7, 100);
+        assert_eq!(find_duplicate(&hash, 100 + DEDUP_TTL_SECS * NANOS_PER_SECOND - 1), Some(7));
+        assert_eq!(find_duplicate(&hash, 100 + DEDUP_TTL_SECS * NANOS_PER_SECOND), None);
+        expire_content_hashes(101 + DEDUP_TTL_SECS * NANOS_PER_SECOND);
+        assert_eq!(find_duplicate(&hash, 100), None);
+    }
+}
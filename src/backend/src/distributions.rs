@@ -0,0 +1,391 @@
+// Scheduled relief distributions: what is handed out, where, when and for how
+// many households, so recipients can register for a slot instead of queueing
+// on the strength of a free-text update. Each caller holds at most one
+// registration per event, and registrations stop once capacity is reached.
+// Verified organisations and coordinators organise events; the organiser or a
+// coordinator may change or cancel one and see who registered.
+use crate::access::{has_role, principal_key, require_any_role, PrincipalKey, Role};
+use crate::access_mode::admit_read;
+use crate::areas::location_key;
+use crate::ids::IdCounter;
+use crate::maintenance::admit_update;
+use crate::normalize::{normalize_line, normalize_multiline};
+use crate::response::{fit, QueryResult};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_TITLE_CHARS: usize = 200;
+const MAX_LOCATION_CHARS: usize = 200;
+const MAX_DESCRIPTION_CHARS: usize = 2000;
+const MAX_CAPACITY: u64 = 1_000_000;
+
+const ORGANIZERS: [Role; 2] = [Role::VerifiedOrg, Role::Coordinator];
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum DistributionStatus {
+    Scheduled,
+    Cancelled,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DistributionEvent {
+    id: u64,
+    // What is handed out, e.g. "Food parcels and water"
+    title: String,
+    description: String,
+    location: String,
+    starts_at: u64,
+    ends_at: u64,
+    // Households that can be served
+    capacity: u64,
+    registered: u64,
+    organizer: Principal,
+    status: DistributionStatus,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+// Implementing Storable and BoundedStorable traits for DistributionEvent
+impl Storable for DistributionEvent {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for DistributionEvent {
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct DistributionEventPayload {
+    title: String,
+    description: String,
+    location: String,
+    starts_at: u64,
+    ends_at: u64,
+    capacity: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DistributionRegistration {
+    event_id: u64,
+    registrant: Principal,
+    registered_at: u64,
+}
+
+// Implementing Storable and BoundedStorable traits for DistributionRegistration
+impl Storable for DistributionRegistration {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for DistributionRegistration {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static EVENT_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(106, "distribution events"));
+
+    static EVENT_STORAGE: RefCell<StableBTreeMap<u64, DistributionEvent, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(107)))
+    ));
+
+    // (event id, registrant)
+    static REGISTRATION_STORAGE: RefCell<StableBTreeMap<(u64, PrincipalKey), DistributionRegistration, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(108)))
+    ));
+}
+
+fn validate_payload(payload: &mut DistributionEventPayload, registered: u64) -> Result<(), Error> {
+    payload.title = normalize_line(&payload.title);
+    if payload.title.is_empty() || payload.title.chars().count() > MAX_TITLE_CHARS {
+        return Err(Error::validation(format!(
+            "titles must be between 1 and {} characters",
+            MAX_TITLE_CHARS
+        )));
+    }
+    payload.location = normalize_line(&payload.location);
+    if payload.location.is_empty() || payload.location.chars().count() > MAX_LOCATION_CHARS {
+        return Err(Error::validation(format!(
+            "locations must be between 1 and {} characters",
+            MAX_LOCATION_CHARS
+        )));
+    }
+    payload.description = normalize_multiline(&payload.description);
+    if payload.description.chars().count() > MAX_DESCRIPTION_CHARS {
+        return Err(Error::validation(format!(
+            "descriptions must be at most {} characters",
+            MAX_DESCRIPTION_CHARS
+        )));
+    }
+    if payload.starts_at >= payload.ends_at {
+        return Err(Error::validation("a distribution must start before it ends"));
+    }
+    if payload.ends_at <= time() {
+        return Err(Error::validation("a distribution must end in the future"));
+    }
+    if payload.capacity == 0 || payload.capacity > MAX_CAPACITY {
+        return Err(Error::validation(format!(
+            "capacity must be between 1 and {}",
+            MAX_CAPACITY
+        )));
+    }
+    if payload.capacity < registered {
+        return Err(Error::conflict(format!(
+            "{} households are already registered; capacity cannot be lower",
+            registered
+        )));
+    }
+    Ok(())
+}
+
+fn find_event(id: u64) -> Result<DistributionEvent, Error> {
+    EVENT_STORAGE
+        .with(|s| s.borrow().get(&id))
+        .ok_or_else(|| Error::not_found(format!("a distribution event with id={} not found", id)).with_detail("id", id))
+}
+
+// Loads an event the caller organises, or any event for coordinators
+fn find_own_event(id: u64) -> Result<DistributionEvent, Error> {
+    let event = find_event(id)?;
+    let caller = caller();
+    if event.organizer != caller && !has_role(&caller, Role::Coordinator) {
+        return Err(Error::unauthorized(format!(
+            "caller does not organise the distribution event with id={}",
+            id
+        )));
+    }
+    Ok(event)
+}
+
+fn store_event(event: &DistributionEvent) -> Result<(), Error> {
+    crate::ensure_storable(event, "distribution event")?;
+    EVENT_STORAGE.with(|s| s.borrow_mut().insert(event.id, event.clone()));
+    Ok(())
+}
+
+fn release_registration(event_id: u64, registrant: PrincipalKey) -> bool {
+    if REGISTRATION_STORAGE
+        .with(|s| s.borrow_mut().remove(&(event_id, registrant)))
+        .is_none()
+    {
+        return false;
+    }
+    EVENT_STORAGE.with(|s| {
+        let mut s = s.borrow_mut();
+        if let Some(mut event) = s.get(&event_id) {
+            event.registered = event.registered.saturating_sub(1);
+            s.insert(event_id, event);
+        }
+    });
+    true
+}
+
+// Drops every registration of the principal and frees their places; returns
+// how many were removed
+pub(crate) fn remove_registrations_of(registrant: &Principal) -> u64 {
+    let key = principal_key(registrant);
+    let event_ids: Vec<u64> = REGISTRATION_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(k, _)| k)
+            .filter(|(_, principal)| *principal == key)
+            .map(|(event_id, _)| event_id)
+            .collect()
+    });
+    event_ids
+        .into_iter()
+        .filter(|event_id| release_registration(*event_id, key))
+        .count() as u64
+}
+
+// 2.77.1 create_distribution_event Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn create_distribution_event(mut payload: DistributionEventPayload) -> Result<DistributionEvent, Error> {
+    crate::call_stats::count_call("create_distribution_event");
+    let organizer = require_any_role(&ORGANIZERS)?;
+    validate_payload(&mut payload, 0)?;
+    let event = DistributionEvent {
+        id: crate::ids::next_id(&EVENT_ID_COUNTER)?,
+        title: payload.title,
+        description: payload.description,
+        location: payload.location,
+        starts_at: payload.starts_at,
+        ends_at: payload.ends_at,
+        capacity: payload.capacity,
+        registered: 0,
+        organizer,
+        status: DistributionStatus::Scheduled,
+        created_at: time(),
+        updated_at: None,
+    };
+    store_event(&event)?;
+    Ok(event)
+}
+
+// 2.77.2 update_distribution_event Function:
+// Capacity cannot drop below the households already registered.
+#[ic_cdk::update(guard = "admit_update")]
+fn update_distribution_event(id: u64, mut payload: DistributionEventPayload) -> Result<DistributionEvent, Error> {
+    crate::call_stats::count_call("update_distribution_event");
+    let mut event = find_own_event(id)?;
+    if event.status == DistributionStatus::Cancelled {
+        return Err(Error::conflict(format!(
+            "distribution event with id={} was cancelled",
+            id
+        )));
+    }
+    validate_payload(&mut payload, event.registered)?;
+    event.title = payload.title;
+    event.description = payload.description;
+    event.location = payload.location;
+    event.starts_at = payload.starts_at;
+    event.ends_at = payload.ends_at;
+    event.capacity = payload.capacity;
+    event.updated_at = Some(time());
+    store_event(&event)?;
+    Ok(event)
+}
+
+// 2.77.3 cancel_distribution_event Function:
+// Registrations are kept so registrants can still see the cancelled event.
+#[ic_cdk::update(guard = "admit_update")]
+fn cancel_distribution_event(id: u64) -> Result<DistributionEvent, Error> {
+    crate::call_stats::count_call("cancel_distribution_event");
+    let mut event = find_own_event(id)?;
+    event.status = DistributionStatus::Cancelled;
+    event.updated_at = Some(time());
+    store_event(&event)?;
+    Ok(event)
+}
+
+// 2.77.4 register_for_distribution Function:
+// One place per caller; fails once the event is full or over.
+#[ic_cdk::update(guard = "admit_update")]
+fn register_for_distribution(event_id: u64) -> Result<DistributionRegistration, Error> {
+    crate::call_stats::count_call("register_for_distribution");
+    let registrant = caller();
+    if registrant == Principal::anonymous() {
+        return Err(Error::unauthorized(
+            "registering for a distribution requires an identity",
+        ));
+    }
+    let mut event = find_event(event_id)?;
+    let now = time();
+    if event.status == DistributionStatus::Cancelled || event.ends_at <= now {
+        return Err(Error::conflict(format!(
+            "distribution event with id={} is not open for registration",
+            event_id
+        )));
+    }
+    let key = (event_id, principal_key(&registrant));
+    if REGISTRATION_STORAGE.with(|s| s.borrow().contains_key(&key)) {
+        return Err(
+            Error::conflict("caller is already registered for this distribution").with_detail("event_id", event_id)
+        );
+    }
+    if event.registered >= event.capacity {
+        return Err(
+            Error::conflict(format!("distribution event with id={} is full", event_id))
+                .with_detail("capacity", event.capacity),
+        );
+    }
+    let registration = DistributionRegistration {
+        event_id,
+        registrant,
+        registered_at: now,
+    };
+    event.registered += 1;
+    store_event(&event)?;
+    REGISTRATION_STORAGE.with(|s| s.borrow_mut().insert(key, registration.clone()));
+    Ok(registration)
+}
+
+// 2.77.5 cancel_distribution_registration Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn cancel_distribution_registration(event_id: u64) -> Result<(), Error> {
+    crate::call_stats::count_call("cancel_distribution_registration");
+    if !release_registration(event_id, principal_key(&caller())) {
+        return Err(
+            Error::not_found("caller is not registered for this distribution").with_detail("event_id", event_id)
+        );
+    }
+    Ok(())
+}
+
+// 2.77.6 get_distribution_event Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_distribution_event(id: u64) -> Result<DistributionEvent, Error> {
+    find_event(id)
+}
+
+// 2.77.7 list_distribution_events Function:
+// Events overlapping [from, to) at the location if given, soonest first; cancelled ones are left out.
+#[ic_cdk::query(guard = "admit_read")]
+fn list_distribution_events(
+    location: Option<String>,
+    from: Option<u64>,
+    to: Option<u64>,
+) -> Result<QueryResult<DistributionEvent>, Error> {
+    let location = location.map(|l| location_key(&l));
+    let mut events: Vec<DistributionEvent> = EVENT_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, event)| event)
+            .filter(|event| event.status == DistributionStatus::Scheduled)
+            .filter(|event| location.is_none_or(|key| location_key(&event.location) == key))
+            .filter(|event| from.is_none_or(|from| event.ends_at > from))
+            .filter(|event| to.is_none_or(|to| event.starts_at < to))
+            .collect()
+    });
+    events.sort_by_key(|event| event.starts_at);
+    fit(events)
+}
+
+// 2.77.8 list_my_distribution_registrations Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn list_my_distribution_registrations() -> Result<QueryResult<DistributionEvent>, Error> {
+    let key = principal_key(&caller());
+    let event_ids: Vec<u64> = REGISTRATION_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(k, _)| k)
+            .filter(|(_, principal)| *principal == key)
+            .map(|(event_id, _)| event_id)
+            .collect()
+    });
+    fit(event_ids.into_iter().filter_map(|id| find_event(id).ok()).collect())
+}
+
+// 2.77.9 list_distribution_registrations Function:
+// For the organiser or a coordinator, in registration order.
+#[ic_cdk::query(guard = "admit_read")]
+fn list_distribution_registrations(event_id: u64) -> Result<QueryResult<DistributionRegistration>, Error> {
+    find_own_event(event_id)?;
+    let mut registrations: Vec<DistributionRegistration> = REGISTRATION_STORAGE.with(|s| {
+        s.borrow()
+            .range((event_id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| *id == event_id)
+            .map(|(_, registration)| registration)
+            .collect()
+    });
+    registrations.sort_by_key(|registration| registration.registered_at);
+    fit(registrations)
+}
@@ -0,0 +1,216 @@
+use crate::access_mode::admit_read;
+use crate::ids::IdCounter;
+use crate::ledger::{self, derive_subaccount, Account};
+use crate::maintenance::admit_update;
+use crate::response::{fit, QueryResult};
+use crate::settings::get_settings;
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, id, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::collections::{BTreeMap, BTreeSet};
+use std::{borrow::Cow, cell::RefCell};
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Donation {
+    pub(crate) id: u64,
+    pub(crate) crisis_id: u64,
+    pub(crate) donor: Principal,
+    // Amount credited to the crisis pool, after the ledger fee
+    pub(crate) amount: u128,
+    pub(crate) block_index: u128,
+    pub(crate) timestamp: u64,
+}
+
+// Implementing Storable and BoundedStorable traits for Donation
+impl Storable for Donation {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Donation {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct CrisisDonationTotal {
+    crisis_id: u64,
+    total_raised: u128,
+    donation_count: u64,
+}
+
+thread_local! {
+    static DONATION_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(26, "donations"));
+
+    pub(crate) static DONATION_STORAGE: RefCell<StableBTreeMap<u64, Donation, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27)))
+    ));
+
+    // Deposits currently being swept, so concurrent notifications don't race
+    static DEPOSITS_IN_FLIGHT: RefCell<BTreeSet<(u64, Principal)>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+// Releases the in-flight marker however the sweep ends
+struct DepositGuard((u64, Principal));
+
+impl DepositGuard {
+    fn acquire(key: (u64, Principal)) -> Result<Self, Error> {
+        if DEPOSITS_IN_FLIGHT.with(|d| d.borrow_mut().insert(key)) {
+            Ok(DepositGuard(key))
+        } else {
+            Err(Error::conflict("a donation for this crisis is already being processed"))
+        }
+    }
+}
+
+impl Drop for DepositGuard {
+    fn drop(&mut self) {
+        DEPOSITS_IN_FLIGHT.with(|d| d.borrow_mut().remove(&self.0));
+    }
+}
+
+pub(crate) fn configured_ledger() -> Result<Principal, Error> {
+    get_settings()
+        .donation_ledger
+        .ok_or_else(|| Error::validation("no donation ledger has been configured"))
+}
+
+fn ensure_crisis_exists(crisis_id: u64) -> Result<(), Error> {
+    if CRISIS_STORAGE.with(|s| s.borrow().contains_key(&crisis_id)) {
+        Ok(())
+    } else {
+        Err(Error::not_found(format!(
+            "a crisis update with id={} not found",
+            crisis_id
+        )))
+    }
+}
+
+fn deposit_subaccount(crisis_id: u64, donor: &Principal) -> Vec<u8> {
+    derive_subaccount("donation-deposit", &[&crisis_id.to_be_bytes(), donor.as_slice()])
+}
+
+// Tokens raised for a crisis are pooled in a dedicated subaccount of this canister
+pub(crate) fn crisis_pool_subaccount(crisis_id: u64) -> Vec<u8> {
+    derive_subaccount("crisis-pool", &[&crisis_id.to_be_bytes()])
+}
+
+pub(crate) fn total_raised(crisis_id: u64) -> u128 {
+    DONATION_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, d)| d.crisis_id == crisis_id)
+            .map(|(_, d)| d.amount)
+            .sum()
+    })
+}
+
+// 2.20.1 get_donation_deposit_account Function:
+// Donors transfer to this account with ICRC-1 and then call `notify_donation`.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_donation_deposit_account(crisis_id: u64) -> Result<Account, Error> {
+    ensure_crisis_exists(crisis_id)?;
+    Ok(Account {
+        owner: id(),
+        subaccount: Some(deposit_subaccount(crisis_id, &caller())),
+    })
+}
+
+// 2.20.2 notify_donation Function:
+// Sweeps the caller's deposit for the crisis into the crisis pool and records it.
+#[ic_cdk::update(guard = "admit_update")]
+async fn notify_donation(crisis_id: u64) -> Result<Donation, Error> {
+    crate::call_stats::count_call("notify_donation");
+    ensure_crisis_exists(crisis_id)?;
+    let ledger = configured_ledger()?;
+    let donor = caller();
+    let _guard = DepositGuard::acquire((crisis_id, donor))?;
+
+    let from_subaccount = deposit_subaccount(crisis_id, &donor);
+    let balance = ledger::balance_of(
+        ledger,
+        Account {
+            owner: id(),
+            subaccount: Some(from_subaccount.clone()),
+        },
+    )
+    .await?;
+    let fee = ledger::fee(ledger).await?;
+    if balance <= fee {
+        return Err(Error::validation(format!(
+            "deposit balance of {} does not cover the ledger fee of {}",
+            balance, fee
+        )));
+    }
+    let amount = balance - fee;
+    let block_index = ledger::transfer(
+        ledger,
+        Some(from_subaccount),
+        Account {
+            owner: id(),
+            subaccount: Some(crisis_pool_subaccount(crisis_id)),
+        },
+        amount,
+        fee,
+    )
+    .await?;
+
+    let id = crate::ids::next_id(&DONATION_ID_COUNTER)?;
+    let donation = Donation {
+        id,
+        crisis_id,
+        donor,
+        amount,
+        block_index,
+        timestamp: time(),
+    };
+    DONATION_STORAGE.with(|s| s.borrow_mut().insert(id, donation.clone()));
+    Ok(donation)
+}
+
+// 2.20.3 get_donations_for_crisis Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_donations_for_crisis(crisis_id: u64) -> Result<QueryResult<Donation>, Error> {
+    fit(DONATION_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, d)| d.crisis_id == crisis_id)
+            .map(|(_, d)| d)
+            .collect()
+    }))
+}
+
+// 2.20.4 get_total_raised Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_total_raised(crisis_id: u64) -> u128 {
+    total_raised(crisis_id)
+}
+
+// 2.20.5 list_donation_totals Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn list_donation_totals() -> Result<QueryResult<CrisisDonationTotal>, Error> {
+    let mut totals: BTreeMap<u64, (u128, u64)> = BTreeMap::new();
+    DONATION_STORAGE.with(|s| {
+        for (_, donation) in s.borrow().iter() {
+            let entry = totals.entry(donation.crisis_id).or_default();
+            entry.0 += donation.amount;
+            entry.1 += 1;
+        }
+    });
+    fit(totals
+        .into_iter()
+        .map(|(crisis_id, (total_raised, donation_count))| CrisisDonationTotal {
+            crisis_id,
+            total_raised,
+            donation_count,
+        })
+        .collect())
+}
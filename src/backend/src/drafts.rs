@@ -0,0 +1,150 @@
+// Unpublished crisis updates, so a long report can be written over several
+// sessions on an unreliable connection. Drafts are kept apart from published
+// updates, only their author can see them, and they are checked like any other
+// submission when published.
+use crate::access::{principal_key, PrincipalKey};
+use crate::access_mode::admit_read;
+use crate::ids::IdCounter;
+use crate::maintenance::admit_update;
+use crate::response::{fit, QueryResult};
+use crate::{CrisisUpdate, CrisisUpdatePayload, Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_DRAFTS_PER_AUTHOR: usize = 20;
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct Draft {
+    id: u64,
+    author: Principal,
+    payload: CrisisUpdatePayload,
+    created_at: u64,
+    updated_at: u64,
+}
+
+// Implementing Storable and BoundedStorable traits for Draft
+impl Storable for Draft {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Draft {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static DRAFT_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(94, "drafts"));
+
+    // (author, draft id)
+    static DRAFT_STORAGE: RefCell<StableBTreeMap<(PrincipalKey, u64), Draft, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(95)))
+    ));
+}
+
+fn drafts_of(author: &Principal) -> Vec<Draft> {
+    let key = principal_key(author);
+    DRAFT_STORAGE.with(|s| {
+        s.borrow()
+            .range((key, 0)..=(key, u64::MAX))
+            .map(|(_, draft)| draft)
+            .collect()
+    })
+}
+
+fn find_own_draft(author: &Principal, id: u64) -> Result<Draft, Error> {
+    DRAFT_STORAGE
+        .with(|s| s.borrow().get(&(principal_key(author), id)))
+        .ok_or_else(|| Error::not_found(format!("a draft with id={} not found", id)).with_detail("id", id))
+}
+
+fn remove_draft(author: &Principal, id: u64) {
+    DRAFT_STORAGE.with(|s| s.borrow_mut().remove(&(principal_key(author), id)));
+}
+
+// Drops every draft of the principal; returns how many were removed
+pub(crate) fn remove_drafts_of(author: &Principal) -> u64 {
+    let drafts = drafts_of(author);
+    for draft in &drafts {
+        remove_draft(author, draft.id);
+    }
+    drafts.len() as u64
+}
+
+// 2.72.1 save_draft Function:
+// Creates a draft, or replaces the content of the caller's draft with the given id.
+// Content is only checked when the draft is published.
+#[ic_cdk::update(guard = "admit_update")]
+fn save_draft(id: Option<u64>, payload: CrisisUpdatePayload) -> Result<Draft, Error> {
+    crate::call_stats::count_call("save_draft");
+    let author = caller();
+    if author == Principal::anonymous() {
+        return Err(Error::unauthorized("anonymous callers cannot keep drafts"));
+    }
+    let now = time();
+    let draft = match id {
+        Some(id) => Draft {
+            payload,
+            updated_at: now,
+            ..find_own_draft(&author, id)?
+        },
+        None => {
+            if drafts_of(&author).len() >= MAX_DRAFTS_PER_AUTHOR {
+                return Err(Error::conflict(format!(
+                    "at most {} drafts can be kept; publish or delete one first",
+                    MAX_DRAFTS_PER_AUTHOR
+                )));
+            }
+            Draft {
+                id: crate::ids::next_id(&DRAFT_ID_COUNTER)?,
+                author,
+                payload,
+                created_at: now,
+                updated_at: now,
+            }
+        }
+    };
+    crate::ensure_storable(&draft, "draft")?;
+    let id = draft.id;
+    DRAFT_STORAGE.with(|s| s.borrow_mut().insert((principal_key(&author), id), draft));
+    find_own_draft(&author, id)
+}
+
+// 2.72.2 list_my_drafts Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn list_my_drafts() -> Result<QueryResult<Draft>, Error> {
+    fit(drafts_of(&caller()))
+}
+
+// 2.72.3 delete_draft Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn delete_draft(id: u64) -> Result<Draft, Error> {
+    crate::call_stats::count_call("delete_draft");
+    let author = caller();
+    let draft = find_own_draft(&author, id)?;
+    remove_draft(&author, id);
+    Ok(draft)
+}
+
+// 2.72.4 publish_draft Function:
+// Submits the draft as a new crisis update and deletes it; a draft that fails
+// validation is kept so it can be corrected. Publishing the same draft twice in
+// a day returns the first update, as for any identical resubmission.
+#[ic_cdk::update(guard = "admit_update")]
+async fn publish_draft(id: u64) -> Result<CrisisUpdate, Error> {
+    crate::call_stats::count_call("publish_draft");
+    let author = caller();
+    let draft = find_own_draft(&author, id)?;
+    let update = crate::add_crisis_update(draft.payload).await?;
+    remove_draft(&author, id);
+    Ok(update)
+}
@@ -0,0 +1,381 @@
+// Automatic priority escalation. Admins configure rules such as "10 confirmations
+// within an hour at the same location -> High" or "mentions casualties -> at least
+// Normal"; rules are evaluated when an update is added and when it is confirmed.
+// Escalation only ever raises a priority, and every escalation is logged with
+// the rule and the reason so coordinators can see why an update moved.
+use crate::access::{require_any_role, require_role, Role};
+use crate::access_mode::admit_read;
+use crate::areas::{location_key, LocationKey};
+use crate::ids::IdCounter;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::logs::{log, LogLevel};
+use crate::maintenance::admit_update;
+use crate::normalize::normalize_line;
+use crate::{CrisisPriority, CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_RULES: u64 = 50;
+const MAX_RULE_NAME_CHARS: usize = 100;
+const MAX_KEYWORD_CHARS: usize = 50;
+const MAX_WINDOW_SECS: u64 = 7 * 24 * 60 * 60;
+const MAX_LOG_ENTRIES: u64 = 10_000;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq)]
+pub(crate) enum EscalationCondition {
+    // The title or description contains the keyword, ignoring case
+    Keyword(String),
+    // At least `min_count` confirmations of updates at the update's location
+    // within the last `window_secs`
+    Confirmations { min_count: u64, window_secs: u64 },
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct EscalationRulePayload {
+    name: String,
+    condition: EscalationCondition,
+    // The priority a matching update is raised to, if it is below it
+    escalate_to: CrisisPriority,
+    enabled: bool,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct EscalationRule {
+    id: u64,
+    name: String,
+    condition: EscalationCondition,
+    escalate_to: CrisisPriority,
+    enabled: bool,
+    created_by: Principal,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct EscalationLogEntry {
+    id: u64,
+    pub(crate) crisis_update_id: u64,
+    rule_id: u64,
+    pub(crate) rule_name: String,
+    pub(crate) from: CrisisPriority,
+    pub(crate) to: CrisisPriority,
+    pub(crate) reason: String,
+    pub(crate) at: u64,
+}
+
+// Implementing Storable and BoundedStorable traits for EscalationRule
+impl Storable for EscalationRule {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for EscalationRule {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Implementing Storable and BoundedStorable traits for EscalationLogEntry
+impl Storable for EscalationLogEntry {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for EscalationLogEntry {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+type ConfirmationEventKey = (LocationKey, (u64, u64));
+
+thread_local! {
+    static RULE_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(65, "escalation rules"));
+
+    static ESCALATION_RULES: RefCell<StableBTreeMap<u64, EscalationRule, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(66)))
+    ));
+
+    // (location, (confirmed at, crisis update id)) -> confirmations at that instant
+    static CONFIRMATION_EVENTS: RefCell<StableBTreeMap<ConfirmationEventKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(67)))
+    ));
+
+    static LOG_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(68, "escalation log entries"));
+
+    static ESCALATION_LOG: RefCell<StableBTreeMap<u64, EscalationLogEntry, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(69)))
+    ));
+}
+
+fn validate_rule(payload: &mut EscalationRulePayload) -> Result<(), Error> {
+    payload.name = normalize_line(&payload.name);
+    if payload.name.is_empty() || payload.name.chars().count() > MAX_RULE_NAME_CHARS {
+        return Err(Error::validation(format!(
+            "rule names must be between 1 and {} characters",
+            MAX_RULE_NAME_CHARS
+        )));
+    }
+    match &mut payload.condition {
+        EscalationCondition::Keyword(keyword) => {
+            *keyword = normalize_line(keyword).to_lowercase();
+            if keyword.is_empty() || keyword.chars().count() > MAX_KEYWORD_CHARS {
+                return Err(Error::validation(format!(
+                    "keywords must be between 1 and {} characters",
+                    MAX_KEYWORD_CHARS
+                )));
+            }
+        }
+        EscalationCondition::Confirmations { min_count, window_secs } => {
+            if *min_count == 0 {
+                return Err(Error::validation("min_count must be at least 1"));
+            }
+            if *window_secs == 0 || *window_secs > MAX_WINDOW_SECS {
+                return Err(
+                    Error::validation(format!("window_secs must be between 1 and {}", MAX_WINDOW_SECS))
+                        .with_detail("window_secs", *window_secs),
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn find_rule(id: u64) -> Result<EscalationRule, Error> {
+    ESCALATION_RULES.with(|s| s.borrow().get(&id)).ok_or_else(|| {
+        Error::not_found(format!("couldn't find an escalation rule with id={}", id)).with_detail("id", id)
+    })
+}
+
+fn longest_window_secs() -> u64 {
+    ESCALATION_RULES.with(|s| {
+        s.borrow()
+            .iter()
+            .filter_map(|(_, rule)| match rule.condition {
+                EscalationCondition::Confirmations { window_secs, .. } => Some(window_secs),
+                EscalationCondition::Keyword(_) => None,
+            })
+            .max()
+            .unwrap_or(0)
+    })
+}
+
+fn confirmations_since(location: LocationKey, since: u64) -> u64 {
+    CONFIRMATION_EVENTS.with(|s| {
+        s.borrow()
+            .range((location, (since, 0))..=(location, (u64::MAX, u64::MAX)))
+            .map(|(_, count)| count)
+            .sum()
+    })
+}
+
+// Drops confirmation events older than any rule looks back
+fn prune_confirmation_events(now: u64) {
+    let cutoff = now.saturating_sub(longest_window_secs().saturating_mul(NANOS_PER_SECOND));
+    let old: Vec<ConfirmationEventKey> = CONFIRMATION_EVENTS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|(_, (at, _))| *at < cutoff)
+            .collect()
+    });
+    CONFIRMATION_EVENTS.with(|s| {
+        let mut s = s.borrow_mut();
+        for key in &old {
+            s.remove(key);
+        }
+    });
+}
+
+// Why the rule matches the update, or None if it does not
+fn rule_reason(rule: &EscalationRule, update: &CrisisUpdate, now: u64) -> Option<String> {
+    match &rule.condition {
+        EscalationCondition::Keyword(keyword) => {
+            let text = format!("{} {}", update.title, update.description).to_lowercase();
+            text.contains(keyword.as_str())
+                .then(|| format!("the report mentions \"{}\"", keyword))
+        }
+        EscalationCondition::Confirmations { min_count, window_secs } => {
+            let since = now.saturating_sub(window_secs.saturating_mul(NANOS_PER_SECOND));
+            let count = confirmations_since(location_key(&update.location), since);
+            (count >= *min_count).then(|| {
+                format!(
+                    "{} confirmations at {} within the last {}s (rule needs {})",
+                    count, update.location, window_secs, min_count
+                )
+            })
+        }
+    }
+}
+
+fn log_escalation(entry: EscalationLogEntry) {
+    ESCALATION_LOG.with(|s| {
+        let mut s = s.borrow_mut();
+        s.insert(entry.id, entry);
+        while s.len() > MAX_LOG_ENTRIES {
+            match s.iter().next() {
+                Some((oldest, _)) => s.remove(&oldest),
+                None => break,
+            };
+        }
+    });
+}
+
+// Counts a confirmation towards the confirmation rules of the update's location
+pub(crate) fn record_confirmation(update: &CrisisUpdate, now: u64) {
+    let key = (location_key(&update.location), (now, update.id));
+    CONFIRMATION_EVENTS.with(|s| {
+        let mut s = s.borrow_mut();
+        let count = s.get(&key).unwrap_or(0);
+        s.insert(key, count + 1);
+    });
+    prune_confirmation_events(now);
+}
+
+async fn try_escalate(update: &CrisisUpdate, now: u64) -> Result<Option<CrisisUpdate>, Error> {
+    let matched = ESCALATION_RULES.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, rule)| rule)
+            .filter(|rule| rule.enabled && rule.escalate_to as u8 > update.priority as u8)
+            .filter_map(|rule| rule_reason(&rule, update, now).map(|reason| (rule, reason)))
+            .max_by_key(|(rule, _)| rule.escalate_to as u8)
+    });
+    let Some((rule, reason)) = matched else {
+        return Ok(None);
+    };
+    let from = update.priority;
+    let escalated = crate::priority::store_priority(update.clone(), rule.escalate_to, ic_cdk::id()).await?;
+    log_escalation(EscalationLogEntry {
+        id: crate::ids::next_id(&LOG_ID_COUNTER)?,
+        crisis_update_id: escalated.id,
+        rule_id: rule.id,
+        rule_name: rule.name,
+        from,
+        to: rule.escalate_to,
+        reason,
+        at: now,
+    });
+    Ok(Some(escalated))
+}
+
+// Raises the update to the highest priority any enabled rule asks for and returns
+// it as stored afterwards. The update is already saved, so a failed escalation is
+// logged rather than failing the caller.
+pub(crate) async fn evaluate_escalation(update: CrisisUpdate) -> CrisisUpdate {
+    match try_escalate(&update, time()).await {
+        Ok(Some(escalated)) => escalated,
+        Ok(None) => update,
+        Err(err) => {
+            log(
+                LogLevel::Error,
+                Some("escalation"),
+                &format!("escalating crisis update {} failed: {:?}", update.id, err),
+            );
+            update
+        }
+    }
+}
+
+// Every logged escalation of the update, oldest first
+pub(crate) fn escalations_of(crisis_update_id: u64) -> Vec<EscalationLogEntry> {
+    ESCALATION_LOG.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, entry)| entry)
+            .filter(|entry| entry.crisis_update_id == crisis_update_id)
+            .collect()
+    })
+}
+
+// Escalations logged within [from, to)
+pub(crate) fn escalations_between(from: u64, to: u64) -> u64 {
+    ESCALATION_LOG.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, entry)| (from..to).contains(&entry.at))
+            .count() as u64
+    })
+}
+
+// 2.53.1 add_escalation_rule Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn add_escalation_rule(mut payload: EscalationRulePayload) -> Result<EscalationRule, Error> {
+    crate::call_stats::count_call("add_escalation_rule");
+    let admin = require_role(Role::Admin)?;
+    validate_rule(&mut payload)?;
+    if ESCALATION_RULES.with(|s| s.borrow().len()) >= MAX_RULES {
+        return Err(Error::conflict(format!(
+            "at most {} escalation rules can be configured",
+            MAX_RULES
+        )));
+    }
+    let rule = EscalationRule {
+        id: crate::ids::next_id(&RULE_ID_COUNTER)?,
+        name: payload.name,
+        condition: payload.condition,
+        escalate_to: payload.escalate_to,
+        enabled: payload.enabled,
+        created_by: admin,
+        created_at: time(),
+        updated_at: None,
+    };
+    ESCALATION_RULES.with(|s| s.borrow_mut().insert(rule.id, rule.clone()));
+    Ok(rule)
+}
+
+// 2.53.2 update_escalation_rule Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn update_escalation_rule(id: u64, mut payload: EscalationRulePayload) -> Result<EscalationRule, Error> {
+    crate::call_stats::count_call("update_escalation_rule");
+    require_role(Role::Admin)?;
+    validate_rule(&mut payload)?;
+    let mut rule = find_rule(id)?;
+    rule.name = payload.name;
+    rule.condition = payload.condition;
+    rule.escalate_to = payload.escalate_to;
+    rule.enabled = payload.enabled;
+    rule.updated_at = Some(time());
+    ESCALATION_RULES.with(|s| s.borrow_mut().insert(id, rule.clone()));
+    Ok(rule)
+}
+
+// 2.53.3 delete_escalation_rule Function:
+// Past log entries keep the rule's name.
+#[ic_cdk::update(guard = "admit_update")]
+fn delete_escalation_rule(id: u64) -> Result<EscalationRule, Error> {
+    crate::call_stats::count_call("delete_escalation_rule");
+    require_role(Role::Admin)?;
+    let rule = find_rule(id)?;
+    ESCALATION_RULES.with(|s| s.borrow_mut().remove(&id));
+    Ok(rule)
+}
+
+// 2.53.4 list_escalation_rules Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn list_escalation_rules() -> Result<Vec<EscalationRule>, Error> {
+    require_any_role(&[Role::Admin, Role::Coordinator])?;
+    Ok(ESCALATION_RULES.with(|s| s.borrow().iter().map(|(_, rule)| rule).collect()))
+}
+
+// 2.53.5 get_escalation_log Function:
+// Every automatic escalation of the update, oldest first.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_escalation_log(crisis_update_id: u64) -> Result<Vec<EscalationLogEntry>, Error> {
+    require_any_role(&[Role::Admin, Role::Coordinator])?;
+    Ok(escalations_of(crisis_update_id))
+}
@@ -0,0 +1,293 @@
+use crate::access::{has_role, require_role, Role};
+use crate::access_mode::admit_read;
+use crate::geo::Coordinates;
+use crate::ids::IdCounter;
+use crate::maintenance::admit_update;
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_WAYPOINTS: usize = 100;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub(crate) enum EvacuationStatus {
+    #[default]
+    Open,
+    Blocked,
+    Closed,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct EvacuationRoute {
+    id: u64,
+    crisis_id: u64,
+    name: String,
+    // Ordered from the start of the route towards safety
+    waypoints: Vec<Coordinates>,
+    status: EvacuationStatus,
+    notes: String,
+    published_by: Principal,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+// Implementing Storable and BoundedStorable traits for EvacuationRoute
+impl Storable for EvacuationRoute {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for EvacuationRoute {
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct EvacuationRoutePayload {
+    crisis_id: u64,
+    name: String,
+    waypoints: Vec<Coordinates>,
+    notes: String,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AssemblyPoint {
+    id: u64,
+    crisis_id: u64,
+    name: String,
+    coordinates: Coordinates,
+    capacity: u64,
+    status: EvacuationStatus,
+    published_by: Principal,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+// Implementing Storable and BoundedStorable traits for AssemblyPoint
+impl Storable for AssemblyPoint {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for AssemblyPoint {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct AssemblyPointPayload {
+    crisis_id: u64,
+    name: String,
+    coordinates: Coordinates,
+    capacity: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct NearbyEvacuationInfo {
+    routes: Vec<EvacuationRoute>,
+    assembly_points: Vec<AssemblyPoint>,
+}
+
+thread_local! {
+    static ROUTE_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(21, "evacuation routes"));
+
+    static ROUTE_STORAGE: RefCell<StableBTreeMap<u64, EvacuationRoute, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22)))
+    ));
+
+    static ASSEMBLY_POINT_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(23, "assembly points"));
+
+    static ASSEMBLY_POINT_STORAGE: RefCell<StableBTreeMap<u64, AssemblyPoint, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24)))
+    ));
+}
+
+fn ensure_crisis_exists(crisis_id: u64) -> Result<(), Error> {
+    if CRISIS_STORAGE.with(|s| s.borrow().contains_key(&crisis_id)) {
+        Ok(())
+    } else {
+        Err(Error::not_found(format!(
+            "a crisis update with id={} not found",
+            crisis_id
+        )))
+    }
+}
+
+// Verified orgs may only change what they published; admins may change anything
+fn ensure_publisher(published_by: &Principal) -> Result<(), Error> {
+    let caller = require_role(Role::VerifiedOrg)?;
+    if *published_by != caller && !has_role(&caller, Role::Admin) {
+        return Err(Error::unauthorized(
+            "only the publishing organization can change this record",
+        ));
+    }
+    Ok(())
+}
+
+// 2.18.1 get_evacuation_route Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_evacuation_route(id: u64) -> Result<EvacuationRoute, Error> {
+    match ROUTE_STORAGE.with(|s| s.borrow().get(&id)) {
+        Some(route) => Ok(route),
+        None => Err(Error::not_found(format!(
+            "an evacuation route with id={} not found",
+            id
+        ))),
+    }
+}
+
+// 2.18.2 publish_evacuation_route Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn publish_evacuation_route(payload: EvacuationRoutePayload) -> Result<EvacuationRoute, Error> {
+    crate::call_stats::count_call("publish_evacuation_route");
+    let publisher = require_role(Role::VerifiedOrg)?;
+    ensure_crisis_exists(payload.crisis_id)?;
+    if payload.waypoints.len() < 2 || payload.waypoints.len() > MAX_WAYPOINTS {
+        return Err(Error::validation(format!(
+            "a route needs between 2 and {} waypoints",
+            MAX_WAYPOINTS
+        )));
+    }
+    for waypoint in &payload.waypoints {
+        waypoint.validate()?;
+    }
+    let id = crate::ids::next_id(&ROUTE_ID_COUNTER)?;
+    let route = EvacuationRoute {
+        id,
+        crisis_id: payload.crisis_id,
+        name: payload.name,
+        waypoints: payload.waypoints,
+        status: EvacuationStatus::Open,
+        notes: payload.notes,
+        published_by: publisher,
+        created_at: time(),
+        updated_at: None,
+    };
+    ROUTE_STORAGE.with(|s| s.borrow_mut().insert(id, route.clone()));
+    Ok(route)
+}
+
+// 2.18.3 set_evacuation_route_status Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn set_evacuation_route_status(id: u64, status: EvacuationStatus) -> Result<EvacuationRoute, Error> {
+    crate::call_stats::count_call("set_evacuation_route_status");
+    let mut route = get_evacuation_route(id)?;
+    ensure_publisher(&route.published_by)?;
+    route.status = status;
+    route.updated_at = Some(time());
+    ROUTE_STORAGE.with(|s| s.borrow_mut().insert(id, route.clone()));
+    Ok(route)
+}
+
+// 2.18.4 get_assembly_point Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_assembly_point(id: u64) -> Result<AssemblyPoint, Error> {
+    match ASSEMBLY_POINT_STORAGE.with(|s| s.borrow().get(&id)) {
+        Some(point) => Ok(point),
+        None => Err(Error::not_found(format!("an assembly point with id={} not found", id))),
+    }
+}
+
+// 2.18.5 publish_assembly_point Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn publish_assembly_point(payload: AssemblyPointPayload) -> Result<AssemblyPoint, Error> {
+    crate::call_stats::count_call("publish_assembly_point");
+    let publisher = require_role(Role::VerifiedOrg)?;
+    ensure_crisis_exists(payload.crisis_id)?;
+    payload.coordinates.validate()?;
+    let id = crate::ids::next_id(&ASSEMBLY_POINT_ID_COUNTER)?;
+    let point = AssemblyPoint {
+        id,
+        crisis_id: payload.crisis_id,
+        name: payload.name,
+        coordinates: payload.coordinates,
+        capacity: payload.capacity,
+        status: EvacuationStatus::Open,
+        published_by: publisher,
+        created_at: time(),
+        updated_at: None,
+    };
+    ASSEMBLY_POINT_STORAGE.with(|s| s.borrow_mut().insert(id, point.clone()));
+    Ok(point)
+}
+
+// 2.18.6 set_assembly_point_status Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn set_assembly_point_status(id: u64, status: EvacuationStatus) -> Result<AssemblyPoint, Error> {
+    crate::call_stats::count_call("set_assembly_point_status");
+    let mut point = get_assembly_point(id)?;
+    ensure_publisher(&point.published_by)?;
+    point.status = status;
+    point.updated_at = Some(time());
+    ASSEMBLY_POINT_STORAGE.with(|s| s.borrow_mut().insert(id, point.clone()));
+    Ok(point)
+}
+
+// 2.18.7 list_evacuation_info_for_crisis Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn list_evacuation_info_for_crisis(crisis_id: u64) -> NearbyEvacuationInfo {
+    NearbyEvacuationInfo {
+        routes: ROUTE_STORAGE.with(|s| {
+            s.borrow()
+                .iter()
+                .filter(|(_, route)| route.crisis_id == crisis_id)
+                .map(|(_, route)| route)
+                .collect()
+        }),
+        assembly_points: ASSEMBLY_POINT_STORAGE.with(|s| {
+            s.borrow()
+                .iter()
+                .filter(|(_, point)| point.crisis_id == crisis_id)
+                .map(|(_, point)| point)
+                .collect()
+        }),
+    }
+}
+
+// 2.18.8 get_active_evacuation_routes_near Function:
+// A route is near when any of its waypoints lies within the radius.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_active_evacuation_routes_near(
+    latitude: f64,
+    longitude: f64,
+    radius_km: f64,
+) -> Result<NearbyEvacuationInfo, Error> {
+    let origin = Coordinates { latitude, longitude };
+    origin.validate()?;
+    Ok(NearbyEvacuationInfo {
+        routes: ROUTE_STORAGE.with(|s| {
+            s.borrow()
+                .iter()
+                .filter(|(_, route)| {
+                    route.status == EvacuationStatus::Open
+                        && route.waypoints.iter().any(|w| origin.distance_km(w) <= radius_km)
+                })
+                .map(|(_, route)| route)
+                .collect()
+        }),
+        assembly_points: ASSEMBLY_POINT_STORAGE.with(|s| {
+            s.borrow()
+                .iter()
+                .filter(|(_, point)| {
+                    point.status == EvacuationStatus::Open && origin.distance_km(&point.coordinates) <= radius_km
+                })
+                .map(|(_, point)| point)
+                .collect()
+        }),
+    })
+}
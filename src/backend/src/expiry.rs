@@ -0,0 +1,64 @@
+// Time-bound advisories such as "road closed until 18:00". An update may carry
+// an expiry time; once it passes, the update drops out of the default listings
+// and shows up in `list_expired_updates` until someone resolves it. Admins can
+// let the heartbeat resolve expired updates automatically instead.
+use crate::access::{require_any_role, require_role, Role};
+use crate::access_mode::admit_read;
+use crate::maintenance::admit_update;
+use crate::response::QueryResult;
+use crate::settings::{get_settings, update_settings, Settings};
+use crate::{CrisisStatus, CrisisUpdate, Error, CRISIS_STORAGE};
+use ic_cdk::api::time;
+
+pub(crate) const EXPIRY_CHECK_INTERVAL_SECS: u64 = 5 * 60;
+// Keeps a run within the heartbeat's instruction limit; the rest wait for the next run
+const MAX_RESOLVED_PER_RUN: usize = 200;
+
+pub(crate) fn validate_expires_at(expires_at: Option<u64>, now: u64) -> Result<(), Error> {
+    match expires_at {
+        Some(t) if t <= now => Err(Error::validation("expires_at must be in the future").with_detail("expires_at", t)),
+        _ => Ok(()),
+    }
+}
+
+// Open updates whose expiry has passed, soonest expired first
+fn expired_active_updates(now: u64) -> Vec<CrisisUpdate> {
+    let mut updates: Vec<CrisisUpdate> = CRISIS_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, update)| update)
+            .filter(|update| update.status.is_open() && update.is_expired(now))
+            .collect()
+    });
+    updates.sort_by_key(|update| (update.expires_at, update.id));
+    updates
+}
+
+// Resolves expired updates when enabled; returns how many were resolved
+pub(crate) fn resolve_expired_updates(now: u64) -> Result<u64, Error> {
+    if get_settings().auto_resolve_expired != Some(true) {
+        return Ok(0);
+    }
+    let mut resolved = 0;
+    for update in expired_active_updates(now).into_iter().take(MAX_RESOLVED_PER_RUN) {
+        crate::store_status(update, CrisisStatus::Resolved, ic_cdk::api::id())?;
+        resolved += 1;
+    }
+    Ok(resolved)
+}
+
+// 2.71.1 list_expired_updates Function:
+// Open updates past their expiry, soonest expired first.
+#[ic_cdk::query(guard = "admit_read")]
+fn list_expired_updates() -> Result<QueryResult<CrisisUpdate>, Error> {
+    require_any_role(&[Role::Coordinator, Role::Moderator])?;
+    crate::fit_updates(expired_active_updates(time()))
+}
+
+// 2.71.2 set_auto_resolve_expired Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn set_auto_resolve_expired(enabled: bool) -> Result<Settings, Error> {
+    crate::call_stats::count_call("set_auto_resolve_expired");
+    require_role(Role::Admin)?;
+    update_settings(|s| s.auto_resolve_expired = Some(enabled))
+}
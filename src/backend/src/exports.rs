@@ -0,0 +1,290 @@
+// Chunked export protocol shared by every export format. An export is rendered
+// once into a heap session and fetched chunk by chunk, so no single reply gets
+// near the message size limit. Sessions expire and are swept by the heartbeat.
+use crate::access::{require_any_role, Role};
+use crate::access_mode::admit_read;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::maintenance::admit_update;
+use crate::{CrisisStatus, CrisisUpdate, Error, TimestampField, CRISIS_STORAGE};
+use candid::{Encode, Principal};
+use ic_cdk::api::time;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+const EXPORT_CHUNK_SIZE: usize = 1024 * 1024;
+const EXPORT_TTL_SECS: u64 = 30 * 60;
+pub(crate) const EXPORT_EXPIRY_INTERVAL_SECS: u64 = 60;
+const MAX_OPEN_EXPORTS_PER_CALLER: usize = 5;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum ExportKind {
+    // Candid-encoded `vec CrisisUpdate`, suitable for restoring
+    Backup,
+    Csv,
+    // FeatureCollection of the filtered crisis updates that have coordinates
+    GeoJson,
+    // FeatureCollection of the shelters; only the location filter applies
+    ShelterGeoJson,
+    // JSON document of the caller's own records, from `export_my_data`
+    PersonalData,
+    // JSON after-action report, from `generate_after_action_report`
+    AfterActionReport,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct ExportFilter {
+    location: Option<String>,
+    status: Option<CrisisStatus>,
+    // The timestamp the range below applies to; creation time by default
+    timestamp_field: Option<TimestampField>,
+    start_timestamp: Option<u64>,
+    end_timestamp: Option<u64>,
+}
+
+impl ExportFilter {
+    fn matches(&self, update: &CrisisUpdate) -> bool {
+        self.location
+            .as_ref()
+            .is_none_or(|l| update.location.contains(l.as_str()))
+            && self.status.is_none_or(|s| update.status == s)
+            && self.in_range(update.timestamp_of(self.timestamp_field.unwrap_or_default()))
+    }
+
+    fn in_range(&self, timestamp: Option<u64>) -> bool {
+        if self.start_timestamp.is_none() && self.end_timestamp.is_none() {
+            return true;
+        }
+        timestamp.is_some_and(|t| {
+            self.start_timestamp.is_none_or(|start| t >= start) && self.end_timestamp.is_none_or(|end| t <= end)
+        })
+    }
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ExportInfo {
+    export_id: u64,
+    kind: ExportKind,
+    total_chunks: u64,
+    total_bytes: u64,
+    expires_at: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ExportChunk {
+    export_id: u64,
+    index: u64,
+    total_chunks: u64,
+    data: Vec<u8>,
+}
+
+struct ExportSession {
+    owner: Principal,
+    kind: ExportKind,
+    data: Vec<u8>,
+    expires_at: u64,
+}
+
+impl ExportSession {
+    fn total_chunks(&self) -> u64 {
+        self.data.len().div_ceil(EXPORT_CHUNK_SIZE).max(1) as u64
+    }
+
+    fn info(&self, export_id: u64) -> ExportInfo {
+        ExportInfo {
+            export_id,
+            kind: self.kind,
+            total_chunks: self.total_chunks(),
+            total_bytes: self.data.len() as u64,
+            expires_at: self.expires_at,
+        }
+    }
+}
+
+thread_local! {
+    static NEXT_EXPORT_ID: RefCell<u64> = const { RefCell::new(0) };
+    static EXPORT_SESSIONS: RefCell<BTreeMap<u64, ExportSession>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+fn optional_field(value: Option<u64>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn filtered_crisis_updates(filter: &ExportFilter) -> Result<Vec<CrisisUpdate>, Error> {
+    let updates: Vec<CrisisUpdate> = CRISIS_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, update)| update)
+            .filter(|update| filter.matches(update))
+            .filter(crate::moderation::visible_to_caller)
+            .map(crate::redaction::redact_update_for_caller)
+            .collect()
+    });
+    crate::query_limits::check_export_rows(updates.len())?;
+    Ok(updates)
+}
+
+// GeoJSON point feature for an update, or None when it has no coordinates
+fn update_geojson_feature(update: &CrisisUpdate) -> Option<serde_json::Value> {
+    let coordinates = update.coordinates.as_ref()?;
+    Some(serde_json::json!({
+        "type": "Feature",
+        "geometry": {
+            "type": "Point",
+            "coordinates": [coordinates.longitude, coordinates.latitude],
+        },
+        "properties": {
+            "id": update.id,
+            "title": update.title,
+            "location": update.location,
+            "status": format!("{:?}", update.status),
+            "created_at": update.created_at,
+            "occurred_at": update.occurred_at,
+        },
+    }))
+}
+
+fn feature_collection(features: Vec<serde_json::Value>) -> Vec<u8> {
+    serde_json::json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+    .to_string()
+    .into_bytes()
+}
+
+fn render_export(kind: ExportKind, filter: &ExportFilter) -> Result<Vec<u8>, Error> {
+    let data = match kind {
+        ExportKind::Backup => Encode!(&filtered_crisis_updates(filter)?)
+            .map_err(|err| Error::internal(format!("cannot encode crisis updates: {}", err)))?,
+        ExportKind::Csv => {
+            let mut csv = String::from("id,title,description,location,created_at,updated_at,occurred_at,status\n");
+            for update in filtered_crisis_updates(filter)? {
+                csv.push_str(&format!(
+                    "{},{},{},{},{},{},{},{:?}\n",
+                    update.id,
+                    csv_field(&update.title),
+                    csv_field(&update.description),
+                    csv_field(&update.location),
+                    update.created_at,
+                    optional_field(update.updated_at),
+                    optional_field(update.occurred_at),
+                    update.status
+                ));
+            }
+            csv.into_bytes()
+        }
+        ExportKind::GeoJson => {
+            let features: Vec<serde_json::Value> = filtered_crisis_updates(filter)?
+                .iter()
+                .filter_map(update_geojson_feature)
+                .collect();
+            feature_collection(features)
+        }
+        ExportKind::ShelterGeoJson => {
+            feature_collection(crate::shelters::shelter_geojson_features(filter.location.as_deref()))
+        }
+        ExportKind::PersonalData => {
+            return Err(Error::validation("personal data exports are started with export_my_data"));
+        }
+        ExportKind::AfterActionReport => {
+            return Err(Error::validation(
+                "after-action reports are started with generate_after_action_report",
+            ));
+        }
+    };
+    Ok(data)
+}
+
+// Drops every session past its expiry
+pub(crate) fn expire_export_sessions(now: u64) {
+    EXPORT_SESSIONS.with(|sessions| sessions.borrow_mut().retain(|_, session| session.expires_at > now));
+}
+
+// Opens a session over already rendered bytes, readable only by `owner`
+pub(crate) fn start_export_session(owner: Principal, kind: ExportKind, data: Vec<u8>) -> Result<ExportInfo, Error> {
+    let now = time();
+    expire_export_sessions(now);
+    let open = EXPORT_SESSIONS.with(|sessions| sessions.borrow().values().filter(|s| s.owner == owner).count());
+    if open >= MAX_OPEN_EXPORTS_PER_CALLER {
+        return Err(Error::validation(format!(
+            "at most {} exports can be open at once",
+            MAX_OPEN_EXPORTS_PER_CALLER
+        )));
+    }
+    let export_id = NEXT_EXPORT_ID.with(|next| {
+        let id = *next.borrow();
+        *next.borrow_mut() = id + 1;
+        id
+    });
+    let session = ExportSession {
+        owner,
+        kind,
+        data,
+        expires_at: now + EXPORT_TTL_SECS * NANOS_PER_SECOND,
+    };
+    let info = session.info(export_id);
+    EXPORT_SESSIONS.with(|sessions| sessions.borrow_mut().insert(export_id, session));
+    Ok(info)
+}
+
+// 2.25.1 start_export Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn start_export(kind: ExportKind, filter: ExportFilter) -> Result<ExportInfo, Error> {
+    crate::call_stats::count_call("start_export");
+    let caller = require_any_role(&[Role::Coordinator, Role::Moderator])?;
+    start_export_session(caller, kind, render_export(kind, &filter)?)
+}
+
+// 2.25.2 get_export_chunk Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_export_chunk(export_id: u64, index: u64) -> Result<ExportChunk, Error> {
+    let caller = ic_cdk::caller();
+    EXPORT_SESSIONS.with(|sessions| {
+        let sessions = sessions.borrow();
+        let session = sessions
+            .get(&export_id)
+            .filter(|s| s.owner == caller && s.expires_at > time())
+            .ok_or_else(|| Error::not_found(format!("an export with id={} not found", export_id)))?;
+        let total_chunks = session.total_chunks();
+        if index >= total_chunks {
+            return Err(Error::validation(format!(
+                "chunk index {} is out of range, the export has {} chunks",
+                index, total_chunks
+            )));
+        }
+        let start = index as usize * EXPORT_CHUNK_SIZE;
+        let end = (start + EXPORT_CHUNK_SIZE).min(session.data.len());
+        Ok(ExportChunk {
+            export_id,
+            index,
+            total_chunks,
+            data: session.data[start..end].to_vec(),
+        })
+    })
+}
+
+// 2.25.3 finish_export Function:
+// Releases a session early once every chunk has been fetched.
+#[ic_cdk::update(guard = "admit_update")]
+fn finish_export(export_id: u64) -> Result<(), Error> {
+    crate::call_stats::count_call("finish_export");
+    let caller = ic_cdk::caller();
+    EXPORT_SESSIONS.with(|sessions| {
+        let mut sessions = sessions.borrow_mut();
+        match sessions.get(&export_id) {
+            Some(session) if session.owner == caller => {
+                sessions.remove(&export_id);
+                Ok(())
+            }
+            _ => Err(Error::not_found(format!("an export with id={} not found", export_id))),
+        }
+    })
+}
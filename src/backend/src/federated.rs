@@ -0,0 +1,161 @@
+// Composite queries that fan out to shard and archive canisters and merge their
+// answers with local storage, so clients keep this canister as their single
+// entry point once data is split. `get_crisis_update` already follows an id to
+// its shard or archive; these cover search and statistics.
+//
+// Shard and archive canisters are expected to expose:
+//   filter_crisis_updates_local : (CrisisUpdateFilter) -> (QueryResult<CrisisUpdate>) query;
+//   crisis_update_stats_local : () -> (CrisisUpdateStats) query;
+use crate::access_mode::admit_read;
+use crate::response::{truncate_to_fit, QueryResult};
+use crate::settings::get_settings;
+use crate::{CrisisPriority, CrisisStatus, CrisisUpdate, CrisisUpdateFilter, Error, CRISIS_STORAGE};
+use candid::Principal;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct CrisisUpdateStats {
+    total: u64,
+    active: u64,
+    resolved: u64,
+    archived: u64,
+    // One entry per priority, lowest first
+    by_priority: Vec<(CrisisPriority, u64)>,
+}
+
+impl CrisisUpdateStats {
+    fn record(&mut self, update: &CrisisUpdate) {
+        self.total += 1;
+        match update.status {
+            // Unconfirmed updates are still open, and peers may predate the status
+            CrisisStatus::Active | CrisisStatus::Unconfirmed => self.active += 1,
+            CrisisStatus::Resolved => self.resolved += 1,
+            CrisisStatus::Archived => self.archived += 1,
+        }
+        if let Some((_, count)) = self.by_priority.iter_mut().find(|(p, _)| *p == update.priority) {
+            *count += 1;
+        }
+    }
+
+    fn merge(&mut self, other: CrisisUpdateStats) {
+        self.total += other.total;
+        self.active += other.active;
+        self.resolved += other.resolved;
+        self.archived += other.archived;
+        for (priority, count) in other.by_priority {
+            if let Some((_, total)) = self.by_priority.iter_mut().find(|(p, _)| *p == priority) {
+                *total += count;
+            }
+        }
+    }
+
+    fn empty() -> Self {
+        CrisisUpdateStats {
+            by_priority: CrisisPriority::ALL.iter().map(|p| (*p, 0)).collect(),
+            ..Default::default()
+        }
+    }
+}
+
+// Shards first, then the archive canister when asked for and holding anything
+fn child_canisters(include_archived: bool) -> Vec<Principal> {
+    let mut canisters = crate::sharding::shard_canisters();
+    if include_archived && crate::archive::archived_count() > 0 {
+        canisters.extend(get_settings().archive_canister);
+    }
+    canisters
+}
+
+fn call_error(canister: Principal, method: &str, err: (ic_cdk::api::call::RejectionCode, String)) -> Error {
+    Error::external_call_failed(format!(
+        "canister {} rejected {}: {:?} {}",
+        canister, method, err.0, err.1
+    ))
+    .with_detail("canister", canister)
+}
+
+fn local_matches(filter: &CrisisUpdateFilter) -> Vec<CrisisUpdate> {
+    CRISIS_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, update)| update)
+            .filter(|update| filter.matches(update))
+            .collect()
+    })
+}
+
+fn local_stats() -> CrisisUpdateStats {
+    let mut stats = CrisisUpdateStats::empty();
+    CRISIS_STORAGE.with(|service| {
+        for (_, update) in service.borrow().iter() {
+            stats.record(&update);
+        }
+    });
+    stats
+}
+
+// 2.47.1 search_all_crisis_updates Function:
+// Fails when a child canister cannot be reached rather than returning partial results.
+#[ic_cdk::query(composite = true, guard = "admit_read")]
+async fn search_all_crisis_updates(
+    filter: CrisisUpdateFilter,
+    include_archived: bool,
+) -> Result<QueryResult<CrisisUpdate>, Error> {
+    let mut items = local_matches(&filter);
+    for canister in child_canisters(include_archived) {
+        let (found,): (QueryResult<CrisisUpdate>,) =
+            ic_cdk::call(canister, "filter_crisis_updates_local", (filter.clone(),))
+                .await
+                .map_err(|e| call_error(canister, "filter_crisis_updates_local", e))?;
+        // A shard that had to cut its reply would make the result silently partial
+        if found.total_count > found.items.len() as u64 {
+            return Err(Error::result_too_large(format!(
+                "canister {} returned {} of {} matches; use a narrower filter",
+                canister,
+                found.items.len(),
+                found.total_count
+            ))
+            .with_detail("canister", canister)
+            .with_detail("total_count", found.total_count));
+        }
+        items.extend(found.items);
+    }
+    items.sort_by_key(|update| update.id);
+    items.dedup_by_key(|update| update.id);
+    crate::query_limits::cap_search_results(crate::present_updates(items))
+}
+
+// 2.47.2 get_crisis_update_stats Function:
+#[ic_cdk::query(composite = true, guard = "admit_read")]
+async fn get_crisis_update_stats(include_archived: bool) -> Result<CrisisUpdateStats, Error> {
+    let mut stats = local_stats();
+    for canister in child_canisters(include_archived) {
+        let (child,): (CrisisUpdateStats,) = ic_cdk::call(canister, "crisis_update_stats_local", ())
+            .await
+            .map_err(|e| call_error(canister, "crisis_update_stats_local", e))?;
+        stats.merge(child);
+    }
+    Ok(stats)
+}
+
+// 2.47.3 filter_crisis_updates_local Function:
+// The per-canister half of `search_all_crisis_updates`, for when this canister
+// serves as a shard or archive of another router. Only updates the calling
+// router may see are returned, redacted for it, and the reply is cut to fit the
+// message limit; `total_count` then exceeds the items returned.
+#[ic_cdk::query(guard = "admit_read")]
+fn filter_crisis_updates_local(filter: CrisisUpdateFilter) -> QueryResult<CrisisUpdate> {
+    let visible: Vec<CrisisUpdate> = local_matches(&filter)
+        .into_iter()
+        .filter(crate::moderation::visible_to_caller)
+        .map(crate::redaction::redact_update_for_caller)
+        .collect();
+    let total_count = visible.len() as u64;
+    QueryResult::limited(truncate_to_fit(visible).0, total_count)
+}
+
+// 2.47.4 crisis_update_stats_local Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn crisis_update_stats_local() -> CrisisUpdateStats {
+    local_stats()
+}
@@ -0,0 +1,315 @@
+// One search over a single text field of crisis updates (title, description or
+// location), replacing the separate title, description and location lookups.
+// Values are compared case-insensitively after normalization, in one of four
+// modes: Exact, Contains, Prefix and Fuzzy. Location covers the primary
+// location and every affected area. Exact and Prefix searches are answered from
+// an ordered index of each field's leading characters, so they don't scan
+// storage, and Exact location searches use the location index; Contains and
+// Fuzzy scan the locally stored updates. Fuzzy matching is word by word: each
+// word searched for must be within a small edit distance of some word in the
+// field. Updates stored before the field index existed are found by Prefix and
+// Exact searches once `rebuild_indexes(Field)` has run.
+use crate::access_mode::admit_read;
+use crate::normalize::normalize_line;
+use crate::response::QueryResult;
+use crate::{CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+const MAX_VALUE_CHARS: usize = 1_000;
+// One byte for the field, the rest for the start of its normalized text
+const FIELD_KEY_BYTES: usize = 64;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum SearchField {
+    Title,
+    Description,
+    Location,
+}
+
+impl SearchField {
+    const ALL: [SearchField; 3] = [SearchField::Title, SearchField::Description, SearchField::Location];
+
+    fn tag(self) -> u8 {
+        match self {
+            SearchField::Title => 0,
+            SearchField::Description => 1,
+            SearchField::Location => 2,
+        }
+    }
+
+    // The field's values on the update; a location search covers the affected areas too
+    fn values(self, update: &CrisisUpdate) -> Vec<&str> {
+        match self {
+            SearchField::Title => vec![update.title.as_str()],
+            SearchField::Description => vec![update.description.as_str()],
+            SearchField::Location => std::iter::once(update.location.as_str())
+                .chain(update.affected_areas.iter().map(|area| area.name.as_str()))
+                .collect(),
+        }
+    }
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum MatchMode {
+    Exact,
+    Contains,
+    Prefix,
+    Fuzzy,
+}
+
+type FieldKey = Blob<FIELD_KEY_BYTES>;
+
+thread_local! {
+    // (field and the start of its normalized text, crisis update id)
+    static FIELD_INDEX: RefCell<StableBTreeMap<(FieldKey, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(125)))
+    ));
+
+    // (crisis update id, field key), to drop an update's old entries on edit
+    static FIELDS_BY_UPDATE: RefCell<StableBTreeMap<(u64, FieldKey), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(126)))
+    ));
+}
+
+fn comparable(text: &str) -> String {
+    normalize_line(text).to_lowercase()
+}
+
+// The field tag followed by as much of the text as fits, cut at a character boundary
+fn field_key(field: SearchField, comparable_text: &str) -> FieldKey {
+    let mut bytes = vec![field.tag()];
+    for c in comparable_text.chars() {
+        if bytes.len() + c.len_utf8() > FIELD_KEY_BYTES {
+            break;
+        }
+        let mut buf = [0; 4];
+        bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+    Blob::try_from(bytes.as_slice()).expect("field keys fit their blob")
+}
+
+fn indexed_keys(update: &CrisisUpdate) -> BTreeSet<FieldKey> {
+    SearchField::ALL
+        .into_iter()
+        .flat_map(|field| {
+            field
+                .values(update)
+                .into_iter()
+                .map(comparable)
+                .filter(|text| !text.is_empty())
+                .map(move |text| field_key(field, &text))
+        })
+        .collect()
+}
+
+pub(crate) fn unindex_update(id: u64) {
+    let keys: Vec<FieldKey> = FIELDS_BY_UPDATE.with(|s| {
+        s.borrow()
+            .range((id, Blob::default())..)
+            .take_while(|((update_id, _), _)| *update_id == id)
+            .map(|((_, key), _)| key)
+            .collect()
+    });
+    for key in keys {
+        FIELD_INDEX.with(|s| s.borrow_mut().remove(&(key, id)));
+        FIELDS_BY_UPDATE.with(|s| s.borrow_mut().remove(&(id, key)));
+    }
+}
+
+// Re-indexes an update under its current title, description and locations
+pub(crate) fn index_update(update: &CrisisUpdate) {
+    unindex_update(update.id);
+    for key in indexed_keys(update) {
+        FIELD_INDEX.with(|s| s.borrow_mut().insert((key, update.id), ()));
+        FIELDS_BY_UPDATE.with(|s| s.borrow_mut().insert((update.id, key), ()));
+    }
+}
+
+// Whether both index maps hold exactly the update's current fields
+pub(crate) fn is_indexed(update: &CrisisUpdate) -> bool {
+    let expected = indexed_keys(update);
+    let indexed: BTreeSet<FieldKey> = FIELDS_BY_UPDATE.with(|s| {
+        s.borrow()
+            .range((update.id, Blob::default())..)
+            .take_while(|((update_id, _), _)| *update_id == update.id)
+            .map(|((_, key), _)| key)
+            .collect()
+    });
+    expected == indexed
+        && FIELD_INDEX.with(|s| {
+            let s = s.borrow();
+            expected.iter().all(|key| s.contains_key(&(*key, update.id)))
+        })
+}
+
+// Reverse entries of updates that no longer exist, and forward entries without
+// a reverse entry, which cannot be found through the update
+type OrphanedEntries = (Vec<(u64, FieldKey)>, Vec<(FieldKey, u64)>);
+
+fn orphaned_index_entries(is_live: &dyn Fn(u64) -> bool) -> OrphanedEntries {
+    let stale: Vec<(u64, FieldKey)> = FIELDS_BY_UPDATE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|(id, _)| !is_live(*id))
+            .collect()
+    });
+    let unreachable: Vec<(FieldKey, u64)> = FIELD_INDEX.with(|index| {
+        FIELDS_BY_UPDATE.with(|by_update| {
+            let by_update = by_update.borrow();
+            index
+                .borrow()
+                .iter()
+                .map(|(key, _)| key)
+                .filter(|(key, id)| !by_update.contains_key(&(*id, *key)))
+                .collect()
+        })
+    });
+    (stale, unreachable)
+}
+
+pub(crate) fn count_orphaned_index_entries(is_live: &dyn Fn(u64) -> bool) -> u64 {
+    let (stale, unreachable) = orphaned_index_entries(is_live);
+    (stale.len() + unreachable.len()) as u64
+}
+
+// Drops index entries of updates that no longer exist; returns how many were removed
+pub(crate) fn remove_orphaned_index_entries(is_live: &dyn Fn(u64) -> bool) -> u64 {
+    let (stale, unreachable) = orphaned_index_entries(is_live);
+    for (id, key) in &stale {
+        FIELD_INDEX.with(|s| s.borrow_mut().remove(&(*key, *id)));
+        FIELDS_BY_UPDATE.with(|s| s.borrow_mut().remove(&(*id, *key)));
+    }
+    FIELD_INDEX.with(|s| {
+        let mut s = s.borrow_mut();
+        for key in &unreachable {
+            s.remove(key);
+        }
+    });
+    (stale.len() + unreachable.len()) as u64
+}
+
+// Ids indexed under a key starting with the given one; `exact` keeps only equal keys
+fn indexed_ids(start: FieldKey, exact: bool) -> BTreeSet<u64> {
+    FIELD_INDEX.with(|s| {
+        s.borrow()
+            .range((start, 0)..)
+            .take_while(|((key, _), _)| {
+                if exact {
+                    *key == start
+                } else {
+                    key.as_slice().starts_with(start.as_slice())
+                }
+            })
+            .map(|((_, id), _)| id)
+            .collect()
+    })
+}
+
+// Edits allowed between a searched word and a word of the field
+fn tolerance(word: &str) -> usize {
+    match word.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn words(text: &str) -> Vec<&str> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+fn fuzzy_matches(text: &str, value: &str) -> bool {
+    let text_words = words(text);
+    words(value).into_iter().all(|word| {
+        let allowed = tolerance(word);
+        text_words
+            .iter()
+            .any(|candidate| edit_distance(word, candidate) <= allowed)
+    })
+}
+
+// `value` is already in comparable form
+fn matches(update: &CrisisUpdate, field: SearchField, mode: MatchMode, value: &str) -> bool {
+    field.values(update).into_iter().any(|text| {
+        let text = comparable(text);
+        match mode {
+            MatchMode::Exact => text == value,
+            MatchMode::Contains => text.contains(value),
+            MatchMode::Prefix => text.starts_with(value),
+            MatchMode::Fuzzy => fuzzy_matches(&text, value),
+        }
+    })
+}
+
+fn candidates(field: SearchField, mode: MatchMode, value: &str) -> Vec<CrisisUpdate> {
+    let ids = match (field, mode) {
+        (SearchField::Location, MatchMode::Exact) => crate::areas::updates_at(value).into_iter().collect(),
+        (_, MatchMode::Exact) => indexed_ids(field_key(field, value), true),
+        (_, MatchMode::Prefix) => indexed_ids(field_key(field, value), false),
+        (_, MatchMode::Contains | MatchMode::Fuzzy) => {
+            return CRISIS_STORAGE.with(|s| s.borrow().iter().map(|(_, update)| update).collect())
+        }
+    };
+    ids.into_iter()
+        .filter_map(|id| crate::_get_crisis_update(&id))
+        .collect()
+}
+
+// Visible, unexpired updates whose field matches, capped like other searches
+pub(crate) fn search_field(
+    field: SearchField,
+    mode: MatchMode,
+    value: &str,
+) -> Result<QueryResult<CrisisUpdate>, Error> {
+    let value = comparable(value);
+    if value.is_empty() {
+        return Err(Error::validation("the search value must not be empty"));
+    }
+    if value.chars().count() > MAX_VALUE_CHARS {
+        return Err(Error::validation(format!(
+            "search values must be at most {} characters",
+            MAX_VALUE_CHARS
+        )));
+    }
+    let now = time();
+    crate::query_limits::cap_search_results(crate::present_updates(
+        candidates(field, mode, &value)
+            .into_iter()
+            .filter(|update| !update.is_expired(now) && matches(update, field, mode, &value))
+            .collect(),
+    ))
+}
+
+// 2.88.1 search_crisis_updates_by_field Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn search_crisis_updates_by_field(
+    field: SearchField,
+    mode: MatchMode,
+    value: String,
+) -> Result<QueryResult<CrisisUpdate>, Error> {
+    search_field(field, mode, &value)
+}
@@ -0,0 +1,284 @@
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::donations::{configured_ledger, crisis_pool_subaccount, total_raised, Donation, DONATION_STORAGE};
+use crate::ids::IdCounter;
+use crate::ledger::{self, Account};
+use crate::maintenance::admit_update;
+use crate::response::{fit, QueryResult};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::collections::BTreeSet;
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_DESCRIPTION_CHARS: usize = 500;
+const MAX_RECIPIENT_ORG_CHARS: usize = 200;
+const MAX_PURPOSE_CHARS: usize = 300;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct FundraisingGoal {
+    crisis_id: u64,
+    target_amount: u128,
+    description: String,
+    set_by: Principal,
+    updated_at: u64,
+}
+
+// Implementing Storable and BoundedStorable traits for FundraisingGoal
+impl Storable for FundraisingGoal {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for FundraisingGoal {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Disbursement {
+    id: u64,
+    crisis_id: u64,
+    amount: u128,
+    // Ledger fee paid out of the crisis pool on top of the amount
+    fee: u128,
+    recipient_org: String,
+    recipient_account: Account,
+    purpose: String,
+    authorized_by: Principal,
+    block_index: u128,
+    timestamp: u64,
+}
+
+// Implementing Storable and BoundedStorable traits for Disbursement
+impl Storable for Disbursement {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Disbursement {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct DisbursementPayload {
+    crisis_id: u64,
+    amount: u128,
+    recipient_org: String,
+    recipient_account: Account,
+    purpose: String,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct FundraisingStatus {
+    crisis_id: u64,
+    goal: Option<FundraisingGoal>,
+    total_raised: u128,
+    total_disbursed: u128,
+    available: u128,
+    // Progress towards the goal in basis points (10_000 = goal reached)
+    progress_bps: Option<u64>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) enum FundsLedgerEntry {
+    Donation(Donation),
+    Disbursement(Disbursement),
+}
+
+thread_local! {
+    static GOAL_STORAGE: RefCell<StableBTreeMap<u64, FundraisingGoal, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28)))
+    ));
+
+    static DISBURSEMENT_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(29, "disbursements"));
+
+    static DISBURSEMENT_STORAGE: RefCell<StableBTreeMap<u64, Disbursement, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30)))
+    ));
+
+    // Crises with a payout awaiting the ledger, so the available balance can't be spent twice
+    static DISBURSEMENTS_IN_FLIGHT: RefCell<BTreeSet<u64>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+// Releases the in-flight marker however the payout ends
+struct DisbursementGuard(u64);
+
+impl DisbursementGuard {
+    fn acquire(crisis_id: u64) -> Result<Self, Error> {
+        if DISBURSEMENTS_IN_FLIGHT.with(|d| d.borrow_mut().insert(crisis_id)) {
+            Ok(DisbursementGuard(crisis_id))
+        } else {
+            Err(Error::conflict(format!(
+                "a disbursement for crisis id={} is already in progress",
+                crisis_id
+            )))
+        }
+    }
+}
+
+impl Drop for DisbursementGuard {
+    fn drop(&mut self) {
+        DISBURSEMENTS_IN_FLIGHT.with(|d| d.borrow_mut().remove(&self.0));
+    }
+}
+
+pub(crate) fn total_disbursed(crisis_id: u64) -> u128 {
+    DISBURSEMENT_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, d)| d.crisis_id == crisis_id)
+            .map(|(_, d)| d.amount + d.fee)
+            .sum()
+    })
+}
+
+// 2.21.1 set_fundraising_goal Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn set_fundraising_goal(crisis_id: u64, target_amount: u128, description: String) -> Result<FundraisingGoal, Error> {
+    crate::call_stats::count_call("set_fundraising_goal");
+    let coordinator = require_role(Role::Coordinator)?;
+    if !CRISIS_STORAGE.with(|s| s.borrow().contains_key(&crisis_id)) {
+        return Err(Error::not_found(format!(
+            "a crisis update with id={} not found",
+            crisis_id
+        )));
+    }
+    if target_amount == 0 {
+        return Err(Error::validation("fundraising goal must be greater than zero"));
+    }
+    if description.chars().count() > MAX_DESCRIPTION_CHARS {
+        return Err(Error::validation(format!(
+            "descriptions must be at most {} characters",
+            MAX_DESCRIPTION_CHARS
+        )));
+    }
+    let goal = FundraisingGoal {
+        crisis_id,
+        target_amount,
+        description,
+        set_by: coordinator,
+        updated_at: time(),
+    };
+    crate::ensure_storable(&goal, "fundraising goal")?;
+    GOAL_STORAGE.with(|s| s.borrow_mut().insert(crisis_id, goal.clone()));
+    Ok(goal)
+}
+
+// 2.21.2 get_fundraising_status Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_fundraising_status(crisis_id: u64) -> FundraisingStatus {
+    let goal = GOAL_STORAGE.with(|s| s.borrow().get(&crisis_id));
+    let total_raised = total_raised(crisis_id);
+    let total_disbursed = total_disbursed(crisis_id);
+    let progress_bps = goal
+        .as_ref()
+        .map(|g| (total_raised.saturating_mul(10_000) / g.target_amount).min(u64::MAX as u128) as u64);
+    FundraisingStatus {
+        crisis_id,
+        goal,
+        total_raised,
+        total_disbursed,
+        available: total_raised.saturating_sub(total_disbursed),
+        progress_bps,
+    }
+}
+
+// 2.21.3 record_disbursement Function:
+// Pays out of the crisis pool through the ledger so every recorded
+// disbursement is backed by an actual transfer.
+#[ic_cdk::update(guard = "admit_update")]
+async fn record_disbursement(payload: DisbursementPayload) -> Result<Disbursement, Error> {
+    crate::call_stats::count_call("record_disbursement");
+    let admin = require_role(Role::Admin)?;
+    let ledger = configured_ledger()?;
+    if payload.amount == 0 || payload.recipient_org.trim().is_empty() || payload.purpose.trim().is_empty() {
+        return Err(Error::validation(
+            "amount, recipient organization and purpose are required",
+        ));
+    }
+    if payload.recipient_org.chars().count() > MAX_RECIPIENT_ORG_CHARS
+        || payload.purpose.chars().count() > MAX_PURPOSE_CHARS
+    {
+        return Err(Error::validation(format!(
+            "recipient organizations must be at most {} characters and purposes at most {}",
+            MAX_RECIPIENT_ORG_CHARS, MAX_PURPOSE_CHARS
+        )));
+    }
+    let _guard = DisbursementGuard::acquire(payload.crisis_id)?;
+    let fee = ledger::fee(ledger).await?;
+    let available = total_raised(payload.crisis_id).saturating_sub(total_disbursed(payload.crisis_id));
+    if payload.amount.saturating_add(fee) > available {
+        return Err(Error::validation(format!(
+            "disbursement of {} plus fee {} exceeds the {} available for crisis id={}",
+            payload.amount, fee, available, payload.crisis_id
+        )));
+    }
+    // Checked before the transfer, since a record that cannot be stored after it would lose the payout
+    let mut disbursement = Disbursement {
+        id: u64::MAX,
+        crisis_id: payload.crisis_id,
+        amount: payload.amount,
+        fee,
+        recipient_org: payload.recipient_org,
+        recipient_account: payload.recipient_account,
+        purpose: payload.purpose,
+        authorized_by: admin,
+        block_index: u128::MAX,
+        timestamp: u64::MAX,
+    };
+    crate::ensure_storable(&disbursement, "disbursement")?;
+    let block_index = ledger::transfer(
+        ledger,
+        Some(crisis_pool_subaccount(payload.crisis_id)),
+        disbursement.recipient_account.clone(),
+        payload.amount,
+        fee,
+    )
+    .await?;
+
+    let id = crate::ids::next_id(&DISBURSEMENT_ID_COUNTER)?;
+    disbursement.id = id;
+    disbursement.block_index = block_index;
+    disbursement.timestamp = time();
+    DISBURSEMENT_STORAGE.with(|s| s.borrow_mut().insert(id, disbursement.clone()));
+    Ok(disbursement)
+}
+
+// 2.21.4 get_crisis_funds_ledger Function:
+// Donations and disbursements of a crisis in chronological order, for donor audits.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_crisis_funds_ledger(crisis_id: u64) -> Result<QueryResult<FundsLedgerEntry>, Error> {
+    let mut entries: Vec<(u64, FundsLedgerEntry)> = DONATION_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, d)| d.crisis_id == crisis_id)
+            .map(|(_, d)| (d.timestamp, FundsLedgerEntry::Donation(d)))
+            .collect()
+    });
+    DISBURSEMENT_STORAGE.with(|s| {
+        entries.extend(
+            s.borrow()
+                .iter()
+                .filter(|(_, d)| d.crisis_id == crisis_id)
+                .map(|(_, d)| (d.timestamp, FundsLedgerEntry::Disbursement(d))),
+        )
+    });
+    entries.sort_by_key(|(timestamp, _)| *timestamp);
+    fit(entries.into_iter().map(|(_, entry)| entry).collect())
+}
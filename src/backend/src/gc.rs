@@ -0,0 +1,89 @@
+// Garbage collection of child records and index entries that outlived their
+// crisis update, for deletion paths that missed them. An update counts as
+// existing when it is stored here, on a shard or in the archive; for the
+// indexes, pins, links and SLA records dropped when an update is archived,
+// only updates stored here or on a shard count. Runs daily from the heartbeat
+// and on demand.
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::maintenance::admit_update;
+use crate::{Error, CRISIS_STORAGE};
+use ic_cdk::api::time;
+use std::cell::RefCell;
+
+pub(crate) const GC_INTERVAL_SECS: u64 = 24 * 60 * 60;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct GcReport {
+    ran_at: u64,
+    links: u64,
+    confirmations: u64,
+    flags: u64,
+    assignments: u64,
+    pins: u64,
+    sla_records: u64,
+    location_index_entries: u64,
+    priority_queue_entries: u64,
+    view_counts: u64,
+    damage_assessments: u64,
+    casualty_revisions: u64,
+    watches: u64,
+    quick_updates: u64,
+    field_index_entries: u64,
+    freshness_records: u64,
+}
+
+thread_local! {
+    static LAST_GC_REPORT: RefCell<Option<GcReport>> = const { RefCell::new(None) };
+}
+
+pub(crate) fn update_exists(id: u64) -> bool {
+    CRISIS_STORAGE.with(|s| s.borrow().contains_key(&id))
+        || crate::sharding::sharded_location(id).is_some()
+        || crate::archive::archived_location(id).is_some()
+}
+
+// Archived updates keep only their archive index entry here
+pub(crate) fn served_here(id: u64) -> bool {
+    CRISIS_STORAGE.with(|s| s.borrow().contains_key(&id)) || crate::sharding::sharded_location(id).is_some()
+}
+
+pub(crate) fn collect_garbage(now: u64) -> Result<GcReport, Error> {
+    let (confirmations, flags) = crate::reputation::remove_orphaned_reputation_records(&update_exists);
+    let report = GcReport {
+        ran_at: now,
+        links: crate::links::remove_orphaned_links(&served_here),
+        confirmations,
+        flags,
+        assignments: crate::assignments::remove_orphaned_assignments(&update_exists),
+        pins: crate::pins::remove_orphaned_pins(&served_here)?,
+        sla_records: crate::sla::remove_orphaned_slas(&served_here),
+        location_index_entries: crate::areas::remove_orphaned_index_entries(&served_here),
+        priority_queue_entries: crate::priority::remove_stale_queue_entries(&served_here),
+        view_counts: crate::views::remove_orphaned_views(&update_exists),
+        damage_assessments: crate::damage::remove_orphaned_assessments(&update_exists),
+        casualty_revisions: crate::casualties::remove_orphaned_revisions(&update_exists),
+        watches: crate::watches::remove_orphaned_watches(&update_exists),
+        quick_updates: crate::quick_updates::remove_orphaned_quick_updates(&update_exists),
+        field_index_entries: crate::field_search::remove_orphaned_index_entries(&served_here),
+        freshness_records: crate::staleness::remove_orphaned_freshness_records(&update_exists),
+    };
+    LAST_GC_REPORT.with(|last| *last.borrow_mut() = Some(report.clone()));
+    Ok(report)
+}
+
+// 2.52.1 run_garbage_collection Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn run_garbage_collection() -> Result<GcReport, Error> {
+    crate::call_stats::count_call("run_garbage_collection");
+    require_role(Role::Admin)?;
+    collect_garbage(time())
+}
+
+// 2.52.2 get_last_gc_report Function:
+// None until a collection has run since the last upgrade.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_last_gc_report() -> Result<Option<GcReport>, Error> {
+    require_role(Role::Admin)?;
+    Ok(LAST_GC_REPORT.with(|last| last.borrow().clone()))
+}
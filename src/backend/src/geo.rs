@@ -0,0 +1,180 @@
+use crate::Error;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Default, Debug)]
+pub(crate) struct Coordinates {
+    pub(crate) latitude: f64,
+    pub(crate) longitude: f64,
+}
+
+impl Coordinates {
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if !(-90.0..=90.0).contains(&self.latitude) || !(-180.0..=180.0).contains(&self.longitude) {
+            return Err(Error::validation(format!(
+                "coordinates ({}, {}) are out of range",
+                self.latitude, self.longitude
+            )));
+        }
+        Ok(())
+    }
+
+    // Great-circle distance using the haversine formula
+    pub(crate) fn distance_km(&self, other: &Coordinates) -> f64 {
+        let d_lat = (other.latitude - self.latitude).to_radians();
+        let d_lng = (other.longitude - self.longitude).to_radians();
+        let a = (d_lat / 2.0).sin().powi(2)
+            + self.latitude.to_radians().cos() * other.latitude.to_radians().cos() * (d_lng / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+}
+
+// An area between two latitudes and two longitudes. A box whose west edge is
+// east of its east edge crosses the antimeridian.
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Debug)]
+pub(crate) struct BoundingBox {
+    pub(crate) south: f64,
+    pub(crate) west: f64,
+    pub(crate) north: f64,
+    pub(crate) east: f64,
+}
+
+impl BoundingBox {
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        Coordinates {
+            latitude: self.south,
+            longitude: self.west,
+        }
+        .validate()?;
+        Coordinates {
+            latitude: self.north,
+            longitude: self.east,
+        }
+        .validate()?;
+        if self.south > self.north {
+            return Err(Error::validation(
+                "the south edge of a bounding box must not be north of its north edge",
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn contains(&self, point: &Coordinates) -> bool {
+        let in_longitude = if self.west <= self.east {
+            (self.west..=self.east).contains(&point.longitude)
+        } else {
+            point.longitude >= self.west || point.longitude <= self.east
+        };
+        (self.south..=self.north).contains(&point.latitude) && in_longitude
+    }
+}
+
+const MAX_GEOFENCE_VERTICES: usize = 100;
+
+// A simple polygon given by its vertices in order; the last vertex joins the
+// first. Edges are straight in latitude/longitude, and a geofence must not
+// cross the antimeridian.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Debug)]
+pub(crate) struct Geofence {
+    pub(crate) vertices: Vec<Coordinates>,
+}
+
+impl Geofence {
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if self.vertices.len() < 3 || self.vertices.len() > MAX_GEOFENCE_VERTICES {
+            return Err(Error::validation(format!(
+                "a geofence must have between 3 and {} vertices",
+                MAX_GEOFENCE_VERTICES
+            )));
+        }
+        self.vertices.iter().try_for_each(Coordinates::validate)
+    }
+
+    // Even-odd ray casting along the latitude of the point
+    pub(crate) fn contains(&self, point: &Coordinates) -> bool {
+        let mut inside = false;
+        let mut previous = self.vertices[self.vertices.len() - 1];
+        for vertex in &self.vertices {
+            if (vertex.latitude > point.latitude) != (previous.latitude > point.latitude) {
+                let crossing = vertex.longitude
+                    + (point.latitude - vertex.latitude) * (previous.longitude - vertex.longitude)
+                        / (previous.latitude - vertex.latitude);
+                if point.longitude < crossing {
+                    inside = !inside;
+                }
+            }
+            previous = *vertex;
+        }
+        inside
+    }
+}
+
+// Validates optional coordinates supplied in a payload
+pub(crate) fn validate_coordinates(coordinates: &Option<Coordinates>) -> Result<(), Error> {
+    match coordinates {
+        Some(c) => c.validate(),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(latitude: f64, longitude: f64) -> Coordinates {
+        Coordinates { latitude, longitude }
+    }
+
+    #[test]
+    fn coordinates_must_be_in_range() {
+        assert!(at(90.0, -180.0).validate().is_ok());
+        assert!(at(90.5, 0.0).validate().is_err());
+        assert!(at(0.0, 180.5).validate().is_err());
+    }
+
+    #[test]
+    fn distance_is_great_circle() {
+        // Nairobi to Mombasa is about 440 km
+        let distance = at(-1.2921, 36.8219).distance_km(&at(-4.0435, 39.6682));
+        assert!((distance - 440.0).abs() < 10.0, "{}", distance);
+        assert_eq!(at(10.0, 10.0).distance_km(&at(10.0, 10.0)), 0.0);
+    }
+
+    #[test]
+    fn bounding_boxes_can_cross_the_antimeridian() {
+        let bbox = BoundingBox {
+            south: -20.0,
+            west: 170.0,
+            north: -10.0,
+            east: -170.0,
+        };
+        assert!(bbox.validate().is_ok());
+        assert!(bbox.contains(&at(-15.0, 179.0)));
+        assert!(bbox.contains(&at(-15.0, -175.0)));
+        assert!(!bbox.contains(&at(-15.0, 0.0)));
+        assert!(!bbox.contains(&at(-25.0, 179.0)));
+        let flipped = BoundingBox {
+            south: 10.0,
+            north: -10.0,
+            ..bbox
+        };
+        assert!(flipped.validate().is_err());
+    }
+
+    #[test]
+    fn geofences_contain_points_inside_the_polygon() {
+        // An L shape, so the notch is inside the bounding box but outside the fence
+        let fence = Geofence {
+            vertices: vec![at(0.0, 0.0), at(0.0, 2.0), at(1.0, 2.0), at(1.0, 1.0), at(2.0, 1.0), at(2.0, 0.0)],
+        };
+        assert!(fence.validate().is_ok());
+        assert!(fence.contains(&at(0.5, 1.5)));
+        assert!(fence.contains(&at(1.5, 0.5)));
+        assert!(!fence.contains(&at(1.5, 1.5)));
+        assert!(!fence.contains(&at(-0.5, 0.5)));
+        let line = Geofence {
+            vertices: vec![at(0.0, 0.0), at(1.0, 1.0)],
+        };
+        assert!(line.validate().is_err());
+    }
+}
@@ -0,0 +1,255 @@
+// A rolling "heat index" per region, so national dashboards can rank where
+// attention is most needed right now. Every open, reviewed update with a region
+// contributes its severity weight (Low 1 to Critical 4), halved for every
+// HEAT_HALF_LIFE_SECS since the incident (or the report, when the incident time
+// is unknown); a region's heat is the sum over its updates, so volume, severity
+// and recency all count. The index is adjusted incrementally when an update is
+// created or its status or priority changes, and a heartbeat job recomputes it
+// from storage to pick up edits, moderation decisions and deletions. Only
+// updates stored in this canister are counted.
+use crate::access_mode::admit_read;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::moderation::ReviewStatus;
+use crate::response::{fit, QueryResult};
+use crate::{CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::{borrow::Cow, cell::RefCell};
+
+pub(crate) const HEAT_REFRESH_INTERVAL_SECS: u64 = 10 * 60;
+const HEAT_HALF_LIFE_SECS: u64 = 12 * 60 * 60;
+// Heat below this with no open reports is dropped rather than kept forever
+const MIN_HEAT: f64 = 0.001;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct HeatRecord {
+    // The region label, e.g. "Mombasa, Coast, KE"
+    region: String,
+    country: String,
+    // Heat as of updated_at
+    heat: f64,
+    open_reports: u64,
+    updated_at: u64,
+}
+
+// Implementing Storable and BoundedStorable traits for HeatRecord
+impl Storable for HeatRecord {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for HeatRecord {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl HeatRecord {
+    fn decayed(&self, now: u64) -> f64 {
+        self.heat * decay(now.saturating_sub(self.updated_at))
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct RegionHeat {
+    region: String,
+    country: String,
+    // Higher needs more attention; only comparable within one reply
+    heat: f64,
+    open_reports: u64,
+}
+
+type RegionKey = Blob<32>;
+
+thread_local! {
+    // Hash of the lower-cased region label -> its heat
+    static HEAT_INDEX: RefCell<StableBTreeMap<RegionKey, HeatRecord, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(130)))
+    ));
+}
+
+fn region_key(label: &str) -> RegionKey {
+    let digest = Sha256::digest(label.to_lowercase().as_bytes());
+    Blob::try_from(digest.as_slice()).expect("sha256 digests are 32 bytes")
+}
+
+fn decay(elapsed_nanos: u64) -> f64 {
+    0.5f64.powf(elapsed_nanos as f64 / (HEAT_HALF_LIFE_SECS * NANOS_PER_SECOND) as f64)
+}
+
+fn counts(update: &CrisisUpdate) -> bool {
+    update.status.is_open()
+        && update.region.is_some()
+        && !matches!(update.review, Some(ReviewStatus::Pending | ReviewStatus::Rejected))
+}
+
+fn contribution(update: &CrisisUpdate, now: u64) -> f64 {
+    let weight = update.priority as u8 as f64 + 1.0;
+    weight * decay(now.saturating_sub(update.occurred_at.unwrap_or(update.created_at)))
+}
+
+fn adjust(update: &CrisisUpdate, sign: f64, now: u64) {
+    let Some(region) = update.region.as_ref() else {
+        return;
+    };
+    let label = region.label();
+    let key = region_key(&label);
+    HEAT_INDEX.with(|s| {
+        let mut s = s.borrow_mut();
+        let mut record = s.get(&key).unwrap_or_else(|| HeatRecord {
+            region: label,
+            country: region.country().to_string(),
+            heat: 0.0,
+            open_reports: 0,
+            updated_at: now,
+        });
+        record.heat = (record.decayed(now) + sign * contribution(update, now)).max(0.0);
+        record.open_reports = if sign > 0.0 {
+            record.open_reports + 1
+        } else {
+            record.open_reports.saturating_sub(1)
+        };
+        record.updated_at = now;
+        if record.open_reports == 0 && record.heat < MIN_HEAT {
+            s.remove(&key);
+        } else {
+            s.insert(key, record);
+        }
+    });
+}
+
+// Moves the index from the update's previous state (None for a new update) to its current one
+pub(crate) fn record_change(before: Option<&CrisisUpdate>, after: &CrisisUpdate) {
+    if crate::sharding::sharded_location(after.id).is_some() {
+        return;
+    }
+    let now = time();
+    if let Some(before) = before.filter(|before| counts(before)) {
+        adjust(before, -1.0, now);
+    }
+    if counts(after) {
+        adjust(after, 1.0, now);
+    }
+}
+
+// Rebuilds the index from the locally stored updates
+pub(crate) fn refresh_heat_index(now: u64) {
+    let mut records: BTreeMap<RegionKey, HeatRecord> = BTreeMap::new();
+    CRISIS_STORAGE.with(|s| {
+        for (_, update) in s.borrow().iter() {
+            let Some(region) = update.region.as_ref().filter(|_| counts(&update)) else {
+                continue;
+            };
+            let label = region.label();
+            let record = records.entry(region_key(&label)).or_insert_with(|| HeatRecord {
+                region: label,
+                country: region.country().to_string(),
+                heat: 0.0,
+                open_reports: 0,
+                updated_at: now,
+            });
+            record.heat += contribution(&update, now);
+            record.open_reports += 1;
+        }
+    });
+    HEAT_INDEX.with(|s| {
+        let mut s = s.borrow_mut();
+        let stale: Vec<RegionKey> = s
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| !records.contains_key(key))
+            .collect();
+        for key in stale {
+            s.remove(&key);
+        }
+        for (key, record) in records {
+            s.insert(key, record);
+        }
+    });
+}
+
+// 2.90.1 get_region_heat_index Function:
+// Hottest region first, with heat decayed to the time of the call.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_region_heat_index() -> Result<QueryResult<RegionHeat>, Error> {
+    let now = time();
+    let mut regions: Vec<RegionHeat> = HEAT_INDEX.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, record)| RegionHeat {
+                heat: record.decayed(now),
+                region: record.region,
+                country: record.country,
+                open_reports: record.open_reports,
+            })
+            .collect()
+    });
+    regions.sort_by(|a, b| b.heat.total_cmp(&a.heat).then_with(|| a.region.cmp(&b.region)));
+    fit(regions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CrisisPriority, CrisisStatus};
+
+    const HALF_LIFE: u64 = HEAT_HALF_LIFE_SECS * NANOS_PER_SECOND;
+
+    #[test]
+    fn heat_halves_every_half_life() {
+        assert_eq!(decay(0), 1.0);
+        assert!((decay(HALF_LIFE) - 0.5).abs() < 1e-9);
+        assert!((decay(3 * HALF_LIFE) - 0.125).abs() < 1e-9);
+        let record = HeatRecord {
+            region: "Mombasa, Coast, KE".to_string(),
+            country: "KE".to_string(),
+            heat: 8.0,
+            open_reports: 2,
+            updated_at: 100,
+        };
+        assert!((record.decayed(100 + 2 * HALF_LIFE) - 2.0).abs() < 1e-9);
+        // A clock behind the record leaves the heat as it was
+        assert_eq!(record.decayed(0), 8.0);
+    }
+
+    #[test]
+    fn contributions_weigh_severity_and_age_from_the_incident() {
+        let update = CrisisUpdate {
+            priority: CrisisPriority::Critical,
+            created_at: 2 * HALF_LIFE,
+            ..Default::default()
+        };
+        assert_eq!(contribution(&update, 2 * HALF_LIFE), 4.0);
+        let low = CrisisUpdate {
+            priority: CrisisPriority::Low,
+            ..update.clone()
+        };
+        assert_eq!(contribution(&low, 2 * HALF_LIFE), 1.0);
+        let occurred_earlier = CrisisUpdate {
+            occurred_at: Some(HALF_LIFE),
+            ..update
+        };
+        assert!((contribution(&occurred_earlier, 2 * HALF_LIFE) - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn closed_or_unplaced_updates_do_not_count() {
+        let unplaced = CrisisUpdate::default();
+        assert!(!counts(&unplaced));
+        let resolved = CrisisUpdate {
+            status: CrisisStatus::Resolved,
+            ..Default::default()
+        };
+        assert!(!counts(&resolved));
+    }
+}
@@ -0,0 +1,74 @@
+// Severity-weighted report density for situational-awareness heatmaps. Counts
+// are aggregated per grid cell so clients never receive the raw records.
+use crate::access_mode::admit_read;
+use crate::clusters::cell_of;
+use crate::geo::BoundingBox;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::response::{fit, QueryResult};
+use crate::{CrisisPriority, Error, CRISIS_STORAGE};
+use ic_cdk::api::time;
+use std::collections::BTreeMap;
+
+const MIN_CELL_SIZE_DEGREES: f64 = 0.001;
+const MAX_CELL_SIZE_DEGREES: f64 = 45.0;
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct HeatmapCell {
+    cell: BoundingBox,
+    count: u64,
+    // Sum of the reports' severity weights: Low 1, Normal 2, High 3, Critical 4
+    weight: u64,
+}
+
+fn severity_weight(priority: CrisisPriority) -> u64 {
+    priority as u64 + 1
+}
+
+// 2.43.1 get_heatmap Function:
+// Counts visible reports whose incident time (or report time, when the incident
+// time is unknown) falls within the last `time_window_secs` seconds.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_heatmap(bbox: BoundingBox, cell_size: f64, time_window_secs: u64) -> Result<QueryResult<HeatmapCell>, Error> {
+    bbox.validate()?;
+    if !(MIN_CELL_SIZE_DEGREES..=MAX_CELL_SIZE_DEGREES).contains(&cell_size) {
+        return Err(Error::validation(format!(
+            "cell size must be between {} and {} degrees",
+            MIN_CELL_SIZE_DEGREES, MAX_CELL_SIZE_DEGREES
+        ))
+        .with_detail("cell_size", cell_size));
+    }
+    let cutoff = time().saturating_sub(time_window_secs.saturating_mul(NANOS_PER_SECOND));
+    let mut cells: BTreeMap<(i64, i64), (u64, u64)> = BTreeMap::new();
+    CRISIS_STORAGE.with(|service| {
+        for (_, update) in service.borrow().iter() {
+            let Some(point) = update.coordinates.filter(|c| bbox.contains(c)) else {
+                continue;
+            };
+            if update.occurred_at.unwrap_or(update.created_at) < cutoff
+                || !crate::moderation::visible_to_caller(&update)
+            {
+                continue;
+            }
+            let totals = cells.entry(cell_of(&point, cell_size)).or_default();
+            totals.0 += 1;
+            totals.1 += severity_weight(update.priority);
+        }
+    });
+    fit(cells
+        .into_iter()
+        .map(|((row, column), (count, weight))| {
+            let south = row as f64 * cell_size - 90.0;
+            let west = column as f64 * cell_size - 180.0;
+            HeatmapCell {
+                cell: BoundingBox {
+                    south,
+                    west,
+                    north: (south + cell_size).min(90.0),
+                    east: (west + cell_size).min(180.0),
+                },
+                count,
+                weight,
+            }
+        })
+        .collect())
+}
@@ -0,0 +1,55 @@
+// Plain HTTP access through the IC HTTP gateway, for clients that cannot
+// speak Candid such as calendar apps and metrics scrapers.
+// Headers and body are sent too but not needed by any route
+#[derive(candid::CandidType, Deserialize)]
+pub(crate) struct HttpRequest {
+    method: String,
+    url: String,
+}
+
+#[derive(candid::CandidType, Serialize)]
+pub(crate) struct HttpGatewayResponse {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpGatewayResponse {
+    fn new(status_code: u16, content_type: &str, body: String) -> Self {
+        HttpGatewayResponse {
+            status_code,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: body.into_bytes(),
+        }
+    }
+}
+
+// 2.46.1 http_request Function:
+// Gateway requests arrive as the anonymous principal, so only public deployments serve them.
+#[ic_cdk::query]
+fn http_request(request: HttpRequest) -> HttpGatewayResponse {
+    if !crate::access_mode::may_read(&ic_cdk::api::caller()) {
+        return HttpGatewayResponse::new(403, "text/plain", "this deployment is not public".to_string());
+    }
+    if request.method != "GET" {
+        return HttpGatewayResponse::new(405, "text/plain", "method not allowed".to_string());
+    }
+    let path = request.url.split(['?', '#']).next().unwrap_or_default();
+    match path {
+        crate::calendar::CALENDAR_PATH => {
+            HttpGatewayResponse::new(200, "text/calendar; charset=utf-8", crate::calendar::render_calendar())
+        }
+        crate::metrics::METRICS_PATH => HttpGatewayResponse::new(
+            200,
+            "text/plain; version=0.0.4; charset=utf-8",
+            crate::metrics::render_metrics(),
+        ),
+        _ => match path
+            .strip_prefix(crate::after_action::AFTER_ACTION_PATH_PREFIX)
+            .and_then(|rest| crate::after_action::render_for_gateway(rest, &ic_cdk::api::caller()))
+        {
+            Some((content_type, body)) => HttpGatewayResponse::new(200, content_type, body),
+            None => HttpGatewayResponse::new(404, "text/plain", "not found".to_string()),
+        },
+    }
+}
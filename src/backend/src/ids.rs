@@ -0,0 +1,60 @@
+// Sequential id allocation, shared by every entity with a numeric id. Each
+// counter lives in its own stable cell. `next_id` reads, increments and writes
+// the counter back in one synchronous step, so no other message can interleave
+// with it, and the new value is persisted before the id is returned. A message
+// that traps later rolls the counter back together with whatever it meant to
+// store under the id; one that awaits in between keeps the increment. Ids can
+// therefore be skipped but are never handed out twice. A counter whose cell
+// cannot be opened reports an error on use instead of trapping at start-up.
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::Cell;
+use std::cell::RefCell;
+use std::thread::LocalKey;
+
+pub(crate) struct IdCounter {
+    // What the ids are for, e.g. "crisis updates"
+    name: &'static str,
+    // Err with the reason when the stable cell could not be opened
+    cell: Result<Cell<u64, Memory>, String>,
+}
+
+impl IdCounter {
+    pub(crate) fn init(memory_id: u8, name: &'static str) -> Self {
+        let memory = CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(memory_id)));
+        IdCounter {
+            name,
+            cell: Cell::init(memory, 0).map_err(|err| format!("{:?}", err)),
+        }
+    }
+
+    // The id the next allocation will return
+    pub(crate) fn peek(&self) -> Result<u64, Error> {
+        match &self.cell {
+            Ok(cell) => Ok(*cell.get()),
+            Err(reason) => Err(unavailable(self.name, reason)),
+        }
+    }
+
+    fn allocate(&mut self) -> Result<u64, Error> {
+        let cell = match &mut self.cell {
+            Ok(cell) => cell,
+            Err(reason) => return Err(unavailable(self.name, reason)),
+        };
+        let id = *cell.get();
+        let next = id
+            .checked_add(1)
+            .ok_or_else(|| Error::internal(format!("the id counter for {} is exhausted", self.name)))?;
+        cell.set(next)
+            .map_err(|err| Error::internal(format!("cannot persist the id counter for {}: {:?}", self.name, err)))?;
+        Ok(id)
+    }
+}
+
+fn unavailable(name: &str, reason: &str) -> Error {
+    Error::internal(format!("the id counter for {} cannot be opened: {}", name, reason))
+}
+
+pub(crate) fn next_id(counter: &'static LocalKey<RefCell<IdCounter>>) -> Result<u64, Error> {
+    counter.with(|counter| counter.borrow_mut().allocate())
+}
@@ -0,0 +1,374 @@
+// Bulk import of crisis updates from CSV or JSON lines, for agencies moving off
+// spreadsheets. The file is uploaded in chunks into a heap session (the mirror
+// of the export protocol), then parsed and imported in one call that validates
+// every row on its own and reports per-row success or failure.
+use crate::access::{require_role, Role};
+use crate::geo::Coordinates;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::maintenance::admit_update;
+use crate::{CrisisPriority, CrisisStatus, CrisisUpdate, CrisisUpdatePayload, Error};
+use candid::Principal;
+use ic_cdk::api::time;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+const IMPORT_TTL_SECS: u64 = 30 * 60;
+pub(crate) const IMPORT_EXPIRY_INTERVAL_SECS: u64 = 60;
+const MAX_IMPORT_BYTES: usize = 8 * 1024 * 1024;
+const MAX_IMPORT_ROWS: usize = 1000;
+const MAX_OPEN_IMPORTS_PER_CALLER: usize = 2;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum ImportFormat {
+    // First line is a header naming the columns
+    Csv,
+    // One JSON object per line
+    JsonLines,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum ImportField {
+    Title,
+    Description,
+    Location,
+    // Nanoseconds since the epoch
+    OccurredAt,
+    Status,
+    Priority,
+    Latitude,
+    Longitude,
+    Ignore,
+}
+
+impl ImportField {
+    const NAMED: [(&'static str, ImportField); 8] = [
+        ("title", ImportField::Title),
+        ("description", ImportField::Description),
+        ("location", ImportField::Location),
+        ("occurred_at", ImportField::OccurredAt),
+        ("status", ImportField::Status),
+        ("priority", ImportField::Priority),
+        ("latitude", ImportField::Latitude),
+        ("longitude", ImportField::Longitude),
+    ];
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ImportInfo {
+    import_id: u64,
+    expires_at: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ImportRowResult {
+    // 1-based data row, not counting a CSV header
+    row: u64,
+    crisis_update_id: Option<u64>,
+    error: Option<Error>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ImportReport {
+    imported: u64,
+    failed: u64,
+    rows: Vec<ImportRowResult>,
+}
+
+struct ImportSession {
+    owner: Principal,
+    format: ImportFormat,
+    // Source column or key -> field; unmapped names are matched to field names
+    column_map: Vec<(String, ImportField)>,
+    data: Vec<u8>,
+    expires_at: u64,
+}
+
+thread_local! {
+    static NEXT_IMPORT_ID: RefCell<u64> = const { RefCell::new(0) };
+    static IMPORT_SESSIONS: RefCell<BTreeMap<u64, ImportSession>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+// Drops every session past its expiry
+pub(crate) fn expire_import_sessions(now: u64) {
+    IMPORT_SESSIONS.with(|sessions| sessions.borrow_mut().retain(|_, session| session.expires_at > now));
+}
+
+fn field_for(column_map: &[(String, ImportField)], column: &str) -> ImportField {
+    let column = column.trim();
+    column_map
+        .iter()
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case(column))
+        .map(|(_, field)| *field)
+        .or_else(|| {
+            ImportField::NAMED
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(column))
+                .map(|(_, field)| *field)
+        })
+        .unwrap_or(ImportField::Ignore)
+}
+
+// Splits CSV text into records of fields, handling quoted commas, quotes and line breaks
+fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, Error> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', true) => in_quotes = false,
+            ('"', false) if field.is_empty() => in_quotes = true,
+            (',', false) => record.push(std::mem::take(&mut field)),
+            ('\r', false) if chars.peek() == Some(&'\n') => {}
+            ('\n', false) => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            (c, _) => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(Error::validation("the CSV ends inside a quoted field"));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records.retain(|record| record.iter().any(|f| !f.trim().is_empty()));
+    Ok(records)
+}
+
+// A parsed row as (field, raw value) pairs, or why it could not be parsed
+type ParsedRow = Result<Vec<(ImportField, String)>, Error>;
+
+// Turns the upload into rows
+fn parse_rows(session: &ImportSession) -> Result<Vec<ParsedRow>, Error> {
+    let text = std::str::from_utf8(&session.data).map_err(|_| Error::validation("the import must be UTF-8 text"))?;
+    let text = text.strip_prefix('\u{FEFF}').unwrap_or(text);
+    let rows = match session.format {
+        ImportFormat::Csv => {
+            let mut records = parse_csv(text)?.into_iter();
+            let header: Vec<ImportField> = records
+                .next()
+                .unwrap_or_default()
+                .iter()
+                .map(|column| field_for(&session.column_map, column))
+                .collect();
+            records
+                .map(|record| Ok(header.iter().copied().zip(record).collect()))
+                .collect::<Vec<_>>()
+        }
+        ImportFormat::JsonLines => text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let object: serde_json::Map<String, serde_json::Value> = serde_json::from_str(line)
+                    .map_err(|err| Error::validation(format!("not a JSON object: {}", err)))?;
+                Ok(object
+                    .into_iter()
+                    .map(|(key, value)| {
+                        let raw = match value {
+                            serde_json::Value::String(s) => s,
+                            serde_json::Value::Null => String::new(),
+                            other => other.to_string(),
+                        };
+                        (field_for(&session.column_map, &key), raw)
+                    })
+                    .collect())
+            })
+            .collect(),
+    };
+    if rows.len() > MAX_IMPORT_ROWS {
+        return Err(Error::validation(format!(
+            "an import can hold at most {} rows",
+            MAX_IMPORT_ROWS
+        )));
+    }
+    Ok(rows)
+}
+
+fn parse_number<T: std::str::FromStr>(field: ImportField, raw: &str) -> Result<T, Error> {
+    raw.trim()
+        .parse()
+        .map_err(|_| Error::validation(format!("{:?} value '{}' is not a number", field, raw)))
+}
+
+fn parse_status(raw: &str) -> Result<CrisisStatus, Error> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "active" => Ok(CrisisStatus::Active),
+        "resolved" => Ok(CrisisStatus::Resolved),
+        "archived" => Ok(CrisisStatus::Archived),
+        "unconfirmed" => Ok(CrisisStatus::Unconfirmed),
+        _ => Err(Error::validation(format!("unknown status '{}'", raw))),
+    }
+}
+
+fn parse_priority(raw: &str) -> Result<CrisisPriority, Error> {
+    CrisisPriority::ALL
+        .into_iter()
+        .find(|p| format!("{:?}", p).eq_ignore_ascii_case(raw.trim()))
+        .ok_or_else(|| Error::validation(format!("unknown priority '{}'", raw)))
+}
+
+// Builds and validates one update from a row; nothing is stored here
+fn row_to_update(row: Vec<(ImportField, String)>, author: Principal, now: u64) -> Result<CrisisUpdate, Error> {
+    let mut payload = CrisisUpdatePayload::default();
+    let mut status = CrisisStatus::Active;
+    let mut priority = CrisisPriority::Normal;
+    let (mut latitude, mut longitude) = (None, None);
+    for (field, raw) in row {
+        if raw.trim().is_empty() {
+            continue;
+        }
+        match field {
+            ImportField::Title => payload.title = raw,
+            ImportField::Description => payload.description = raw,
+            ImportField::Location => payload.location = raw,
+            ImportField::OccurredAt => payload.occurred_at = Some(parse_number(field, &raw)?),
+            ImportField::Status => status = parse_status(&raw)?,
+            ImportField::Priority => priority = parse_priority(&raw)?,
+            ImportField::Latitude => latitude = Some(parse_number(field, &raw)?),
+            ImportField::Longitude => longitude = Some(parse_number(field, &raw)?),
+            ImportField::Ignore => {}
+        }
+    }
+    payload.coordinates = match (latitude, longitude) {
+        (Some(latitude), Some(longitude)) => Some(Coordinates { latitude, longitude }),
+        (None, None) => None,
+        _ => return Err(Error::validation("latitude and longitude must be given together")),
+    };
+    crate::_check_input(&mut payload, now)?;
+    if payload.title.is_empty() {
+        return Err(Error::validation("title must not be empty"));
+    }
+    Ok(CrisisUpdate {
+        title: payload.title,
+        description: payload.description,
+        location: payload.location,
+        occurred_at: payload.occurred_at,
+        coordinates: payload.coordinates,
+        created_at: now,
+        status,
+        priority,
+        author: Some(author),
+        tenant: crate::tenants::tenant_of(&author),
+        ..Default::default()
+    })
+}
+
+async fn store_imported(mut update: CrisisUpdate) -> Result<u64, Error> {
+    update.id = crate::ids::next_id(&crate::CRISIS_ID_COUNTER)?;
+    crate::store_new_crisis_update(&update).await?;
+    Ok(crate::escalation::evaluate_escalation(update).await.id)
+}
+
+fn owned_session<R>(import_id: u64, owner: Principal, f: impl FnOnce(&mut ImportSession) -> R) -> Result<R, Error> {
+    IMPORT_SESSIONS.with(|sessions| {
+        sessions
+            .borrow_mut()
+            .get_mut(&import_id)
+            .filter(|s| s.owner == owner && s.expires_at > time())
+            .map(f)
+            .ok_or_else(|| Error::not_found(format!("an import with id={} not found", import_id)))
+    })
+}
+
+// 2.49.1 start_import Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn start_import(format: ImportFormat, column_map: Vec<(String, ImportField)>) -> Result<ImportInfo, Error> {
+    crate::call_stats::count_call("start_import");
+    let owner = require_role(Role::Admin)?;
+    let now = time();
+    expire_import_sessions(now);
+    let open = IMPORT_SESSIONS.with(|sessions| sessions.borrow().values().filter(|s| s.owner == owner).count());
+    if open >= MAX_OPEN_IMPORTS_PER_CALLER {
+        return Err(Error::validation(format!(
+            "at most {} imports can be open at once",
+            MAX_OPEN_IMPORTS_PER_CALLER
+        )));
+    }
+    let import_id = NEXT_IMPORT_ID.with(|next| {
+        let id = *next.borrow();
+        *next.borrow_mut() = id + 1;
+        id
+    });
+    let expires_at = now + IMPORT_TTL_SECS * NANOS_PER_SECOND;
+    IMPORT_SESSIONS.with(|sessions| {
+        sessions.borrow_mut().insert(
+            import_id,
+            ImportSession {
+                owner,
+                format,
+                column_map,
+                data: Vec::new(),
+                expires_at,
+            },
+        )
+    });
+    Ok(ImportInfo { import_id, expires_at })
+}
+
+// 2.49.2 upload_import_chunk Function:
+// Chunks are appended in call order; returns the bytes received so far.
+#[ic_cdk::update(guard = "admit_update")]
+fn upload_import_chunk(import_id: u64, data: Vec<u8>) -> Result<u64, Error> {
+    crate::call_stats::count_call("upload_import_chunk");
+    let owner = require_role(Role::Admin)?;
+    owned_session(import_id, owner, |session| {
+        if session.data.len() + data.len() > MAX_IMPORT_BYTES {
+            return Err(Error::validation(format!(
+                "an import can be at most {} bytes",
+                MAX_IMPORT_BYTES
+            )));
+        }
+        session.data.extend_from_slice(&data);
+        Ok(session.data.len() as u64)
+    })?
+}
+
+// 2.49.3 import_crisis_updates Function:
+// Imports every valid row and closes the session. Imported updates are
+// published directly and attributed to the importing admin.
+#[ic_cdk::update(guard = "admit_update")]
+async fn import_crisis_updates(import_id: u64) -> Result<ImportReport, Error> {
+    crate::call_stats::count_call("import_crisis_updates");
+    let author = require_role(Role::Admin)?;
+    owned_session(import_id, author, |_| ())?;
+    let session = IMPORT_SESSIONS
+        .with(|sessions| sessions.borrow_mut().remove(&import_id))
+        .ok_or_else(|| Error::not_found(format!("an import with id={} not found", import_id)))?;
+    let rows = parse_rows(&session)?;
+    let now = time();
+    let mut report = ImportReport {
+        imported: 0,
+        failed: 0,
+        rows: Vec::with_capacity(rows.len()),
+    };
+    for (index, row) in rows.into_iter().enumerate() {
+        let stored = match row.and_then(|row| row_to_update(row, author, now)) {
+            Ok(update) => store_imported(update).await,
+            Err(err) => Err(err),
+        };
+        let (crisis_update_id, error) = match stored {
+            Ok(id) => {
+                report.imported += 1;
+                (Some(id), None)
+            }
+            Err(err) => {
+                report.failed += 1;
+                (None, Some(err))
+            }
+        };
+        report.rows.push(ImportRowResult {
+            row: index as u64 + 1,
+            crisis_update_id,
+            error,
+        });
+    }
+    Ok(report)
+}
@@ -0,0 +1,159 @@
+// Consistency checks and repair for the secondary indexes kept beside primary
+// storage: the location index (areas.rs), the dispatcher priority queue
+// (priority.rs) and the field search index (field_search.rs). An upgrade
+// interrupted between writing an update and its index entries can leave entries
+// missing or pointing at nothing. Rebuilding walks the locally stored updates in
+// id order in batches, so it stays within the instruction limit; the caller
+// passes back the returned cursor until it is None.
+// The last batch also drops entries of updates that no longer exist.
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::gc::served_here;
+use crate::logs::{log, LogLevel};
+use crate::maintenance::admit_update;
+use crate::{CrisisUpdate, Error, CRISIS_STORAGE};
+use std::ops::Bound;
+
+const REBUILD_BATCH: usize = 500;
+const MAX_SAMPLE_IDS: usize = 20;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum IndexKind {
+    Location,
+    Priority,
+    Field,
+}
+
+impl IndexKind {
+    const ALL: [IndexKind; 3] = [IndexKind::Location, IndexKind::Priority, IndexKind::Field];
+
+    fn is_consistent(self, update: &CrisisUpdate) -> bool {
+        match self {
+            IndexKind::Location => crate::areas::is_indexed(update),
+            IndexKind::Priority => crate::priority::is_queued_correctly(update),
+            IndexKind::Field => crate::field_search::is_indexed(update),
+        }
+    }
+
+    fn reindex(self, update: &CrisisUpdate) {
+        match self {
+            IndexKind::Location => crate::areas::index_update(update),
+            IndexKind::Priority => crate::priority::sync_priority_queue(update),
+            IndexKind::Field => crate::field_search::index_update(update),
+        }
+    }
+
+    fn count_stale(self) -> u64 {
+        match self {
+            IndexKind::Location => crate::areas::count_orphaned_index_entries(&served_here),
+            IndexKind::Priority => crate::priority::count_stale_queue_entries(&served_here),
+            IndexKind::Field => crate::field_search::count_orphaned_index_entries(&served_here),
+        }
+    }
+
+    fn remove_stale(self) -> u64 {
+        match self {
+            IndexKind::Location => crate::areas::remove_orphaned_index_entries(&served_here),
+            IndexKind::Priority => crate::priority::remove_stale_queue_entries(&served_here),
+            IndexKind::Field => crate::field_search::remove_orphaned_index_entries(&served_here),
+        }
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct IndexReport {
+    kind: IndexKind,
+    // Locally stored updates checked
+    checked: u64,
+    // Updates whose entries are missing or out of date
+    inconsistent: u64,
+    // Entries of updates that no longer exist, or that cannot be reached from their update
+    stale_entries: u64,
+    // Up to 20 ids of inconsistent updates
+    sample_ids: Vec<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct RebuildProgress {
+    kind: IndexKind,
+    reindexed: u64,
+    // Set once the last batch ran
+    stale_entries_removed: u64,
+    // Pass back as start_after to continue; None when the rebuild is complete
+    next_start_after: Option<u64>,
+}
+
+fn verify(kind: IndexKind) -> IndexReport {
+    let mut report = IndexReport {
+        kind,
+        checked: 0,
+        inconsistent: 0,
+        stale_entries: kind.count_stale(),
+        sample_ids: Vec::new(),
+    };
+    CRISIS_STORAGE.with(|s| {
+        for (id, update) in s.borrow().iter() {
+            report.checked += 1;
+            if !kind.is_consistent(&update) {
+                report.inconsistent += 1;
+                if report.sample_ids.len() < MAX_SAMPLE_IDS {
+                    report.sample_ids.push(id);
+                }
+            }
+        }
+    });
+    report
+}
+
+// 2.67.1 verify_indexes Function:
+// Reports inconsistencies in every secondary index without changing anything.
+#[ic_cdk::query(guard = "admit_read")]
+fn verify_indexes() -> Result<Vec<IndexReport>, Error> {
+    require_role(Role::Admin)?;
+    Ok(IndexKind::ALL.into_iter().map(verify).collect())
+}
+
+// 2.67.2 rebuild_indexes Function:
+// Re-indexes the next batch of updates after start_after (None to begin).
+#[ic_cdk::update(guard = "admit_update")]
+fn rebuild_indexes(kind: IndexKind, start_after: Option<u64>) -> Result<RebuildProgress, Error> {
+    crate::call_stats::count_call("rebuild_indexes");
+    require_role(Role::Admin)?;
+    let start = match start_after {
+        Some(id) => Bound::Excluded(id),
+        None => Bound::Unbounded,
+    };
+    let batch: Vec<CrisisUpdate> = CRISIS_STORAGE.with(|s| {
+        s.borrow()
+            .range((start, Bound::Unbounded))
+            .take(REBUILD_BATCH + 1)
+            .map(|(_, update)| update)
+            .collect()
+    });
+    let more = batch.len() > REBUILD_BATCH;
+    let batch = &batch[..batch.len().min(REBUILD_BATCH)];
+    for update in batch {
+        kind.reindex(update);
+    }
+    let (stale_entries_removed, next_start_after) = if more {
+        (0, batch.last().map(|update| update.id))
+    } else {
+        (kind.remove_stale(), None)
+    };
+    log(
+        LogLevel::Info,
+        Some("rebuild_indexes"),
+        &format!(
+            "{:?} index: re-indexed {} updates, removed {} stale entries",
+            kind,
+            batch.len(),
+            stale_entries_removed
+        ),
+    );
+    Ok(RebuildProgress {
+        kind,
+        reindexed: batch.len() as u64,
+        stale_entries_removed,
+        next_start_after,
+    })
+}
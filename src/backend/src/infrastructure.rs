@@ -0,0 +1,301 @@
+// The state of roads, bridges, power lines and water mains, so logistics teams
+// can plan routes around damaged infrastructure. Each entry is a segment given
+// as a path of points (a single point for a bridge or a substation). Verified
+// organisations and coordinators add entries; any of them may report a change
+// of state, since the organisation that first mapped a road is rarely the one
+// that finds it blocked. Geo queries match on the points of the path, so long
+// segments should be given with intermediate points.
+use crate::access::{has_role, require_any_role, Role};
+use crate::access_mode::admit_read;
+use crate::geo::{BoundingBox, Coordinates};
+use crate::ids::IdCounter;
+use crate::maintenance::admit_update;
+use crate::normalize::{normalize_line, normalize_multiline};
+use crate::response::{fit, QueryResult};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_NAME_CHARS: usize = 200;
+const MAX_NOTE_CHARS: usize = 1000;
+const MAX_PATH_POINTS: usize = 50;
+const MAX_NEARBY_SEGMENTS: usize = 50;
+const MAX_RADIUS_KM: f64 = 500.0;
+
+const REPORTERS: [Role; 2] = [Role::VerifiedOrg, Role::Coordinator];
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+pub(crate) enum InfrastructureKind {
+    #[default]
+    Road,
+    Bridge,
+    Power,
+    Water,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+pub(crate) enum InfrastructureState {
+    #[default]
+    Open,
+    // Usable with care, e.g. one lane passable or reduced supply
+    Damaged,
+    Closed,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct InfrastructureStatus {
+    id: u64,
+    kind: InfrastructureKind,
+    name: String,
+    // Points along the segment in order; one point for a site
+    path: Vec<Coordinates>,
+    state: InfrastructureState,
+    note: String,
+    reported_by: Principal,
+    created_at: u64,
+    state_changed_by: Principal,
+    state_changed_at: u64,
+}
+
+// Implementing Storable and BoundedStorable traits for InfrastructureStatus
+impl Storable for InfrastructureStatus {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for InfrastructureStatus {
+    const MAX_SIZE: u32 = 8192;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct InfrastructureStatusPayload {
+    kind: InfrastructureKind,
+    name: String,
+    path: Vec<Coordinates>,
+    state: InfrastructureState,
+    note: String,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct NearbyInfrastructure {
+    infrastructure: InfrastructureStatus,
+    // To the closest point of the path
+    distance_km: f64,
+}
+
+thread_local! {
+    static INFRASTRUCTURE_ID_COUNTER: RefCell<IdCounter> =
+        RefCell::new(IdCounter::init(104, "infrastructure segments"));
+
+    static INFRASTRUCTURE_STORAGE: RefCell<StableBTreeMap<u64, InfrastructureStatus, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(105)))
+    ));
+}
+
+fn validate_note(note: &str) -> Result<String, Error> {
+    let note = normalize_multiline(note);
+    if note.chars().count() > MAX_NOTE_CHARS {
+        return Err(Error::validation(format!(
+            "notes must be at most {} characters",
+            MAX_NOTE_CHARS
+        )));
+    }
+    Ok(note)
+}
+
+fn validate_payload(payload: &mut InfrastructureStatusPayload) -> Result<(), Error> {
+    payload.name = normalize_line(&payload.name);
+    if payload.name.is_empty() || payload.name.chars().count() > MAX_NAME_CHARS {
+        return Err(Error::validation(format!(
+            "names must be between 1 and {} characters",
+            MAX_NAME_CHARS
+        )));
+    }
+    if payload.path.is_empty() || payload.path.len() > MAX_PATH_POINTS {
+        return Err(Error::validation(format!(
+            "a path must have between 1 and {} points",
+            MAX_PATH_POINTS
+        )));
+    }
+    for point in &payload.path {
+        point.validate()?;
+    }
+    payload.note = validate_note(&payload.note)?;
+    Ok(())
+}
+
+fn find_segment(id: u64) -> Result<InfrastructureStatus, Error> {
+    INFRASTRUCTURE_STORAGE.with(|s| s.borrow().get(&id)).ok_or_else(|| {
+        Error::not_found(format!("an infrastructure segment with id={} not found", id)).with_detail("id", id)
+    })
+}
+
+// Loads a segment the caller reported, or any segment for coordinators
+fn find_own_segment(id: u64) -> Result<InfrastructureStatus, Error> {
+    let segment = find_segment(id)?;
+    let caller = caller();
+    if segment.reported_by != caller && !has_role(&caller, Role::Coordinator) {
+        return Err(Error::unauthorized(format!(
+            "caller did not report the infrastructure segment with id={}",
+            id
+        )));
+    }
+    Ok(segment)
+}
+
+fn store_segment(segment: &InfrastructureStatus) -> Result<(), Error> {
+    crate::ensure_storable(segment, "infrastructure segment")?;
+    INFRASTRUCTURE_STORAGE.with(|s| s.borrow_mut().insert(segment.id, segment.clone()));
+    Ok(())
+}
+
+fn matches(segment: &InfrastructureStatus, kind: Option<InfrastructureKind>, impaired_only: bool) -> bool {
+    kind.is_none_or(|kind| segment.kind == kind) && (!impaired_only || segment.state != InfrastructureState::Open)
+}
+
+// 2.76.1 add_infrastructure_status Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn add_infrastructure_status(mut payload: InfrastructureStatusPayload) -> Result<InfrastructureStatus, Error> {
+    crate::call_stats::count_call("add_infrastructure_status");
+    let reporter = require_any_role(&REPORTERS)?;
+    validate_payload(&mut payload)?;
+    let now = time();
+    let segment = InfrastructureStatus {
+        id: crate::ids::next_id(&INFRASTRUCTURE_ID_COUNTER)?,
+        kind: payload.kind,
+        name: payload.name,
+        path: payload.path,
+        state: payload.state,
+        note: payload.note,
+        reported_by: reporter,
+        created_at: now,
+        state_changed_by: reporter,
+        state_changed_at: now,
+    };
+    store_segment(&segment)?;
+    Ok(segment)
+}
+
+// 2.76.2 update_infrastructure_status Function:
+// Replaces the whole entry; only its reporter or a coordinator may do so.
+#[ic_cdk::update(guard = "admit_update")]
+fn update_infrastructure_status(
+    id: u64,
+    mut payload: InfrastructureStatusPayload,
+) -> Result<InfrastructureStatus, Error> {
+    crate::call_stats::count_call("update_infrastructure_status");
+    let mut segment = find_own_segment(id)?;
+    validate_payload(&mut payload)?;
+    if segment.state != payload.state {
+        segment.state = payload.state;
+        segment.state_changed_by = caller();
+        segment.state_changed_at = time();
+    }
+    segment.kind = payload.kind;
+    segment.name = payload.name;
+    segment.path = payload.path;
+    segment.note = payload.note;
+    store_segment(&segment)?;
+    Ok(segment)
+}
+
+// 2.76.3 set_infrastructure_state Function:
+// Any verified organisation or coordinator may report a change of state.
+#[ic_cdk::update(guard = "admit_update")]
+fn set_infrastructure_state(id: u64, state: InfrastructureState, note: String) -> Result<InfrastructureStatus, Error> {
+    crate::call_stats::count_call("set_infrastructure_state");
+    let reporter = require_any_role(&REPORTERS)?;
+    let mut segment = find_segment(id)?;
+    segment.note = validate_note(&note)?;
+    segment.state = state;
+    segment.state_changed_by = reporter;
+    segment.state_changed_at = time();
+    store_segment(&segment)?;
+    Ok(segment)
+}
+
+// 2.76.4 delete_infrastructure_status Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn delete_infrastructure_status(id: u64) -> Result<InfrastructureStatus, Error> {
+    crate::call_stats::count_call("delete_infrastructure_status");
+    let segment = find_own_segment(id)?;
+    INFRASTRUCTURE_STORAGE.with(|s| s.borrow_mut().remove(&id));
+    Ok(segment)
+}
+
+// 2.76.5 get_infrastructure_status Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_infrastructure_status(id: u64) -> Result<InfrastructureStatus, Error> {
+    find_segment(id)
+}
+
+// 2.76.6 list_infrastructure_in_area Function:
+// Segments with at least one point inside the box; impaired_only leaves out open ones.
+#[ic_cdk::query(guard = "admit_read")]
+fn list_infrastructure_in_area(
+    bbox: BoundingBox,
+    kind: Option<InfrastructureKind>,
+    impaired_only: bool,
+) -> Result<QueryResult<InfrastructureStatus>, Error> {
+    bbox.validate()?;
+    fit(INFRASTRUCTURE_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, segment)| segment)
+            .filter(|segment| matches(segment, kind, impaired_only))
+            .filter(|segment| segment.path.iter().any(|point| bbox.contains(point)))
+            .collect()
+    }))
+}
+
+// 2.76.7 find_infrastructure_near Function:
+// Segments with a point within radius_km of the origin, nearest first.
+#[ic_cdk::query(guard = "admit_read")]
+fn find_infrastructure_near(
+    latitude: f64,
+    longitude: f64,
+    radius_km: f64,
+    kind: Option<InfrastructureKind>,
+    impaired_only: bool,
+) -> Result<QueryResult<NearbyInfrastructure>, Error> {
+    let origin = Coordinates { latitude, longitude };
+    origin.validate()?;
+    if !(radius_km > 0.0 && radius_km <= MAX_RADIUS_KM) {
+        return Err(Error::validation(format!(
+            "the radius must be greater than 0 and at most {} km",
+            MAX_RADIUS_KM
+        )));
+    }
+    let mut nearby: Vec<NearbyInfrastructure> = INFRASTRUCTURE_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, segment)| segment)
+            .filter(|segment| matches(segment, kind, impaired_only))
+            .filter_map(|segment| {
+                let distance_km = segment
+                    .path
+                    .iter()
+                    .map(|point| origin.distance_km(point))
+                    .fold(f64::INFINITY, f64::min);
+                (distance_km <= radius_km).then_some(NearbyInfrastructure {
+                    infrastructure: segment,
+                    distance_km,
+                })
+            })
+            .collect()
+    });
+    nearby.sort_by(|a, b| a.distance_km.total_cmp(&b.distance_km));
+    let total_count = nearby.len() as u64;
+    nearby.truncate(MAX_NEARBY_SEGMENTS);
+    Ok(QueryResult::limited(nearby, total_count))
+}
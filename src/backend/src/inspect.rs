@@ -0,0 +1,121 @@
+// Cheap checks on ingress update calls before they are accepted, so banned
+// principals, anonymous callers, writes during maintenance and oversized
+// payloads cost no consensus or execution cycles during attack or panic
+// traffic. Inspection runs on a single replica and does not see calls from
+// other canisters; bans and maintenance mode are also enforced by every update
+// method's guard.
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::bans::reject_banned;
+use crate::maintenance::{admit_update, is_read_only, READ_ONLY_EXEMPT_METHODS};
+use crate::settings::{get_settings, update_settings};
+use crate::Error;
+use candid::Principal;
+use ic_cdk::api::call::{accept_message, arg_data_raw_size, method_name};
+use ic_cdk::api::caller;
+
+// The IC rejects ingress messages above 2 MiB regardless
+const MAX_ARG_BYTES_LIMIT: u64 = 2 * 1024 * 1024;
+const MIN_ARG_BYTES_LIMIT: u64 = 1024;
+const DEFAULT_MAX_ARG_BYTES: u64 = 256 * 1024;
+const MAX_METHOD_LIMITS: usize = 50;
+// Import chunks are sized by the client, so they keep the IC maximum unless configured
+const DEFAULT_METHOD_MAX_ARG_BYTES: [(&str, u64); 1] = [("upload_import_chunk", MAX_ARG_BYTES_LIMIT)];
+
+// Update methods open to the anonymous principal: reporters without an
+// identity, sensors that authenticate with an API key and public view counting
+const ANONYMOUS_METHODS: [&str; 3] = [
+    "add_anonymous_crisis_update",
+    "add_crisis_update_with_key",
+    "record_crisis_update_views",
+];
+
+// Update methods banned principals may still call
+const BAN_EXEMPT_METHODS: [&str; 1] = ["erase_my_data"];
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct IngressLimits {
+    // None applies the built-in default
+    pub(crate) max_arg_bytes: Option<u64>,
+    // Per-method overrides of `max_arg_bytes`, e.g. larger for `upload_import_chunk`
+    pub(crate) method_max_arg_bytes: Vec<(String, u64)>,
+}
+
+fn max_arg_bytes(limits: &IngressLimits, method: &str) -> u64 {
+    limits
+        .method_max_arg_bytes
+        .iter()
+        .find(|(name, _)| name == method)
+        .map(|(_, bytes)| *bytes)
+        .or_else(|| {
+            DEFAULT_METHOD_MAX_ARG_BYTES
+                .iter()
+                .find(|(name, _)| *name == method)
+                .map(|(_, bytes)| *bytes)
+        })
+        .or(limits.max_arg_bytes)
+        .unwrap_or(DEFAULT_MAX_ARG_BYTES)
+}
+
+fn validate_limit(bytes: u64) -> Result<(), Error> {
+    if !(MIN_ARG_BYTES_LIMIT..=MAX_ARG_BYTES_LIMIT).contains(&bytes) {
+        return Err(Error::validation(format!(
+            "argument size limits must be between {} and {} bytes",
+            MIN_ARG_BYTES_LIMIT, MAX_ARG_BYTES_LIMIT
+        ))
+        .with_detail("bytes", bytes));
+    }
+    Ok(())
+}
+
+#[ic_cdk::inspect_message]
+fn inspect_message() {
+    let method = method_name();
+    if !BAN_EXEMPT_METHODS.contains(&method.as_str()) && reject_banned().is_err() {
+        return;
+    }
+    if is_read_only() && !READ_ONLY_EXEMPT_METHODS.contains(&method.as_str()) {
+        return;
+    }
+    if caller() == Principal::anonymous() && !ANONYMOUS_METHODS.contains(&method.as_str()) {
+        return;
+    }
+    let limits = get_settings().ingress_limits.unwrap_or_default();
+    if arg_data_raw_size() as u64 > max_arg_bytes(&limits, &method) {
+        return;
+    }
+    accept_message();
+}
+
+// 2.61.1 set_ingress_limits Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn set_ingress_limits(mut limits: IngressLimits) -> Result<IngressLimits, Error> {
+    crate::call_stats::count_call("set_ingress_limits");
+    require_role(Role::Admin)?;
+    if let Some(bytes) = limits.max_arg_bytes {
+        validate_limit(bytes)?;
+    }
+    if limits.method_max_arg_bytes.len() > MAX_METHOD_LIMITS {
+        return Err(Error::validation(format!(
+            "at most {} per-method limits can be set",
+            MAX_METHOD_LIMITS
+        )));
+    }
+    for (method, bytes) in &limits.method_max_arg_bytes {
+        if method.is_empty() {
+            return Err(Error::validation("method names must not be empty"));
+        }
+        validate_limit(*bytes)?;
+    }
+    limits.method_max_arg_bytes.sort();
+    limits.method_max_arg_bytes.dedup_by(|a, b| a.0 == b.0);
+    let stored = limits.clone();
+    update_settings(|s| s.ingress_limits = Some(stored))?;
+    Ok(limits)
+}
+
+// 2.61.2 get_ingress_limits Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_ingress_limits() -> IngressLimits {
+    get_settings().ingress_limits.unwrap_or_default()
+}
@@ -0,0 +1,374 @@
+// Relief supply stock held by organizations at shelters and warehouses. Stock
+// only changes through receive and dispatch transactions, which are kept per
+// item as its history. A dispatch can be recorded against the resource request
+// it fulfills, and one that takes an item to or below its low-stock threshold
+// notifies the owning organization and coordinators.
+use crate::access::{has_role, holders_of, require_any_role, require_role, Role};
+use crate::access_mode::admit_read;
+use crate::ids::IdCounter;
+use crate::maintenance::admit_update;
+use crate::normalize::normalize_line;
+use crate::notifications::{try_notify, NotificationKind};
+use crate::resources::{do_insert_resource_request, find_resource_request, ResourceType};
+use crate::response::{fit, QueryResult};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_ITEM_NAME_CHARS: usize = 100;
+const MAX_UNIT_CHARS: usize = 20;
+const MAX_LOCATION_CHARS: usize = 200;
+const MAX_NOTE_CHARS: usize = 300;
+
+// Roles that may see stock levels
+const STOCK_VIEWERS: [Role; 3] = [Role::VerifiedOrg, Role::Coordinator, Role::Responder];
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct StockItem {
+    id: u64,
+    // The organization holding the stock
+    owner: Principal,
+    resource_type: ResourceType,
+    item: String,
+    // e.g. "boxes", "litres"
+    unit: String,
+    quantity: u64,
+    location: String,
+    shelter_id: Option<u64>,
+    // Owner and coordinators are notified when a dispatch leaves this much or less
+    low_stock_threshold: Option<u64>,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+// Implementing Storable and BoundedStorable traits for StockItem
+impl Storable for StockItem {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for StockItem {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl StockItem {
+    fn is_low(&self) -> bool {
+        self.low_stock_threshold
+            .is_some_and(|threshold| self.quantity <= threshold)
+    }
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct StockItemPayload {
+    resource_type: ResourceType,
+    item: String,
+    unit: String,
+    location: String,
+    shelter_id: Option<u64>,
+    low_stock_threshold: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum StockTransactionKind {
+    Received,
+    Dispatched,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct StockTransaction {
+    id: u64,
+    stock_item_id: u64,
+    kind: StockTransactionKind,
+    quantity: u64,
+    // The stock level after the transaction
+    balance: u64,
+    // The request a dispatch was sent to fulfill
+    resource_request_id: Option<u64>,
+    note: Option<String>,
+    recorded_by: Principal,
+    recorded_at: u64,
+}
+
+// Implementing Storable and BoundedStorable traits for StockTransaction
+impl Storable for StockTransaction {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for StockTransaction {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static STOCK_ITEM_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(96, "stock items"));
+
+    static STOCK_ITEMS: RefCell<StableBTreeMap<u64, StockItem, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(97)))
+    ));
+
+    static STOCK_TRANSACTION_ID_COUNTER: RefCell<IdCounter> =
+        RefCell::new(IdCounter::init(98, "stock transactions"));
+
+    // (stock item id, transaction id)
+    static STOCK_TRANSACTIONS: RefCell<StableBTreeMap<(u64, u64), StockTransaction, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(99)))
+    ));
+}
+
+fn validate_text(field: &str, value: &str, max_chars: usize) -> Result<String, Error> {
+    let value = normalize_line(value);
+    if value.is_empty() || value.chars().count() > max_chars {
+        return Err(
+            Error::validation(format!("{} must be between 1 and {} characters", field, max_chars))
+                .with_detail("field", field),
+        );
+    }
+    Ok(value)
+}
+
+fn validate_note(note: Option<String>) -> Result<Option<String>, Error> {
+    note.map(|note| validate_text("note", &note, MAX_NOTE_CHARS))
+        .transpose()
+}
+
+fn validate_stock_payload(payload: &mut StockItemPayload) -> Result<(), Error> {
+    payload.item = validate_text("item", &payload.item, MAX_ITEM_NAME_CHARS)?;
+    payload.unit = validate_text("unit", &payload.unit, MAX_UNIT_CHARS)?;
+    payload.location = validate_text("location", &payload.location, MAX_LOCATION_CHARS)?;
+    if let Some(id) = payload.shelter_id {
+        if !crate::shelters::shelter_exists(id) {
+            return Err(Error::not_found(format!("a shelter with id={} not found", id)).with_detail("shelter_id", id));
+        }
+    }
+    Ok(())
+}
+
+fn find_stock_item(id: u64) -> Result<StockItem, Error> {
+    STOCK_ITEMS
+        .with(|s| s.borrow().get(&id))
+        .ok_or_else(|| Error::not_found(format!("a stock item with id={} not found", id)).with_detail("id", id))
+}
+
+// Loads a stock item and checks that the caller is its owner, a coordinator or an admin
+fn find_managed_stock_item(id: u64) -> Result<(StockItem, Principal), Error> {
+    let item = find_stock_item(id)?;
+    let caller = require_any_role(&[Role::VerifiedOrg, Role::Coordinator])?;
+    if item.owner != caller && !has_role(&caller, Role::Coordinator) {
+        return Err(Error::unauthorized(format!(
+            "caller does not manage the stock item with id={}",
+            id
+        )));
+    }
+    Ok((item, caller))
+}
+
+fn record_transaction(
+    item: &StockItem,
+    kind: StockTransactionKind,
+    quantity: u64,
+    resource_request_id: Option<u64>,
+    note: Option<String>,
+    recorded_by: Principal,
+    now: u64,
+) -> Result<(), Error> {
+    let transaction = StockTransaction {
+        id: crate::ids::next_id(&STOCK_TRANSACTION_ID_COUNTER)?,
+        stock_item_id: item.id,
+        kind,
+        quantity,
+        balance: item.quantity,
+        resource_request_id,
+        note,
+        recorded_by,
+        recorded_at: now,
+    };
+    crate::ensure_storable(&transaction, "stock transaction")?;
+    STOCK_TRANSACTIONS.with(|s| s.borrow_mut().insert((item.id, transaction.id), transaction));
+    Ok(())
+}
+
+fn notify_low_stock(item: &StockItem) {
+    let message = format!(
+        "Low stock: {} {} of {} left at {}",
+        item.quantity, item.unit, item.item, item.location
+    );
+    let mut recipients = holders_of(&[Role::Coordinator]);
+    recipients.push(item.owner);
+    recipients.sort();
+    recipients.dedup();
+    for recipient in recipients {
+        try_notify(recipient, NotificationKind::LowStock, message.clone(), Some(item.id));
+    }
+}
+
+// 2.73.1 add_stock_item Function:
+// Starts with no stock; record deliveries with `receive_stock`.
+#[ic_cdk::update(guard = "admit_update")]
+fn add_stock_item(mut payload: StockItemPayload) -> Result<StockItem, Error> {
+    crate::call_stats::count_call("add_stock_item");
+    let owner = require_role(Role::VerifiedOrg)?;
+    validate_stock_payload(&mut payload)?;
+    let item = StockItem {
+        id: crate::ids::next_id(&STOCK_ITEM_ID_COUNTER)?,
+        owner,
+        resource_type: payload.resource_type,
+        item: payload.item,
+        unit: payload.unit,
+        quantity: 0,
+        location: payload.location,
+        shelter_id: payload.shelter_id,
+        low_stock_threshold: payload.low_stock_threshold,
+        created_at: time(),
+        updated_at: None,
+    };
+    crate::ensure_storable(&item, "stock item")?;
+    STOCK_ITEMS.with(|s| s.borrow_mut().insert(item.id, item.clone()));
+    Ok(item)
+}
+
+// 2.73.2 update_stock_item Function:
+// Changes the description of an item; the quantity only changes through transactions.
+#[ic_cdk::update(guard = "admit_update")]
+fn update_stock_item(id: u64, mut payload: StockItemPayload) -> Result<StockItem, Error> {
+    crate::call_stats::count_call("update_stock_item");
+    let (mut item, _) = find_managed_stock_item(id)?;
+    validate_stock_payload(&mut payload)?;
+    item.resource_type = payload.resource_type;
+    item.item = payload.item;
+    item.unit = payload.unit;
+    item.location = payload.location;
+    item.shelter_id = payload.shelter_id;
+    item.low_stock_threshold = payload.low_stock_threshold;
+    item.updated_at = Some(time());
+    crate::ensure_storable(&item, "stock item")?;
+    STOCK_ITEMS.with(|s| s.borrow_mut().insert(item.id, item.clone()));
+    Ok(item)
+}
+
+// 2.73.3 receive_stock Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn receive_stock(id: u64, quantity: u64, note: Option<String>) -> Result<StockItem, Error> {
+    crate::call_stats::count_call("receive_stock");
+    let (mut item, caller) = find_managed_stock_item(id)?;
+    if quantity == 0 {
+        return Err(Error::validation("quantity must be greater than zero"));
+    }
+    let note = validate_note(note)?;
+    let now = time();
+    item.quantity = item
+        .quantity
+        .checked_add(quantity)
+        .ok_or_else(|| Error::validation("the stock level would overflow"))?;
+    item.updated_at = Some(now);
+    record_transaction(&item, StockTransactionKind::Received, quantity, None, note, caller, now)?;
+    STOCK_ITEMS.with(|s| s.borrow_mut().insert(item.id, item.clone()));
+    Ok(item)
+}
+
+// 2.73.4 dispatch_stock Function:
+// A dispatch against a resource request counts toward that request's fulfillment.
+#[ic_cdk::update(guard = "admit_update")]
+fn dispatch_stock(
+    id: u64,
+    quantity: u64,
+    resource_request_id: Option<u64>,
+    note: Option<String>,
+) -> Result<StockItem, Error> {
+    crate::call_stats::count_call("dispatch_stock");
+    let (mut item, caller) = find_managed_stock_item(id)?;
+    if quantity == 0 {
+        return Err(Error::validation("quantity must be greater than zero"));
+    }
+    if quantity > item.quantity {
+        return Err(Error::conflict(format!(
+            "only {} {} of stock item id={} are available",
+            item.quantity, item.unit, id
+        ))
+        .with_detail("available", item.quantity));
+    }
+    let note = validate_note(note)?;
+    let request = resource_request_id.map(find_resource_request).transpose()?;
+    if let Some(request) = &request {
+        if !crate::matching::is_request_open(request) {
+            return Err(Error::conflict(format!(
+                "resource request with id={} is no longer open",
+                request.id
+            )));
+        }
+        if request.resource_type != item.resource_type {
+            return Err(Error::validation(format!(
+                "resource request with id={} is for a different type of resource",
+                request.id
+            )));
+        }
+    }
+    let now = time();
+    let was_low = item.is_low();
+    item.quantity -= quantity;
+    item.updated_at = Some(now);
+    record_transaction(
+        &item,
+        StockTransactionKind::Dispatched,
+        quantity,
+        resource_request_id,
+        note,
+        caller,
+        now,
+    )?;
+    STOCK_ITEMS.with(|s| s.borrow_mut().insert(item.id, item.clone()));
+    if let Some(mut request) = request {
+        request.record_fulfillment(quantity.min(request.remaining_quantity()), now);
+        do_insert_resource_request(&request);
+    }
+    if item.is_low() && !was_low {
+        notify_low_stock(&item);
+    }
+    Ok(item)
+}
+
+// 2.73.5 list_stock_items Function:
+// Optionally narrowed to a location and to items at or below their threshold.
+#[ic_cdk::query(guard = "admit_read")]
+fn list_stock_items(location: Option<String>, low_only: bool) -> Result<QueryResult<StockItem>, Error> {
+    require_any_role(&STOCK_VIEWERS)?;
+    let location = location.map(|l| normalize_line(&l).to_lowercase());
+    fit(STOCK_ITEMS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, item)| item)
+            .filter(|item| location.as_ref().is_none_or(|l| item.location.to_lowercase() == *l))
+            .filter(|item| !low_only || item.is_low())
+            .collect()
+    }))
+}
+
+// 2.73.6 get_stock_transactions Function:
+// Oldest first.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_stock_transactions(id: u64) -> Result<QueryResult<StockTransaction>, Error> {
+    require_any_role(&STOCK_VIEWERS)?;
+    find_stock_item(id)?;
+    fit(STOCK_TRANSACTIONS.with(|s| {
+        s.borrow()
+            .range((id, 0)..=(id, u64::MAX))
+            .map(|(_, transaction)| transaction)
+            .collect()
+    }))
+}
@@ -0,0 +1,179 @@
+// Periodic background jobs, driven by the canister heartbeat. Each job keeps
+// its own interval so the heartbeat itself stays cheap on rounds with nothing to do,
+// and rounds closer together than the shortest interval return before checking any
+// job. The heartbeat is used rather than timers because some intervals are set at
+// run time (the cycles check) and timers are cleared on upgrade, so every job would
+// have to be re-armed in post_upgrade; with the heartbeat a job only needs a line here.
+use crate::logs::{log, LogLevel};
+use ic_cdk::api::time;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
+pub(crate) const NANOS_PER_SECOND: u64 = 1_000_000_000;
+// No job runs more often than this
+const MIN_HEARTBEAT_GAP_SECS: u64 = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Job {
+    CyclesMonitor,
+    Archive,
+    ExportExpiry,
+    ImportExpiry,
+    TombstoneExpiry,
+    RealtimeClientExpiry,
+    DedupExpiry,
+    Replication,
+    GarbageCollection,
+    CacheWarmup,
+    Anonymization,
+    SitRep,
+    ExpiryResolution,
+    CredentialRecheck,
+    WatchExpiry,
+    AggregatorPublish,
+    WebhookDelivery,
+    StaleReportCheck,
+    HeatIndexRefresh,
+}
+
+thread_local! {
+    static LAST_RUN: RefCell<BTreeMap<Job, u64>> = const { RefCell::new(BTreeMap::new()) };
+
+    static LAST_HEARTBEAT: Cell<u64> = const { Cell::new(0) };
+}
+
+// Returns true and records the run when the job's interval has elapsed
+fn due(job: Job, interval_secs: u64, now: u64) -> bool {
+    LAST_RUN.with(|runs| {
+        let mut runs = runs.borrow_mut();
+        let last = runs.get(&job).copied();
+        if last.is_some_and(|last| now.saturating_sub(last) < interval_secs.saturating_mul(NANOS_PER_SECOND)) {
+            return false;
+        }
+        runs.insert(job, now);
+        true
+    })
+}
+
+#[ic_cdk::heartbeat]
+fn heartbeat() {
+    let now = time();
+    if now.saturating_sub(LAST_HEARTBEAT.with(Cell::get)) < MIN_HEARTBEAT_GAP_SECS * NANOS_PER_SECOND {
+        return;
+    }
+    LAST_HEARTBEAT.with(|last| last.set(now));
+    let _scope = crate::logs::HeartbeatScope::enter();
+    if due(Job::CyclesMonitor, crate::cycles::check_interval_secs(), now) {
+        crate::cycles::check_cycles_balance(now);
+    }
+    if due(Job::Archive, crate::archive::ARCHIVE_CHECK_INTERVAL_SECS, now) && crate::archive::archive_needed() {
+        ic_cdk::spawn(async {
+            if let Err(err) = crate::archive::run_archive().await {
+                log(LogLevel::Error, Some("archive"), &format!("archive run failed: {:?}", err));
+            }
+        });
+    }
+    if due(Job::ExportExpiry, crate::exports::EXPORT_EXPIRY_INTERVAL_SECS, now) {
+        crate::exports::expire_export_sessions(now);
+    }
+    if due(Job::ImportExpiry, crate::imports::IMPORT_EXPIRY_INTERVAL_SECS, now) {
+        crate::imports::expire_import_sessions(now);
+    }
+    if due(
+        Job::TombstoneExpiry,
+        crate::changes::TOMBSTONE_EXPIRY_INTERVAL_SECS,
+        now,
+    ) {
+        crate::changes::expire_tombstones(now);
+    }
+    if due(
+        Job::RealtimeClientExpiry,
+        crate::realtime::CLIENT_EXPIRY_INTERVAL_SECS,
+        now,
+    ) {
+        crate::realtime::expire_idle_clients(now);
+    }
+    if due(Job::DedupExpiry, crate::dedup::DEDUP_EXPIRY_INTERVAL_SECS, now) {
+        crate::dedup::expire_content_hashes(now);
+    }
+    if due(Job::Replication, crate::replication::REPLICATION_INTERVAL_SECS, now)
+        && crate::replication::replication_pending()
+    {
+        ic_cdk::spawn(crate::replication::run_replication());
+    }
+    if due(Job::GarbageCollection, crate::gc::GC_INTERVAL_SECS, now) {
+        if let Err(err) = crate::gc::collect_garbage(now) {
+            log(
+                LogLevel::Error,
+                Some("garbage_collection"),
+                &format!("garbage collection failed: {:?}", err),
+            );
+        }
+    }
+    if due(Job::CacheWarmup, crate::cache::CACHE_CHECK_INTERVAL_SECS, now) && !crate::cache::is_built() {
+        crate::cache::rebuild();
+    }
+    if due(Job::Anonymization, crate::privacy::ANONYMIZATION_INTERVAL_SECS, now) {
+        if let Err(err) = crate::privacy::run_anonymization(now) {
+            log(
+                LogLevel::Error,
+                Some("anonymization"),
+                &format!("anonymization failed: {:?}", err),
+            );
+        }
+    }
+    if due(Job::SitRep, crate::sitreps::SITREP_CHECK_INTERVAL_SECS, now) && crate::sitreps::sitrep_due(now) {
+        if let Err(err) = crate::sitreps::generate_sitrep(now) {
+            log(LogLevel::Error, Some("sitrep"), &format!("situation report failed: {:?}", err));
+        }
+    }
+    if due(Job::ExpiryResolution, crate::expiry::EXPIRY_CHECK_INTERVAL_SECS, now) {
+        if let Err(err) = crate::expiry::resolve_expired_updates(now) {
+            log(
+                LogLevel::Error,
+                Some("expiry_resolution"),
+                &format!("resolving expired updates failed: {:?}", err),
+            );
+        }
+    }
+    if due(
+        Job::CredentialRecheck,
+        crate::credentials::CREDENTIAL_CHECK_INTERVAL_SECS,
+        now,
+    ) {
+        ic_cdk::spawn(async move {
+            if let Err(err) = crate::credentials::recheck_credentials(now).await {
+                log(
+                    LogLevel::Error,
+                    Some("credential_recheck"),
+                    &format!("credential re-check failed: {:?}", err),
+                );
+            }
+        });
+    }
+    if due(Job::WatchExpiry, crate::watches::WATCH_EXPIRY_INTERVAL_SECS, now) {
+        crate::watches::expire_watches(now);
+    }
+    if due(Job::AggregatorPublish, crate::aggregator::AGGREGATOR_INTERVAL_SECS, now)
+        && crate::aggregator::publish_pending(now)
+    {
+        ic_cdk::spawn(crate::aggregator::run_publishing());
+    }
+    if due(Job::WebhookDelivery, crate::webhooks::WEBHOOK_DELIVERY_INTERVAL_SECS, now)
+        && crate::webhooks::deliveries_due(now)
+    {
+        ic_cdk::spawn(crate::webhooks::deliver_webhooks(now));
+    }
+    if due(Job::StaleReportCheck, crate::staleness::STALENESS_CHECK_INTERVAL_SECS, now) {
+        if let Err(err) = crate::staleness::check_stale_updates(now) {
+            log(
+                LogLevel::Error,
+                Some("stale_report_check"),
+                &format!("stale report check failed: {:?}", err),
+            );
+        }
+    }
+    if due(Job::HeatIndexRefresh, crate::heat_index::HEAT_REFRESH_INTERVAL_SECS, now) {
+        crate::heat_index::refresh_heat_index(now);
+    }
+}
@@ -0,0 +1,153 @@
+// Community recognition: a leaderboard of the most active reporters and badges
+// awarded for milestones. Badges are kept per principal and shown on profiles.
+use crate::access::{principal_key, PrincipalKey};
+use crate::access_mode::admit_read;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::moderation::ReviewStatus;
+use crate::response::{fit, QueryResult};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::collections::BTreeMap;
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_LEADERBOARD_SIZE: u64 = 100;
+pub(crate) const CONFIRMED_REPORTS_FOR_BADGE: u64 = 10;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum Badge {
+    FirstReport,
+    TenConfirmedReports,
+    VerifiedResponder,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct BadgeAward {
+    badge: Badge,
+    awarded_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct BadgeSet {
+    awards: Vec<BadgeAward>,
+}
+
+// Implementing Storable and BoundedStorable traits for BadgeSet
+impl Storable for BadgeSet {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for BadgeSet {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum LeaderboardWindow {
+    Day,
+    Week,
+    Month,
+    AllTime,
+}
+
+impl LeaderboardWindow {
+    pub(crate) fn cutoff(self, now: u64) -> u64 {
+        const DAY: u64 = 24 * 60 * 60;
+        let secs = match self {
+            LeaderboardWindow::Day => DAY,
+            LeaderboardWindow::Week => 7 * DAY,
+            LeaderboardWindow::Month => 30 * DAY,
+            LeaderboardWindow::AllTime => return 0,
+        };
+        now.saturating_sub(secs * NANOS_PER_SECOND)
+    }
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct TopReporter {
+    principal: Principal,
+    display_name: Option<String>,
+    reports: u64,
+    badges: Vec<Badge>,
+}
+
+thread_local! {
+    static BADGE_STORAGE: RefCell<StableBTreeMap<PrincipalKey, BadgeSet, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(55)))
+    ));
+}
+
+pub(crate) fn badges_of(principal: &Principal) -> Vec<BadgeAward> {
+    BADGE_STORAGE
+        .with(|s| s.borrow().get(&principal_key(principal)))
+        .unwrap_or_default()
+        .awards
+}
+
+pub(crate) fn remove_badges(principal: &Principal) -> bool {
+    BADGE_STORAGE.with(|s| s.borrow_mut().remove(&principal_key(principal)).is_some())
+}
+
+// Grants a badge once; later awards of the same badge are ignored
+pub(crate) fn award_badge(principal: &Principal, badge: Badge) {
+    let key = principal_key(principal);
+    BADGE_STORAGE.with(|s| {
+        let mut storage = s.borrow_mut();
+        let mut set = storage.get(&key).unwrap_or_default();
+        if set.awards.iter().any(|award| award.badge == badge) {
+            return;
+        }
+        set.awards.push(BadgeAward {
+            badge,
+            awarded_at: time(),
+        });
+        storage.insert(key, set);
+    });
+}
+
+// 2.37.1 get_top_reporters Function:
+// Ranks authors by published reports created within the window; ties go to the earlier reporter.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_top_reporters(window: LeaderboardWindow, limit: u64) -> Result<QueryResult<TopReporter>, Error> {
+    let cutoff = window.cutoff(time());
+    let mut counts: BTreeMap<Principal, (u64, u64)> = BTreeMap::new();
+    CRISIS_STORAGE.with(|service| {
+        for (_, update) in service.borrow().iter() {
+            let published = update.review.is_none_or(|r| r == ReviewStatus::Approved);
+            if let (Some(author), true) = (update.author, published && update.created_at >= cutoff) {
+                let entry = counts.entry(author).or_insert((0, update.created_at));
+                entry.0 += 1;
+                entry.1 = entry.1.min(update.created_at);
+            }
+        }
+    });
+    let mut ranked: Vec<(Principal, (u64, u64))> = counts.into_iter().collect();
+    ranked.sort_by(|(_, (a_reports, a_first)), (_, (b_reports, b_first))| {
+        b_reports.cmp(a_reports).then(a_first.cmp(b_first))
+    });
+    ranked.truncate(limit.clamp(1, MAX_LEADERBOARD_SIZE) as usize);
+    fit(ranked
+        .into_iter()
+        .map(|(principal, (reports, _))| TopReporter {
+            principal,
+            display_name: crate::profiles::display_label(&principal),
+            reports,
+            badges: badges_of(&principal).into_iter().map(|award| award.badge).collect(),
+        })
+        .collect())
+}
+
+// 2.37.2 get_badges Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_badges(principal: Principal) -> Vec<BadgeAward> {
+    badges_of(&principal)
+}
@@ -0,0 +1,91 @@
+// Minimal ICRC-1 client used to move tokens through a configured ledger canister
+use crate::Error;
+use candid::{CandidType, Nat, Principal};
+use sha2::{Digest, Sha256};
+
+#[derive(CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) struct Account {
+    pub(crate) owner: Principal,
+    pub(crate) subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+struct TransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Debug)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+// Derives a 32-byte subaccount from a domain tag and the given parts
+pub(crate) fn derive_subaccount(domain: &str, parts: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(domain.as_bytes());
+    for part in parts {
+        hasher.update((part.len() as u32).to_be_bytes());
+        hasher.update(part);
+    }
+    hasher.finalize().to_vec()
+}
+
+fn nat_to_u128(value: Nat) -> Result<u128, Error> {
+    u128::try_from(value.0)
+        .map_err(|_| Error::external_call_failed("ledger returned an amount that does not fit into 128 bits"))
+}
+
+fn call_failed(method: &str, err: impl std::fmt::Debug) -> Error {
+    Error::external_call_failed(format!("ledger call {} failed: {:?}", method, err))
+}
+
+pub(crate) async fn balance_of(ledger: Principal, account: Account) -> Result<u128, Error> {
+    let (balance,): (Nat,) = ic_cdk::call(ledger, "icrc1_balance_of", (account,))
+        .await
+        .map_err(|e| call_failed("icrc1_balance_of", e))?;
+    nat_to_u128(balance)
+}
+
+pub(crate) async fn fee(ledger: Principal) -> Result<u128, Error> {
+    let (fee,): (Nat,) = ic_cdk::call(ledger, "icrc1_fee", ())
+        .await
+        .map_err(|e| call_failed("icrc1_fee", e))?;
+    nat_to_u128(fee)
+}
+
+// Transfers from one of this canister's subaccounts and returns the block index
+pub(crate) async fn transfer(
+    ledger: Principal,
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    amount: u128,
+    fee: u128,
+) -> Result<u128, Error> {
+    let arg = TransferArg {
+        from_subaccount,
+        to,
+        amount: Nat::from(amount),
+        fee: Some(Nat::from(fee)),
+        memo: None,
+        created_at_time: None,
+    };
+    let (result,): (Result<Nat, TransferError>,) = ic_cdk::call(ledger, "icrc1_transfer", (arg,))
+        .await
+        .map_err(|e| call_failed("icrc1_transfer", e))?;
+    match result {
+        Ok(block_index) => nat_to_u128(block_index),
+        Err(err) => Err(call_failed("icrc1_transfer", err)),
+    }
+}
@@ -1,39 +1,408 @@
 #[macro_use]
 extern crate serde;
-use candid::{Decode, Encode};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
 use ic_cdk::api::time;
+use ic_websocket_cdk::{
+    CanisterWsCloseArguments, CanisterWsCloseResult, CanisterWsGetMessagesArguments, CanisterWsGetMessagesResult,
+    CanisterWsMessageArguments, CanisterWsMessageResult, CanisterWsOpenArguments, CanisterWsOpenResult,
+};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{BoundedStorable, Cell, DefaultMemoryImpl, StableBTreeMap, Storable};
+use ic_stable_structures::{BoundedStorable, DefaultMemoryImpl, StableBTreeMap, Storable};
 use std::{borrow::Cow, cell::RefCell};
 
+mod access;
+mod access_mode;
+mod after_action;
+mod aggregator;
+mod alerts;
+mod anonymous;
+mod api_keys;
+mod approvals;
+mod archive;
+mod areas;
+mod bans;
+mod cache;
+mod calendar;
+mod call_stats;
+mod casualties;
+mod assignments;
+mod changes;
+mod clusters;
+mod contact_info;
+mod contacts;
+mod credentials;
+mod cycles;
+mod damage;
+mod dedup;
+mod distributions;
+mod donations;
+mod drafts;
+mod escalation;
+mod evacuation;
+mod expiry;
+mod exports;
+mod federated;
+mod field_search;
+mod fundraising;
+mod gc;
+mod geo;
+mod heat_index;
+mod heatmap;
+mod http;
+mod ids;
+mod imports;
+mod indexes;
+mod infrastructure;
+mod inspect;
+mod inventory;
+mod jobs;
+mod leaderboard;
+mod ledger;
+mod links;
+mod logs;
+mod maintenance;
+mod matching;
+mod medical;
+mod metadata;
+mod metrics;
+mod migrations;
+mod missing_persons;
+mod moderation;
+mod normalize;
+mod notifications;
+mod pins;
+mod priority;
+mod privacy;
+mod profiles;
+mod query_limits;
+mod quick_updates;
+mod realtime;
+mod redaction;
+mod regions;
+mod replication;
+mod reputation;
+mod resources;
+mod response;
+mod response_times;
+mod safety;
+mod sanitize;
+mod search;
+mod seed;
+mod settings;
+mod sharding;
+mod sitreps;
+mod shelters;
+mod signatures;
+mod snapshots;
+mod sla;
+mod staleness;
+mod teams;
+mod tenants;
+mod text;
+mod timeline;
+mod uniqueness;
+mod views;
+mod visibility;
+mod volunteers;
+mod watches;
+mod webhooks;
+
+use access::Role;
+use access_mode::{admit_read, AccessMode};
+use aggregator::AggregatorStatus;
+use alerts::{Alert, AlertAckStats, AlertPayload};
+use archive::ArchiveReport;
+use areas::Location;
+use anonymous::AnonymousSubmission;
+use api_keys::{ApiKey, ApiKeyPayload, IssuedApiKey};
+use approvals::{DestructiveAction, Proposal};
+use assignments::{Assignee, Assignment, Reassignment};
+use bans::Ban;
+use call_stats::{CallerStats, MethodStats};
+use casualties::{CasualtyFigures, CasualtyRevision, CasualtyTotals, VerifiedCasualtyFigures};
+use changes::{ChangeFeedPage, ChangeKind};
+use clusters::UpdateCluster;
+use contacts::{EmergencyContact, EmergencyContactPayload};
+use credentials::{CredentialRecheckReport, CredentialVerification};
+use cycles::{CyclesAlertTarget, CyclesBalanceEntry, CyclesStatus};
+use damage::{DamageAssessment, DamageAssessmentPayload, RegionDamageSummary};
+use distributions::{DistributionEvent, DistributionEventPayload, DistributionRegistration};
+use donations::{CrisisDonationTotal, Donation};
+use drafts::Draft;
+use escalation::{EscalationLogEntry, EscalationRule, EscalationRulePayload};
+use evacuation::{
+    AssemblyPoint, AssemblyPointPayload, EvacuationRoute, EvacuationRoutePayload, EvacuationStatus,
+    NearbyEvacuationInfo,
+};
+use exports::{ExportChunk, ExportFilter, ExportInfo, ExportKind};
+use federated::CrisisUpdateStats;
+use field_search::{MatchMode, SearchField};
+use fundraising::{
+    Disbursement, DisbursementPayload, FundraisingGoal, FundraisingStatus, FundsLedgerEntry,
+};
+use gc::GcReport;
+use geo::{BoundingBox, Coordinates};
+use heat_index::RegionHeat;
+use heatmap::HeatmapCell;
+use http::{HttpGatewayResponse, HttpRequest};
+use ids::IdCounter;
+use imports::{ImportField, ImportFormat, ImportInfo, ImportReport};
+use indexes::{IndexKind, IndexReport, RebuildProgress};
+use infrastructure::{
+    InfrastructureKind, InfrastructureState, InfrastructureStatus, InfrastructureStatusPayload, NearbyInfrastructure,
+};
+use inspect::IngressLimits;
+use inventory::{StockItem, StockItemPayload, StockTransaction};
+use ledger::Account;
+use matching::{AcceptedMatch, ResourceMatch};
+use medical::{FacilityCapacityPayload, MedicalFacility, MedicalFacilityPayload, NearbyFacility, Specialty};
+use missing_persons::{MissingPersonPayload, MissingPersonView};
+use notifications::{Notification, NotificationKind, NotificationPreferences};
+use realtime::{RealtimeFilter, RealtimeMessage};
+use regions::Region;
+use replication::ReplicationStatus;
+use resources::{
+    ResourceOffer, ResourceOfferPayload, ResourceRequest, ResourceRequestPayload, ResourceRequestStatus,
+    ResourceType,
+};
+use leaderboard::{BadgeAward, LeaderboardWindow, TopReporter};
+use links::LinkAttachment;
+use logs::{LogEntry, LogLevel};
+use maintenance::{admit_update, MaintenanceMode};
+use metadata::MetadataField;
+use privacy::{AnonymizationPolicy, AnonymizationReport, ErasureAuditEntry, ErasureSummary};
+use profiles::{Profile, ProfilePayload};
+use query_limits::{EffectiveQueryLimits, QueryLimits};
+use quick_updates::{QuickUpdate, QuickUpdatePayload};
+use reputation::ReporterReputation;
+use response::{fit, PagedResult, QueryResult};
+use response_times::{ResolutionStatsFilter, ResolutionTimeGroup};
+use safety::SafetyCheckIn;
+use sanitize::SanitizeMode;
+use search::RankedResult;
+use seed::SeedReport;
+use settings::{InitArgs, Settings};
+use sharding::{Shard, ShardPayload, ShardStrategy};
+use sitreps::SitRep;
+use shelters::{NearbyShelter, Shelter, ShelterPayload};
+use signatures::{SignaturePayload, SignatureVerification};
+use sla::{SlaTarget, TeamSlaCompliance, UpdateSla};
+use snapshots::SnapshotInfo;
+use staleness::StalenessPolicy;
+use teams::{Team, TeamPayload};
+use tenants::Tenant;
+use timeline::TimelineEvent;
+use uniqueness::UniquenessConstraint;
+use views::ViewedUpdate;
+use visibility::Visibility;
+use volunteers::{Task, TaskPayload, TaskStatus, Volunteer, VolunteerPayload};
+use webhooks::{WebhookDelivery, WebhookDeliveryStatus, WebhookEndpoint, WebhookEndpointPayload};
+
 type Memory = VirtualMemory<DefaultMemoryImpl>;
-type IdCell = Cell<u64, Memory>;
+// Fails with a validation error when a value would not fit its stable memory bound,
+// which would otherwise trap inside the insert
+fn ensure_storable<T: BoundedStorable>(value: &T, what: &str) -> Result<(), Error> {
+    let size = value.to_bytes().len();
+    if size > T::MAX_SIZE as usize {
+        return Err(Error::validation(format!(
+            "the {} encodes to {} bytes, over the {} byte storage limit",
+            what,
+            size,
+            T::MAX_SIZE
+        ))
+        .with_detail("encoded_bytes", size)
+        .with_detail("max_bytes", T::MAX_SIZE));
+    }
+    Ok(())
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default, Debug)]
+enum CrisisStatus {
+    #[default]
+    Active,
+    Resolved,
+    Archived,
+    // Still open, but nobody confirmed it was accurate when asked; see staleness.rs
+    Unconfirmed,
+}
+
+impl CrisisStatus {
+    // Active and unconfirmed updates both still need a response
+    fn is_open(self) -> bool {
+        matches!(self, CrisisStatus::Active | CrisisStatus::Unconfirmed)
+    }
+}
+
+// Dispatch priority set by coordinators, independent of the update's status
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default, Debug)]
+enum CrisisPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Critical,
+}
+
+impl CrisisPriority {
+    const ALL: [CrisisPriority; 4] = [
+        CrisisPriority::Low,
+        CrisisPriority::Normal,
+        CrisisPriority::High,
+        CrisisPriority::Critical,
+    ];
+}
 
 #[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
 struct CrisisUpdate {
     id: u64,
     title: String,
     description: String,
+    // Primary location
     location: String,
-    timestamp: u64,
+    // Further areas the incident covers, each indexed like the primary location
+    affected_areas: Vec<Location>,
+    // When the update was reported
+    created_at: u64,
+    // When the update was last edited; None until the first edit
+    updated_at: Option<u64>,
+    // When the incident happened, as given by the reporter
+    occurred_at: Option<u64>,
+    status: CrisisStatus,
+    priority: CrisisPriority,
+    // None for updates created before authorship was recorded
+    author: Option<Principal>,
+    // The author's profile label, filled in on query responses and never stored
+    author_name: Option<String>,
+    signature: Option<signatures::UpdateSignature>,
+    // None when the update was created outside moderation mode
+    review: Option<moderation::ReviewStatus>,
+    // Set instead of `author` for reports submitted anonymously
+    anonymous: Option<anonymous::AnonymousAuthorship>,
+    // Deployment-specific fields, sorted by key
+    metadata: metadata::Metadata,
+    // Where the incident is, when the reporter could tell
+    coordinates: Option<Coordinates>,
+    // Administrative region, set by the reporter or a geocoder
+    region: Option<Region>,
+    visibility: Visibility,
+    // The agency the author belonged to when reporting; None is visible across tenants
+    tenant: Option<u64>,
+    // Set instead of `author` for reports sent with an API key
+    api_key_id: Option<u64>,
+    // How responders can reach the reporter; hidden from other viewers
+    contact: Option<contact_info::ContactInfo>,
+    // When a time-bound advisory stops applying, e.g. a road closure
+    expires_at: Option<u64>,
 }
 
 // Implementing Storable and BoundedStorable traits for CrisisUpdate
 impl Storable for CrisisUpdate {
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
 
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        Decode!(bytes.as_ref(), StoredCrisisUpdate).unwrap().into()
     }
 }
 
 impl BoundedStorable for CrisisUpdate {
-    const MAX_SIZE: u32 = 1024;
+    // Raised from 1024 when affected areas and metadata were added; see migrations.rs
+    const MAX_SIZE: u32 = 4096;
     const IS_FIXED_SIZE: bool = false;
 }
 
+// How a stored crisis update is read back. Fields added since the first release
+// are `opt` here, so records written before them decode with defaults instead of
+// trapping; records written by this version decode unchanged, since a field of
+// type T also decodes as opt T. The first release stored `timestamp` where
+// `created_at` is now.
+#[derive(candid::CandidType, Deserialize)]
+struct StoredCrisisUpdate {
+    id: u64,
+    title: String,
+    description: String,
+    location: String,
+    timestamp: Option<u64>,
+    affected_areas: Option<Vec<Location>>,
+    created_at: Option<u64>,
+    updated_at: Option<u64>,
+    occurred_at: Option<u64>,
+    status: Option<CrisisStatus>,
+    priority: Option<CrisisPriority>,
+    author: Option<Principal>,
+    signature: Option<signatures::UpdateSignature>,
+    review: Option<moderation::ReviewStatus>,
+    anonymous: Option<anonymous::AnonymousAuthorship>,
+    metadata: Option<metadata::Metadata>,
+    coordinates: Option<Coordinates>,
+    region: Option<Region>,
+    visibility: Option<Visibility>,
+    tenant: Option<u64>,
+    api_key_id: Option<u64>,
+    contact: Option<contact_info::ContactInfo>,
+    expires_at: Option<u64>,
+}
+
+impl From<StoredCrisisUpdate> for CrisisUpdate {
+    fn from(stored: StoredCrisisUpdate) -> Self {
+        CrisisUpdate {
+            id: stored.id,
+            title: stored.title,
+            description: stored.description,
+            location: stored.location,
+            affected_areas: stored.affected_areas.unwrap_or_default(),
+            created_at: stored.created_at.or(stored.timestamp).unwrap_or_default(),
+            updated_at: stored.updated_at,
+            occurred_at: stored.occurred_at,
+            status: stored.status.unwrap_or_default(),
+            priority: stored.priority.unwrap_or_default(),
+            author: stored.author,
+            author_name: None,
+            signature: stored.signature,
+            review: stored.review,
+            anonymous: stored.anonymous,
+            metadata: stored.metadata.unwrap_or_default(),
+            coordinates: stored.coordinates,
+            region: stored.region,
+            visibility: stored.visibility.unwrap_or_default(),
+            tenant: stored.tenant,
+            api_key_id: stored.api_key_id,
+            contact: stored.contact,
+            expires_at: stored.expires_at,
+        }
+    }
+}
+
+// Which of an update's timestamps a time-based query filters on
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default, Debug)]
+enum TimestampField {
+    #[default]
+    Created,
+    Updated,
+    Occurred,
+}
+
+impl CrisisUpdate {
+    // None when the update does not carry that timestamp, so it never matches a range on it
+    fn timestamp_of(&self, field: TimestampField) -> Option<u64> {
+        match field {
+            TimestampField::Created => Some(self.created_at),
+            TimestampField::Updated => self.updated_at,
+            TimestampField::Occurred => self.occurred_at,
+        }
+    }
+
+    fn last_modified(&self) -> u64 {
+        self.updated_at.unwrap_or(self.created_at)
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|t| t <= now)
+    }
+}
+
 // Existing thread-local variables and payload structure
 
 thread_local! {
@@ -41,14 +410,11 @@ thread_local! {
         MemoryManager::init(DefaultMemoryImpl::default())
     );
 
-    static CRISIS_ID_COUNTER: RefCell<IdCell> = RefCell::new(
-        IdCell::init(CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))), 0)
-            .expect("Cannot create a counter for crisis updates")
-    );
+    static CRISIS_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(0, "crisis updates"));
 
     static CRISIS_STORAGE: RefCell<StableBTreeMap<u64, CrisisUpdate, Memory>> =
         RefCell::new(StableBTreeMap::init(
-            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(131)))
     ));
 }
 
@@ -59,228 +425,699 @@ struct CrisisUpdatePayload {
     title: String,
     description: String,
     location: String,
+    affected_areas: Vec<Location>,
+    // When the incident happened; must not be in the future
+    occurred_at: Option<u64>,
+    // Author signature over `crisis_update_signing_digest` of the title, description and location
+    signature: Option<SignaturePayload>,
+    // Keys and value types must match the admin-defined metadata schema
+    metadata: metadata::Metadata,
+    coordinates: Option<Coordinates>,
+    region: Option<Region>,
+    // Public when omitted on creation; an edit without it keeps the current visibility
+    visibility: Option<Visibility>,
+    contact: Option<contact_info::ContactInfo>,
+    // Must be in the future; expired updates drop out of default listings
+    expires_at: Option<u64>,
 }
 
-#[derive(candid::CandidType, Deserialize, Serialize)]
-enum Error {
-    NotFound { msg: String },
+// Criteria for `filter_crisis_updates`; every given criterion must match
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct CrisisUpdateFilter {
+    location: Option<String>,
+    status: Option<CrisisStatus>,
+    min_priority: Option<CrisisPriority>,
+    max_priority: Option<CrisisPriority>,
+    // The timestamp the range below applies to; creation time by default
+    timestamp_field: Option<TimestampField>,
+    start_timestamp: Option<u64>,
+    end_timestamp: Option<u64>,
+    // (key, value) pairs the update's metadata must contain
+    metadata: metadata::Metadata,
+    // Words or phrases the title or description must contain, ignoring case
+    text: Option<Vec<String>>,
+    // Expired updates are left out unless true
+    include_expired: Option<bool>,
+}
+
+impl CrisisUpdateFilter {
+    fn matches(&self, update: &CrisisUpdate) -> bool {
+        let in_range = if self.start_timestamp.is_none() && self.end_timestamp.is_none() {
+            true
+        } else {
+            update
+                .timestamp_of(self.timestamp_field.unwrap_or_default())
+                .is_some_and(|t| {
+                    self.start_timestamp.is_none_or(|start| t >= start)
+                        && self.end_timestamp.is_none_or(|end| t <= end)
+                })
+        };
+        self.location.as_ref().is_none_or(|l| areas::covers(update, l))
+            && self.status.is_none_or(|s| update.status == s)
+            && self.min_priority.is_none_or(|p| update.priority as u8 >= p as u8)
+            && self.max_priority.is_none_or(|p| update.priority as u8 <= p as u8)
+            && in_range
+            && metadata::matches(&update.metadata, &self.metadata)
+            && self.text.as_ref().is_none_or(|terms| search::contains_terms(update, terms))
+            && (self.include_expired == Some(true) || !update.is_expired(time()))
+    }
+}
+
+fn validate_occurred_at(occurred_at: Option<u64>, now: u64) -> Result<(), Error> {
+    match occurred_at {
+        Some(t) if t > now => Err(Error::validation("occurred_at must not be in the future").with_detail("occurred_at", t)),
+        _ => Ok(()),
+    }
+}
+
+// Validates a submitted payload, then sanitizes and normalizes its text in
+// place. A signed payload must already be clean and normalized, since its
+// signature covers the submitted text.
+fn _check_input(payload: &mut CrisisUpdatePayload, now: u64) -> Result<(), Error> {
+    validate_occurred_at(payload.occurred_at, now)?;
+    expiry::validate_expires_at(payload.expires_at, now)?;
+    geo::validate_coordinates(&payload.coordinates)?;
+    regions::validate_region(&mut payload.region)?;
+    areas::validate_affected_areas(&mut payload.affected_areas)?;
+    contact_info::validate_contact(&mut payload.contact)?;
+    if let Some(visibility) = &mut payload.visibility {
+        visibility::validate_visibility(visibility)?;
+    }
+    payload.title = normalize::normalize_line(&sanitize::sanitize_text("title", &payload.title, false)?);
+    payload.description =
+        normalize::normalize_multiline(&sanitize::sanitize_text("description", &payload.description, true)?);
+    payload.location = normalize::normalize_line(&sanitize::sanitize_text("location", &payload.location, false)?);
+    for (key, value) in payload.metadata.iter_mut() {
+        if let metadata::MetadataValue::Text(text) = value {
+            *text = normalize::normalize_line(&sanitize::sanitize_text(key, text, false)?);
+        }
+    }
+    metadata::validate_metadata(&mut payload.metadata)
+}
+
+// Stable, documented error codes that clients can branch on. New codes may be
+// added; existing ones keep their meaning.
+#[derive(candid::CandidType, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Debug)]
+enum ErrorCode {
+    // The addressed record does not exist (or is not visible to the caller)
+    NotFound,
+    // The caller lacks the role or ownership the call requires
+    Unauthorized,
+    // An argument is malformed, empty or out of range
+    ValidationFailed,
+    // The call is valid but clashes with the record's current state
+    Conflict,
+    // The caller is making calls faster than allowed
+    RateLimited,
+    // A call to another canister (ledger, shard, archive) failed
+    ExternalCallFailed,
+    // The result would not fit in a reply; paginate or narrow the query
+    ResultTooLarge,
+    // An invariant was broken inside the canister
+    Internal,
+    // Writes are paused by maintenance mode
+    ReadOnlyMode,
+    // A record matching the uniqueness constraint exists; its id is in the details
+    Duplicate,
+}
+
+#[derive(candid::CandidType, Deserialize, Serialize, Debug)]
+struct Error {
+    code: ErrorCode,
+    msg: String,
+    // Machine-readable context such as the offending id or field
+    details: Option<Vec<(String, String)>>,
+}
+
+impl Error {
+    // Internal and external call failures are logged as they are created; the
+    // rest are ordinary replies to the caller and would only crowd the log
+    fn new(code: ErrorCode, msg: impl Into<String>) -> Self {
+        let msg = msg.into();
+        if matches!(code, ErrorCode::Internal | ErrorCode::ExternalCallFailed) {
+            logs::log(logs::LogLevel::Error, None, &format!("{:?}: {}", code, msg));
+        }
+        Error {
+            code,
+            msg,
+            details: None,
+        }
+    }
+
+    fn not_found(msg: impl Into<String>) -> Self {
+        Error::new(ErrorCode::NotFound, msg)
+    }
+
+    fn unauthorized(msg: impl Into<String>) -> Self {
+        Error::new(ErrorCode::Unauthorized, msg)
+    }
+
+    fn validation(msg: impl Into<String>) -> Self {
+        Error::new(ErrorCode::ValidationFailed, msg)
+    }
+
+    fn conflict(msg: impl Into<String>) -> Self {
+        Error::new(ErrorCode::Conflict, msg)
+    }
+
+    fn rate_limited(msg: impl Into<String>) -> Self {
+        Error::new(ErrorCode::RateLimited, msg)
+    }
+
+    fn external_call_failed(msg: impl Into<String>) -> Self {
+        Error::new(ErrorCode::ExternalCallFailed, msg)
+    }
+
+    fn result_too_large(msg: impl Into<String>) -> Self {
+        Error::new(ErrorCode::ResultTooLarge, msg)
+    }
+
+    fn internal(msg: impl Into<String>) -> Self {
+        Error::new(ErrorCode::Internal, msg)
+    }
+
+    fn read_only_mode(msg: impl Into<String>) -> Self {
+        Error::new(ErrorCode::ReadOnlyMode, msg)
+    }
+
+    fn duplicate(msg: impl Into<String>, existing_id: u64) -> Self {
+        Error::new(ErrorCode::Duplicate, msg).with_detail("existing_id", existing_id)
+    }
+
+    // Attaches a key/value pair to `details`
+    fn with_detail(mut self, key: &str, value: impl ToString) -> Self {
+        self.details
+            .get_or_insert_with(Vec::new)
+            .push((key.to_string(), value.to_string()));
+        self
+    }
 }
 
 // 2.7.1 get_crisis_update Function:
-#[ic_cdk::query]
-fn get_crisis_update(id: u64) -> Result<CrisisUpdate, Error> {
-    match _get_crisis_update(&id) {
+// Updates held by a shard or moved to the archive canister are fetched from there transparently.
+#[ic_cdk::query(composite = true, guard = "admit_read")]
+async fn get_crisis_update(id: u64) -> Result<CrisisUpdate, Error> {
+    let update = match _get_crisis_update(&id) {
+        Some(update) => Some(update),
+        None => match sharding::fetch_from_shard(id).await? {
+            Some(update) => Some(update),
+            None => archive::get_archived_crisis_update(id).await?,
+        },
+    };
+    match update.and_then(present_update) {
         Some(update) => Ok(update),
-        None => Err(Error::NotFound {
-            msg: format!("a crisis update with id={} not found", id),
-        }),
+        None => Err(Error::not_found(format!("a crisis update with id={} not found", id)).with_detail("id", id)),
     }
 }
 
 // 2.7.2 _get_crisis_update Function:
 fn _get_crisis_update(id: &u64) -> Option<CrisisUpdate> {
-    CRISIS_STORAGE.with(|s| s.borrow().get(id))
+    cache::cached(*id).or_else(|| CRISIS_STORAGE.with(|s| s.borrow().get(id)))
+}
+
+// Looks an update up locally, then on the shard holding it
+async fn _find_crisis_update(id: u64) -> Result<Option<CrisisUpdate>, Error> {
+    match _get_crisis_update(&id) {
+        Some(update) => Ok(Some(update)),
+        None => sharding::fetch_from_shard(id).await,
+    }
+}
+
+// Prepares crisis updates for a query reply: drops those the caller may not see
+// and fills in author labels
+fn present_updates(updates: Vec<CrisisUpdate>) -> Vec<CrisisUpdate> {
+    profiles::label_updates(
+        updates
+            .into_iter()
+            .filter(moderation::visible_to_caller)
+            .map(redaction::redact_update_for_caller)
+            .collect(),
+    )
+}
+
+// Wraps crisis updates for a list reply
+fn fit_updates(updates: Vec<CrisisUpdate>) -> Result<QueryResult<CrisisUpdate>, Error> {
+    fit(present_updates(updates))
+}
+
+fn present_update(update: CrisisUpdate) -> Option<CrisisUpdate> {
+    moderation::visible_to_caller(&update)
+        .then(|| profiles::label_update(redaction::redact_update_for_caller(update)))
 }
 
 // Helper method to perform insert for CrisisUpdate
-fn do_insert_crisis_update(update: &CrisisUpdate) {
+fn do_insert_crisis_update(update: &CrisisUpdate) -> Result<(), Error> {
+    ensure_storable(update, "crisis update")?;
     CRISIS_STORAGE.with(|service| service.borrow_mut().insert(update.id, update.clone()));
+    cache::record(update);
+    Ok(())
+}
+
+// Stores a new update locally or on its shard and indexes it
+async fn store_new_crisis_update(crisis_update: &CrisisUpdate) -> Result<(), Error> {
+    ensure_storable(crisis_update, "crisis update")?;
+    match sharding::shard_for_new_update(crisis_update) {
+        Some(shard) => sharding::forward_insert(shard, crisis_update).await?,
+        None => do_insert_crisis_update(crisis_update)?,
+    }
+    priority::sync_priority_queue(crisis_update);
+    areas::index_update(crisis_update);
+    field_search::index_update(crisis_update);
+    heat_index::record_change(None, crisis_update);
+    sla::open_sla(crisis_update);
+    changes::record_change(crisis_update.id, ChangeKind::Created)?;
+    Ok(())
+}
+
+// Writes an edited update back to wherever it is held
+async fn store_edited_crisis_update(update: &CrisisUpdate) -> Result<(), Error> {
+    ensure_storable(update, "crisis update")?;
+    match sharding::sharded_location(update.id) {
+        Some(shard) => sharding::forward_insert(shard, update).await?,
+        None => do_insert_crisis_update(update)?,
+    }
+    areas::index_update(update);
+    field_search::index_update(update);
+    changes::record_change(update.id, ChangeKind::Updated)?;
+    Ok(())
+}
+
+// Replaces the reported content of an update with an edit
+// The payload must have passed `_check_input`
+fn apply_edit(update: &mut CrisisUpdate, payload: CrisisUpdatePayload, now: u64) -> Result<(), Error> {
+    // A signature always covers the current content, so an unsigned edit drops it
+    update.signature = payload
+        .signature
+        .map(|s| signatures::attach_signature(s, &payload.title, &payload.description, &payload.location))
+        .transpose()?;
+    update.title = payload.title;
+    update.description = payload.description;
+    update.location = payload.location;
+    update.affected_areas = payload.affected_areas;
+    update.occurred_at = payload.occurred_at;
+    update.metadata = payload.metadata;
+    update.coordinates = payload.coordinates;
+    update.region = payload.region;
+    update.contact = payload.contact;
+    update.expires_at = payload.expires_at;
+    if let Some(visibility) = payload.visibility {
+        update.visibility = visibility;
+    }
+    update.updated_at = Some(now);
+    Ok(())
 }
 
 // 2.7.3 add_crisis_update Function:
-#[ic_cdk::update]
-fn add_crisis_update(update: CrisisUpdatePayload) -> Option<CrisisUpdate> {
-    let id = CRISIS_ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("cannot increment id counter for crisis updates");
+// With a shard strategy configured, the update is stored on the matching shard.
+// Resubmitting an identical report on the same day returns the original update.
+#[ic_cdk::update(guard = "admit_update")]
+async fn add_crisis_update(mut update: CrisisUpdatePayload) -> Result<CrisisUpdate, Error> {
+    call_stats::count_call("add_crisis_update");
+    let author = ic_cdk::caller();
+    let now = time();
+    _check_input(&mut update, now)?;
+    let signature = update
+        .signature
+        .map(|s| signatures::attach_signature(s, &update.title, &update.description, &update.location))
+        .transpose()?;
+    let hash = dedup::content_hash(&update.title, &update.description, &update.location, &author, now);
+    if let Some(existing_id) = dedup::find_duplicate(&hash, now) {
+        match _find_crisis_update(existing_id).await {
+            Ok(Some(existing)) => return Ok(existing),
+            Ok(None) => {}
+            Err(err) => logs::log(
+                LogLevel::Warn,
+                Some("add_crisis_update"),
+                &format!("duplicate lookup for crisis update {} failed: {:?}", existing_id, err),
+            ),
+        }
+    }
+    uniqueness::check_unique(&uniqueness::NewUpdate {
+        author: &author,
+        title: &update.title,
+        description: &update.description,
+        location: &update.location,
+    })?;
+    let id = ids::next_id(&CRISIS_ID_COUNTER)?;
     let crisis_update = CrisisUpdate {
         id,
         title: update.title,
         description: update.description,
         location: update.location,
-        timestamp: time(),
+        affected_areas: update.affected_areas,
+        created_at: now,
+        updated_at: None,
+        occurred_at: update.occurred_at,
+        status: CrisisStatus::Active,
+        priority: CrisisPriority::Normal,
+        author: Some(author),
+        author_name: None,
+        signature,
+        review: moderation::initial_review(&author),
+        anonymous: None,
+        metadata: update.metadata,
+        coordinates: update.coordinates,
+        region: update.region,
+        visibility: update.visibility.unwrap_or_default(),
+        tenant: tenants::tenant_of(&author),
+        api_key_id: None,
+        contact: update.contact,
+        expires_at: update.expires_at,
     };
-    do_insert_crisis_update(&crisis_update);
-    Some(crisis_update)
+    store_new_crisis_update(&crisis_update).await?;
+    dedup::record_content_hash(hash, crisis_update.id, now);
+    reputation::record_submission(&author);
+    Ok(escalation::evaluate_escalation(crisis_update).await)
+}
+
+// The author, a coordinator or an admin may change or delete an update they can
+// see; updates hidden from the caller are reported as missing
+fn require_editor(update: &CrisisUpdate, action: &str) -> Result<Principal, Error> {
+    let caller = ic_cdk::caller();
+    if !moderation::visible_to(update, &caller) {
+        return Err(Error::not_found(format!(
+            "couldn't {} a crisis update with id={}. update not found",
+            action, update.id
+        ))
+        .with_detail("id", update.id));
+    }
+    let allowed = update.author == Some(caller)
+        || access::has_role(&caller, Role::Coordinator)
+        || access::has_role(&caller, Role::Admin);
+    if !allowed {
+        return Err(Error::unauthorized(format!(
+            "only the author, a coordinator or an admin can {} the crisis update with id={}",
+            action, update.id
+        )));
+    }
+    Ok(caller)
 }
 
 // 2.7.4 update_crisis_update Function:
-#[ic_cdk::update]
-fn update_crisis_update(id: u64, payload: CrisisUpdatePayload) -> Result<CrisisUpdate, Error> {
-    match CRISIS_STORAGE.with(|service| service.borrow().get(&id)) {
+#[ic_cdk::update(guard = "admit_update")]
+async fn update_crisis_update(id: u64, mut payload: CrisisUpdatePayload) -> Result<CrisisUpdate, Error> {
+    call_stats::count_call("update_crisis_update");
+    let now = time();
+    _check_input(&mut payload, now)?;
+    match _find_crisis_update(id).await? {
         Some(mut update) => {
-            update.title = payload.title;
-            update.description = payload.description;
-            update.location = payload.location;
-            update.timestamp = time();
-            do_insert_crisis_update(&update);
-            Ok(update)
+            let editor = require_editor(&update, "update")?;
+            apply_edit(&mut update, payload, now)?;
+            store_edited_crisis_update(&update).await?;
+            watches::notify_watchers_of_edit(&update, editor);
+            Ok(redaction::redact_update(update, &editor))
         }
-        None => Err(Error::NotFound {
-            msg: format!(
-                "couldn't update a crisis update with id={}. update not found",
-                id
-            ),
-        }),
+        None => Err(Error::not_found(format!(
+            "couldn't update a crisis update with id={}. update not found",
+            id
+        )).with_detail("id", id)),
     }
 }
 
 // 2.7.5 delete_crisis_update Function:
-#[ic_cdk::update]
-fn delete_crisis_update(id: u64) -> Result<CrisisUpdate, Error> {
-    match CRISIS_STORAGE.with(|service| service.borrow_mut().remove(&id)) {
-        Some(update) => Ok(update),
-        None => Err(Error::NotFound {
-            msg: format!(
-                "couldn't delete a crisis update with id={}. update not found.",
-                id
-            ),
-        }),
+#[ic_cdk::update(guard = "admit_update")]
+async fn delete_crisis_update(id: u64) -> Result<CrisisUpdate, Error> {
+    call_stats::count_call("delete_crisis_update");
+    let Some(existing) = _find_crisis_update(id).await? else {
+        return Err(Error::not_found(format!(
+            "couldn't delete a crisis update with id={}. update not found.",
+            id
+        )).with_detail("id", id));
+    };
+    require_editor(&existing, "delete")?;
+    let removed = match sharding::sharded_location(id) {
+        Some(shard) => sharding::forward_remove(shard, id).await?,
+        None => CRISIS_STORAGE.with(|service| service.borrow_mut().remove(&id)),
+    };
+    match removed {
+        Some(update) => {
+            cache::forget(id);
+            pins::unpin_deleted(id);
+            links::remove_links_for_update(id);
+            areas::unindex_update(id);
+            field_search::unindex_update(id);
+            priority::remove_from_priority_queue(id);
+            sla::remove_sla(id);
+            changes::record_deletion(id, ic_cdk::caller())?;
+            Ok(redaction::redact_update_for_caller(update))
+        }
+        None => Err(Error::not_found(format!(
+            "couldn't delete a crisis update with id={}. update not found.",
+            id
+        )).with_detail("id", id)),
     }
 }
 
 // 2.7.7 list_all_crisis_updates Function:
-#[ic_cdk::query]
-fn list_all_crisis_updates() -> Vec<CrisisUpdate> {
-    CRISIS_STORAGE.with(|service| {
+// Leaves out expired updates; see `list_expired_updates`.
+#[ic_cdk::query(guard = "admit_read")]
+fn list_all_crisis_updates() -> Result<QueryResult<CrisisUpdate>, Error> {
+    let now = time();
+    fit_updates(CRISIS_STORAGE.with(|service| {
         service
             .borrow()
             .iter()
             .map(|(_, item)| item.clone())
+            .filter(|update| !update.is_expired(now))
             .collect()
-    })
+    }))
 }
 
 // 2.7.8 get_latest_crisis_update Function:
-#[ic_cdk::query]
+#[ic_cdk::query(guard = "admit_read")]
 fn get_latest_crisis_update() -> Option<CrisisUpdate> {
+    let now = time();
+    if let Some(update) = cache::latest_visible(&ic_cdk::caller(), now) {
+        return present_update(update);
+    }
     CRISIS_STORAGE
         .with(|service| {
             let map = service.borrow();
-            map.iter().max_by_key(|(_, update)| update.timestamp).map(|(_, update)| update.clone())
+            map.iter()
+                .map(|(_, update)| update)
+                .filter(|update| !update.is_expired(now))
+                .filter(moderation::visible_to_caller)
+                .max_by_key(|update| update.last_modified())
         })
+        .and_then(present_update)
 }
 
 // 2.7.9 search_crisis_updates_by_location Function:
-#[ic_cdk::query]
-fn search_crisis_updates_by_location(location: String) -> Vec<CrisisUpdate> {
-    CRISIS_STORAGE
-        .with(|service| {
-            let map = service.borrow();
-            map.iter().filter_map(|(_, update)| {
-                if update.location == location {
-                    Some(update.clone())
-                } else {
-                    None
-                }
-            }).collect()
-        })
+// Matches the primary location or any affected area, ignoring case. Updates
+// stored before the location index existed are found once `rebuild_location_index` has run.
+#[ic_cdk::query(guard = "admit_read")]
+fn search_crisis_updates_by_location(location: String) -> Result<QueryResult<CrisisUpdate>, Error> {
+    field_search::search_field(SearchField::Location, MatchMode::Exact, &location)
 }
 
 // 2.7.10 get_crisis_updates_in_range Function:
-#[ic_cdk::query]
-fn get_crisis_updates_in_range(start_timestamp: u64, end_timestamp: u64) -> Vec<CrisisUpdate> {
-    CRISIS_STORAGE.with(|service| {
+#[ic_cdk::query(guard = "admit_read")]
+fn get_crisis_updates_in_range(
+    field: TimestampField,
+    start_timestamp: u64,
+    end_timestamp: u64,
+) -> Result<QueryResult<CrisisUpdate>, Error> {
+    let now = time();
+    fit_updates(CRISIS_STORAGE.with(|service| {
         let map = service.borrow();
         map.iter()
             .filter_map(|(_, update)| {
-                if update.timestamp >= start_timestamp && update.timestamp <= end_timestamp {
+                let in_range = update.timestamp_of(field).is_some_and(|t| t >= start_timestamp && t <= end_timestamp);
+                if in_range && !update.is_expired(now) {
                     Some(update.clone())
                 } else {
                     None
                 }
             })
             .collect()
-    })
+    }))
 }
 
 // 2.7.11 get_crisis_updates_before Function:
-#[ic_cdk::query]
-fn get_crisis_updates_before(end_timestamp: u64) -> Vec<CrisisUpdate> {
-    CRISIS_STORAGE.with(|service| {
+#[ic_cdk::query(guard = "admit_read")]
+fn get_crisis_updates_before(field: TimestampField, end_timestamp: u64) -> Result<QueryResult<CrisisUpdate>, Error> {
+    let now = time();
+    fit_updates(CRISIS_STORAGE.with(|service| {
         let map = service.borrow();
         map.iter()
             .filter_map(|(_, update)| {
-                if update.timestamp < end_timestamp {
+                if update.timestamp_of(field).is_some_and(|t| t < end_timestamp) && !update.is_expired(now) {
                     Some(update.clone())
                 } else {
                     None
                 }
             })
             .collect()
-    })
+    }))
 }
 
 // 2.7.12 get_crisis_updates_after Function:
-#[ic_cdk::query]
-fn get_crisis_updates_after(start_timestamp: u64) -> Vec<CrisisUpdate> {
-    CRISIS_STORAGE.with(|service| {
+#[ic_cdk::query(guard = "admit_read")]
+fn get_crisis_updates_after(field: TimestampField, start_timestamp: u64) -> Result<QueryResult<CrisisUpdate>, Error> {
+    let now = time();
+    fit_updates(CRISIS_STORAGE.with(|service| {
         let map = service.borrow();
         map.iter()
             .filter_map(|(_, update)| {
-                if update.timestamp > start_timestamp {
+                if update.timestamp_of(field).is_some_and(|t| t > start_timestamp) && !update.is_expired(now) {
                     Some(update.clone())
                 } else {
                     None
                 }
             })
             .collect()
-    })
+    }))
 }
 
 // 2.7.16 get_crisis_updates_by_id_range Function:
-#[ic_cdk::query]
-fn get_crisis_updates_by_id_range(start_id: u64, end_id: u64) -> Vec<CrisisUpdate> {
-    CRISIS_STORAGE.with(|service| {
+#[ic_cdk::query(guard = "admit_read")]
+fn get_crisis_updates_by_id_range(start_id: u64, end_id: u64) -> Result<QueryResult<CrisisUpdate>, Error> {
+    let now = time();
+    fit_updates(CRISIS_STORAGE.with(|service| {
         let map = service.borrow();
         map.iter()
             .filter_map(|(_, update)| {
-                if update.id >= start_id && update.id <= end_id {
+                if update.id >= start_id && update.id <= end_id && !update.is_expired(now) {
                     Some(update.clone())
                 } else {
                     None
                 }
             })
             .collect()
-    })
+    }))
 }
 
 // 2.7.20 get_crisis_updates_by_title Function:
-#[ic_cdk::query]
-fn get_crisis_updates_by_title(title: String) -> Vec<CrisisUpdate> {
-    CRISIS_STORAGE.with(|service| {
-        let map = service.borrow();
-        map.iter()
-            .filter_map(|(_, update)| {
-                if update.title.contains(&title) {
-                    Some(update.clone())
-                } else {
-                    None
-                }
-            })
-            .collect()
-    })
+// The same as searching the title field with Contains.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_crisis_updates_by_title(title: String) -> Result<QueryResult<CrisisUpdate>, Error> {
+    field_search::search_field(SearchField::Title, MatchMode::Contains, &title)
 }
 
 // 2.7.21 get_crisis_updates_by_description Function:
-#[ic_cdk::query]
-fn get_crisis_updates_by_description(description: String) -> Vec<CrisisUpdate> {
-    CRISIS_STORAGE.with(|service| {
-        let map = service.borrow();
-        map.iter()
-            .filter_map(|(_, update)| {
-                if update.description.contains(&description) {
-                    Some(update.clone())
-                } else {
-                    None
-                }
-            })
+// The same as searching the description field with Contains.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_crisis_updates_by_description(description: String) -> Result<QueryResult<CrisisUpdate>, Error> {
+    field_search::search_field(SearchField::Description, MatchMode::Contains, &description)
+}
+
+// Stores a status change and tells those following the update
+fn store_status(mut update: CrisisUpdate, status: CrisisStatus, changed_by: Principal) -> Result<CrisisUpdate, Error> {
+    let before = update.clone();
+    let previous = update.status;
+    update.status = status;
+    update.updated_at = Some(time());
+    do_insert_crisis_update(&update)?;
+    priority::sync_priority_queue(&update);
+    heat_index::record_change(Some(&before), &update);
+    sla::sync_sla(&update);
+    changes::record_change(update.id, ChangeKind::Updated)?;
+    if previous != status {
+        timeline::record_status_change(update.id, previous, status, changed_by);
+        webhooks::enqueue(webhooks::WebhookEvent::StatusChanged { from: previous, to: status }, update.id);
+        notifications::notify_crisis_update_changed(&update, NotificationKind::CrisisStatusChanged, changed_by);
+    }
+    Ok(update)
+}
+
+// 2.7.22 set_crisis_update_status Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn set_crisis_update_status(id: u64, status: CrisisStatus) -> Result<CrisisUpdate, Error> {
+    call_stats::count_call("set_crisis_update_status");
+    let caller = access::require_any_role(&[Role::Coordinator, Role::Moderator])?;
+    match _get_crisis_update(&id) {
+        Some(update) => Ok(redaction::redact_update_for_caller(store_status(update, status, caller)?)),
+        None => Err(Error::not_found(format!(
+            "couldn't update the status of a crisis update with id={}. update not found",
+            id
+        )).with_detail("id", id)),
+    }
+}
+
+// 2.7.23 filter_crisis_updates Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn filter_crisis_updates(filter: CrisisUpdateFilter) -> Result<QueryResult<CrisisUpdate>, Error> {
+    fit_updates(CRISIS_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, update)| update)
+            .filter(|update| filter.matches(update))
             .collect()
+    }))
+}
+
+// 2.7.24 count_crisis_updates Function:
+// Counts what `filter_crisis_updates` would return, without the records.
+#[ic_cdk::query(guard = "admit_read")]
+fn count_crisis_updates(filter: CrisisUpdateFilter) -> u64 {
+    CRISIS_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, update)| filter.matches(update) && moderation::visible_to_caller(update))
+            .count() as u64
     })
 }
 
+// 2.7.25 crisis_update_exists Function:
+// Answers from the shard and archive location indexes, without calling those canisters.
+#[ic_cdk::query(guard = "admit_read")]
+fn crisis_update_exists(id: u64) -> bool {
+    match _get_crisis_update(&id) {
+        Some(update) => moderation::visible_to_caller(&update),
+        None => sharding::sharded_location(id).is_some() || archive::archived_location(id).is_some(),
+    }
+}
+
 // To generate the Candid interface definitions for our canister
 ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(candid::CandidType)]
+    struct FirstReleaseCrisisUpdate {
+        id: u64,
+        title: String,
+        description: String,
+        location: String,
+        timestamp: u64,
+    }
+
+    #[test]
+    fn first_release_records_decode_with_defaults() {
+        let old = FirstReleaseCrisisUpdate {
+            id: 7,
+            title: "Flooding".to_string(),
+            description: "Water over the road".to_string(),
+            location: "Bridge St".to_string(),
+            timestamp: 42,
+        };
+        let update = CrisisUpdate::from_bytes(Cow::Owned(Encode!(&old).unwrap()));
+        assert_eq!(update.id, 7);
+        assert_eq!(update.created_at, 42);
+        assert_eq!(update.status, CrisisStatus::Active);
+        assert_eq!(update.priority, CrisisPriority::Normal);
+        assert_eq!(update.visibility, Visibility::Public);
+        assert!(update.affected_areas.is_empty() && update.metadata.is_empty());
+    }
+
+    #[test]
+    fn current_records_round_trip() {
+        let update = CrisisUpdate {
+            id: 3,
+            created_at: 10,
+            updated_at: Some(11),
+            status: CrisisStatus::Resolved,
+            priority: CrisisPriority::Critical,
+            ..Default::default()
+        };
+        let decoded = CrisisUpdate::from_bytes(update.to_bytes());
+        assert_eq!(decoded.created_at, 10);
+        assert_eq!(decoded.updated_at, Some(11));
+        assert_eq!(decoded.status, CrisisStatus::Resolved);
+        assert_eq!(decoded.priority, CrisisPriority::Critical);
+    }
+}
@@ -0,0 +1,320 @@
+// External links (news articles, official bulletins) attached to crisis
+// updates. Only https URLs on admin-allowlisted hosts are accepted; the page
+// title can be fetched once through an HTTPS outcall and stored as a preview.
+use crate::access::{has_role, Role};
+use crate::access_mode::admit_read;
+use crate::ids::IdCounter;
+use crate::logs::{log, LogLevel};
+use crate::maintenance::admit_update;
+use crate::response::{fit, QueryResult};
+use crate::settings::{get_settings, update_settings};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpMethod, HttpResponse, TransformArgs, TransformContext,
+};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_URL_CHARS: usize = 512;
+const MAX_ALLOWLIST_HOSTS: usize = 100;
+const MAX_PREVIEW_CHARS: usize = 200;
+const PREVIEW_MAX_RESPONSE_BYTES: u64 = 64 * 1024;
+// Comfortably above the outcall price for a 64 KiB response; unused cycles are refunded
+const PREVIEW_OUTCALL_CYCLES: u128 = 1_000_000_000;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct LinkAttachment {
+    pub(crate) id: u64,
+    crisis_update_id: u64,
+    pub(crate) url: String,
+    host: String,
+    // Page title fetched when the link was attached, if requested and available
+    preview_title: Option<String>,
+    pub(crate) added_by: Principal,
+    pub(crate) added_at: u64,
+}
+
+// Implementing Storable and BoundedStorable traits for LinkAttachment
+impl Storable for LinkAttachment {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for LinkAttachment {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static LINK_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(57, "link attachments"));
+
+    static LINK_STORAGE: RefCell<StableBTreeMap<u64, LinkAttachment, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(58)))
+    ));
+}
+
+fn valid_host(host: &str) -> bool {
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').count() >= 2
+        && host
+            .split('.')
+            .all(|label| !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+}
+
+// Returns the lowercased host of an https URL without credentials or a port
+fn parse_https_host(url: &str) -> Result<String, Error> {
+    if url.chars().count() > MAX_URL_CHARS {
+        return Err(Error::validation(format!(
+            "links must be at most {} characters",
+            MAX_URL_CHARS
+        )));
+    }
+    if url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(Error::validation(
+            "links must not contain whitespace or control characters",
+        ));
+    }
+    let Some(rest) = url
+        .get(..8)
+        .filter(|s| s.eq_ignore_ascii_case("https://"))
+        .map(|_| &url[8..])
+    else {
+        return Err(Error::validation("only https links can be attached"));
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    if authority.contains('@') || authority.contains(':') {
+        return Err(Error::validation("links must not include credentials or a port"));
+    }
+    let host = authority.to_ascii_lowercase();
+    if !valid_host(&host) {
+        return Err(Error::validation(format!("'{}' is not a valid host name", authority)));
+    }
+    Ok(host)
+}
+
+// An allowlist entry covers the host itself and its subdomains
+fn host_allowed(host: &str, allowlist: &[String]) -> bool {
+    allowlist
+        .iter()
+        .any(|allowed| host == allowed || host.strip_suffix(allowed.as_str()).is_some_and(|p| p.ends_with('.')))
+}
+
+// Trims an https URL and checks its host against the admin allowlist; returns the URL and host
+pub(crate) fn validate_allowed_url(url: &str) -> Result<(String, String), Error> {
+    let url = url.trim().to_string();
+    let host = parse_https_host(&url)?;
+    if !host_allowed(&host, &get_settings().link_host_allowlist.unwrap_or_default()) {
+        return Err(
+            Error::validation(format!("links to '{}' are not on the allowlist", host)).with_detail("host", &host),
+        );
+    }
+    Ok((url, host))
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+fn extract_title(html: &str) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+    let open = lower.find("<title")?;
+    let start = open + lower[open..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+    let title = decode_entities(&html[start..end]);
+    let title: String = title.split_whitespace().collect::<Vec<_>>().join(" ");
+    let title: String = title
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_PREVIEW_CHARS)
+        .collect();
+    (!title.is_empty()).then_some(title)
+}
+
+// Replicas must agree on the outcall response, so only the extracted title is kept.
+// Called by the system rather than a member, so it has no read guard.
+#[ic_cdk::query]
+fn transform_link_preview(args: TransformArgs) -> HttpResponse {
+    let title = extract_title(&String::from_utf8_lossy(&args.response.body)).unwrap_or_default();
+    HttpResponse {
+        status: args.response.status,
+        headers: Vec::new(),
+        body: title.into_bytes(),
+    }
+}
+
+async fn fetch_preview_title(url: &str) -> Result<Option<String>, Error> {
+    let request = CanisterHttpRequestArgument {
+        url: url.to_string(),
+        max_response_bytes: Some(PREVIEW_MAX_RESPONSE_BYTES),
+        method: HttpMethod::GET,
+        headers: Vec::new(),
+        body: None,
+        transform: Some(TransformContext::from_name(
+            "transform_link_preview".to_string(),
+            Vec::new(),
+        )),
+    };
+    let (response,) = http_request(request, PREVIEW_OUTCALL_CYCLES)
+        .await
+        .map_err(|(code, msg)| Error::external_call_failed(format!("link preview fetch failed: {:?} {}", code, msg)))?;
+    if response.status != 200u64 {
+        return Ok(None);
+    }
+    let title = String::from_utf8_lossy(&response.body).into_owned();
+    Ok((!title.is_empty()).then_some(title))
+}
+
+pub(crate) fn links_for_update(crisis_update_id: u64) -> Vec<LinkAttachment> {
+    LINK_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, link)| link)
+            .filter(|link| link.crisis_update_id == crisis_update_id)
+            .collect()
+    })
+}
+
+// Drops the links of a deleted update
+pub(crate) fn remove_links_for_update(crisis_update_id: u64) {
+    let ids: Vec<u64> = links_for_update(crisis_update_id).iter().map(|link| link.id).collect();
+    LINK_STORAGE.with(|s| {
+        let mut s = s.borrow_mut();
+        for id in ids {
+            s.remove(&id);
+        }
+    });
+}
+
+// Drops links whose update no longer exists; returns how many were removed
+pub(crate) fn remove_orphaned_links(is_live: &dyn Fn(u64) -> bool) -> u64 {
+    let ids: Vec<u64> = LINK_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, link)| !is_live(link.crisis_update_id))
+            .map(|(id, _)| id)
+            .collect()
+    });
+    LINK_STORAGE.with(|s| {
+        let mut s = s.borrow_mut();
+        for id in &ids {
+            s.remove(id);
+        }
+    });
+    ids.len() as u64
+}
+
+fn is_link_moderator(principal: &Principal) -> bool {
+    has_role(principal, Role::Moderator) || has_role(principal, Role::Coordinator)
+}
+
+// 2.40.1 attach_link Function:
+// The update's author, moderators and coordinators may attach links.
+#[ic_cdk::update(guard = "admit_update")]
+async fn attach_link(crisis_update_id: u64, url: String, fetch_preview: bool) -> Result<LinkAttachment, Error> {
+    crate::call_stats::count_call("attach_link");
+    let added_by = caller();
+    let (url, host) = validate_allowed_url(&url)?;
+    let Some(update) = crate::_find_crisis_update(crisis_update_id).await? else {
+        return Err(Error::not_found(format!(
+            "couldn't attach a link to a crisis update with id={}. update not found",
+            crisis_update_id
+        )));
+    };
+    if update.author != Some(added_by) && !is_link_moderator(&added_by) {
+        return Err(Error::unauthorized(
+            "only the update's author, moderators and coordinators can attach links",
+        ));
+    }
+    crate::query_limits::check_attachment_count(links_for_update(crisis_update_id).len())?;
+    let preview_title = if fetch_preview {
+        match fetch_preview_title(&url).await {
+            Ok(title) => title,
+            Err(err) => {
+                log(
+                    LogLevel::Warn,
+                    Some("attach_link"),
+                    &format!("no preview for {}: {:?}", url, err),
+                );
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let id = crate::ids::next_id(&LINK_ID_COUNTER)?;
+    let link = LinkAttachment {
+        id,
+        crisis_update_id,
+        url,
+        host,
+        preview_title,
+        added_by,
+        added_at: time(),
+    };
+    LINK_STORAGE.with(|s| s.borrow_mut().insert(id, link.clone()));
+    Ok(link)
+}
+
+// 2.40.2 get_links_for_update Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_links_for_update(crisis_update_id: u64) -> Result<QueryResult<LinkAttachment>, Error> {
+    fit(links_for_update(crisis_update_id))
+}
+
+// 2.40.3 remove_link Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn remove_link(id: u64) -> Result<LinkAttachment, Error> {
+    crate::call_stats::count_call("remove_link");
+    let caller = caller();
+    let Some(link) = LINK_STORAGE.with(|s| s.borrow().get(&id)) else {
+        return Err(Error::not_found(format!("a link with id={} not found", id)));
+    };
+    if link.added_by != caller && !is_link_moderator(&caller) {
+        return Err(Error::unauthorized(
+            "only whoever attached a link or a moderator can remove it",
+        ));
+    }
+    LINK_STORAGE.with(|s| s.borrow_mut().remove(&id));
+    Ok(link)
+}
+
+// 2.40.4 set_link_allowlist Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn set_link_allowlist(hosts: Vec<String>) -> Result<Vec<String>, Error> {
+    crate::call_stats::count_call("set_link_allowlist");
+    crate::access::require_role(Role::Admin)?;
+    if hosts.len() > MAX_ALLOWLIST_HOSTS {
+        return Err(Error::validation(format!(
+            "the allowlist can hold at most {} hosts",
+            MAX_ALLOWLIST_HOSTS
+        )));
+    }
+    let mut hosts: Vec<String> = hosts.iter().map(|h| h.trim().to_ascii_lowercase()).collect();
+    if let Some(invalid) = hosts.iter().find(|h| !valid_host(h)) {
+        return Err(Error::validation(format!("'{}' is not a valid host name", invalid)));
+    }
+    hosts.sort();
+    hosts.dedup();
+    update_settings(|s| s.link_host_allowlist = Some(hosts.clone()))?;
+    Ok(hosts)
+}
+
+// 2.40.5 get_link_allowlist Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_link_allowlist() -> Vec<String> {
+    get_settings().link_host_allowlist.unwrap_or_default()
+}
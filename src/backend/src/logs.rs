@@ -0,0 +1,157 @@
+// Structured logs kept in a bounded ring buffer in stable memory, so problems
+// in production can be investigated without replica access. Internal and
+// external call errors raised during an update call are logged automatically
+// when they are created; entries written during a query are discarded with the
+// rest of its state.
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::response::{truncate_to_fit, PagedResult};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const LOG_CAPACITY: u64 = 5_000;
+const MAX_MESSAGE_CHARS: usize = 1_000;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub(crate) enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct LogEntry {
+    seq: u64,
+    level: LogLevel,
+    // The endpoint the entry was written from, when known
+    method: Option<String>,
+    // None for entries written by the heartbeat
+    caller: Option<Principal>,
+    message: String,
+    at: u64,
+}
+
+// Implementing Storable and BoundedStorable traits for LogEntry
+impl Storable for LogEntry {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for LogEntry {
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static LOG_STORAGE: RefCell<StableBTreeMap<u64, LogEntry, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(85)))
+    ));
+
+    // The heartbeat has no caller; reading it there would trap
+    static IN_HEARTBEAT: RefCell<bool> = const { RefCell::new(false) };
+}
+
+// Marks the synchronous part of a heartbeat, for as long as the value lives
+pub(crate) struct HeartbeatScope;
+
+impl HeartbeatScope {
+    pub(crate) fn enter() -> Self {
+        IN_HEARTBEAT.with(|h| *h.borrow_mut() = true);
+        HeartbeatScope
+    }
+}
+
+impl Drop for HeartbeatScope {
+    fn drop(&mut self) {
+        IN_HEARTBEAT.with(|h| *h.borrow_mut() = false);
+    }
+}
+
+// The time and caller entries are stamped with. Outside a canister, as in unit
+// tests, there is no system API to ask, so entries get time 0 and no caller.
+#[cfg(target_arch = "wasm32")]
+fn current_time() -> u64 {
+    ic_cdk::api::time()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn current_time() -> u64 {
+    0
+}
+
+#[cfg(target_arch = "wasm32")]
+fn current_caller() -> Option<Principal> {
+    (!IN_HEARTBEAT.with(|h| *h.borrow())).then(ic_cdk::api::caller)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn current_caller() -> Option<Principal> {
+    None
+}
+
+// Appends an entry, dropping the oldest once the buffer is full. Never fails,
+// since it runs while errors are being built.
+pub(crate) fn log(level: LogLevel, method: Option<&str>, message: &str) {
+    let message: String = message.chars().take(MAX_MESSAGE_CHARS).collect();
+    LOG_STORAGE.with(|s| {
+        let mut s = s.borrow_mut();
+        let seq = s.last_key_value().map_or(0, |(seq, _)| seq.saturating_add(1));
+        let entry = LogEntry {
+            seq,
+            level,
+            method: method.map(str::to_string),
+            caller: current_caller(),
+            message,
+            at: current_time(),
+        };
+        s.insert(seq, entry);
+        while s.len() > LOG_CAPACITY {
+            let Some((oldest, _)) = s.first_key_value() else {
+                break;
+            };
+            s.remove(&oldest);
+        }
+    });
+}
+
+// 2.60.1 get_logs Function:
+// Newest first, at or above `level` when given. The cursor is the seq of the last entry returned.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_logs(level: Option<LogLevel>, start_after: Option<u64>, limit: u64) -> Result<PagedResult<LogEntry>, Error> {
+    require_role(Role::Admin)?;
+    let matching: Vec<LogEntry> = LOG_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, entry)| entry)
+            .filter(|entry| level.is_none_or(|level| entry.level >= level))
+            .collect()
+    });
+    let total_count = matching.len() as u64;
+    let limit = crate::query_limits::page_size(limit) as usize;
+    let page: Vec<LogEntry> = matching
+        .into_iter()
+        .rev()
+        .filter(|entry| start_after.is_none_or(|cursor| entry.seq < cursor))
+        .take(limit + 1)
+        .collect();
+    let more = page.len() > limit;
+    let (items, truncated) = truncate_to_fit(page.into_iter().take(limit).collect());
+    let next_cursor = (more || truncated)
+        .then(|| items.last().map(|entry| entry.seq))
+        .flatten();
+    Ok(PagedResult {
+        items,
+        total_count,
+        next_cursor,
+    })
+}
@@ -0,0 +1,90 @@
+// Read-only maintenance mode, for migrations, a suspected compromise or a
+// handover between operating organizations. While it is on, every update
+// method except the switch itself is rejected and queries keep working.
+// Scheduled jobs in the heartbeat are not calls and keep running.
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::bans::reject_banned;
+use crate::normalize::normalize_line;
+use crate::settings::{get_settings, update_settings};
+use crate::{Error, ErrorCode};
+use candid::Principal;
+use ic_cdk::api::time;
+
+const MAX_REASON_CHARS: usize = 500;
+
+// Update methods that stay open in maintenance mode
+pub(crate) const READ_ONLY_EXEMPT_METHODS: [&str; 1] = ["set_maintenance_mode"];
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct MaintenanceMode {
+    reason: String,
+    enabled_by: Principal,
+    enabled_at: u64,
+}
+
+fn read_only_message(mode: &MaintenanceMode) -> String {
+    format!("the canister is in read-only maintenance mode: {}", mode.reason)
+}
+
+pub(crate) fn is_read_only() -> bool {
+    get_settings().maintenance_mode.is_some()
+}
+
+// For update methods that cannot run `admit_update` as their guard
+pub(crate) fn check_writable() -> Result<(), Error> {
+    match get_settings().maintenance_mode {
+        Some(mode) => Err(Error::read_only_mode(read_only_message(&mode))),
+        None => Ok(()),
+    }
+}
+
+// Guard for every update method. Not logged, since a long maintenance window
+// would otherwise flood the log with rejected calls.
+pub(crate) fn admit_update() -> Result<(), String> {
+    reject_banned()?;
+    match get_settings().maintenance_mode {
+        Some(mode) => Err(format!("{:?}: {}", ErrorCode::ReadOnlyMode, read_only_message(&mode))),
+        None => Ok(()),
+    }
+}
+
+// 2.64.1 set_maintenance_mode Function:
+// A reason turns maintenance mode on, None turns it off.
+#[ic_cdk::update(guard = "reject_banned")]
+fn set_maintenance_mode(reason: Option<String>) -> Result<Option<MaintenanceMode>, Error> {
+    crate::call_stats::count_call("set_maintenance_mode");
+    let admin = require_role(Role::Admin)?;
+    let mode = match reason {
+        Some(reason) => {
+            let reason = normalize_line(&reason);
+            if reason.is_empty() || reason.chars().count() > MAX_REASON_CHARS {
+                return Err(Error::validation(format!(
+                    "reasons must be between 1 and {} characters",
+                    MAX_REASON_CHARS
+                )));
+            }
+            Some(MaintenanceMode {
+                reason,
+                enabled_by: admin,
+                enabled_at: time(),
+            })
+        }
+        None => None,
+    };
+    let level = crate::logs::LogLevel::Warn;
+    let message = match &mode {
+        Some(mode) => format!("maintenance mode enabled: {}", mode.reason),
+        None => "maintenance mode disabled".to_string(),
+    };
+    crate::logs::log(level, Some("set_maintenance_mode"), &message);
+    let stored = mode.clone();
+    update_settings(|s| s.maintenance_mode = stored)?;
+    Ok(mode)
+}
+
+// 2.64.2 get_maintenance_mode Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_maintenance_mode() -> Option<MaintenanceMode> {
+    get_settings().maintenance_mode
+}
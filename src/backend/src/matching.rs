@@ -0,0 +1,140 @@
+use crate::access_mode::admit_read;
+use crate::maintenance::admit_update;
+use crate::resources::{
+    do_insert_resource_offer, do_insert_resource_request, find_resource_request, get_resource_offer, ResourceOffer,
+    ResourceOfferStatus, ResourceRequest, ResourceRequestStatus, RESOURCE_OFFER_STORAGE,
+};
+use crate::response::{fit, QueryResult};
+use crate::Error;
+use ic_cdk::api::{caller, time};
+
+// Offers further away than this are never proposed for a request
+const MAX_MATCH_DISTANCE_KM: f64 = 250.0;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ResourceMatch {
+    offer: ResourceOffer,
+    matched_quantity: u64,
+    // None when either side has no coordinates
+    distance_km: Option<f64>,
+    same_location: bool,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AcceptedMatch {
+    request: ResourceRequest,
+    offer: ResourceOffer,
+    matched_quantity: u64,
+}
+
+pub(crate) fn is_request_open(request: &ResourceRequest) -> bool {
+    matches!(
+        request.status,
+        ResourceRequestStatus::Open | ResourceRequestStatus::PartiallyFulfilled
+    ) && request.remaining_quantity() > 0
+}
+
+fn is_offer_available(offer: &ResourceOffer) -> bool {
+    matches!(
+        offer.status,
+        ResourceOfferStatus::Available | ResourceOfferStatus::PartiallyCommitted
+    ) && offer.remaining_quantity() > 0
+}
+
+// Returns a match when the offer can serve the request, None otherwise
+fn evaluate_match(request: &ResourceRequest, offer: &ResourceOffer) -> Option<ResourceMatch> {
+    if offer.resource_type != request.resource_type || !is_offer_available(offer) {
+        return None;
+    }
+    let distance_km = match (&request.coordinates, &offer.coordinates) {
+        (Some(a), Some(b)) => Some(a.distance_km(b)),
+        _ => None,
+    };
+    if distance_km.is_some_and(|d| d > MAX_MATCH_DISTANCE_KM) {
+        return None;
+    }
+    Some(ResourceMatch {
+        offer: offer.clone(),
+        matched_quantity: request.remaining_quantity().min(offer.remaining_quantity()),
+        distance_km,
+        same_location: request.location.eq_ignore_ascii_case(&offer.location),
+    })
+}
+
+// Closest offers first; offers in the same named location count as distance zero
+// and offers of unknown proximity come last. Ties go to the larger contribution.
+fn proximity(m: &ResourceMatch) -> f64 {
+    match (m.distance_km, m.same_location) {
+        (Some(d), _) => d,
+        (None, true) => 0.0,
+        (None, false) => f64::MAX,
+    }
+}
+
+// 2.9.1 get_matches_for_request Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_matches_for_request(request_id: u64) -> Result<QueryResult<ResourceMatch>, Error> {
+    let request = find_resource_request(request_id)?;
+    if !is_request_open(&request) {
+        return Ok(QueryResult::new(Vec::new()));
+    }
+    let mut matches: Vec<ResourceMatch> = RESOURCE_OFFER_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter_map(|(_, offer)| evaluate_match(&request, &offer))
+            .collect()
+    });
+    matches.sort_by(|a, b| {
+        proximity(a)
+            .total_cmp(&proximity(b))
+            .then(b.matched_quantity.cmp(&a.matched_quantity))
+    });
+    fit(matches)
+}
+
+// 2.9.2 accept_match Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn accept_match(request_id: u64, offer_id: u64) -> Result<AcceptedMatch, Error> {
+    crate::call_stats::count_call("accept_match");
+    let mut request = find_resource_request(request_id)?;
+    let mut offer = get_resource_offer(offer_id)?;
+    let caller = caller();
+    if caller != request.requester && caller != offer.donor {
+        return Err(Error::unauthorized(
+            "only the requester or the donor can accept a match",
+        ));
+    }
+    if !is_request_open(&request) {
+        return Err(Error::conflict(format!(
+            "resource request with id={} is no longer open",
+            request_id
+        )));
+    }
+    let matched_quantity = match evaluate_match(&request, &offer) {
+        Some(m) => m.matched_quantity,
+        None => {
+            return Err(Error::validation(format!(
+                "resource offer with id={} is not compatible with request id={}",
+                offer_id, request_id
+            )))
+        }
+    };
+
+    let now = time();
+    request.record_fulfillment(matched_quantity, now);
+    offer.committed_quantity += matched_quantity;
+    offer.status = if offer.remaining_quantity() == 0 {
+        ResourceOfferStatus::Committed
+    } else {
+        ResourceOfferStatus::PartiallyCommitted
+    };
+    offer.updated_at = Some(now);
+    do_insert_resource_request(&request);
+    do_insert_resource_offer(&offer);
+    Ok(AcceptedMatch {
+        request: crate::contact_info::redact_request_for_caller(request),
+        offer,
+        matched_quantity,
+    })
+}
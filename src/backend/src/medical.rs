@@ -0,0 +1,282 @@
+use crate::access::{has_role, require_any_role, require_role, Role};
+use crate::access_mode::admit_read;
+use crate::geo::Coordinates;
+use crate::ids::IdCounter;
+use crate::maintenance::admit_update;
+use crate::normalize::normalize_line;
+use crate::response::{fit, QueryResult};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_FACILITY_PRINCIPALS: usize = 10;
+const MAX_NEARBY_FACILITIES: usize = 20;
+const MAX_NAME_CHARS: usize = 200;
+const MAX_LOCATION_CHARS: usize = 200;
+const MAX_CONTACT_CHARS: usize = 200;
+const MAX_SPECIALTIES: usize = 20;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum Specialty {
+    GeneralMedicine,
+    Trauma,
+    Burns,
+    Dialysis,
+    Maternity,
+    Pediatrics,
+    Surgery,
+    IntensiveCare,
+    InfectiousDisease,
+    MentalHealth,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub(crate) enum FacilityStatus {
+    #[default]
+    Operational,
+    Limited,
+    Overwhelmed,
+    Closed,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct MedicalFacility {
+    id: u64,
+    name: String,
+    location: String,
+    coordinates: Coordinates,
+    total_beds: u64,
+    available_beds: u64,
+    specialties: Vec<Specialty>,
+    status: FacilityStatus,
+    contact: String,
+    // Principals verified to report on behalf of this facility
+    facility_principals: Vec<Principal>,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+// Implementing Storable and BoundedStorable traits for MedicalFacility
+impl Storable for MedicalFacility {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for MedicalFacility {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct MedicalFacilityPayload {
+    name: String,
+    location: String,
+    coordinates: Coordinates,
+    total_beds: u64,
+    specialties: Vec<Specialty>,
+    contact: String,
+    facility_principals: Vec<Principal>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct FacilityCapacityPayload {
+    available_beds: u64,
+    status: FacilityStatus,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct NearbyFacility {
+    facility: MedicalFacility,
+    distance_km: f64,
+}
+
+thread_local! {
+    static FACILITY_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(9, "medical facilities"));
+
+    static FACILITY_STORAGE: RefCell<StableBTreeMap<u64, MedicalFacility, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10)))
+    ));
+}
+
+// Helper method to perform insert for MedicalFacility
+fn do_insert_facility(facility: &MedicalFacility) -> Result<(), Error> {
+    crate::ensure_storable(facility, "medical facility")?;
+    FACILITY_STORAGE.with(|service| service.borrow_mut().insert(facility.id, facility.clone()));
+    Ok(())
+}
+
+// Normalizes the text fields in place before checking them
+fn validate_facility_payload(payload: &mut MedicalFacilityPayload) -> Result<(), Error> {
+    payload.name = normalize_line(&payload.name);
+    payload.location = normalize_line(&payload.location);
+    payload.contact = normalize_line(&payload.contact);
+    if payload.name.is_empty() {
+        return Err(Error::validation("facility name must not be empty"));
+    }
+    for (field, value, max) in [
+        ("name", &payload.name, MAX_NAME_CHARS),
+        ("location", &payload.location, MAX_LOCATION_CHARS),
+        ("contact", &payload.contact, MAX_CONTACT_CHARS),
+    ] {
+        if value.chars().count() > max {
+            return Err(Error::validation(format!("{} must be at most {} characters", field, max))
+                .with_detail("field", field));
+        }
+    }
+    if payload.specialties.len() > MAX_SPECIALTIES {
+        return Err(Error::validation(format!(
+            "a facility can list at most {} specialties",
+            MAX_SPECIALTIES
+        )));
+    }
+    if payload.facility_principals.len() > MAX_FACILITY_PRINCIPALS {
+        return Err(Error::validation(format!(
+            "a facility can have at most {} verified principals",
+            MAX_FACILITY_PRINCIPALS
+        )));
+    }
+    payload.coordinates.validate()
+}
+
+// Loads a facility and checks that the caller is one of its verified principals or an admin
+fn get_reporting_facility(id: u64) -> Result<MedicalFacility, Error> {
+    let facility = get_medical_facility(id)?;
+    let caller = caller();
+    if !facility.facility_principals.contains(&caller) && !has_role(&caller, Role::Admin) {
+        return Err(Error::unauthorized(format!(
+            "caller is not verified for the medical facility with id={}",
+            id
+        )));
+    }
+    Ok(facility)
+}
+
+// 2.12.1 get_medical_facility Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_medical_facility(id: u64) -> Result<MedicalFacility, Error> {
+    match FACILITY_STORAGE.with(|s| s.borrow().get(&id)) {
+        Some(facility) => Ok(facility),
+        None => Err(Error::not_found(format!("a medical facility with id={} not found", id))),
+    }
+}
+
+// 2.12.2 register_medical_facility Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn register_medical_facility(mut payload: MedicalFacilityPayload) -> Result<MedicalFacility, Error> {
+    crate::call_stats::count_call("register_medical_facility");
+    require_any_role(&[Role::Admin, Role::Coordinator])?;
+    validate_facility_payload(&mut payload)?;
+    let id = crate::ids::next_id(&FACILITY_ID_COUNTER)?;
+    let facility = MedicalFacility {
+        id,
+        name: payload.name,
+        location: payload.location,
+        coordinates: payload.coordinates,
+        total_beds: payload.total_beds,
+        available_beds: payload.total_beds,
+        specialties: payload.specialties,
+        status: FacilityStatus::Operational,
+        contact: payload.contact,
+        facility_principals: payload.facility_principals,
+        created_at: time(),
+        updated_at: None,
+    };
+    do_insert_facility(&facility)?;
+    Ok(facility)
+}
+
+// 2.12.3 update_medical_facility Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn update_medical_facility(id: u64, mut payload: MedicalFacilityPayload) -> Result<MedicalFacility, Error> {
+    crate::call_stats::count_call("update_medical_facility");
+    require_any_role(&[Role::Admin, Role::Coordinator])?;
+    validate_facility_payload(&mut payload)?;
+    let mut facility = get_medical_facility(id)?;
+    facility.name = payload.name;
+    facility.location = payload.location;
+    facility.coordinates = payload.coordinates;
+    facility.total_beds = payload.total_beds;
+    facility.available_beds = facility.available_beds.min(payload.total_beds);
+    facility.specialties = payload.specialties;
+    facility.contact = payload.contact;
+    facility.facility_principals = payload.facility_principals;
+    facility.updated_at = Some(time());
+    do_insert_facility(&facility)?;
+    Ok(facility)
+}
+
+// 2.12.4 update_medical_facility_capacity Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn update_medical_facility_capacity(id: u64, payload: FacilityCapacityPayload) -> Result<MedicalFacility, Error> {
+    crate::call_stats::count_call("update_medical_facility_capacity");
+    let mut facility = get_reporting_facility(id)?;
+    if payload.available_beds > facility.total_beds {
+        return Err(Error::validation(format!(
+            "available beds ({}) exceed the total of {} beds",
+            payload.available_beds, facility.total_beds
+        )));
+    }
+    facility.available_beds = payload.available_beds;
+    facility.status = payload.status;
+    facility.updated_at = Some(time());
+    do_insert_facility(&facility)?;
+    Ok(facility)
+}
+
+// 2.12.5 delete_medical_facility Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn delete_medical_facility(id: u64) -> Result<MedicalFacility, Error> {
+    crate::call_stats::count_call("delete_medical_facility");
+    require_role(Role::Admin)?;
+    match FACILITY_STORAGE.with(|service| service.borrow_mut().remove(&id)) {
+        Some(facility) => Ok(facility),
+        None => Err(Error::not_found(format!(
+            "couldn't delete a medical facility with id={}. facility not found.",
+            id
+        ))),
+    }
+}
+
+// 2.12.6 list_all_medical_facilities Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn list_all_medical_facilities() -> Result<QueryResult<MedicalFacility>, Error> {
+    fit(FACILITY_STORAGE.with(|service| service.borrow().iter().map(|(_, facility)| facility).collect()))
+}
+
+// 2.12.7 find_medical_facilities_near Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn find_medical_facilities_near(
+    latitude: f64,
+    longitude: f64,
+    need: Option<Specialty>,
+) -> Result<QueryResult<NearbyFacility>, Error> {
+    let origin = Coordinates { latitude, longitude };
+    origin.validate()?;
+    let mut facilities: Vec<NearbyFacility> = FACILITY_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, facility)| {
+                facility.status != FacilityStatus::Closed
+                    && facility.available_beds > 0
+                    && need.is_none_or(|n| facility.specialties.contains(&n))
+            })
+            .map(|(_, facility)| NearbyFacility {
+                distance_km: origin.distance_km(&facility.coordinates),
+                facility,
+            })
+            .collect()
+    });
+    facilities.sort_by(|a, b| a.distance_km.total_cmp(&b.distance_km));
+    facilities.truncate(MAX_NEARBY_FACILITIES);
+    fit(facilities)
+}
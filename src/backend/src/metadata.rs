@@ -0,0 +1,147 @@
+// Deployment-specific extra fields on crisis updates (water level, wind speed,
+// casualty count, ...). Admins declare which keys are allowed and the type of
+// each; updates carrying unknown keys or mistyped values are rejected.
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::maintenance::admit_update;
+use crate::settings::{get_settings, update_settings};
+use crate::Error;
+
+const MAX_SCHEMA_FIELDS: usize = 32;
+const MAX_METADATA_ENTRIES: usize = 16;
+const MAX_KEY_CHARS: usize = 40;
+const MAX_TEXT_VALUE_CHARS: usize = 200;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum MetadataType {
+    Text,
+    Integer,
+    Number,
+    Bool,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Debug)]
+pub(crate) enum MetadataValue {
+    Text(String),
+    Integer(i64),
+    Number(f64),
+    Bool(bool),
+}
+
+impl MetadataValue {
+    fn value_type(&self) -> MetadataType {
+        match self {
+            MetadataValue::Text(_) => MetadataType::Text,
+            MetadataValue::Integer(_) => MetadataType::Integer,
+            MetadataValue::Number(_) => MetadataType::Number,
+            MetadataValue::Bool(_) => MetadataType::Bool,
+        }
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct MetadataField {
+    pub(crate) key: String,
+    pub(crate) value_type: MetadataType,
+}
+
+pub(crate) type Metadata = Vec<(String, MetadataValue)>;
+
+pub(crate) fn schema() -> Vec<MetadataField> {
+    get_settings().metadata_schema.unwrap_or_default()
+}
+
+// Checks entries against the schema and sorts them by key
+pub(crate) fn validate_metadata(metadata: &mut Metadata) -> Result<(), Error> {
+    if metadata.len() > MAX_METADATA_ENTRIES {
+        return Err(Error::validation(format!(
+            "an update can carry at most {} metadata entries",
+            MAX_METADATA_ENTRIES
+        )));
+    }
+    let schema = schema();
+    for (key, value) in metadata.iter() {
+        let Some(field) = schema.iter().find(|field| field.key == *key) else {
+            return Err(Error::validation(format!("metadata key '{}' is not allowed", key)).with_detail("key", key));
+        };
+        if field.value_type != value.value_type() {
+            return Err(
+                Error::validation(format!("metadata key '{}' expects a {:?} value", key, field.value_type))
+                    .with_detail("key", key),
+            );
+        }
+        match value {
+            MetadataValue::Text(text) if text.chars().count() > MAX_TEXT_VALUE_CHARS => {
+                return Err(Error::validation(format!(
+                    "metadata text values must be at most {} characters",
+                    MAX_TEXT_VALUE_CHARS
+                ))
+                .with_detail("key", key));
+            }
+            MetadataValue::Number(number) if !number.is_finite() => {
+                return Err(
+                    Error::validation(format!("metadata key '{}' must be a finite number", key))
+                        .with_detail("key", key),
+                );
+            }
+            _ => {}
+        }
+    }
+    metadata.sort_by(|(a, _), (b, _)| a.cmp(b));
+    if let Some(pair) = metadata.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        return Err(
+            Error::validation(format!("metadata key '{}' is given more than once", pair[0].0))
+                .with_detail("key", &pair[0].0),
+        );
+    }
+    Ok(())
+}
+
+// True when every (key, value) in `wanted` is present on the update
+pub(crate) fn matches(metadata: &Metadata, wanted: &Metadata) -> bool {
+    wanted
+        .iter()
+        .all(|(key, value)| metadata.iter().any(|(k, v)| k == key && v == value))
+}
+
+// 2.39.1 set_metadata_schema Function:
+// Keys already stored on updates stay readable when removed from the schema.
+#[ic_cdk::update(guard = "admit_update")]
+fn set_metadata_schema(mut fields: Vec<MetadataField>) -> Result<Vec<MetadataField>, Error> {
+    crate::call_stats::count_call("set_metadata_schema");
+    require_role(Role::Admin)?;
+    if fields.len() > MAX_SCHEMA_FIELDS {
+        return Err(Error::validation(format!(
+            "the metadata schema can define at most {} fields",
+            MAX_SCHEMA_FIELDS
+        )));
+    }
+    for field in &mut fields {
+        field.key = field.key.trim().to_string();
+        let valid_key = !field.key.is_empty()
+            && field.key.chars().count() <= MAX_KEY_CHARS
+            && field.key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_key {
+            return Err(Error::validation(format!(
+                "metadata keys must be 1-{} characters of letters, digits and underscores",
+                MAX_KEY_CHARS
+            ))
+            .with_detail("key", &field.key));
+        }
+    }
+    fields.sort_by(|a, b| a.key.cmp(&b.key));
+    if let Some(pair) = fields.windows(2).find(|pair| pair[0].key == pair[1].key) {
+        return Err(Error::validation(format!(
+            "metadata key '{}' is defined more than once",
+            pair[0].key
+        )));
+    }
+    update_settings(|s| s.metadata_schema = Some(fields.clone()))?;
+    Ok(fields)
+}
+
+// 2.39.2 get_metadata_schema Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_metadata_schema() -> Vec<MetadataField> {
+    schema()
+}
@@ -0,0 +1,89 @@
+// Prometheus text exposition at /metrics, so standard monitoring stacks can
+// scrape the canister through the HTTP gateway. Call counters come from the
+// stable call statistics, so they survive upgrades. Only update calls can be
+// counted: state changed during a query is discarded when the query returns.
+use crate::{CrisisPriority, CrisisStatus, CRISIS_STORAGE};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+pub(crate) const METRICS_PATH: &str = "/metrics";
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+pub(crate) fn render_metrics() -> String {
+    let mut out = String::new();
+
+    let mut by_status_priority: BTreeMap<(String, String), u64> = BTreeMap::new();
+    for status in [
+        CrisisStatus::Active,
+        CrisisStatus::Resolved,
+        CrisisStatus::Archived,
+        CrisisStatus::Unconfirmed,
+    ] {
+        for priority in CrisisPriority::ALL {
+            by_status_priority.insert((format!("{:?}", status), format!("{:?}", priority)), 0);
+        }
+    }
+    CRISIS_STORAGE.with(|s| {
+        for (_, update) in s.borrow().iter() {
+            *by_status_priority
+                .entry((format!("{:?}", update.status), format!("{:?}", update.priority)))
+                .or_default() += 1;
+        }
+    });
+    header(
+        &mut out,
+        "crisis_updates",
+        "gauge",
+        "Crisis updates stored on this canister by status and priority.",
+    );
+    for ((status, priority), count) in by_status_priority {
+        let _ = writeln!(
+            out,
+            "crisis_updates{{status=\"{}\",priority=\"{}\"}} {}",
+            status, priority, count
+        );
+    }
+
+    header(
+        &mut out,
+        "crisis_update_calls_total",
+        "counter",
+        "Update calls handled, by method.",
+    );
+    for (method, count) in crate::call_stats::method_totals() {
+        let _ = writeln!(out, "crisis_update_calls_total{{method=\"{}\"}} {}", method, count);
+    }
+
+    header(
+        &mut out,
+        "notification_outbox_unread",
+        "gauge",
+        "Notifications waiting to be read by their recipients.",
+    );
+    let _ = writeln!(
+        out,
+        "notification_outbox_unread {}",
+        crate::notifications::unread_count()
+    );
+
+    header(
+        &mut out,
+        "replication_backlog",
+        "gauge",
+        "Changes not yet pushed to the replica canister.",
+    );
+    let _ = writeln!(out, "replication_backlog {}", crate::replication::replication_backlog());
+
+    header(
+        &mut out,
+        "canister_cycles_balance",
+        "gauge",
+        "Cycles held by the canister.",
+    );
+    let _ = writeln!(out, "canister_cycles_balance {}", ic_cdk::api::canister_balance128());
+    out
+}
@@ -0,0 +1,90 @@
+// Stable layout changes applied on upgrade. Crisis updates outgrew the 1024 byte
+// bound their maps were created with, and a map's bound cannot be raised in
+// place, so post_upgrade moves them once into maps created with the current
+// bound. The old maps are left empty, so later upgrades find nothing to move.
+// The change feed is compacted once as well, since it only started keeping
+// the newest record of each update after it had grown.
+use crate::logs::{log, LogLevel};
+use crate::snapshots::SNAPSHOT_UPDATES;
+use crate::{CrisisUpdate, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::cell::RefCell;
+
+// A crisis update under the bound the old maps were created with
+struct LegacyCrisisUpdate(CrisisUpdate);
+
+// Implementing Storable and BoundedStorable traits for LegacyCrisisUpdate
+impl Storable for LegacyCrisisUpdate {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        self.0.to_bytes()
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        LegacyCrisisUpdate(CrisisUpdate::from_bytes(bytes))
+    }
+}
+
+impl BoundedStorable for LegacyCrisisUpdate {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static LEGACY_CRISIS_STORAGE: RefCell<StableBTreeMap<u64, LegacyCrisisUpdate, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))
+    ));
+
+    static LEGACY_SNAPSHOT_UPDATES: RefCell<StableBTreeMap<(u64, u64), LegacyCrisisUpdate, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(64)))
+    ));
+}
+
+// One entry at a time, so the heap never holds a whole map
+fn move_crisis_updates() -> u64 {
+    let mut moved = 0;
+    while let Some((id, LegacyCrisisUpdate(update))) = LEGACY_CRISIS_STORAGE.with(|s| s.borrow().iter().next()) {
+        CRISIS_STORAGE.with(|s| s.borrow_mut().insert(id, update));
+        LEGACY_CRISIS_STORAGE.with(|s| s.borrow_mut().remove(&id));
+        moved += 1;
+    }
+    moved
+}
+
+fn move_snapshot_updates() -> u64 {
+    let mut moved = 0;
+    while let Some((key, LegacyCrisisUpdate(update))) = LEGACY_SNAPSHOT_UPDATES.with(|s| s.borrow().iter().next()) {
+        SNAPSHOT_UPDATES.with(|s| s.borrow_mut().insert(key, update));
+        LEGACY_SNAPSHOT_UPDATES.with(|s| s.borrow_mut().remove(&key));
+        moved += 1;
+    }
+    moved
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    // The WebSocket CDK keeps its state on the heap, so it is set up again after every upgrade
+    crate::realtime::init_websocket();
+    let updates = move_crisis_updates();
+    let snapshot_entries = move_snapshot_updates();
+    let superseded_changes = crate::changes::compact_change_feed();
+    if superseded_changes > 0 {
+        log(
+            LogLevel::Info,
+            Some("post_upgrade"),
+            &format!("dropped {} superseded change feed records", superseded_changes),
+        );
+    }
+    if updates + snapshot_entries > 0 {
+        log(
+            LogLevel::Info,
+            Some("post_upgrade"),
+            &format!(
+                "moved {} crisis updates and {} snapshot entries to the larger storage layout",
+                updates, snapshot_entries
+            ),
+        );
+    }
+}
@@ -0,0 +1,311 @@
+use crate::access::{has_role, Role};
+use crate::access_mode::admit_read;
+use crate::ids::IdCounter;
+use crate::maintenance::admit_update;
+use crate::notifications::{try_notify, NotificationKind};
+use crate::response::QueryResult;
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_NAME_CHARS: usize = 200;
+const MAX_DESCRIPTION_CHARS: usize = 1_000;
+const MAX_LOCATION_CHARS: usize = 200;
+const MAX_CONTACT_CHARS: usize = 200;
+const MAX_NOTE_CHARS: usize = 500;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub(crate) enum MissingPersonStatus {
+    #[default]
+    Missing,
+    Found,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct MissingPerson {
+    id: u64,
+    name: String,
+    description: String,
+    last_seen_location: String,
+    last_seen_at: u64,
+    photo_attachment_id: Option<u64>,
+    reporter: Principal,
+    reporter_contact: String,
+    status: MissingPersonStatus,
+    found_by: Option<Principal>,
+    found_note: Option<String>,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+// Implementing Storable and BoundedStorable traits for MissingPerson
+impl Storable for MissingPerson {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for MissingPerson {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct MissingPersonPayload {
+    name: String,
+    description: String,
+    last_seen_location: String,
+    last_seen_at: u64,
+    photo_attachment_id: Option<u64>,
+    reporter_contact: String,
+}
+
+// What callers get back: the reporter's identity and contact are only
+// disclosed to the reporter and to responders working the case
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct MissingPersonView {
+    id: u64,
+    name: String,
+    description: String,
+    last_seen_location: String,
+    last_seen_at: u64,
+    photo_attachment_id: Option<u64>,
+    status: MissingPersonStatus,
+    reporter: Option<Principal>,
+    reporter_contact: Option<String>,
+    found_note: Option<String>,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+thread_local! {
+    static MISSING_PERSON_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(16, "missing persons"));
+
+    static MISSING_PERSON_STORAGE: RefCell<StableBTreeMap<u64, MissingPerson, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17)))
+    ));
+}
+
+fn is_case_worker(principal: &Principal) -> bool {
+    [Role::Moderator, Role::Coordinator, Role::Responder]
+        .iter()
+        .any(|role| has_role(principal, *role))
+}
+
+fn to_view(person: MissingPerson, viewer: &Principal) -> MissingPersonView {
+    let privileged = person.reporter == *viewer || is_case_worker(viewer);
+    MissingPersonView {
+        id: person.id,
+        name: person.name,
+        description: person.description,
+        last_seen_location: person.last_seen_location,
+        last_seen_at: person.last_seen_at,
+        photo_attachment_id: person.photo_attachment_id,
+        status: person.status,
+        reporter: privileged.then_some(person.reporter),
+        reporter_contact: privileged.then_some(person.reporter_contact),
+        found_note: person.found_note,
+        created_at: person.created_at,
+        updated_at: person.updated_at,
+    }
+}
+
+// Helper method to perform insert for MissingPerson
+fn do_insert_missing_person(person: &MissingPerson) -> Result<(), Error> {
+    crate::ensure_storable(person, "missing person report")?;
+    MISSING_PERSON_STORAGE.with(|service| service.borrow_mut().insert(person.id, person.clone()));
+    Ok(())
+}
+
+pub(crate) fn missing_person_reports_of(principal: &Principal) -> Vec<MissingPerson> {
+    MISSING_PERSON_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, person)| person)
+            .filter(|person| person.reporter == *principal || person.found_by == Some(*principal))
+            .collect()
+    })
+}
+
+// Reports stay open for the search; the reporter and their contact are dropped,
+// and the anonymous principal, which cannot call updates, stands in as reporter
+pub(crate) fn anonymize_missing_person_reports_of(principal: &Principal, now: u64) -> u64 {
+    let reports = missing_person_reports_of(principal);
+    for mut person in reports.iter().cloned() {
+        if person.reporter == *principal {
+            person.reporter = Principal::anonymous();
+            person.reporter_contact = String::new();
+        }
+        if person.found_by == Some(*principal) {
+            person.found_by = None;
+        }
+        person.updated_at = Some(now);
+        MISSING_PERSON_STORAGE.with(|s| s.borrow_mut().insert(person.id, person));
+    }
+    reports.len() as u64
+}
+
+fn _get_missing_person(id: u64) -> Result<MissingPerson, Error> {
+    MISSING_PERSON_STORAGE
+        .with(|s| s.borrow().get(&id))
+        .ok_or_else(|| Error::not_found(format!("a missing person report with id={} not found", id)))
+}
+
+// Only the reporter or a moderator may change or withdraw a report
+fn get_editable_missing_person(id: u64) -> Result<MissingPerson, Error> {
+    let person = _get_missing_person(id)?;
+    let caller = caller();
+    if person.reporter != caller && !has_role(&caller, Role::Moderator) {
+        return Err(Error::unauthorized(format!(
+            "caller cannot modify the missing person report with id={}",
+            id
+        )));
+    }
+    Ok(person)
+}
+
+fn validate_missing_person_payload(payload: &MissingPersonPayload) -> Result<(), Error> {
+    if payload.name.trim().is_empty() || payload.last_seen_location.trim().is_empty() {
+        return Err(Error::validation("name and last seen location are required"));
+    }
+    for (field, value, max) in [
+        ("name", &payload.name, MAX_NAME_CHARS),
+        ("description", &payload.description, MAX_DESCRIPTION_CHARS),
+        ("last_seen_location", &payload.last_seen_location, MAX_LOCATION_CHARS),
+        ("reporter_contact", &payload.reporter_contact, MAX_CONTACT_CHARS),
+    ] {
+        if value.chars().count() > max {
+            return Err(Error::validation(format!("{} must be at most {} characters", field, max))
+                .with_detail("field", field));
+        }
+    }
+    Ok(())
+}
+
+// 2.15.1 report_missing_person Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn report_missing_person(payload: MissingPersonPayload) -> Result<MissingPersonView, Error> {
+    crate::call_stats::count_call("report_missing_person");
+    validate_missing_person_payload(&payload)?;
+    let id = crate::ids::next_id(&MISSING_PERSON_ID_COUNTER)?;
+    let reporter = caller();
+    let person = MissingPerson {
+        id,
+        name: payload.name,
+        description: payload.description,
+        last_seen_location: payload.last_seen_location,
+        last_seen_at: payload.last_seen_at,
+        photo_attachment_id: payload.photo_attachment_id,
+        reporter,
+        reporter_contact: payload.reporter_contact,
+        status: MissingPersonStatus::Missing,
+        found_by: None,
+        found_note: None,
+        created_at: time(),
+        updated_at: None,
+    };
+    do_insert_missing_person(&person)?;
+    Ok(to_view(person, &reporter))
+}
+
+// 2.15.2 update_missing_person Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn update_missing_person(id: u64, payload: MissingPersonPayload) -> Result<MissingPersonView, Error> {
+    crate::call_stats::count_call("update_missing_person");
+    validate_missing_person_payload(&payload)?;
+    let mut person = get_editable_missing_person(id)?;
+    person.name = payload.name;
+    person.description = payload.description;
+    person.last_seen_location = payload.last_seen_location;
+    person.last_seen_at = payload.last_seen_at;
+    person.photo_attachment_id = payload.photo_attachment_id;
+    person.reporter_contact = payload.reporter_contact;
+    person.updated_at = Some(time());
+    do_insert_missing_person(&person)?;
+    Ok(to_view(person, &caller()))
+}
+
+// 2.15.3 get_missing_person Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_missing_person(id: u64) -> Result<MissingPersonView, Error> {
+    _get_missing_person(id).map(|person| to_view(person, &caller()))
+}
+
+// 2.15.4 search_missing_persons Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn search_missing_persons(name_or_location: String) -> Result<QueryResult<MissingPersonView>, Error> {
+    let needle = name_or_location.to_lowercase();
+    let viewer = caller();
+    crate::query_limits::cap_search_results(MISSING_PERSON_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, person)| {
+                person.name.to_lowercase().contains(&needle)
+                    || person.last_seen_location.to_lowercase().contains(&needle)
+            })
+            .map(|(_, person)| to_view(person, &viewer))
+            .collect()
+    }))
+}
+
+// 2.15.5 mark_found Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn mark_found(id: u64, note: Option<String>) -> Result<MissingPersonView, Error> {
+    crate::call_stats::count_call("mark_found");
+    let mut person = _get_missing_person(id)?;
+    let caller = caller();
+    if person.reporter != caller && !is_case_worker(&caller) {
+        return Err(Error::unauthorized(
+            "only the reporter or a responder can mark a person as found",
+        ));
+    }
+    if person.status == MissingPersonStatus::Found {
+        return Err(Error::conflict(format!(
+            "missing person report with id={} is already marked found",
+            id
+        )));
+    }
+    if note.as_ref().is_some_and(|note| note.chars().count() > MAX_NOTE_CHARS) {
+        return Err(Error::validation(format!(
+            "notes must be at most {} characters",
+            MAX_NOTE_CHARS
+        )));
+    }
+    person.status = MissingPersonStatus::Found;
+    person.found_by = Some(caller);
+    person.found_note = note;
+    person.updated_at = Some(time());
+    do_insert_missing_person(&person)?;
+    if person.reporter != caller && person.reporter != Principal::anonymous() {
+        try_notify(
+            person.reporter,
+            NotificationKind::MissingPersonFound,
+            format!("{} has been marked as found", person.name),
+            Some(person.id),
+        );
+    }
+    Ok(to_view(person, &caller))
+}
+
+// 2.15.6 delete_missing_person Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn delete_missing_person(id: u64) -> Result<MissingPersonView, Error> {
+    crate::call_stats::count_call("delete_missing_person");
+    get_editable_missing_person(id)?;
+    match MISSING_PERSON_STORAGE.with(|service| service.borrow_mut().remove(&id)) {
+        Some(person) => Ok(to_view(person, &caller())),
+        None => Err(Error::not_found(format!(
+            "couldn't delete a missing person report with id={}. report not found.",
+            id
+        ))),
+    }
+}
@@ -0,0 +1,161 @@
+// Moderation mode. While it is on, new crisis updates from authors without a
+// trusted reputation wait in a review queue and stay hidden from everyone but
+// their author and staff until a moderator approves them.
+use crate::access::{is_staff, require_role, Role};
+use crate::access_mode::admit_read;
+use crate::logs::{log, LogLevel};
+use crate::maintenance::admit_update;
+use crate::reputation::{is_trusted, record_flag, record_rejection};
+use crate::response::QueryResult;
+use crate::settings::{get_settings, update_settings, Settings};
+use crate::{CrisisUpdate, Error, CRISIS_STORAGE};
+use candid::Principal;
+use ic_cdk::api::{caller, time};
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum ReviewStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+// The review state a new update starts in; None when moderation mode is off
+pub(crate) fn initial_review(author: &Principal) -> Option<ReviewStatus> {
+    if !get_settings().moderation_mode.unwrap_or(false) {
+        return None;
+    }
+    if is_trusted(author) || is_staff(author) {
+        Some(ReviewStatus::Approved)
+    } else {
+        Some(ReviewStatus::Pending)
+    }
+}
+
+// Unreviewed and rejected updates are shown only to their author and to staff;
+// reviewed ones are then subject to the update's visibility and tenant
+pub(crate) fn visible_to(update: &CrisisUpdate, viewer: &Principal) -> bool {
+    let reviewed = match update.review {
+        None | Some(ReviewStatus::Approved) => true,
+        Some(ReviewStatus::Pending | ReviewStatus::Rejected) => {
+            update.author.as_ref() == Some(viewer) || is_staff(viewer)
+        }
+    };
+    reviewed && crate::visibility::can_see(update, viewer) && crate::tenants::can_see(update, viewer)
+}
+
+pub(crate) fn visible_to_caller(update: &CrisisUpdate) -> bool {
+    visible_to(update, &caller())
+}
+
+// A locally stored update records are attached to; one the caller cannot see
+// is reported as not found, like a missing one
+pub(crate) fn visible_parent(crisis_update_id: u64) -> Result<CrisisUpdate, Error> {
+    crate::_get_crisis_update(&crisis_update_id)
+        .filter(visible_to_caller)
+        .ok_or_else(|| {
+            Error::not_found(format!("a crisis update with id={} not found", crisis_update_id))
+                .with_detail("crisis_update_id", crisis_update_id)
+        })
+}
+
+// 2.36.1 set_moderation_mode Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn set_moderation_mode(enabled: bool) -> Result<Settings, Error> {
+    crate::call_stats::count_call("set_moderation_mode");
+    require_role(Role::Admin)?;
+    update_settings(|s| s.moderation_mode = Some(enabled))
+}
+
+// 2.36.2 list_pending_crisis_updates Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn list_pending_crisis_updates() -> Result<QueryResult<CrisisUpdate>, Error> {
+    require_role(Role::Moderator)?;
+    crate::fit_updates(CRISIS_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, update)| update)
+            .filter(|update| update.review == Some(ReviewStatus::Pending))
+            .collect()
+    }))
+}
+
+// 2.36.3 review_crisis_update Function:
+// Rejecting counts against the author's reputation and towards an automatic ban.
+#[ic_cdk::update(guard = "admit_update")]
+async fn review_crisis_update(id: u64, approve: bool) -> Result<CrisisUpdate, Error> {
+    crate::call_stats::count_call("review_crisis_update");
+    require_role(Role::Moderator)?;
+    let shard = crate::sharding::sharded_location(id);
+    let existing = match shard {
+        Some(_) => crate::sharding::fetch_from_shard(id).await?,
+        None => crate::_get_crisis_update(&id),
+    };
+    let Some(mut update) = existing else {
+        return Err(Error::not_found(format!(
+            "couldn't review a crisis update with id={}. update not found",
+            id
+        )));
+    };
+    if update.review != Some(ReviewStatus::Pending) {
+        return Err(Error::conflict(format!(
+            "the crisis update with id={} is not awaiting review",
+            id
+        )));
+    }
+    update.review = Some(if approve {
+        ReviewStatus::Approved
+    } else {
+        ReviewStatus::Rejected
+    });
+    update.updated_at = Some(time());
+    match shard {
+        Some(shard) => crate::sharding::forward_insert(shard, &update).await?,
+        None => crate::do_insert_crisis_update(&update)?,
+    }
+    if !approve {
+        if let Some(author) = &update.author {
+            record_rejection(author);
+            crate::bans::record_strike(author, time());
+        }
+    }
+    crate::changes::record_change(id, crate::changes::ChangeKind::Updated)?;
+    crate::timeline::record_review(id, approve, caller());
+    Ok(update)
+}
+
+// 2.36.4 flag_crisis_update Function:
+// Marks a published update as inaccurate or abusive; an update counts against its author once.
+#[ic_cdk::update(guard = "admit_update")]
+async fn flag_crisis_update(id: u64, reason: String) -> Result<bool, Error> {
+    crate::call_stats::count_call("flag_crisis_update");
+    let moderator = require_role(Role::Moderator)?;
+    let Some(update) = crate::_find_crisis_update(id).await? else {
+        return Err(Error::not_found(format!(
+            "couldn't flag a crisis update with id={}. update not found",
+            id
+        )));
+    };
+    let Some(author) = update.author else {
+        return Err(Error::validation(format!(
+            "the crisis update with id={} has no recorded author",
+            id
+        )));
+    };
+    let counted = record_flag(id, &author);
+    if counted {
+        crate::bans::record_strike(&author, time());
+    }
+    log(
+        LogLevel::Info,
+        Some("flag_crisis_update"),
+        &format!("crisis update {} flagged by {}: {}", id, moderator, reason),
+    );
+    Ok(counted)
+}
+
+// 2.36.5 get_moderation_mode Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_moderation_mode() -> bool {
+    get_settings().moderation_mode.unwrap_or(false)
+}
@@ -0,0 +1,76 @@
+// Text normalization applied before storing and indexing, so that "Nairobi "
+// and "Nairobi" are the same location and padding cannot stretch a value past
+// a length check. Composition to NFC covers the accented Latin letters that a
+// base letter plus combining mark commonly produces; sequences outside that
+// table are kept as submitted.
+
+// (combining mark, base letters, precomposed letters in the same order)
+const COMPOSITIONS: &[(char, &str, &str)] = &[
+    ('\u{0300}', "AEIOUaeiou", "ÀÈÌÒÙàèìòù"),
+    ('\u{0301}', "AEIOUYaeiouyCcNnSsZz", "ÁÉÍÓÚÝáéíóúýĆćŃńŚśŹź"),
+    ('\u{0302}', "AEIOUaeiou", "ÂÊÎÔÛâêîôû"),
+    ('\u{0303}', "ANOano", "ÃÑÕãñõ"),
+    ('\u{0308}', "AEIOUaeiouy", "ÄËÏÖÜäëïöüÿ"),
+    ('\u{030A}', "Aa", "Åå"),
+    ('\u{030C}', "CcSsZzEeRr", "ČčŠšŽžĚěŘř"),
+    ('\u{0327}', "CcSs", "ÇçŞş"),
+];
+
+fn compose(base: char, mark: char) -> Option<char> {
+    let (_, bases, composed) = COMPOSITIONS.iter().find(|(m, _, _)| *m == mark)?;
+    let index = bases.chars().position(|c| c == base)?;
+    composed.chars().nth(index)
+}
+
+fn to_nfc(text: &str) -> String {
+    let mut composed = String::with_capacity(text.len());
+    for c in text.chars() {
+        match composed.pop() {
+            Some(last) => match compose(last, c) {
+                Some(merged) => composed.push(merged),
+                None => {
+                    composed.push(last);
+                    composed.push(c);
+                }
+            },
+            None => composed.push(c),
+        }
+    }
+    composed
+}
+
+// NFC, trimmed, with every run of whitespace replaced by a single space
+pub(crate) fn normalize_line(text: &str) -> String {
+    to_nfc(text).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Like `normalize_line` for each line, keeping line breaks but not leading or
+// trailing blank lines
+pub(crate) fn normalize_multiline(text: &str) -> String {
+    let lines: Vec<String> = text.lines().map(normalize_line).collect();
+    lines.join("\n").trim_matches('\n').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combining_marks_compose() {
+        assert_eq!(normalize_line("Cafe\u{0301}"), "Café");
+        assert_eq!(normalize_line("Ko\u{0308}ln"), "Köln");
+        // No precomposed letter for this pair, so it is kept as submitted
+        assert_eq!(normalize_line("q\u{0301}"), "q\u{0301}");
+    }
+
+    #[test]
+    fn whitespace_collapses_to_single_spaces() {
+        assert_eq!(normalize_line("  Nairobi \t  West\n"), "Nairobi West");
+        assert_eq!(normalize_line(" \n\t "), "");
+    }
+
+    #[test]
+    fn multiline_keeps_inner_line_breaks() {
+        assert_eq!(normalize_multiline("\n\n  first   line \n\nsecond\n\n"), "first line\n\nsecond");
+    }
+}
@@ -0,0 +1,321 @@
+use crate::access::{principal_key, PrincipalKey};
+use crate::access_mode::admit_read;
+use crate::geo::Geofence;
+use crate::ids::IdCounter;
+use crate::logs::{log, LogLevel};
+use crate::maintenance::admit_update;
+use crate::response::{fit, QueryResult};
+use crate::{CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::collections::BTreeMap;
+use std::{borrow::Cow, cell::RefCell};
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum NotificationKind {
+    MissingPersonFound,
+    AssignedToUpdate,
+    CrisisStatusChanged,
+    CrisisPriorityChanged,
+    MassAlert,
+    SitRep,
+    LowStock,
+    WatchedUpdateEdited,
+    StaleReportCheck,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Notification {
+    pub(crate) id: u64,
+    pub(crate) recipient: Principal,
+    pub(crate) kind: NotificationKind,
+    pub(crate) message: String,
+    // Id of the record the notification is about, interpreted according to `kind`
+    pub(crate) related_id: Option<u64>,
+    pub(crate) created_at: u64,
+    pub(crate) read: bool,
+}
+
+// Implementing Storable and BoundedStorable traits for Notification
+impl Storable for Notification {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Notification {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+const MAX_SUBSCRIBED_LOCATIONS: usize = 20;
+
+// Per-user opt-outs and location subscriptions for automatic notifications
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct NotificationPreferences {
+    status_changes: bool,
+    priority_changes: bool,
+    subscribed_locations: Vec<String>,
+}
+
+impl Default for NotificationPreferences {
+    fn default() -> Self {
+        NotificationPreferences {
+            status_changes: true,
+            priority_changes: true,
+            subscribed_locations: Vec::new(),
+        }
+    }
+}
+
+// Implementing Storable and BoundedStorable traits for NotificationPreferences
+impl Storable for NotificationPreferences {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for NotificationPreferences {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl NotificationPreferences {
+    fn wants(&self, kind: NotificationKind) -> bool {
+        match kind {
+            NotificationKind::CrisisStatusChanged => self.status_changes,
+            NotificationKind::CrisisPriorityChanged => self.priority_changes,
+            _ => true,
+        }
+    }
+}
+
+thread_local! {
+    static NOTIFICATION_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(14, "notifications"));
+
+    static NOTIFICATION_OUTBOX: RefCell<StableBTreeMap<u64, Notification, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15)))
+    ));
+
+    static NOTIFICATION_PREFERENCES: RefCell<StableBTreeMap<PrincipalKey, NotificationPreferences, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(45)))
+    ));
+}
+
+pub(crate) fn notifications_of(principal: &Principal) -> Vec<Notification> {
+    NOTIFICATION_OUTBOX.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, n)| n)
+            .filter(|n| n.recipient == *principal)
+            .collect()
+    })
+}
+
+// Removes the principal's notifications and preferences; returns how many notifications there were
+pub(crate) fn remove_notifications_of(principal: &Principal) -> u64 {
+    let ids: Vec<u64> = NOTIFICATION_OUTBOX.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, n)| n.recipient == *principal)
+            .map(|(id, _)| id)
+            .collect()
+    });
+    NOTIFICATION_OUTBOX.with(|s| {
+        let mut s = s.borrow_mut();
+        for id in &ids {
+            s.remove(id);
+        }
+    });
+    NOTIFICATION_PREFERENCES.with(|s| s.borrow_mut().remove(&principal_key(principal)));
+    ids.len() as u64
+}
+
+pub(crate) fn preferences_of(principal: &Principal) -> NotificationPreferences {
+    NOTIFICATION_PREFERENCES
+        .with(|p| p.borrow().get(&principal_key(principal)))
+        .unwrap_or_default()
+}
+
+// Subscribers to the update's primary location or any of its affected areas
+fn location_subscribers(update: &CrisisUpdate) -> Vec<Principal> {
+    NOTIFICATION_PREFERENCES.with(|p| {
+        p.borrow()
+            .iter()
+            .filter(|(_, prefs)| {
+                prefs
+                    .subscribed_locations
+                    .iter()
+                    .any(|l| crate::areas::covers(update, l))
+            })
+            .map(|(key, _)| Principal::from_slice(key.as_slice()))
+            .collect()
+    })
+}
+
+// Subscribers to any of the named places
+pub(crate) fn subscribers_of(places: &[String]) -> Vec<Principal> {
+    NOTIFICATION_PREFERENCES.with(|p| {
+        p.borrow()
+            .iter()
+            .filter(|(_, prefs)| {
+                prefs
+                    .subscribed_locations
+                    .iter()
+                    .any(|l| places.iter().any(|place| crate::areas::same_place(l, place)))
+            })
+            .map(|(key, _)| Principal::from_slice(key.as_slice()))
+            .collect()
+    })
+}
+
+// Subscribers with a subscribed location whose known coordinates fall inside the
+// geofence; names without recorded coordinates cannot be placed and do not match
+pub(crate) fn subscribers_within(geofence: &Geofence) -> Vec<Principal> {
+    let mut placed: BTreeMap<String, bool> = BTreeMap::new();
+    let mut inside = |name: &String| {
+        *placed.entry(name.clone()).or_insert_with(|| {
+            crate::areas::coordinates_of(name).is_some_and(|point| geofence.contains(&point))
+        })
+    };
+    let subscriptions: Vec<(PrincipalKey, Vec<String>)> = NOTIFICATION_PREFERENCES.with(|p| {
+        p.borrow()
+            .iter()
+            .map(|(key, prefs)| (key, prefs.subscribed_locations))
+            .collect()
+    });
+    subscriptions
+        .into_iter()
+        .filter(|(_, locations)| locations.iter().any(&mut inside))
+        .map(|(key, _)| Principal::from_slice(key.as_slice()))
+        .collect()
+}
+
+// Notifies the author, assignees, watchers and location subscribers of a status or
+// priority change, skipping whoever made the change and anyone who opted out
+pub(crate) fn notify_crisis_update_changed(update: &CrisisUpdate, kind: NotificationKind, changed_by: Principal) {
+    let mut recipients: Vec<Principal> = crate::assignments::assigned_principals(update.id);
+    recipients.extend(update.author);
+    recipients.extend(crate::watches::watchers_of(update.id));
+    recipients.extend(location_subscribers(update));
+    recipients.sort();
+    recipients.dedup();
+    let title: String = update.title.chars().take(200).collect();
+    let message = match kind {
+        NotificationKind::CrisisPriorityChanged => {
+            format!(
+                "Crisis update #{} is now {:?} priority: {}",
+                update.id, update.priority, title
+            )
+        }
+        _ => format!("Crisis update #{} is now {:?}: {}", update.id, update.status, title),
+    };
+    for recipient in recipients {
+        if recipient != changed_by
+            && preferences_of(&recipient).wants(kind)
+            && crate::moderation::visible_to(update, &recipient)
+        {
+            try_notify(recipient, kind, message.clone(), Some(update.id));
+        }
+    }
+}
+
+// Queues a notification for the recipient and returns its id
+pub(crate) fn enqueue_notification(
+    recipient: Principal,
+    kind: NotificationKind,
+    message: String,
+    related_id: Option<u64>,
+) -> Result<u64, Error> {
+    let id = crate::ids::next_id(&NOTIFICATION_ID_COUNTER)?;
+    let notification = Notification {
+        id,
+        recipient,
+        kind,
+        message,
+        related_id,
+        created_at: time(),
+        read: false,
+    };
+    crate::ensure_storable(&notification, "notification")?;
+    NOTIFICATION_OUTBOX.with(|s| s.borrow_mut().insert(id, notification));
+    Ok(id)
+}
+
+// Notifications are a side effect of a change that has already been stored, so a
+// failure to queue one is logged rather than failing the change
+pub(crate) fn unread_count() -> u64 {
+    NOTIFICATION_OUTBOX.with(|s| s.borrow().iter().filter(|(_, n)| !n.read).count() as u64)
+}
+
+pub(crate) fn try_notify(recipient: Principal, kind: NotificationKind, message: String, related_id: Option<u64>) {
+    if let Err(err) = enqueue_notification(recipient, kind, message, related_id) {
+        log(
+            LogLevel::Error,
+            Some("notifications"),
+            &format!("failed to queue a {:?} notification: {:?}", kind, err),
+        );
+    }
+}
+
+// 2.14.1 get_my_notifications Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_my_notifications(unread_only: bool) -> Result<QueryResult<Notification>, Error> {
+    let caller = caller();
+    fit(NOTIFICATION_OUTBOX.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, n)| n.recipient == caller && (!unread_only || !n.read))
+            .map(|(_, n)| n)
+            .collect()
+    }))
+}
+
+// 2.14.2 mark_notification_read Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn mark_notification_read(id: u64) -> Result<Notification, Error> {
+    crate::call_stats::count_call("mark_notification_read");
+    match NOTIFICATION_OUTBOX.with(|s| s.borrow().get(&id)) {
+        Some(mut notification) if notification.recipient == caller() => {
+            notification.read = true;
+            NOTIFICATION_OUTBOX.with(|s| s.borrow_mut().insert(id, notification.clone()));
+            Ok(notification)
+        }
+        _ => Err(Error::not_found(format!("a notification with id={} not found", id))),
+    }
+}
+
+// 2.14.3 get_notification_preferences Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_notification_preferences() -> NotificationPreferences {
+    preferences_of(&caller())
+}
+
+// 2.14.4 set_notification_preferences Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn set_notification_preferences(mut preferences: NotificationPreferences) -> Result<NotificationPreferences, Error> {
+    crate::call_stats::count_call("set_notification_preferences");
+    preferences.subscribed_locations.retain(|l| !l.trim().is_empty());
+    preferences.subscribed_locations.dedup();
+    if preferences.subscribed_locations.len() > MAX_SUBSCRIBED_LOCATIONS {
+        return Err(Error::validation(format!(
+            "at most {} locations can be subscribed to",
+            MAX_SUBSCRIBED_LOCATIONS
+        )));
+    }
+    NOTIFICATION_PREFERENCES.with(|p| p.borrow_mut().insert(principal_key(&caller()), preferences.clone()));
+    Ok(preferences)
+}
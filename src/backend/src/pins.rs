@@ -0,0 +1,125 @@
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::logs::{log, LogLevel};
+use crate::maintenance::admit_update;
+use crate::response::QueryResult;
+use crate::{CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{Cell, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_PINNED_UPDATES: usize = 10;
+
+// Crisis update ids in the order clients should show them
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct PinnedUpdates(Vec<u64>);
+
+impl Storable for PinnedUpdates {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    static PINNED_UPDATES: RefCell<Cell<PinnedUpdates, Memory>> = RefCell::new(
+        Cell::init(CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(37))), PinnedUpdates::default())
+            .expect("Cannot create the pinned updates cell")
+    );
+}
+
+pub(crate) fn pinned_ids() -> Vec<u64> {
+    PINNED_UPDATES.with(|p| p.borrow().get().0.clone())
+}
+
+fn set_pinned_ids(ids: Vec<u64>) -> Result<(), Error> {
+    // The cache keeps the pinned updates, so it is rebuilt for the new set
+    crate::cache::clear();
+    PINNED_UPDATES
+        .with(|p| p.borrow_mut().set(PinnedUpdates(ids)))
+        .map(|_| ())
+        .map_err(|err| Error::internal(format!("cannot persist pinned updates: {:?}", err)))
+}
+
+// Drops a deleted update from the pin list, if it was pinned
+pub(crate) fn unpin_deleted(id: u64) {
+    let mut ids = pinned_ids();
+    if let Some(position) = ids.iter().position(|pinned| *pinned == id) {
+        ids.remove(position);
+        if let Err(err) = set_pinned_ids(ids) {
+            log(
+                LogLevel::Error,
+                Some("unpin_deleted"),
+                &format!("failed to unpin deleted crisis update {}: {:?}", id, err),
+            );
+        }
+    }
+}
+
+// Drops pins of updates that no longer exist; returns how many were removed
+pub(crate) fn remove_orphaned_pins(is_live: &dyn Fn(u64) -> bool) -> Result<u64, Error> {
+    let ids = pinned_ids();
+    let kept: Vec<u64> = ids.iter().copied().filter(|id| is_live(*id)).collect();
+    let removed = (ids.len() - kept.len()) as u64;
+    if removed > 0 {
+        set_pinned_ids(kept)?;
+    }
+    Ok(removed)
+}
+
+// 2.26.1 pin_crisis_update Function:
+// Pins go to the end of the list unless a position is given.
+#[ic_cdk::update(guard = "admit_update")]
+fn pin_crisis_update(id: u64, position: Option<u64>) -> Result<Vec<u64>, Error> {
+    crate::call_stats::count_call("pin_crisis_update");
+    require_role(Role::Moderator)?;
+    if crate::_get_crisis_update(&id).is_none() && crate::sharding::sharded_location(id).is_none() {
+        return Err(Error::not_found(format!(
+            "couldn't pin a crisis update with id={}. update not found",
+            id
+        )));
+    }
+    let mut ids = pinned_ids();
+    ids.retain(|pinned| *pinned != id);
+    if ids.len() >= MAX_PINNED_UPDATES {
+        return Err(Error::validation(format!(
+            "at most {} crisis updates can be pinned",
+            MAX_PINNED_UPDATES
+        )));
+    }
+    let position = position.map_or(ids.len(), |p| (p as usize).min(ids.len()));
+    ids.insert(position, id);
+    set_pinned_ids(ids.clone())?;
+    Ok(ids)
+}
+
+// 2.26.2 unpin_crisis_update Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn unpin_crisis_update(id: u64) -> Result<Vec<u64>, Error> {
+    crate::call_stats::count_call("unpin_crisis_update");
+    require_role(Role::Moderator)?;
+    let mut ids = pinned_ids();
+    let Some(position) = ids.iter().position(|pinned| *pinned == id) else {
+        return Err(Error::not_found(format!(
+            "a crisis update with id={} is not pinned",
+            id
+        )));
+    };
+    ids.remove(position);
+    set_pinned_ids(ids.clone())?;
+    Ok(ids)
+}
+
+// 2.26.3 list_pinned_crisis_updates Function:
+#[ic_cdk::query(composite = true, guard = "admit_read")]
+async fn list_pinned_crisis_updates() -> Result<QueryResult<CrisisUpdate>, Error> {
+    let mut updates = Vec::new();
+    for id in pinned_ids() {
+        updates.extend(crate::_find_crisis_update(id).await?);
+    }
+    crate::fit_updates(updates)
+}
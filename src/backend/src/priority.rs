@@ -0,0 +1,168 @@
+// Dispatcher work queue: open crisis updates indexed by priority, most urgent
+// first and oldest first within a priority, so the queue is read without sorting.
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::maintenance::admit_update;
+use crate::notifications::{notify_crisis_update_changed, NotificationKind};
+use crate::response::QueryResult;
+use crate::{CrisisPriority, CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+
+thread_local! {
+    // (u8::MAX - priority rank, crisis update id) for every active update
+    static PRIORITY_QUEUE: RefCell<StableBTreeMap<(u8, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(38)))
+    ));
+}
+
+fn queue_key(priority: CrisisPriority, id: u64) -> (u8, u64) {
+    (u8::MAX - priority as u8, id)
+}
+
+pub(crate) fn remove_from_priority_queue(id: u64) {
+    PRIORITY_QUEUE.with(|queue| {
+        let mut queue = queue.borrow_mut();
+        for priority in CrisisPriority::ALL {
+            queue.remove(&queue_key(priority, id));
+        }
+    });
+}
+
+// Re-indexes an update after any change to its priority or status
+pub(crate) fn sync_priority_queue(update: &CrisisUpdate) {
+    remove_from_priority_queue(update.id);
+    if update.status.is_open() {
+        PRIORITY_QUEUE.with(|queue| queue.borrow_mut().insert(queue_key(update.priority, update.id), ()));
+    }
+}
+
+// Whether the update is queued under its current priority if active, and not at all otherwise
+pub(crate) fn is_queued_correctly(update: &CrisisUpdate) -> bool {
+    PRIORITY_QUEUE.with(|queue| {
+        let queue = queue.borrow();
+        CrisisPriority::ALL.iter().all(|priority| {
+            let expected = update.status.is_open() && *priority == update.priority;
+            queue.contains_key(&queue_key(*priority, update.id)) == expected
+        })
+    })
+}
+
+// Queue entries of updates that no longer exist, and entries of local updates
+// that are no longer active or sit under an old priority
+fn stale_queue_entries(is_live: &dyn Fn(u64) -> bool) -> Vec<(u8, u64)> {
+    PRIORITY_QUEUE.with(|queue| {
+        CRISIS_STORAGE.with(|storage| {
+            let storage = storage.borrow();
+            queue
+                .borrow()
+                .iter()
+                .map(|(key, _)| key)
+                .filter(|key| match storage.get(&key.1) {
+                    Some(update) => {
+                        !update.status.is_open() || queue_key(update.priority, update.id) != *key
+                    }
+                    None => !is_live(key.1),
+                })
+                .collect()
+        })
+    })
+}
+
+pub(crate) fn count_stale_queue_entries(is_live: &dyn Fn(u64) -> bool) -> u64 {
+    stale_queue_entries(is_live).len() as u64
+}
+
+// Drops stale queue entries; returns how many were removed
+pub(crate) fn remove_stale_queue_entries(is_live: &dyn Fn(u64) -> bool) -> u64 {
+    let stale = stale_queue_entries(is_live);
+    PRIORITY_QUEUE.with(|queue| {
+        let mut queue = queue.borrow_mut();
+        for key in &stale {
+            queue.remove(key);
+        }
+    });
+    stale.len() as u64
+}
+
+// Writes a new priority back to wherever the update is held and tells those following it
+pub(crate) async fn store_priority(
+    mut update: CrisisUpdate,
+    priority: CrisisPriority,
+    changed_by: Principal,
+) -> Result<CrisisUpdate, Error> {
+    let before = update.clone();
+    let previous = update.priority;
+    let changed = previous != priority;
+    update.priority = priority;
+    update.updated_at = Some(time());
+    crate::ensure_storable(&update, "crisis update")?;
+    match crate::sharding::sharded_location(update.id) {
+        Some(shard) => crate::sharding::forward_insert(shard, &update).await?,
+        None => crate::do_insert_crisis_update(&update)?,
+    }
+    sync_priority_queue(&update);
+    crate::sla::sync_sla(&update);
+    crate::heat_index::record_change(Some(&before), &update);
+    crate::changes::record_change(update.id, crate::changes::ChangeKind::Updated)?;
+    if changed {
+        notify_crisis_update_changed(&update, NotificationKind::CrisisPriorityChanged, changed_by);
+        crate::webhooks::enqueue(
+            crate::webhooks::WebhookEvent::PriorityChanged {
+                from: previous,
+                to: priority,
+            },
+            update.id,
+        );
+    }
+    Ok(update)
+}
+
+// 2.27.1 set_crisis_update_priority Function:
+#[ic_cdk::update(guard = "admit_update")]
+async fn set_crisis_update_priority(id: u64, priority: CrisisPriority) -> Result<CrisisUpdate, Error> {
+    crate::call_stats::count_call("set_crisis_update_priority");
+    let caller = require_role(Role::Coordinator)?;
+    let shard = crate::sharding::sharded_location(id);
+    let existing = match shard {
+        Some(_) => crate::sharding::fetch_from_shard(id).await?,
+        None => crate::_get_crisis_update(&id),
+    };
+    let Some(update) = existing else {
+        return Err(Error::not_found(format!(
+            "couldn't update the priority of a crisis update with id={}. update not found",
+            id
+        )));
+    };
+    let previous = update.priority;
+    let update = store_priority(update, priority, caller).await?;
+    if previous != priority {
+        crate::timeline::record_priority_change(id, previous, priority, caller);
+    }
+    Ok(update)
+}
+
+// 2.27.2 get_next_priority_updates Function:
+#[ic_cdk::query(composite = true, guard = "admit_read")]
+// `total_count` is the number of updates waiting in the queue.
+async fn get_next_priority_updates(limit: u64) -> Result<QueryResult<CrisisUpdate>, Error> {
+    let (ids, queued): (Vec<u64>, u64) = PRIORITY_QUEUE.with(|queue| {
+        let queue = queue.borrow();
+        let ids = queue
+            .iter()
+            .take(crate::query_limits::page_size(limit) as usize)
+            .map(|((_, id), _)| id)
+            .collect();
+        (ids, queue.len())
+    });
+    let mut updates = Vec::with_capacity(ids.len());
+    for id in ids {
+        updates.extend(crate::_find_crisis_update(id).await?);
+    }
+    Ok(QueryResult::limited(crate::present_updates(updates), queued))
+}
@@ -0,0 +1,315 @@
+// Data-protection requests from the people whose data the canister holds,
+// and the retention policy for old updates. Crisis updates are part of the
+// public record of an emergency, so erasing them would hide information others
+// rely on; they are anonymized instead, as are resource requests and offers and
+// missing person reports, which responders still act on. Personal records are
+// removed, along with the caller's call counts and ban strikes. A ban in force
+// is kept, or erasure would lift it. Each erasure leaves an audit entry that
+// names the subject only by a hash of their principal.
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::changes::{record_change, ChangeKind};
+use crate::exports::{start_export_session, ExportInfo, ExportKind};
+use crate::ids::IdCounter;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::maintenance::admit_update;
+use crate::response::{fit, QueryResult};
+use crate::settings::{get_settings, update_settings};
+use crate::{CrisisStatus, CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+pub(crate) const ANONYMIZATION_INTERVAL_SECS: u64 = 24 * 60 * 60;
+const MIN_ANONYMIZE_AFTER_SECS: u64 = 24 * 60 * 60;
+const MAX_COORDINATE_DECIMALS: u8 = 6;
+const MAX_ANONYMIZED_PER_RUN: usize = 500;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AnonymizationPolicy {
+    // Resolved and archived updates untouched for this long are anonymized
+    after_secs: u64,
+    // Decimal places kept in coordinates; 2 is roughly a kilometre
+    coordinate_decimals: u8,
+    // Metadata keys holding personal data, such as a contact number
+    metadata_keys: Vec<String>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AnonymizationReport {
+    anonymized: u64,
+    // Eligible updates left for later runs
+    remaining: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ErasureSummary {
+    crisis_updates_anonymized: u64,
+    confirmations_removed: u64,
+    notifications_removed: u64,
+    safety_check_ins_removed: u64,
+    alert_acknowledgments_removed: u64,
+    profile_removed: bool,
+    volunteer_record_removed: bool,
+    badges_removed: bool,
+    drafts_removed: u64,
+    distribution_registrations_removed: u64,
+    watches_removed: u64,
+    quick_updates_removed: u64,
+    resource_requests_anonymized: u64,
+    resource_offers_anonymized: u64,
+    missing_person_reports_anonymized: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ErasureAuditEntry {
+    id: u64,
+    // Hex sha256 of the subject's principal, so a specific request can be verified
+    subject_hash: String,
+    erased_at: u64,
+    summary: ErasureSummary,
+}
+
+// Implementing Storable and BoundedStorable traits for ErasureAuditEntry
+impl Storable for ErasureAuditEntry {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ErasureAuditEntry {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static ERASURE_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(86, "erasure audit entries"));
+
+    static ERASURE_AUDIT: RefCell<StableBTreeMap<u64, ErasureAuditEntry, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(87)))
+    ));
+}
+
+fn subject_hash(principal: &Principal) -> String {
+    Sha256::digest(principal.as_slice())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn strip_author(update: &mut CrisisUpdate) {
+    update.author = None;
+    update.author_name = None;
+    update.signature = None;
+    update.anonymous = None;
+    update.contact = None;
+}
+
+// Drops the author, their label and their signature from every local update they wrote
+fn anonymize_updates_of(principal: &Principal, now: u64) -> Result<u64, Error> {
+    let authored: Vec<CrisisUpdate> = CRISIS_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, update)| update)
+            .filter(|update| update.author.as_ref() == Some(principal))
+            .collect()
+    });
+    for mut update in authored.iter().cloned() {
+        strip_author(&mut update);
+        update.updated_at = Some(now);
+        crate::do_insert_crisis_update(&update)?;
+        record_change(update.id, ChangeKind::Updated)?;
+    }
+    Ok(authored.len() as u64)
+}
+
+// 2.62.1 erase_my_data Function:
+// Covers updates stored on this canister; copies on shards, the archive and
+// taken snapshots are not rewritten. Open to banned principals as well.
+#[ic_cdk::update]
+fn erase_my_data() -> Result<ErasureSummary, Error> {
+    crate::call_stats::count_call("erase_my_data");
+    crate::maintenance::check_writable()?;
+    let subject = caller();
+    if subject == Principal::anonymous() {
+        return Err(Error::unauthorized("the anonymous principal holds no personal data"));
+    }
+    let now = time();
+    let summary = ErasureSummary {
+        crisis_updates_anonymized: anonymize_updates_of(&subject, now)?,
+        confirmations_removed: crate::reputation::remove_reporter_records(&subject),
+        notifications_removed: crate::notifications::remove_notifications_of(&subject),
+        safety_check_ins_removed: crate::safety::remove_check_ins_of(&subject),
+        alert_acknowledgments_removed: crate::alerts::remove_alert_records_of(&subject),
+        profile_removed: crate::profiles::remove_profile(&subject),
+        volunteer_record_removed: crate::volunteers::remove_volunteer(&subject),
+        badges_removed: crate::leaderboard::remove_badges(&subject),
+        drafts_removed: crate::drafts::remove_drafts_of(&subject),
+        distribution_registrations_removed: crate::distributions::remove_registrations_of(&subject),
+        watches_removed: crate::watches::remove_watches_of(&subject),
+        quick_updates_removed: crate::quick_updates::remove_quick_updates_of(&subject),
+        resource_requests_anonymized: crate::resources::anonymize_resource_requests_of(&subject, now),
+        resource_offers_anonymized: crate::resources::anonymize_resource_offers_of(&subject, now),
+        missing_person_reports_anonymized: crate::missing_persons::anonymize_missing_person_reports_of(
+            &subject, now,
+        ),
+    };
+    let entry = ErasureAuditEntry {
+        id: crate::ids::next_id(&ERASURE_ID_COUNTER)?,
+        subject_hash: subject_hash(&subject),
+        erased_at: now,
+        summary: summary.clone(),
+    };
+    ERASURE_AUDIT.with(|s| s.borrow_mut().insert(entry.id, entry));
+    crate::bans::remove_strikes(&subject);
+    // Last, so the count of this very call goes too
+    crate::call_stats::remove_caller_counts(&subject);
+    Ok(summary)
+}
+
+fn render_personal_data(subject: &Principal) -> Result<Vec<u8>, Error> {
+    let updates: Vec<CrisisUpdate> = CRISIS_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, update)| update)
+            .filter(|update| update.author.as_ref() == Some(subject))
+            .collect()
+    });
+    let document = serde_json::json!({
+        "principal": subject.to_text(),
+        "exported_at": time(),
+        "profile": crate::profiles::find_profile(subject),
+        "crisis_updates": updates,
+        "reputation": crate::reputation::reputation_of(*subject),
+        "confirmed_crisis_update_ids": crate::reputation::confirmations_of(subject),
+        "notification_preferences": crate::notifications::preferences_of(subject),
+        "notifications": crate::notifications::notifications_of(subject),
+        "volunteer": crate::volunteers::volunteer_of(subject),
+        "safety_check_ins": crate::safety::check_ins_of(subject),
+        "alert_acknowledgments": crate::alerts::acknowledgments_of(subject),
+        "badges": crate::leaderboard::badges_of(subject),
+        "tenant_id": crate::tenants::tenant_of(subject),
+        "resource_requests": crate::resources::resource_requests_of(subject),
+        "resource_offers": crate::resources::resource_offers_of(subject),
+        "missing_person_reports": crate::missing_persons::missing_person_reports_of(subject),
+    });
+    serde_json::to_vec(&document).map_err(|err| Error::internal(format!("cannot encode personal data: {}", err)))
+}
+
+// 2.62.2 list_erasure_audit Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn list_erasure_audit() -> Result<QueryResult<ErasureAuditEntry>, Error> {
+    require_role(Role::Admin)?;
+    fit(ERASURE_AUDIT.with(|s| s.borrow().iter().map(|(_, entry)| entry).collect()))
+}
+
+fn round_to(value: f64, decimals: u8) -> f64 {
+    let factor = 10f64.powi(decimals as i32);
+    (value * factor).round() / factor
+}
+
+// Applies the policy to an update; false when there was nothing left to strip
+fn anonymize(update: &mut CrisisUpdate, policy: &AnonymizationPolicy) -> bool {
+    let before = (
+        update.author.is_some()
+            || update.author_name.is_some()
+            || update.signature.is_some()
+            || update.anonymous.is_some()
+            || update.contact.is_some(),
+        update.metadata.len(),
+        update.coordinates,
+    );
+    strip_author(update);
+    update.metadata.retain(|(key, _)| !policy.metadata_keys.contains(key));
+    if let Some(coordinates) = update.coordinates.as_mut() {
+        coordinates.latitude = round_to(coordinates.latitude, policy.coordinate_decimals);
+        coordinates.longitude = round_to(coordinates.longitude, policy.coordinate_decimals);
+    }
+    before.0 || before.1 != update.metadata.len() || before.2 != update.coordinates
+}
+
+// Anonymizes up to a batch of eligible local updates. The updates keep their
+// modification time, so statistics over time are unaffected.
+pub(crate) fn run_anonymization(now: u64) -> Result<AnonymizationReport, Error> {
+    let Some(policy) = get_settings().anonymization_policy else {
+        return Ok(AnonymizationReport {
+            anonymized: 0,
+            remaining: 0,
+        });
+    };
+    let cutoff = now.saturating_sub(policy.after_secs.saturating_mul(NANOS_PER_SECOND));
+    let eligible: Vec<CrisisUpdate> = CRISIS_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, update)| update)
+            .filter(|update| matches!(update.status, CrisisStatus::Resolved | CrisisStatus::Archived))
+            .filter(|update| update.last_modified() <= cutoff)
+            .filter_map(|mut update| anonymize(&mut update, &policy).then_some(update))
+            .collect()
+    });
+    let mut anonymized = 0;
+    for update in eligible.iter().take(MAX_ANONYMIZED_PER_RUN) {
+        crate::do_insert_crisis_update(update)?;
+        record_change(update.id, ChangeKind::Updated)?;
+        anonymized += 1;
+    }
+    Ok(AnonymizationReport {
+        anonymized,
+        remaining: eligible.len() as u64 - anonymized,
+    })
+}
+
+// 2.62.3 set_anonymization_policy Function:
+// None turns scheduled anonymization off.
+#[ic_cdk::update(guard = "admit_update")]
+fn set_anonymization_policy(mut policy: Option<AnonymizationPolicy>) -> Result<Option<AnonymizationPolicy>, Error> {
+    crate::call_stats::count_call("set_anonymization_policy");
+    require_role(Role::Admin)?;
+    if let Some(policy) = policy.as_mut() {
+        if policy.after_secs < MIN_ANONYMIZE_AFTER_SECS {
+            return Err(
+                Error::validation(format!("after_secs must be at least {}", MIN_ANONYMIZE_AFTER_SECS))
+                    .with_detail("after_secs", policy.after_secs),
+            );
+        }
+        if policy.coordinate_decimals > MAX_COORDINATE_DECIMALS {
+            return Err(Error::validation(format!(
+                "coordinate_decimals must be at most {}",
+                MAX_COORDINATE_DECIMALS
+            )));
+        }
+        policy.metadata_keys.sort();
+        policy.metadata_keys.dedup();
+    }
+    update_settings(|s| s.anonymization_policy = policy.clone())?;
+    Ok(policy)
+}
+
+// 2.62.4 run_anonymization_now Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn run_anonymization_now() -> Result<AnonymizationReport, Error> {
+    crate::call_stats::count_call("run_anonymization_now");
+    require_role(Role::Admin)?;
+    run_anonymization(time())
+}
+
+// 2.62.5 export_my_data Function:
+// A JSON document of the caller's records, fetched with `get_export_chunk`.
+// Covers updates stored on this canister, like `erase_my_data`.
+#[ic_cdk::update(guard = "admit_update")]
+fn export_my_data() -> Result<ExportInfo, Error> {
+    crate::call_stats::count_call("export_my_data");
+    let subject = caller();
+    if subject == Principal::anonymous() {
+        return Err(Error::unauthorized("the anonymous principal holds no personal data"));
+    }
+    start_export_session(subject, ExportKind::PersonalData, render_personal_data(&subject)?)
+}
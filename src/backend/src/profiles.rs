@@ -0,0 +1,180 @@
+// Reporter profiles. Query responses carry the author's display label, such as
+// "Jane – Red Cross", so dashboards need not resolve raw principals themselves.
+use crate::access::{has_role, principal_key, PrincipalKey, Role};
+use crate::access_mode::admit_read;
+use crate::leaderboard::{badges_of, BadgeAward};
+use crate::maintenance::admit_update;
+use crate::normalize::normalize_line;
+use crate::{CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_DISPLAY_NAME_CHARS: usize = 64;
+const MAX_ORGANIZATION_CHARS: usize = 100;
+const MAX_CONTACT_CHANNEL_CHARS: usize = 200;
+const MAX_LANGUAGE_TAG_CHARS: usize = 16;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Profile {
+    principal: Principal,
+    display_name: String,
+    organization: Option<String>,
+    // Only shown to the owner and to coordinators and admins
+    contact_channel: Option<String>,
+    // BCP 47 language tag, e.g. "en" or "pt-BR"
+    preferred_language: Option<String>,
+    updated_at: u64,
+    // Filled in from the badge store on reads; never stored here
+    badges: Vec<BadgeAward>,
+}
+
+// Implementing Storable and BoundedStorable traits for Profile
+impl Storable for Profile {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Profile {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct ProfilePayload {
+    display_name: String,
+    organization: Option<String>,
+    contact_channel: Option<String>,
+    preferred_language: Option<String>,
+}
+
+thread_local! {
+    static PROFILE_STORAGE: RefCell<StableBTreeMap<PrincipalKey, Profile, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(51)))
+    ));
+}
+
+pub(crate) fn find_profile(principal: &Principal) -> Option<Profile> {
+    PROFILE_STORAGE.with(|s| s.borrow().get(&principal_key(principal)))
+}
+
+pub(crate) fn remove_profile(principal: &Principal) -> bool {
+    PROFILE_STORAGE.with(|s| s.borrow_mut().remove(&principal_key(principal)).is_some())
+}
+
+// "Display Name – Organization", or just the name when no organization is set
+// Users who have registered a profile
+pub(crate) fn profile_count() -> u64 {
+    PROFILE_STORAGE.with(|s| s.borrow().len())
+}
+
+pub(crate) fn display_label(principal: &Principal) -> Option<String> {
+    find_profile(principal).map(|profile| match profile.organization {
+        Some(organization) => format!("{} – {}", profile.display_name, organization),
+        None => profile.display_name,
+    })
+}
+
+// Fills in the author's display label for a query response
+pub(crate) fn label_update(mut update: CrisisUpdate) -> CrisisUpdate {
+    update.author_name = update.author.as_ref().and_then(display_label);
+    update
+}
+
+pub(crate) fn label_updates(updates: Vec<CrisisUpdate>) -> Vec<CrisisUpdate> {
+    updates.into_iter().map(label_update).collect()
+}
+
+fn check_length(field: &str, value: &str, max_chars: usize) -> Result<(), Error> {
+    if value.chars().count() > max_chars {
+        return Err(Error::validation(format!(
+            "{} must be at most {} characters",
+            field, max_chars
+        )));
+    }
+    Ok(())
+}
+
+// Normalizes every field and treats blank optional fields as unset
+fn validate_profile_payload(payload: ProfilePayload) -> Result<ProfilePayload, Error> {
+    let optional = |value: Option<String>| value.map(|v| normalize_line(&v)).filter(|v| !v.is_empty());
+    let payload = ProfilePayload {
+        display_name: normalize_line(&payload.display_name),
+        organization: optional(payload.organization),
+        contact_channel: optional(payload.contact_channel),
+        preferred_language: optional(payload.preferred_language),
+    };
+    if payload.display_name.is_empty() {
+        return Err(Error::validation("display name must not be empty"));
+    }
+    check_length("display name", &payload.display_name, MAX_DISPLAY_NAME_CHARS)?;
+    if let Some(organization) = &payload.organization {
+        check_length("organization", organization, MAX_ORGANIZATION_CHARS)?;
+    }
+    if let Some(contact_channel) = &payload.contact_channel {
+        check_length("contact channel", contact_channel, MAX_CONTACT_CHANNEL_CHARS)?;
+    }
+    if let Some(language) = &payload.preferred_language {
+        check_length("preferred language", language, MAX_LANGUAGE_TAG_CHARS)?;
+        if !language.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(Error::validation("preferred language must be a BCP 47 language tag"));
+        }
+    }
+    Ok(payload)
+}
+
+// 2.34.1 set_my_profile Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn set_my_profile(payload: ProfilePayload) -> Result<Profile, Error> {
+    crate::call_stats::count_call("set_my_profile");
+    let principal = caller();
+    if principal == Principal::anonymous() {
+        return Err(Error::unauthorized("anonymous callers cannot have a profile"));
+    }
+    let payload = validate_profile_payload(payload)?;
+    let mut profile = Profile {
+        principal,
+        display_name: payload.display_name,
+        organization: payload.organization,
+        contact_channel: payload.contact_channel,
+        preferred_language: payload.preferred_language,
+        updated_at: time(),
+        badges: Vec::new(),
+    };
+    PROFILE_STORAGE.with(|s| s.borrow_mut().insert(principal_key(&principal), profile.clone()));
+    profile.badges = badges_of(&principal);
+    Ok(profile)
+}
+
+// 2.34.2 get_profile Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_profile(principal: Principal) -> Result<Profile, Error> {
+    let Some(mut profile) = find_profile(&principal) else {
+        return Err(Error::not_found(format!("no profile for principal {}", principal)));
+    };
+    let viewer = caller();
+    if viewer != principal && !has_role(&viewer, Role::Coordinator) && !has_role(&viewer, Role::Admin) {
+        profile.contact_channel = None;
+    }
+    profile.badges = badges_of(&principal);
+    Ok(profile)
+}
+
+// 2.34.3 delete_my_profile Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn delete_my_profile() -> Result<Profile, Error> {
+    crate::call_stats::count_call("delete_my_profile");
+    let principal = caller();
+    match PROFILE_STORAGE.with(|s| s.borrow_mut().remove(&principal_key(&principal))) {
+        Some(profile) => Ok(profile),
+        None => Err(Error::not_found(format!("no profile for principal {}", principal))),
+    }
+}
@@ -0,0 +1,121 @@
+// Limits on how much a single call may read or attach, kept in settings so
+// admins can tighten them under load or loosen them for a large export. Paged
+// queries clamp their `limit` to the page size, search endpoints return at most
+// the configured number of results with `total_count` still counting every
+// match, exports with more matching rows than allowed are rejected, and links
+// per update are capped. Replies must still fit the message size limit.
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::maintenance::admit_update;
+use crate::response::{fit, QueryResult};
+use crate::settings::{get_settings, update_settings};
+use crate::Error;
+use candid::CandidType;
+
+const DEFAULT_MAX_PAGE_SIZE: u64 = 200;
+const DEFAULT_MAX_EXPORT_ROWS: u64 = 10_000;
+const DEFAULT_MAX_SEARCH_RESULTS: u64 = 100;
+const DEFAULT_MAX_ATTACHMENTS_PER_UPDATE: u64 = 10;
+
+const MAX_PAGE_SIZE_LIMIT: u64 = 1_000;
+const MAX_EXPORT_ROWS_LIMIT: u64 = 100_000;
+const MAX_SEARCH_RESULTS_LIMIT: u64 = 1_000;
+const MAX_ATTACHMENTS_LIMIT: u64 = 100;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct QueryLimits {
+    // Each field left as None applies the built-in default
+    pub(crate) max_page_size: Option<u64>,
+    pub(crate) max_export_rows: Option<u64>,
+    pub(crate) max_search_results: Option<u64>,
+    // Links attached to a single crisis update
+    pub(crate) max_attachments_per_update: Option<u64>,
+}
+
+// The limits in force, with defaults filled in
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct EffectiveQueryLimits {
+    pub(crate) max_page_size: u64,
+    pub(crate) max_export_rows: u64,
+    pub(crate) max_search_results: u64,
+    pub(crate) max_attachments_per_update: u64,
+}
+
+pub(crate) fn query_limits() -> EffectiveQueryLimits {
+    let limits = get_settings().query_limits.unwrap_or_default();
+    EffectiveQueryLimits {
+        max_page_size: limits.max_page_size.unwrap_or(DEFAULT_MAX_PAGE_SIZE),
+        max_export_rows: limits.max_export_rows.unwrap_or(DEFAULT_MAX_EXPORT_ROWS),
+        max_search_results: limits.max_search_results.unwrap_or(DEFAULT_MAX_SEARCH_RESULTS),
+        max_attachments_per_update: limits
+            .max_attachments_per_update
+            .unwrap_or(DEFAULT_MAX_ATTACHMENTS_PER_UPDATE),
+    }
+}
+
+// A requested page size, at least one and at most the configured maximum
+pub(crate) fn page_size(requested: u64) -> u64 {
+    requested.clamp(1, query_limits().max_page_size)
+}
+
+// Keeps the first results up to the search limit; `total_count` counts them all
+pub(crate) fn cap_search_results<T: CandidType>(mut items: Vec<T>) -> Result<QueryResult<T>, Error> {
+    let total_count = items.len() as u64;
+    items.truncate(query_limits().max_search_results as usize);
+    Ok(QueryResult::limited(fit(items)?.items, total_count))
+}
+
+pub(crate) fn check_export_rows(rows: usize) -> Result<(), Error> {
+    let max = query_limits().max_export_rows;
+    if rows as u64 > max {
+        return Err(Error::validation(format!(
+            "the export matches {} rows, over the limit of {}; use a narrower filter",
+            rows, max
+        ))
+        .with_detail("rows", rows)
+        .with_detail("max_rows", max));
+    }
+    Ok(())
+}
+
+pub(crate) fn check_attachment_count(existing: usize) -> Result<(), Error> {
+    let max = query_limits().max_attachments_per_update;
+    if existing as u64 >= max {
+        return Err(Error::validation(format!("an update can have at most {} links", max)));
+    }
+    Ok(())
+}
+
+fn validate_limit(name: &str, value: Option<u64>, max: u64) -> Result<(), Error> {
+    if value.is_some_and(|v| !(1..=max).contains(&v)) {
+        return Err(Error::validation(format!("{} must be between 1 and {}", name, max)));
+    }
+    Ok(())
+}
+
+// 2.83.1 set_query_limits Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn set_query_limits(limits: QueryLimits) -> Result<EffectiveQueryLimits, Error> {
+    crate::call_stats::count_call("set_query_limits");
+    require_role(Role::Admin)?;
+    validate_limit("max_page_size", limits.max_page_size, MAX_PAGE_SIZE_LIMIT)?;
+    validate_limit("max_export_rows", limits.max_export_rows, MAX_EXPORT_ROWS_LIMIT)?;
+    validate_limit(
+        "max_search_results",
+        limits.max_search_results,
+        MAX_SEARCH_RESULTS_LIMIT,
+    )?;
+    validate_limit(
+        "max_attachments_per_update",
+        limits.max_attachments_per_update,
+        MAX_ATTACHMENTS_LIMIT,
+    )?;
+    update_settings(|s| s.query_limits = Some(limits))?;
+    Ok(query_limits())
+}
+
+// 2.83.2 get_query_limits Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_query_limits() -> EffectiveQueryLimits {
+    query_limits()
+}
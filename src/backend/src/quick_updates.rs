@@ -0,0 +1,256 @@
+// Short, text-message-style notes for field teams posting rapid status updates
+// ("bridge still holding", "second team on site") without composing a full
+// report. A quick update is at most 280 characters with a location and may
+// hang off a crisis update, in which case it appears in that update's
+// timeline. Validation is deliberately light: text is sanitized and
+// normalized, but there is no duplicate detection, uniqueness check, metadata
+// or moderation queue. Quick updates have their own newest-first feed.
+use crate::access::{has_role, principal_key, require_any_role, PrincipalKey, Role};
+use crate::access_mode::admit_read;
+use crate::ids::IdCounter;
+use crate::maintenance::admit_update;
+use crate::response::{fit, PagedResult, QueryResult};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_TEXT_CHARS: usize = 280;
+const MAX_LOCATION_CHARS: usize = 200;
+
+const POSTER_ROLES: [Role; 4] = [Role::Responder, Role::VerifiedOrg, Role::Coordinator, Role::Moderator];
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct QuickUpdate {
+    pub(crate) id: u64,
+    pub(crate) text: String,
+    location: String,
+    // The crisis update this note belongs to, if any
+    parent_id: Option<u64>,
+    pub(crate) author: Principal,
+    pub(crate) created_at: u64,
+}
+
+// Implementing Storable and BoundedStorable traits for QuickUpdate
+impl Storable for QuickUpdate {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for QuickUpdate {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct QuickUpdatePayload {
+    text: String,
+    location: String,
+    parent_id: Option<u64>,
+}
+
+thread_local! {
+    static QUICK_UPDATE_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(121, "quick updates"));
+
+    static QUICK_UPDATES: RefCell<StableBTreeMap<u64, QuickUpdate, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(122)))
+    ));
+
+    // (parent crisis update id, quick update id), for timelines
+    static QUICK_UPDATES_BY_PARENT: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(123)))
+    ));
+
+    // (author, quick update id), so erasure need not scan every note
+    static QUICK_UPDATES_BY_AUTHOR: RefCell<StableBTreeMap<(PrincipalKey, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(124)))
+    ));
+}
+
+fn clean_line(field: &str, text: &str, max_chars: usize) -> Result<String, Error> {
+    let text = crate::normalize::normalize_line(&crate::sanitize::sanitize_text(field, text, false)?);
+    if text.is_empty() {
+        return Err(Error::validation(format!("{} must not be empty", field)));
+    }
+    if text.chars().count() > max_chars {
+        return Err(
+            Error::validation(format!("{} must be at most {} characters", field, max_chars))
+                .with_detail("max_chars", max_chars),
+        );
+    }
+    Ok(text)
+}
+
+// Notes on a local parent the caller cannot see are hidden with it
+fn visible_to_caller(quick_update: &QuickUpdate) -> bool {
+    quick_update.parent_id.is_none_or(|id| {
+        crate::_get_crisis_update(&id).is_none_or(|parent| crate::moderation::visible_to_caller(&parent))
+    })
+}
+
+fn remove(quick_update: &QuickUpdate) {
+    QUICK_UPDATES.with(|s| s.borrow_mut().remove(&quick_update.id));
+    if let Some(parent_id) = quick_update.parent_id {
+        QUICK_UPDATES_BY_PARENT.with(|s| s.borrow_mut().remove(&(parent_id, quick_update.id)));
+    }
+    QUICK_UPDATES_BY_AUTHOR.with(|s| {
+        s.borrow_mut()
+            .remove(&(principal_key(&quick_update.author), quick_update.id))
+    });
+}
+
+// The notes posted on a crisis update, oldest first
+pub(crate) fn quick_updates_of(crisis_update_id: u64) -> Vec<QuickUpdate> {
+    let ids: Vec<u64> = QUICK_UPDATES_BY_PARENT.with(|s| {
+        s.borrow()
+            .range((crisis_update_id, 0)..=(crisis_update_id, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    QUICK_UPDATES.with(|s| {
+        let s = s.borrow();
+        ids.into_iter().filter_map(|id| s.get(&id)).collect()
+    })
+}
+
+pub(crate) fn remove_orphaned_quick_updates(is_live: &dyn Fn(u64) -> bool) -> u64 {
+    let orphaned: Vec<u64> = QUICK_UPDATES_BY_PARENT.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|((parent_id, _), _)| !is_live(*parent_id))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    orphaned
+        .into_iter()
+        .filter_map(|id| QUICK_UPDATES.with(|s| s.borrow().get(&id)))
+        .map(|quick_update| remove(&quick_update))
+        .count() as u64
+}
+
+// Drops every note the principal posted; returns how many were removed
+pub(crate) fn remove_quick_updates_of(author: &Principal) -> u64 {
+    let key = principal_key(author);
+    let ids: Vec<u64> = QUICK_UPDATES_BY_AUTHOR.with(|s| {
+        s.borrow()
+            .range((key, 0)..=(key, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    ids.into_iter()
+        .filter_map(|id| QUICK_UPDATES.with(|s| s.borrow().get(&id)))
+        .map(|quick_update| remove(&quick_update))
+        .count() as u64
+}
+
+// 2.87.1 post_quick_update Function:
+#[ic_cdk::update(guard = "admit_update")]
+async fn post_quick_update(payload: QuickUpdatePayload) -> Result<QuickUpdate, Error> {
+    crate::call_stats::count_call("post_quick_update");
+    let author = require_any_role(&POSTER_ROLES)?;
+    let text = clean_line("text", &payload.text, MAX_TEXT_CHARS)?;
+    let location = clean_line("location", &payload.location, MAX_LOCATION_CHARS)?;
+    if let Some(parent_id) = payload.parent_id {
+        let visible = crate::_find_crisis_update(parent_id)
+            .await?
+            .is_some_and(|parent| crate::moderation::visible_to(&parent, &author));
+        if !visible {
+            return Err(
+                Error::not_found(format!("a crisis update with id={} not found", parent_id))
+                    .with_detail("parent_id", parent_id),
+            );
+        }
+    }
+    let quick_update = QuickUpdate {
+        id: crate::ids::next_id(&QUICK_UPDATE_ID_COUNTER)?,
+        text,
+        location,
+        parent_id: payload.parent_id,
+        author,
+        created_at: time(),
+    };
+    crate::ensure_storable(&quick_update, "quick update")?;
+    QUICK_UPDATES.with(|s| s.borrow_mut().insert(quick_update.id, quick_update.clone()));
+    if let Some(parent_id) = quick_update.parent_id {
+        QUICK_UPDATES_BY_PARENT.with(|s| s.borrow_mut().insert((parent_id, quick_update.id), ()));
+    }
+    QUICK_UPDATES_BY_AUTHOR.with(|s| s.borrow_mut().insert((principal_key(&author), quick_update.id), ()));
+    Ok(quick_update)
+}
+
+// 2.87.2 delete_quick_update Function:
+// The author or a moderator can delete a note.
+#[ic_cdk::update(guard = "admit_update")]
+fn delete_quick_update(id: u64) -> Result<QuickUpdate, Error> {
+    crate::call_stats::count_call("delete_quick_update");
+    let caller = caller();
+    let quick_update = QUICK_UPDATES
+        .with(|s| s.borrow().get(&id))
+        .ok_or_else(|| Error::not_found(format!("a quick update with id={} not found", id)).with_detail("id", id))?;
+    if quick_update.author != caller && !has_role(&caller, Role::Moderator) {
+        return Err(Error::unauthorized(
+            "only the author or a moderator can delete a quick update",
+        ));
+    }
+    remove(&quick_update);
+    Ok(quick_update)
+}
+
+// 2.87.3 list_quick_updates Function:
+// Newest first; pass the returned cursor as `before` for older notes.
+#[ic_cdk::query(guard = "admit_read")]
+fn list_quick_updates(
+    before: Option<u64>,
+    location: Option<String>,
+    limit: u64,
+) -> Result<PagedResult<QuickUpdate>, Error> {
+    let limit = crate::query_limits::page_size(limit) as usize;
+    let location = location.map(|l| l.trim().to_lowercase()).filter(|l| !l.is_empty());
+    let end = before.unwrap_or(u64::MAX);
+    let mut matching: Vec<QuickUpdate> = QUICK_UPDATES.with(|s| {
+        s.borrow()
+            .range(..end)
+            .map(|(_, quick_update)| quick_update)
+            .filter(|q| {
+                location
+                    .as_ref()
+                    .is_none_or(|l| q.location.to_lowercase().contains(l.as_str()))
+            })
+            .filter(visible_to_caller)
+            .collect()
+    });
+    let total_count = matching.len() as u64;
+    matching.reverse();
+    matching.truncate(limit);
+    let (items, truncated) = crate::response::truncate_to_fit(matching);
+    let next_cursor = if truncated || total_count > items.len() as u64 {
+        items.last().map(|q| q.id)
+    } else {
+        None
+    };
+    Ok(PagedResult {
+        items,
+        total_count,
+        next_cursor,
+    })
+}
+
+// 2.87.4 get_quick_updates_for Function:
+// Oldest first, as they appear in the timeline.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_quick_updates_for(crisis_update_id: u64) -> Result<QueryResult<QuickUpdate>, Error> {
+    fit(quick_updates_of(crisis_update_id)
+        .into_iter()
+        .filter(visible_to_caller)
+        .collect())
+}
@@ -0,0 +1,304 @@
+// Real-time delivery of crisis update changes to connected dashboards.
+//
+// Each client has a filter and is fed from the change feed. Clients connected
+// through an IC WebSocket gateway get every matching change pushed as a
+// Candid-encoded RealtimeMessage; they set their filter by sending one over the
+// socket. Clients without a socket, or whose push fails, subscribe with
+// `subscribe_realtime`, drain a bounded heap queue with the cheap
+// `poll_realtime_messages` query and acknowledge what they processed.
+use crate::access_mode::{access_mode, admit_read, may_read};
+use crate::changes::ChangeKind;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::logs::{log, LogLevel};
+use crate::maintenance::admit_update;
+use crate::{CrisisPriority, CrisisUpdate, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_websocket_cdk::{
+    CanisterWsCloseArguments, CanisterWsCloseResult, CanisterWsGetMessagesArguments, CanisterWsGetMessagesResult,
+    CanisterWsMessageArguments, CanisterWsMessageResult, CanisterWsOpenArguments, CanisterWsOpenResult,
+    OnCloseCallbackArgs, OnMessageCallbackArgs, OnOpenCallbackArgs, WsHandlers, WsInitParams,
+};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+
+const MAX_QUEUED_MESSAGES: usize = 200;
+const MAX_REALTIME_CLIENTS: usize = 1000;
+const MAX_FILTER_LOCATIONS: usize = 20;
+const CLIENT_IDLE_TIMEOUT_SECS: u64 = 10 * 60;
+pub(crate) const CLIENT_EXPIRY_INTERVAL_SECS: u64 = 60;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct RealtimeFilter {
+    // Empty means every location
+    locations: Vec<String>,
+    min_priority: Option<CrisisPriority>,
+}
+
+impl RealtimeFilter {
+    fn matches(&self, update: Option<&CrisisUpdate>) -> bool {
+        // Deletions and updates held on shards carry no body and always pass
+        let Some(update) = update else {
+            return true;
+        };
+        (self.locations.is_empty() || self.locations.iter().any(|l| crate::areas::covers(update, l)))
+            && self.min_priority.is_none_or(|p| update.priority as u8 >= p as u8)
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct RealtimeMessage {
+    seq: u64,
+    crisis_update_id: u64,
+    kind: ChangeKind,
+    update: Option<CrisisUpdate>,
+}
+
+struct ClientState {
+    filter: RealtimeFilter,
+    queue: VecDeque<RealtimeMessage>,
+    last_seen: u64,
+    // Has an open WebSocket; messages are pushed rather than queued
+    connected: bool,
+}
+
+impl ClientState {
+    fn new() -> Self {
+        ClientState {
+            filter: RealtimeFilter::default(),
+            queue: VecDeque::new(),
+            last_seen: 0,
+            connected: false,
+        }
+    }
+}
+
+thread_local! {
+    static REALTIME_CLIENTS: RefCell<BTreeMap<Principal, ClientState>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+// Pushes to a connected client, falling back to its queue when the push fails
+fn deliver(principal: &Principal, client: &mut ClientState, message: RealtimeMessage) {
+    if client.connected {
+        match ic_websocket_cdk::send(*principal, Encode!(&message).unwrap()) {
+            Ok(()) => return,
+            Err(err) => {
+                client.connected = false;
+                log(
+                    LogLevel::Warn,
+                    Some("realtime_push"),
+                    &format!("websocket push to {} failed, queueing instead: {}", principal, err),
+                );
+            }
+        }
+    }
+    if client.queue.len() >= MAX_QUEUED_MESSAGES {
+        client.queue.pop_front();
+    }
+    client.queue.push_back(message);
+}
+
+// Pushes a change to every client whose filter matches it
+pub(crate) fn publish(seq: u64, crisis_update_id: u64, kind: ChangeKind) {
+    let update = match kind {
+        ChangeKind::Deleted => None,
+        ChangeKind::Created | ChangeKind::Updated => crate::_get_crisis_update(&crisis_update_id),
+    };
+    REALTIME_CLIENTS.with(|clients| {
+        for (principal, client) in clients.borrow_mut().iter_mut() {
+            // The access mode may have changed since the client subscribed
+            let visible = may_read(principal)
+                && update.as_ref().is_none_or(|u| crate::moderation::visible_to(u, principal));
+            if visible && client.filter.matches(update.as_ref()) {
+                let message = RealtimeMessage {
+                    seq,
+                    crisis_update_id,
+                    kind,
+                    update: update.clone().map(|u| crate::redaction::redact_update(u, principal)),
+                };
+                deliver(principal, client, message);
+            }
+        }
+    });
+}
+
+// Forgets clients that have not subscribed or acknowledged within the idle timeout;
+// the gateway's keep-alive covers connected ones
+pub(crate) fn expire_idle_clients(now: u64) {
+    let cutoff = now.saturating_sub(CLIENT_IDLE_TIMEOUT_SECS * NANOS_PER_SECOND);
+    REALTIME_CLIENTS.with(|clients| {
+        clients
+            .borrow_mut()
+            .retain(|_, client| client.connected || client.last_seen >= cutoff)
+    });
+}
+
+fn validate_filter(filter: &RealtimeFilter) -> Result<(), Error> {
+    if filter.locations.len() > MAX_FILTER_LOCATIONS {
+        return Err(Error::validation(format!(
+            "a filter can list at most {} locations",
+            MAX_FILTER_LOCATIONS
+        )));
+    }
+    Ok(())
+}
+
+// Sets a client's filter, registering the client if it is new
+fn set_filter(client: Principal, filter: RealtimeFilter) -> Result<(), Error> {
+    REALTIME_CLIENTS.with(|clients| {
+        let mut clients = clients.borrow_mut();
+        if !clients.contains_key(&client) && clients.len() >= MAX_REALTIME_CLIENTS {
+            return Err(Error::conflict("the real-time client limit has been reached"));
+        }
+        let state = clients.entry(client).or_insert_with(ClientState::new);
+        state.filter = filter;
+        state.last_seen = time();
+        Ok(())
+    })
+}
+
+// A new socket starts with the default filter, so it receives every visible change
+fn on_open(args: OnOpenCallbackArgs) {
+    let client = args.client_principal;
+    if !may_read(&client) {
+        let _ = ic_websocket_cdk::close(client);
+        return;
+    }
+    let opened = REALTIME_CLIENTS.with(|clients| {
+        let mut clients = clients.borrow_mut();
+        if !clients.contains_key(&client) && clients.len() >= MAX_REALTIME_CLIENTS {
+            return false;
+        }
+        let state = clients.entry(client).or_insert_with(ClientState::new);
+        state.connected = true;
+        state.last_seen = time();
+        true
+    });
+    if !opened {
+        let _ = ic_websocket_cdk::close(client);
+    }
+}
+
+// The only message clients send is a new RealtimeFilter
+fn on_message(args: OnMessageCallbackArgs) {
+    let client = args.client_principal;
+    let result = Decode!(&args.message, RealtimeFilter)
+        .map_err(|err| Error::validation(format!("cannot decode the real-time filter: {}", err)))
+        .and_then(|filter| {
+            validate_filter(&filter)?;
+            set_filter(client, filter)
+        });
+    if let Err(err) = result {
+        log(
+            LogLevel::Warn,
+            Some("ws_message"),
+            &format!("ignored a websocket message from {}: {}", client, err.msg),
+        );
+    }
+}
+
+// Messages queued from here on wait for the client to poll or reconnect
+fn on_close(args: OnCloseCallbackArgs) {
+    REALTIME_CLIENTS.with(|clients| {
+        if let Some(state) = clients.borrow_mut().get_mut(&args.client_principal) {
+            state.connected = false;
+            state.last_seen = time();
+        }
+    });
+}
+
+// Called on install and upgrade; sockets do not survive an upgrade, so clients reconnect
+pub(crate) fn init_websocket() {
+    ic_websocket_cdk::init(WsInitParams::new(WsHandlers {
+        on_open: Some(on_open),
+        on_message: Some(on_message),
+        on_close: Some(on_close),
+    }));
+}
+
+// 2.32.1 subscribe_realtime Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn subscribe_realtime(filter: RealtimeFilter) -> Result<(), Error> {
+    crate::call_stats::count_call("subscribe_realtime");
+    let client = caller();
+    if client == Principal::anonymous() {
+        return Err(Error::unauthorized(
+            "anonymous callers cannot subscribe to real-time updates",
+        ));
+    }
+    if !may_read(&client) {
+        return Err(Error::unauthorized(format!(
+            "the caller cannot read updates in {:?} mode",
+            access_mode()
+        )));
+    }
+    validate_filter(&filter)?;
+    set_filter(client, filter)
+}
+
+// 2.32.2 unsubscribe_realtime Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn unsubscribe_realtime() {
+    crate::call_stats::count_call("unsubscribe_realtime");
+    REALTIME_CLIENTS.with(|clients| clients.borrow_mut().remove(&caller()));
+}
+
+// 2.32.3 poll_realtime_messages Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn poll_realtime_messages(after_seq: u64) -> Result<Vec<RealtimeMessage>, Error> {
+    REALTIME_CLIENTS.with(|clients| match clients.borrow().get(&caller()) {
+        Some(client) => Ok(client.queue.iter().filter(|m| m.seq > after_seq).cloned().collect()),
+        None => Err(Error::not_found("caller is not subscribed to real-time updates")),
+    })
+}
+
+// 2.32.4 ack_realtime_messages Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn ack_realtime_messages(up_to_seq: u64) -> Result<(), Error> {
+    crate::call_stats::count_call("ack_realtime_messages");
+    REALTIME_CLIENTS.with(|clients| match clients.borrow_mut().get_mut(&caller()) {
+        Some(client) => {
+            client.queue.retain(|m| m.seq > up_to_seq);
+            client.last_seen = time();
+            Ok(())
+        }
+        None => Err(Error::not_found("caller is not subscribed to real-time updates")),
+    })
+}
+
+// 2.32.5 ws_open Function:
+// Called by the client through its WebSocket gateway; see ic-websocket-cdk.
+#[ic_cdk::update(guard = "admit_update")]
+fn ws_open(args: CanisterWsOpenArguments) -> CanisterWsOpenResult {
+    crate::call_stats::count_call("ws_open");
+    if caller() == Principal::anonymous() {
+        return Err("anonymous callers cannot subscribe to real-time updates".to_string());
+    }
+    if !may_read(&caller()) {
+        return Err(format!("the caller cannot read updates in {:?} mode", access_mode()));
+    }
+    ic_websocket_cdk::ws_open(args)
+}
+
+// 2.32.6 ws_close Function:
+// Called by the gateway when a client disconnects.
+#[ic_cdk::update]
+fn ws_close(args: CanisterWsCloseArguments) -> CanisterWsCloseResult {
+    crate::call_stats::count_call("ws_close");
+    ic_websocket_cdk::ws_close(args)
+}
+
+// 2.32.7 ws_message Function:
+// Carries a client's RealtimeFilter; the second argument only names the message type.
+#[ic_cdk::update(guard = "admit_update")]
+fn ws_message(args: CanisterWsMessageArguments, msg_type: Option<RealtimeFilter>) -> CanisterWsMessageResult {
+    crate::call_stats::count_call("ws_message");
+    ic_websocket_cdk::ws_message(args, msg_type)
+}
+
+// 2.32.8 ws_get_messages Function:
+// Polled by gateways; each gets only the certified messages of its own clients.
+#[ic_cdk::query]
+fn ws_get_messages(args: CanisterWsGetMessagesArguments) -> CanisterWsGetMessagesResult {
+    ic_websocket_cdk::ws_get_messages(args)
+}
@@ -0,0 +1,51 @@
+// Read-time redaction of crisis updates, applied to every reply that carries
+// them: lists, searches, the change feed, realtime pushes, exports and the
+// calendar feed. Stored updates are never changed. Contact details follow the
+// contact rules; exact coordinates are rounded to about a kilometre unless the
+// viewer is the author or a responder, verified organisation or staff
+// member; and the submitter hashes of anonymous reports, which would link a
+// reporter's reports to each other, are shown to moderators and admins only.
+use crate::access::{has_role, Role};
+use crate::anonymous::AnonymousAuthorship;
+use crate::geo::Coordinates;
+use crate::CrisisUpdate;
+use candid::Principal;
+use ic_cdk::api::caller;
+
+// Two decimal places of a degree, about 1.1 km of latitude
+const FUZZ_STEP_DEGREES: f64 = 0.01;
+
+const EXACT_LOCATION_ROLES: [Role; 5] = [
+    Role::Responder,
+    Role::VerifiedOrg,
+    Role::Coordinator,
+    Role::Moderator,
+    Role::Admin,
+];
+
+fn fuzz(coordinates: Coordinates) -> Coordinates {
+    let round = |degrees: f64| (degrees / FUZZ_STEP_DEGREES).round() * FUZZ_STEP_DEGREES;
+    Coordinates {
+        latitude: round(coordinates.latitude),
+        longitude: round(coordinates.longitude),
+    }
+}
+
+fn sees_exact_location(update: &CrisisUpdate, viewer: &Principal) -> bool {
+    update.author.as_ref() == Some(viewer) || EXACT_LOCATION_ROLES.iter().any(|role| has_role(viewer, *role))
+}
+
+pub(crate) fn redact_update(update: CrisisUpdate, viewer: &Principal) -> CrisisUpdate {
+    let mut update = crate::contact_info::redact_update(update, viewer);
+    if !sees_exact_location(&update, viewer) {
+        update.coordinates = update.coordinates.map(fuzz);
+    }
+    if update.anonymous.is_some() && !has_role(viewer, Role::Moderator) && !has_role(viewer, Role::Admin) {
+        update.anonymous = Some(AnonymousAuthorship::masked());
+    }
+    update
+}
+
+pub(crate) fn redact_update_for_caller(update: CrisisUpdate) -> CrisisUpdate {
+    redact_update(update, &caller())
+}
@@ -0,0 +1,98 @@
+// Structured administrative regions on crisis updates (country, admin area,
+// city), so coordinators can drill down from a country to a city without
+// string-matching free-text locations.
+use crate::access_mode::admit_read;
+use crate::normalize::normalize_line;
+use crate::response::QueryResult;
+use crate::{CrisisUpdate, Error, CRISIS_STORAGE};
+
+const MAX_REGION_NAME_CHARS: usize = 100;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Debug)]
+pub(crate) struct Region {
+    // ISO 3166-1 alpha-2 code, stored upper case
+    country: String,
+    // State, province or similar first-level division
+    admin_area: Option<String>,
+    city: Option<String>,
+}
+
+impl Region {
+    pub(crate) fn country(&self) -> &str {
+        &self.country
+    }
+
+    // True when the country code, admin area or city is the given name, ignoring case
+    pub(crate) fn has_name(&self, name: &str) -> bool {
+        std::iter::once(&self.country)
+            .chain(self.admin_area.iter())
+            .chain(self.city.iter())
+            .any(|level| level.eq_ignore_ascii_case(name.trim()))
+    }
+
+    // Most specific level first, e.g. "Mombasa, Coast, KE"
+    pub(crate) fn label(&self) -> String {
+        let mut parts: Vec<&str> = self.city.iter().chain(self.admin_area.iter()).map(String::as_str).collect();
+        parts.push(&self.country);
+        parts.join(", ")
+    }
+}
+
+fn normalize_name(field: &str, name: Option<String>) -> Result<Option<String>, Error> {
+    let name = name.map(|n| normalize_line(&n)).filter(|n| !n.is_empty());
+    if name.as_ref().is_some_and(|n| n.chars().count() > MAX_REGION_NAME_CHARS) {
+        return Err(Error::validation(format!(
+            "{} must be at most {} characters",
+            field, MAX_REGION_NAME_CHARS
+        )));
+    }
+    Ok(name)
+}
+
+fn normalize_country(country: &str) -> Result<String, Error> {
+    let country = country.trim().to_ascii_uppercase();
+    if country.len() != 2 || !country.chars().all(|c| c.is_ascii_uppercase()) {
+        return Err(Error::validation("country must be an ISO 3166-1 alpha-2 code").with_detail("country", country));
+    }
+    Ok(country)
+}
+
+pub(crate) fn validate_region(region: &mut Option<Region>) -> Result<(), Error> {
+    if let Some(r) = region {
+        r.country = normalize_country(&r.country)?;
+        r.admin_area = normalize_name("admin area", r.admin_area.take())?;
+        r.city = normalize_name("city", r.city.take())?;
+    }
+    Ok(())
+}
+
+fn same_name(stored: &Option<String>, wanted: &Option<String>) -> bool {
+    wanted
+        .as_ref()
+        .is_none_or(|w| stored.as_ref().is_some_and(|s| s.to_lowercase() == w.to_lowercase()))
+}
+
+// 2.44.1 get_updates_in_region Function:
+// Narrower levels are optional; a city can be given without its admin area.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_updates_in_region(
+    country: String,
+    admin_area: Option<String>,
+    city: Option<String>,
+) -> Result<QueryResult<CrisisUpdate>, Error> {
+    let country = normalize_country(&country)?;
+    let admin_area = normalize_name("admin area", admin_area)?;
+    let city = normalize_name("city", city)?;
+    crate::fit_updates(CRISIS_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, update)| update)
+            .filter(|update| {
+                update.region.as_ref().is_some_and(|r| {
+                    r.country == country && same_name(&r.admin_area, &admin_area) && same_name(&r.city, &city)
+                })
+            })
+            .collect()
+    }))
+}
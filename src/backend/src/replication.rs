@@ -0,0 +1,281 @@
+// Continuous replication of crisis updates to a follower canister, to protect
+// the data if this canister is lost. The change feed already records every
+// mutation in order, so it doubles as the replication queue: the heartbeat
+// pushes feed records after the follower's cursor in batches. A full resync
+// streams every stored update after a reset, and starts on its own when the
+// feed has been pruned past the cursor.
+//
+// The follower canister is expected to expose:
+//   replica_reset : () -> ();
+//   replica_apply_changes : (vec ReplicationEntry) -> ();
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::changes::{changes_after, current_seq, pruned_through_seq, ChangeKind};
+use crate::jobs::NANOS_PER_SECOND;
+use crate::logs::{log, LogLevel};
+use crate::maintenance::admit_update;
+use crate::settings::{get_settings, update_settings};
+use crate::{CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{Cell, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const REPLICATION_BATCH_SIZE: usize = 50;
+pub(crate) const REPLICATION_INTERVAL_SECS: u64 = 5;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ReplicationEntry {
+    // Change feed sequence number; 0 for entries sent during a full resync
+    seq: u64,
+    crisis_update_id: u64,
+    // None when the update has been deleted
+    update: Option<CrisisUpdate>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct ReplicationState {
+    // Every change up to this sequence number has reached the follower
+    pushed_through_seq: u64,
+    // Set while a full resync runs: the feed position it started at and the last id sent
+    resync: Option<(u64, Option<u64>)>,
+    last_push_at: Option<u64>,
+    last_error: Option<String>,
+}
+
+// Implementing Storable trait for ReplicationState
+impl Storable for ReplicationState {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ReplicationStatus {
+    replica_canister: Option<Principal>,
+    pushed_through_seq: u64,
+    current_seq: u64,
+    // Feed records not yet on the follower
+    lag_changes: u64,
+    // Age of the oldest change not yet on the follower
+    lag_secs: u64,
+    resync_in_progress: bool,
+    last_push_at: Option<u64>,
+    last_error: Option<String>,
+}
+
+thread_local! {
+    static REPLICATION_STATE: RefCell<Cell<ReplicationState, Memory>> = RefCell::new(
+        Cell::init(CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(61))), ReplicationState::default())
+            .expect("Cannot create the replication state cell")
+    );
+
+    static REPLICATION_IN_PROGRESS: RefCell<bool> = const { RefCell::new(false) };
+}
+
+fn state() -> ReplicationState {
+    REPLICATION_STATE.with(|s| s.borrow().get().clone())
+}
+
+fn save_state(state: ReplicationState) -> Result<(), Error> {
+    REPLICATION_STATE.with(|s| {
+        s.borrow_mut()
+            .set(state)
+            .map(|_| ())
+            .map_err(|err| Error::internal(format!("cannot persist the replication state: {:?}", err)))
+    })
+}
+
+fn start_resync() -> Result<(), Error> {
+    let mut state = state();
+    state.resync = Some((current_seq(), None));
+    save_state(state)
+}
+
+// Changes recorded but not yet pushed; 0 without a replica
+pub(crate) fn replication_backlog() -> u64 {
+    if get_settings().replica_canister.is_none() {
+        return 0;
+    }
+    current_seq().saturating_sub(state().pushed_through_seq)
+}
+
+// True when there is anything to push; checked by the heartbeat before spawning
+pub(crate) fn replication_pending() -> bool {
+    if get_settings().replica_canister.is_none() || REPLICATION_IN_PROGRESS.with(|r| *r.borrow()) {
+        return false;
+    }
+    let state = state();
+    state.resync.is_some() || state.pushed_through_seq < current_seq()
+}
+
+struct ReplicationRunGuard;
+
+impl ReplicationRunGuard {
+    fn acquire() -> Result<Self, Error> {
+        if REPLICATION_IN_PROGRESS.with(|r| r.replace(true)) {
+            return Err(Error::conflict("a replication push is already in progress"));
+        }
+        Ok(ReplicationRunGuard)
+    }
+}
+
+impl Drop for ReplicationRunGuard {
+    fn drop(&mut self) {
+        REPLICATION_IN_PROGRESS.with(|r| *r.borrow_mut() = false);
+    }
+}
+
+async fn call_replica(replica: Principal, method: &str, entries: Option<Vec<ReplicationEntry>>) -> Result<(), Error> {
+    let result: Result<(), _> = match entries {
+        Some(entries) => ic_cdk::call(replica, method, (entries,)).await,
+        None => ic_cdk::call(replica, method, ()).await,
+    };
+    result.map_err(|(code, msg)| {
+        Error::external_call_failed(format!("replica {} rejected {}: {:?} {}", replica, method, code, msg))
+    })
+}
+
+// Sends the next batch of a full resync; returns the new resync cursor
+async fn push_resync_batch(replica: Principal, last_id: Option<u64>) -> Result<Option<Option<u64>>, Error> {
+    if last_id.is_none() {
+        call_replica(replica, "replica_reset", None).await?;
+    }
+    let start = last_id.map_or(0, |id| id.saturating_add(1));
+    let batch: Vec<ReplicationEntry> = CRISIS_STORAGE.with(|s| {
+        s.borrow()
+            .range(start..)
+            .take(REPLICATION_BATCH_SIZE)
+            .map(|(id, update)| ReplicationEntry {
+                seq: 0,
+                crisis_update_id: id,
+                update: Some(update),
+            })
+            .collect()
+    });
+    let Some(next_last) = batch.last().map(|entry| entry.crisis_update_id) else {
+        return Ok(None);
+    };
+    call_replica(replica, "replica_apply_changes", Some(batch)).await?;
+    Ok(Some(Some(next_last)))
+}
+
+// Sends the next feed records after the cursor; returns the new cursor
+async fn push_change_batch(replica: Principal, since: u64) -> Result<u64, Error> {
+    let records = changes_after(since, REPLICATION_BATCH_SIZE);
+    let Some(last_seq) = records.last().map(|(seq, _)| *seq) else {
+        return Ok(since);
+    };
+    let mut batch = Vec::with_capacity(records.len());
+    for (seq, record) in records {
+        let update = match record.kind {
+            ChangeKind::Deleted => None,
+            ChangeKind::Created | ChangeKind::Updated => crate::_find_crisis_update(record.crisis_update_id).await?,
+        };
+        batch.push(ReplicationEntry {
+            seq,
+            crisis_update_id: record.crisis_update_id,
+            update,
+        });
+    }
+    call_replica(replica, "replica_apply_changes", Some(batch)).await?;
+    Ok(last_seq)
+}
+
+async fn push_once(replica: Principal) -> Result<(), Error> {
+    let read = state();
+    let mut resync = read.resync;
+    if resync.is_none() && read.pushed_through_seq < pruned_through_seq() {
+        // Deletions the follower never saw have been pruned from the feed
+        resync = Some((current_seq(), None));
+    }
+    let (pushed_through_seq, resync) = match resync {
+        Some((started_at_seq, last_id)) => match push_resync_batch(replica, last_id).await? {
+            Some(cursor) => (read.pushed_through_seq, Some((started_at_seq, cursor))),
+            // Changes made during the resync are replayed from where it started
+            None => (started_at_seq, None),
+        },
+        None => (push_change_batch(replica, read.pushed_through_seq).await?, None),
+    };
+    // A resync started or a follower set during the push supersedes its cursor
+    let mut state = state();
+    if state.resync == read.resync && get_settings().replica_canister == Some(replica) {
+        state.pushed_through_seq = pushed_through_seq;
+        state.resync = resync;
+    }
+    state.last_push_at = Some(time());
+    state.last_error = None;
+    save_state(state)
+}
+
+// Pushes one batch to the follower; run from the heartbeat
+pub(crate) async fn run_replication() {
+    let Some(replica) = get_settings().replica_canister else {
+        return;
+    };
+    let Ok(_guard) = ReplicationRunGuard::acquire() else {
+        return;
+    };
+    if let Err(err) = push_once(replica).await {
+        log(
+            LogLevel::Error,
+            Some("replication"),
+            &format!("replication to {} failed: {:?}", replica, err),
+        );
+        let mut state = state();
+        state.last_error = Some(err.msg);
+        if let Err(err) = save_state(state) {
+            log(LogLevel::Error, Some("replication"), &format!("{:?}", err));
+        }
+    }
+}
+
+// 2.50.1 set_replica_canister Function:
+// A new follower starts with a full resync; None stops replication.
+#[ic_cdk::update(guard = "admit_update")]
+fn set_replica_canister(replica: Option<Principal>) -> Result<(), Error> {
+    crate::call_stats::count_call("set_replica_canister");
+    require_role(Role::Admin)?;
+    update_settings(|s| s.replica_canister = replica)?;
+    if replica.is_some() {
+        start_resync()?;
+    }
+    Ok(())
+}
+
+// 2.50.2 force_full_resync Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn force_full_resync() -> Result<(), Error> {
+    crate::call_stats::count_call("force_full_resync");
+    require_role(Role::Admin)?;
+    if get_settings().replica_canister.is_none() {
+        return Err(Error::conflict("no replica canister is configured"));
+    }
+    start_resync()
+}
+
+// 2.50.3 get_replication_status Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_replication_status() -> Result<ReplicationStatus, Error> {
+    require_role(Role::Admin)?;
+    let state = state();
+    let current_seq = current_seq();
+    let oldest_pending = changes_after(state.pushed_through_seq, 1)
+        .first()
+        .map(|(_, record)| record.timestamp);
+    Ok(ReplicationStatus {
+        replica_canister: get_settings().replica_canister,
+        pushed_through_seq: state.pushed_through_seq,
+        current_seq,
+        lag_changes: current_seq.saturating_sub(state.pushed_through_seq),
+        lag_secs: oldest_pending.map_or(0, |t| time().saturating_sub(t) / NANOS_PER_SECOND),
+        resync_in_progress: state.resync.is_some(),
+        last_push_at: state.last_push_at,
+        last_error: state.last_error,
+    })
+}
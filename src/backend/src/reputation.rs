@@ -0,0 +1,276 @@
+// Per-author track record. Reports submitted, confirmations from other users and
+// moderator flags or rejections feed a 0-100 score; moderation mode skips the
+// review queue for authors whose score marks them as trusted.
+use crate::access::{principal_key, PrincipalKey};
+use crate::access_mode::admit_read;
+use crate::leaderboard::{award_badge, Badge, CONFIRMED_REPORTS_FOR_BADGE};
+use crate::maintenance::admit_update;
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::caller;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const BASE_SCORE: i64 = 50;
+const POINTS_PER_SUBMISSION: i64 = 1;
+// Submissions alone can raise the score by at most this much
+const MAX_SUBMISSION_POINTS: i64 = 20;
+const POINTS_PER_CONFIRMATION: i64 = 5;
+const POINTS_PER_FLAG: i64 = -10;
+const POINTS_PER_REJECTION: i64 = -15;
+const TRUSTED_MIN_SCORE: u64 = 70;
+const TRUSTED_MIN_SUBMISSIONS: u64 = 5;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default)]
+struct ReporterStats {
+    submitted: u64,
+    // Confirmations received from other users across all of the author's updates
+    confirmed: u64,
+    // Updates by the author confirmed by at least one other user
+    confirmed_reports: u64,
+    flagged: u64,
+    rejected: u64,
+}
+
+// Implementing Storable and BoundedStorable traits for ReporterStats
+impl Storable for ReporterStats {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ReporterStats {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ReporterReputation {
+    principal: Principal,
+    submitted: u64,
+    confirmed: u64,
+    confirmed_reports: u64,
+    flagged: u64,
+    rejected: u64,
+    score: u64,
+    trusted: bool,
+}
+
+thread_local! {
+    static REPORTER_STATS: RefCell<StableBTreeMap<PrincipalKey, ReporterStats, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(52)))
+    ));
+
+    // (crisis update id, confirming principal); one confirmation per user per update
+    static CONFIRMATIONS: RefCell<StableBTreeMap<(u64, PrincipalKey), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(53)))
+    ));
+
+    // Updates already counted against their author as flagged
+    static FLAGGED_UPDATES: RefCell<StableBTreeMap<u64, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(54)))
+    ));
+}
+
+fn stats_of(principal: &Principal) -> ReporterStats {
+    REPORTER_STATS
+        .with(|s| s.borrow().get(&principal_key(principal)))
+        .unwrap_or_default()
+}
+
+fn modify_stats(principal: &Principal, change: impl FnOnce(&mut ReporterStats)) {
+    let mut stats = stats_of(principal);
+    change(&mut stats);
+    REPORTER_STATS.with(|s| s.borrow_mut().insert(principal_key(principal), stats));
+}
+
+fn score(stats: &ReporterStats) -> u64 {
+    let submissions = (stats.submitted as i64 * POINTS_PER_SUBMISSION).min(MAX_SUBMISSION_POINTS);
+    let score = BASE_SCORE
+        .saturating_add(submissions)
+        .saturating_add((stats.confirmed as i64).saturating_mul(POINTS_PER_CONFIRMATION))
+        .saturating_add((stats.flagged as i64).saturating_mul(POINTS_PER_FLAG))
+        .saturating_add((stats.rejected as i64).saturating_mul(POINTS_PER_REJECTION));
+    score.clamp(0, 100) as u64
+}
+
+pub(crate) fn reputation_of(principal: Principal) -> ReporterReputation {
+    let stats = stats_of(&principal);
+    let score = score(&stats);
+    ReporterReputation {
+        principal,
+        submitted: stats.submitted,
+        confirmed: stats.confirmed,
+        confirmed_reports: stats.confirmed_reports,
+        flagged: stats.flagged,
+        rejected: stats.rejected,
+        score,
+        trusted: score >= TRUSTED_MIN_SCORE && stats.submitted >= TRUSTED_MIN_SUBMISSIONS,
+    }
+}
+
+pub(crate) fn is_trusted(principal: &Principal) -> bool {
+    reputation_of(*principal).trusted
+}
+
+pub(crate) fn record_submission(author: &Principal) {
+    modify_stats(author, |stats| stats.submitted += 1);
+    award_badge(author, Badge::FirstReport);
+}
+
+pub(crate) fn record_rejection(author: &Principal) {
+    modify_stats(author, |stats| stats.rejected += 1);
+}
+
+fn has_confirmations(crisis_update_id: u64) -> bool {
+    let lowest = (crisis_update_id, principal_key(&Principal::from_slice(&[])));
+    CONFIRMATIONS.with(|c| {
+        c.borrow()
+            .range(lowest..)
+            .next()
+            .is_some_and(|((id, _), _)| id == crisis_update_id)
+    })
+}
+
+pub(crate) fn confirmation_count(crisis_update_id: u64) -> u64 {
+    let lowest = (crisis_update_id, principal_key(&Principal::from_slice(&[])));
+    CONFIRMATIONS.with(|c| {
+        c.borrow()
+            .range(lowest..)
+            .take_while(|((id, _), _)| *id == crisis_update_id)
+            .count() as u64
+    })
+}
+
+// Ids of the updates the principal confirmed
+pub(crate) fn confirmations_of(principal: &Principal) -> Vec<u64> {
+    let key = principal_key(principal);
+    CONFIRMATIONS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(k, _)| k)
+            .filter(|k| k.1 == key)
+            .map(|(id, _)| id)
+            .collect()
+    })
+}
+
+// Removes the principal's reporter stats and confirmations; returns the confirmations removed
+pub(crate) fn remove_reporter_records(principal: &Principal) -> u64 {
+    let key = principal_key(principal);
+    REPORTER_STATS.with(|s| s.borrow_mut().remove(&key));
+    let confirmations: Vec<(u64, PrincipalKey)> =
+        CONFIRMATIONS.with(|s| s.borrow().iter().map(|(k, _)| k).filter(|k| k.1 == key).collect());
+    CONFIRMATIONS.with(|s| {
+        let mut s = s.borrow_mut();
+        for k in &confirmations {
+            s.remove(k);
+        }
+    });
+    confirmations.len() as u64
+}
+
+// Drops confirmations and flag markers of updates that no longer exist;
+// returns (confirmations, flags) removed. Authors keep the counts they earned.
+pub(crate) fn remove_orphaned_reputation_records(is_live: &dyn Fn(u64) -> bool) -> (u64, u64) {
+    let confirmations: Vec<(u64, PrincipalKey)> = CONFIRMATIONS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|(id, _)| !is_live(*id))
+            .collect()
+    });
+    let flags: Vec<u64> = FLAGGED_UPDATES.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(id, _)| id)
+            .filter(|id| !is_live(*id))
+            .collect()
+    });
+    CONFIRMATIONS.with(|s| {
+        let mut s = s.borrow_mut();
+        for key in &confirmations {
+            s.remove(key);
+        }
+    });
+    FLAGGED_UPDATES.with(|s| {
+        let mut s = s.borrow_mut();
+        for id in &flags {
+            s.remove(id);
+        }
+    });
+    (confirmations.len() as u64, flags.len() as u64)
+}
+
+// Counts a flag against the author once per update; false if it was already flagged
+pub(crate) fn record_flag(crisis_update_id: u64, author: &Principal) -> bool {
+    if FLAGGED_UPDATES
+        .with(|f| f.borrow_mut().insert(crisis_update_id, ()))
+        .is_some()
+    {
+        return false;
+    }
+    modify_stats(author, |stats| stats.flagged += 1);
+    true
+}
+
+// 2.35.1 get_reporter_reputation Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_reporter_reputation(principal: Principal) -> ReporterReputation {
+    reputation_of(principal)
+}
+
+// 2.35.2 confirm_crisis_update Function:
+// Vouches for someone else's report; each user can confirm an update once.
+#[ic_cdk::update(guard = "admit_update")]
+async fn confirm_crisis_update(id: u64) -> Result<ReporterReputation, Error> {
+    crate::call_stats::count_call("confirm_crisis_update");
+    let confirmer = caller();
+    if confirmer == Principal::anonymous() {
+        return Err(Error::unauthorized("anonymous callers cannot confirm crisis updates"));
+    }
+    let Some(update) = crate::_find_crisis_update(id).await? else {
+        return Err(Error::not_found(format!(
+            "couldn't confirm a crisis update with id={}. update not found",
+            id
+        )));
+    };
+    let Some(author) = update.author else {
+        return Err(Error::validation(format!(
+            "the crisis update with id={} has no recorded author",
+            id
+        )));
+    };
+    if author == confirmer {
+        return Err(Error::validation("authors cannot confirm their own crisis updates"));
+    }
+    let first_confirmation = !has_confirmations(id);
+    let key = (id, principal_key(&confirmer));
+    if CONFIRMATIONS.with(|c| c.borrow_mut().insert(key, ())).is_some() {
+        return Err(Error::conflict(format!(
+            "caller has already confirmed the crisis update with id={}",
+            id
+        )));
+    }
+    modify_stats(&author, |stats| {
+        stats.confirmed += 1;
+        if first_confirmation {
+            stats.confirmed_reports += 1;
+        }
+    });
+    if stats_of(&author).confirmed_reports >= CONFIRMED_REPORTS_FOR_BADGE {
+        award_badge(&author, Badge::TenConfirmedReports);
+    }
+    crate::escalation::record_confirmation(&update, ic_cdk::api::time());
+    crate::staleness::record_confirmation(id, ic_cdk::api::time());
+    crate::escalation::evaluate_escalation(update).await;
+    Ok(reputation_of(author))
+}
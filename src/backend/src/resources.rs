@@ -0,0 +1,536 @@
+use crate::access::{has_role, Role};
+use crate::access_mode::admit_read;
+use crate::contact_info::{redact_request_for_caller, validate_contact, ContactInfo};
+use crate::geo::{validate_coordinates, Coordinates};
+use crate::ids::IdCounter;
+use crate::maintenance::admit_update;
+use crate::normalize::{normalize_line, normalize_multiline};
+use crate::response::{fit, QueryResult};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_DESCRIPTION_CHARS: usize = 500;
+const MAX_LOCATION_CHARS: usize = 200;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default, Debug)]
+pub(crate) enum ResourceType {
+    #[default]
+    Food,
+    Water,
+    Medical,
+    Shelter,
+    Clothing,
+    Hygiene,
+    Equipment,
+    Transport,
+    Other,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub(crate) enum Urgency {
+    Low,
+    #[default]
+    Medium,
+    High,
+    Critical,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default, Debug)]
+pub(crate) enum ResourceRequestStatus {
+    #[default]
+    Open,
+    PartiallyFulfilled,
+    Fulfilled,
+    Cancelled,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ResourceRequest {
+    pub(crate) id: u64,
+    pub(crate) crisis_update_id: u64,
+    pub(crate) requester: Principal,
+    pub(crate) resource_type: ResourceType,
+    pub(crate) description: String,
+    pub(crate) quantity: u64,
+    pub(crate) fulfilled_quantity: u64,
+    pub(crate) urgency: Urgency,
+    pub(crate) status: ResourceRequestStatus,
+    pub(crate) location: String,
+    pub(crate) coordinates: Option<Coordinates>,
+    pub(crate) created_at: u64,
+    pub(crate) updated_at: Option<u64>,
+    // How responders can reach the requester; hidden from other viewers
+    pub(crate) contact: Option<ContactInfo>,
+}
+
+// Implementing Storable and BoundedStorable traits for ResourceRequest
+impl Storable for ResourceRequest {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ResourceRequest {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl ResourceRequest {
+    pub(crate) fn remaining_quantity(&self) -> u64 {
+        self.quantity.saturating_sub(self.fulfilled_quantity)
+    }
+
+    // Counts quantity delivered against the request and updates its status
+    pub(crate) fn record_fulfillment(&mut self, quantity: u64, now: u64) {
+        self.fulfilled_quantity += quantity;
+        self.status = if self.remaining_quantity() == 0 {
+            ResourceRequestStatus::Fulfilled
+        } else {
+            ResourceRequestStatus::PartiallyFulfilled
+        };
+        self.updated_at = Some(now);
+    }
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct ResourceRequestPayload {
+    crisis_update_id: u64,
+    resource_type: ResourceType,
+    description: String,
+    quantity: u64,
+    urgency: Urgency,
+    // Defaults to the location of the referenced crisis update when omitted
+    location: Option<String>,
+    coordinates: Option<Coordinates>,
+    contact: Option<ContactInfo>,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub(crate) enum ResourceOfferStatus {
+    #[default]
+    Available,
+    PartiallyCommitted,
+    Committed,
+    Withdrawn,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ResourceOffer {
+    pub(crate) id: u64,
+    pub(crate) donor: Principal,
+    pub(crate) resource_type: ResourceType,
+    pub(crate) description: String,
+    pub(crate) quantity: u64,
+    pub(crate) committed_quantity: u64,
+    pub(crate) status: ResourceOfferStatus,
+    pub(crate) location: String,
+    pub(crate) coordinates: Option<Coordinates>,
+    pub(crate) created_at: u64,
+    pub(crate) updated_at: Option<u64>,
+}
+
+// Implementing Storable and BoundedStorable traits for ResourceOffer
+impl Storable for ResourceOffer {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ResourceOffer {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl ResourceOffer {
+    pub(crate) fn remaining_quantity(&self) -> u64 {
+        self.quantity.saturating_sub(self.committed_quantity)
+    }
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct ResourceOfferPayload {
+    resource_type: ResourceType,
+    description: String,
+    quantity: u64,
+    location: String,
+    coordinates: Option<Coordinates>,
+}
+
+thread_local! {
+    pub(crate) static RESOURCE_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(2, "resource requests"));
+
+    pub(crate) static RESOURCE_REQUEST_STORAGE: RefCell<StableBTreeMap<u64, ResourceRequest, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))
+    ));
+
+    pub(crate) static OFFER_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(4, "resource offers"));
+
+    pub(crate) static RESOURCE_OFFER_STORAGE: RefCell<StableBTreeMap<u64, ResourceOffer, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5)))
+    ));
+}
+
+// Helper method to perform insert for ResourceRequest
+pub(crate) fn do_insert_resource_request(request: &ResourceRequest) {
+    RESOURCE_REQUEST_STORAGE.with(|service| service.borrow_mut().insert(request.id, request.clone()));
+}
+
+// Helper method to perform insert for ResourceOffer
+pub(crate) fn do_insert_resource_offer(offer: &ResourceOffer) {
+    RESOURCE_OFFER_STORAGE.with(|service| service.borrow_mut().insert(offer.id, offer.clone()));
+}
+
+// Resolves the location of a request, falling back to the referenced crisis update
+fn resolve_location(crisis_update_id: u64, location: Option<String>) -> Result<String, Error> {
+    let update = crate::moderation::visible_parent(crisis_update_id)?;
+    Ok(location.unwrap_or(update.location))
+}
+
+fn validate_quantity(quantity: u64) -> Result<(), Error> {
+    if quantity == 0 {
+        return Err(Error::validation("quantity must be greater than zero"));
+    }
+    Ok(())
+}
+
+// Normalizes the description and location in place before checking their length
+fn validate_text(description: &mut String, location: Option<&mut String>) -> Result<(), Error> {
+    *description = normalize_multiline(description);
+    let location = location.map(|location| {
+        *location = normalize_line(location);
+        &*location
+    });
+    if description.chars().count() > MAX_DESCRIPTION_CHARS {
+        return Err(Error::validation(format!(
+            "descriptions must be at most {} characters",
+            MAX_DESCRIPTION_CHARS
+        )));
+    }
+    if location.is_some_and(|location| location.chars().count() > MAX_LOCATION_CHARS) {
+        return Err(Error::validation(format!(
+            "locations must be at most {} characters",
+            MAX_LOCATION_CHARS
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) fn find_resource_request(id: u64) -> Result<ResourceRequest, Error> {
+    match RESOURCE_REQUEST_STORAGE.with(|s| s.borrow().get(&id)) {
+        Some(request) => Ok(request),
+        None => Err(Error::not_found(format!("a resource request with id={} not found", id))),
+    }
+}
+
+pub(crate) fn resource_requests_of(principal: &Principal) -> Vec<ResourceRequest> {
+    RESOURCE_REQUEST_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, request)| request)
+            .filter(|request| request.requester == *principal)
+            .collect()
+    })
+}
+
+// Requests stay for matching and fulfilment records; the anonymous principal,
+// which cannot call updates, takes the requester's place and the contact goes
+pub(crate) fn anonymize_resource_requests_of(principal: &Principal, now: u64) -> u64 {
+    let requests = resource_requests_of(principal);
+    for mut request in requests.iter().cloned() {
+        request.requester = Principal::anonymous();
+        request.contact = None;
+        request.updated_at = Some(now);
+        do_insert_resource_request(&request);
+    }
+    requests.len() as u64
+}
+
+pub(crate) fn resource_offers_of(principal: &Principal) -> Vec<ResourceOffer> {
+    RESOURCE_OFFER_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, offer)| offer)
+            .filter(|offer| offer.donor == *principal)
+            .collect()
+    })
+}
+
+// Like requests, offers keep their commitments and lose the donor
+pub(crate) fn anonymize_resource_offers_of(principal: &Principal, now: u64) -> u64 {
+    let offers = resource_offers_of(principal);
+    for mut offer in offers.iter().cloned() {
+        offer.donor = Principal::anonymous();
+        offer.updated_at = Some(now);
+        do_insert_resource_offer(&offer);
+    }
+    offers.len() as u64
+}
+
+// Loads a request and checks that the caller is the requester or a coordinator
+fn get_own_request(id: u64) -> Result<ResourceRequest, Error> {
+    let request = find_resource_request(id)?;
+    let caller = caller();
+    if request.requester != caller && !has_role(&caller, Role::Coordinator) {
+        return Err(Error::unauthorized(format!(
+            "only the requester or a coordinator can modify the resource request with id={}",
+            id
+        )));
+    }
+    Ok(request)
+}
+
+// 2.8.1 get_resource_request Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_resource_request(id: u64) -> Result<ResourceRequest, Error> {
+    find_resource_request(id).map(redact_request_for_caller)
+}
+
+// 2.8.2 add_resource_request Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn add_resource_request(mut payload: ResourceRequestPayload) -> Result<ResourceRequest, Error> {
+    crate::call_stats::count_call("add_resource_request");
+    validate_quantity(payload.quantity)?;
+    validate_text(&mut payload.description, payload.location.as_mut())?;
+    validate_contact(&mut payload.contact)?;
+    validate_coordinates(&payload.coordinates)?;
+    let location = resolve_location(payload.crisis_update_id, payload.location)?;
+    let id = crate::ids::next_id(&RESOURCE_ID_COUNTER)?;
+    let request = ResourceRequest {
+        id,
+        crisis_update_id: payload.crisis_update_id,
+        requester: caller(),
+        resource_type: payload.resource_type,
+        description: payload.description,
+        quantity: payload.quantity,
+        fulfilled_quantity: 0,
+        urgency: payload.urgency,
+        status: ResourceRequestStatus::Open,
+        location,
+        coordinates: payload.coordinates,
+        created_at: time(),
+        updated_at: None,
+        contact: payload.contact,
+    };
+    crate::ensure_storable(&request, "resource request")?;
+    do_insert_resource_request(&request);
+    Ok(request)
+}
+
+// 2.8.3 update_resource_request Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn update_resource_request(id: u64, mut payload: ResourceRequestPayload) -> Result<ResourceRequest, Error> {
+    crate::call_stats::count_call("update_resource_request");
+    validate_quantity(payload.quantity)?;
+    validate_text(&mut payload.description, payload.location.as_mut())?;
+    validate_contact(&mut payload.contact)?;
+    validate_coordinates(&payload.coordinates)?;
+    let mut request = get_own_request(id)?;
+    if payload.quantity < request.fulfilled_quantity {
+        return Err(Error::conflict(format!(
+            "quantity cannot be lower than the {} units already fulfilled",
+            request.fulfilled_quantity
+        )));
+    }
+    request.location = resolve_location(payload.crisis_update_id, payload.location)?;
+    request.crisis_update_id = payload.crisis_update_id;
+    request.resource_type = payload.resource_type;
+    request.description = payload.description;
+    request.quantity = payload.quantity;
+    request.urgency = payload.urgency;
+    request.coordinates = payload.coordinates;
+    request.contact = payload.contact;
+    request.updated_at = Some(time());
+    crate::ensure_storable(&request, "resource request")?;
+    do_insert_resource_request(&request);
+    Ok(request)
+}
+
+// 2.8.4 set_resource_request_status Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn set_resource_request_status(id: u64, status: ResourceRequestStatus) -> Result<ResourceRequest, Error> {
+    crate::call_stats::count_call("set_resource_request_status");
+    let mut request = get_own_request(id)?;
+    request.status = status;
+    request.updated_at = Some(time());
+    do_insert_resource_request(&request);
+    Ok(redact_request_for_caller(request))
+}
+
+// 2.8.5 delete_resource_request Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn delete_resource_request(id: u64) -> Result<ResourceRequest, Error> {
+    crate::call_stats::count_call("delete_resource_request");
+    get_own_request(id)?;
+    match RESOURCE_REQUEST_STORAGE.with(|service| service.borrow_mut().remove(&id)) {
+        Some(request) => Ok(redact_request_for_caller(request)),
+        None => Err(Error::not_found(format!(
+            "couldn't delete a resource request with id={}. request not found.",
+            id
+        ))),
+    }
+}
+
+// 2.8.6 get_resource_requests_for_update Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_resource_requests_for_update(crisis_update_id: u64) -> Result<QueryResult<ResourceRequest>, Error> {
+    crate::moderation::visible_parent(crisis_update_id)?;
+    fit(RESOURCE_REQUEST_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, request)| request.crisis_update_id == crisis_update_id)
+            .map(|(_, request)| redact_request_for_caller(request))
+            .collect()
+    }))
+}
+
+// 2.8.7 get_resource_requests_by_location Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_resource_requests_by_location(location: String) -> Result<QueryResult<ResourceRequest>, Error> {
+    let location = normalize_line(&location);
+    fit(RESOURCE_REQUEST_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, request)| normalize_line(&request.location) == location)
+            .map(|(_, request)| redact_request_for_caller(request))
+            .collect()
+    }))
+}
+
+// 2.8.8 get_resource_requests_by_type Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_resource_requests_by_type(resource_type: ResourceType) -> Result<QueryResult<ResourceRequest>, Error> {
+    fit(RESOURCE_REQUEST_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, request)| request.resource_type == resource_type)
+            .map(|(_, request)| redact_request_for_caller(request))
+            .collect()
+    }))
+}
+
+// 2.8.9 get_resource_offer Function:
+#[ic_cdk::query(guard = "admit_read")]
+pub(crate) fn get_resource_offer(id: u64) -> Result<ResourceOffer, Error> {
+    match RESOURCE_OFFER_STORAGE.with(|s| s.borrow().get(&id)) {
+        Some(offer) => Ok(offer),
+        None => Err(Error::not_found(format!("a resource offer with id={} not found", id))),
+    }
+}
+
+// 2.8.10 add_resource_offer Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn add_resource_offer(mut payload: ResourceOfferPayload) -> Result<ResourceOffer, Error> {
+    crate::call_stats::count_call("add_resource_offer");
+    validate_quantity(payload.quantity)?;
+    validate_text(&mut payload.description, Some(&mut payload.location))?;
+    validate_coordinates(&payload.coordinates)?;
+    let id = crate::ids::next_id(&OFFER_ID_COUNTER)?;
+    let offer = ResourceOffer {
+        id,
+        donor: caller(),
+        resource_type: payload.resource_type,
+        description: payload.description,
+        quantity: payload.quantity,
+        committed_quantity: 0,
+        status: ResourceOfferStatus::Available,
+        location: payload.location,
+        coordinates: payload.coordinates,
+        created_at: time(),
+        updated_at: None,
+    };
+    crate::ensure_storable(&offer, "resource offer")?;
+    do_insert_resource_offer(&offer);
+    Ok(offer)
+}
+
+// Loads an offer and checks that the caller is the donor who made it
+fn get_own_offer(id: u64) -> Result<ResourceOffer, Error> {
+    let offer = get_resource_offer(id)?;
+    if offer.donor != caller() {
+        return Err(Error::unauthorized(format!(
+            "only the donor can modify the resource offer with id={}",
+            id
+        )));
+    }
+    Ok(offer)
+}
+
+// 2.8.11 update_resource_offer Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn update_resource_offer(id: u64, mut payload: ResourceOfferPayload) -> Result<ResourceOffer, Error> {
+    crate::call_stats::count_call("update_resource_offer");
+    validate_quantity(payload.quantity)?;
+    validate_text(&mut payload.description, Some(&mut payload.location))?;
+    validate_coordinates(&payload.coordinates)?;
+    let mut offer = get_own_offer(id)?;
+    if payload.quantity < offer.committed_quantity {
+        return Err(Error::conflict(format!(
+            "quantity cannot be lower than the {} units already committed",
+            offer.committed_quantity
+        )));
+    }
+    offer.resource_type = payload.resource_type;
+    offer.description = payload.description;
+    offer.quantity = payload.quantity;
+    offer.location = payload.location;
+    offer.coordinates = payload.coordinates;
+    offer.updated_at = Some(time());
+    crate::ensure_storable(&offer, "resource offer")?;
+    do_insert_resource_offer(&offer);
+    Ok(offer)
+}
+
+// 2.8.12 withdraw_resource_offer Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn withdraw_resource_offer(id: u64) -> Result<ResourceOffer, Error> {
+    crate::call_stats::count_call("withdraw_resource_offer");
+    let mut offer = get_own_offer(id)?;
+    offer.status = ResourceOfferStatus::Withdrawn;
+    offer.updated_at = Some(time());
+    do_insert_resource_offer(&offer);
+    Ok(offer)
+}
+
+// 2.8.13 get_my_resource_offers Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_my_resource_offers() -> Result<QueryResult<ResourceOffer>, Error> {
+    let donor = caller();
+    fit(RESOURCE_OFFER_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, offer)| offer.donor == donor)
+            .map(|(_, offer)| offer)
+            .collect()
+    }))
+}
+
+// 2.8.14 get_resource_offers_by_type Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_resource_offers_by_type(resource_type: ResourceType) -> Result<QueryResult<ResourceOffer>, Error> {
+    fit(RESOURCE_OFFER_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, offer)| offer.resource_type == resource_type)
+            .map(|(_, offer)| offer)
+            .collect()
+    }))
+}
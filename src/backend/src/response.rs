@@ -0,0 +1,76 @@
+// Keeps query replies under the IC response limit. Unbounded list queries go
+// through `fit`, which rejects oversized results with a pagination hint instead
+// of letting the reply trap; paged queries use `truncate_to_fit` to cut a page
+// short and report that more is available.
+//
+// List and search endpoints reply with `QueryResult` (or `PagedResult` when
+// cursor-paged). Nothing matching is `Ok` with `total_count: 0`; an `Err` always
+// means the query itself failed.
+use crate::Error;
+use candid::{CandidType, Encode};
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct QueryResult<T> {
+    pub(crate) items: Vec<T>,
+    // Number of matching records; larger than `items.len()` when a limit applied
+    pub(crate) total_count: u64,
+}
+
+impl<T> QueryResult<T> {
+    pub(crate) fn new(items: Vec<T>) -> Self {
+        let total_count = items.len() as u64;
+        QueryResult { items, total_count }
+    }
+
+    pub(crate) fn limited(items: Vec<T>, total_count: u64) -> Self {
+        QueryResult { items, total_count }
+    }
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct PagedResult<T> {
+    pub(crate) items: Vec<T>,
+    // Number of records across all pages
+    pub(crate) total_count: u64,
+    // Pass back to fetch the next page; None when there is nothing left
+    pub(crate) next_cursor: Option<u64>,
+}
+
+// The IC caps replies at 2 MiB; leave headroom for the envelope around the result
+pub(crate) const MAX_RESPONSE_BYTES: usize = 2 * 1024 * 1024 - 64 * 1024;
+
+fn encoded_size<T: CandidType>(value: &T) -> usize {
+    Encode!(value).map(|bytes| bytes.len()).unwrap_or(usize::MAX)
+}
+
+// Wraps the items in a `QueryResult` if they encode within the response limit
+pub(crate) fn fit<T: CandidType>(items: Vec<T>) -> Result<QueryResult<T>, Error> {
+    let size = encoded_size(&items);
+    if size > MAX_RESPONSE_BYTES {
+        return Err(Error::result_too_large(format!(
+            "the result has {} items and encodes to {} bytes, over the {} byte reply limit; use a narrower filter or a paginated query",
+            items.len(),
+            size,
+            MAX_RESPONSE_BYTES
+        ))
+        .with_detail("encoded_bytes", size)
+        .with_detail("max_bytes", MAX_RESPONSE_BYTES));
+    }
+    Ok(QueryResult::new(items))
+}
+
+// Keeps the longest prefix that fits the reply limit; the flag is true when items were dropped.
+// Per-item sizes include their own type table, so the estimate errs on the safe side.
+pub(crate) fn truncate_to_fit<T: CandidType>(mut items: Vec<T>) -> (Vec<T>, bool) {
+    let mut total = 0usize;
+    let keep = items
+        .iter()
+        .take_while(|item| {
+            total = total.saturating_add(encoded_size(*item));
+            total <= MAX_RESPONSE_BYTES
+        })
+        .count();
+    let has_more = keep < items.len();
+    items.truncate(keep);
+    (items, has_more)
+}
@@ -0,0 +1,144 @@
+// How quickly crisis updates are verified and resolved, measured from the
+// recorded timeline: time to verify runs from the report to a moderator's
+// approval, and time to resolve from the report to the first change to
+// Resolved. Figures are grouped by priority, which serves as the severity, and
+// by region, so agencies can see where response lags. Updates approved or
+// resolved before transitions were recorded contribute no samples; only
+// updates stored in this canister are counted.
+use crate::access_mode::admit_read;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::timeline::{recorded_events, TimelineEventKind};
+use crate::{CrisisPriority, CrisisStatus, CrisisUpdate, Error, CRISIS_STORAGE};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct ResolutionStatsFilter {
+    priority: Option<CrisisPriority>,
+    // ISO 3166-1 alpha-2 code of the region's country
+    country: Option<String>,
+    // Bounds on when the update was reported
+    created_from: Option<u64>,
+    created_to: Option<u64>,
+}
+
+impl ResolutionStatsFilter {
+    fn matches(&self, update: &CrisisUpdate) -> bool {
+        self.priority.is_none_or(|p| update.priority == p)
+            && self.country.as_ref().is_none_or(|country| {
+                update
+                    .region
+                    .as_ref()
+                    .is_some_and(|r| r.country().eq_ignore_ascii_case(country))
+            })
+            && self.created_from.is_none_or(|from| update.created_at >= from)
+            && self.created_to.is_none_or(|to| update.created_at <= to)
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DurationStats {
+    samples: u64,
+    median_secs: u64,
+    p75_secs: u64,
+    p90_secs: u64,
+    p95_secs: u64,
+    max_secs: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ResolutionTimeGroup {
+    priority: CrisisPriority,
+    // The region label, e.g. "Mombasa, Coast, KE"; None for updates without a region
+    region: Option<String>,
+    updates: u64,
+    // None when no update in the group has been verified or resolved
+    time_to_verify: Option<DurationStats>,
+    time_to_resolve: Option<DurationStats>,
+}
+
+// Nearest-rank percentile of sorted samples
+fn percentile(sorted: &[u64], percent: usize) -> u64 {
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+fn duration_stats(mut samples: Vec<u64>) -> Option<DurationStats> {
+    if samples.is_empty() {
+        return None;
+    }
+    samples.sort_unstable();
+    Some(DurationStats {
+        samples: samples.len() as u64,
+        median_secs: percentile(&samples, 50),
+        p75_secs: percentile(&samples, 75),
+        p90_secs: percentile(&samples, 90),
+        p95_secs: percentile(&samples, 95),
+        max_secs: samples[samples.len() - 1],
+    })
+}
+
+// Seconds from the report to the first verification and to the first resolution
+fn transition_times(update: &CrisisUpdate) -> (Option<u64>, Option<u64>) {
+    let events = recorded_events(update.id);
+    let since_report = |at: u64| at.saturating_sub(update.created_at) / NANOS_PER_SECOND;
+    let verified = events
+        .iter()
+        .find(|event| matches!(event.kind, TimelineEventKind::Reviewed { approved: true }))
+        .map(|event| since_report(event.at));
+    let resolved = events
+        .iter()
+        .find(|event| {
+            matches!(
+                event.kind,
+                TimelineEventKind::StatusChanged {
+                    to: CrisisStatus::Resolved,
+                    ..
+                }
+            )
+        })
+        .map(|event| since_report(event.at));
+    (verified, resolved)
+}
+
+#[derive(Default)]
+struct GroupSamples {
+    updates: u64,
+    verify: Vec<u64>,
+    resolve: Vec<u64>,
+}
+
+// 2.85.1 get_resolution_time_stats Function:
+// One group per priority and region with matching updates, most severe first.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_resolution_time_stats(filter: ResolutionStatsFilter) -> Result<Vec<ResolutionTimeGroup>, Error> {
+    let mut groups: BTreeMap<(Reverse<usize>, Option<String>), GroupSamples> = BTreeMap::new();
+    CRISIS_STORAGE.with(|s| {
+        for (_, update) in s.borrow().iter() {
+            if !filter.matches(&update) || !crate::moderation::visible_to_caller(&update) {
+                continue;
+            }
+            let tier = CrisisPriority::ALL
+                .iter()
+                .position(|p| *p == update.priority)
+                .unwrap_or_default();
+            let group = groups
+                .entry((Reverse(tier), update.region.as_ref().map(|r| r.label())))
+                .or_default();
+            let (verified, resolved) = transition_times(&update);
+            group.updates += 1;
+            group.verify.extend(verified);
+            group.resolve.extend(resolved);
+        }
+    });
+    Ok(groups
+        .into_iter()
+        .map(|((Reverse(tier), region), samples)| ResolutionTimeGroup {
+            priority: CrisisPriority::ALL[tier],
+            region,
+            updates: samples.updates,
+            time_to_verify: duration_stats(samples.verify),
+            time_to_resolve: duration_stats(samples.resolve),
+        })
+        .collect())
+}
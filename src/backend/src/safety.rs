@@ -0,0 +1,145 @@
+use crate::access::{principal_key, require_any_role, PrincipalKey, Role};
+use crate::access_mode::admit_read;
+use crate::maintenance::admit_update;
+use crate::response::{fit, QueryResult};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_CHECK_IN_MESSAGE_LEN: usize = 280;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SafetyCheckIn {
+    crisis_id: u64,
+    principal: Principal,
+    message: Option<String>,
+    checked_in_at: u64,
+}
+
+// Implementing Storable and BoundedStorable traits for SafetyCheckIn
+impl Storable for SafetyCheckIn {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for SafetyCheckIn {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Keyed by (crisis id, principal) so all check-ins of a crisis are one range scan
+    static SAFETY_CHECK_INS: RefCell<StableBTreeMap<(u64, PrincipalKey), SafetyCheckIn, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18)))
+    ));
+}
+
+fn crisis_range(crisis_id: u64) -> std::ops::Range<(u64, PrincipalKey)> {
+    (crisis_id, Blob::default())..(crisis_id.saturating_add(1), Blob::default())
+}
+
+pub(crate) fn check_ins_of(principal: &Principal) -> Vec<SafetyCheckIn> {
+    let key = principal_key(principal);
+    SAFETY_CHECK_INS.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(k, _)| k.1 == key)
+            .map(|(_, check_in)| check_in)
+            .collect()
+    })
+}
+
+// Removes every check-in of the principal; returns how many there were
+pub(crate) fn remove_check_ins_of(principal: &Principal) -> u64 {
+    let key = principal_key(principal);
+    SAFETY_CHECK_INS.with(|s| {
+        let keys: Vec<(u64, PrincipalKey)> = s.borrow().iter().map(|(k, _)| k).filter(|k| k.1 == key).collect();
+        let mut s = s.borrow_mut();
+        for k in &keys {
+            s.remove(k);
+        }
+        keys.len() as u64
+    })
+}
+
+// 2.16.1 check_in_safe Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn check_in_safe(crisis_id: u64, message: Option<String>) -> Result<SafetyCheckIn, Error> {
+    crate::call_stats::count_call("check_in_safe");
+    if !CRISIS_STORAGE.with(|s| s.borrow().contains_key(&crisis_id)) {
+        return Err(Error::not_found(format!(
+            "a crisis update with id={} not found",
+            crisis_id
+        )));
+    }
+    if message
+        .as_ref()
+        .is_some_and(|m| m.chars().count() > MAX_CHECK_IN_MESSAGE_LEN)
+    {
+        return Err(Error::validation(format!(
+            "check-in message must be at most {} characters",
+            MAX_CHECK_IN_MESSAGE_LEN
+        )));
+    }
+    let principal = caller();
+    let check_in = SafetyCheckIn {
+        crisis_id,
+        principal,
+        message,
+        checked_in_at: time(),
+    };
+    SAFETY_CHECK_INS.with(|s| {
+        s.borrow_mut()
+            .insert((crisis_id, principal_key(&principal)), check_in.clone())
+    });
+    Ok(check_in)
+}
+
+// 2.16.2 get_safety_status Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_safety_status(principal: Principal, crisis_id: u64) -> Option<SafetyCheckIn> {
+    SAFETY_CHECK_INS.with(|s| s.borrow().get(&(crisis_id, principal_key(&principal))))
+}
+
+// 2.16.3 get_safe_count Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_safe_count(crisis_id: u64) -> u64 {
+    SAFETY_CHECK_INS.with(|s| s.borrow().range(crisis_range(crisis_id)).count() as u64)
+}
+
+// 2.16.4 get_safe_counts_by_crisis Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_safe_counts_by_crisis() -> Result<QueryResult<(u64, u64)>, Error> {
+    fit(SAFETY_CHECK_INS.with(|s| {
+        let mut counts: Vec<(u64, u64)> = Vec::new();
+        for ((crisis_id, _), _) in s.borrow().iter() {
+            match counts.last_mut() {
+                Some((id, count)) if *id == crisis_id => *count += 1,
+                _ => counts.push((crisis_id, 1)),
+            }
+        }
+        counts
+    }))
+}
+
+// 2.16.5 list_safe_check_ins Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn list_safe_check_ins(crisis_id: u64) -> Result<QueryResult<SafetyCheckIn>, Error> {
+    require_any_role(&[Role::Coordinator, Role::Responder])?;
+    fit(SAFETY_CHECK_INS.with(|s| {
+        s.borrow()
+            .range(crisis_range(crisis_id))
+            .map(|(_, check_in)| check_in)
+            .collect()
+    }))
+}
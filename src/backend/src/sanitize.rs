@@ -0,0 +1,159 @@
+// Sanitization of free text that dashboards render. Control characters and
+// bidi overrides (which can make text display differently from what is stored)
+// are always removed; markup is escaped, stripped or rejected depending on the
+// configured mode.
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::maintenance::admit_update;
+use crate::settings::{get_settings, update_settings};
+use crate::Error;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+pub(crate) enum SanitizeMode {
+    // <, > and & are HTML-escaped and kept as visible text
+    #[default]
+    Escape,
+    // Tags are removed, together with the contents of script and style elements
+    Strip,
+    // Input containing markup, control or bidi override characters is refused
+    Reject,
+}
+
+// Embeddings, overrides and isolates, plus the implicit direction marks
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{061C}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+// Single-line fields get newlines and tabs turned into spaces
+fn remove_controls(text: &str, multiline: bool) -> String {
+    text.chars()
+        .filter_map(|c| match c {
+            '\n' | '\t' if multiline => Some(c),
+            '\r' if multiline => None,
+            '\n' | '\t' | '\r' => Some(' '),
+            c if c.is_control() || is_bidi_control(c) => None,
+            c => Some(c),
+        })
+        .collect()
+}
+
+// Leaves existing entities such as "&amp;" alone so that re-submitting stored
+// text does not escape it twice
+fn starts_entity(rest: &str) -> bool {
+    let Some(end) = rest.find(';') else {
+        return false;
+    };
+    let name = &rest[1..end];
+    let named = !name.is_empty() && name.len() <= 10 && name.chars().all(|c| c.is_ascii_alphanumeric());
+    let numeric = name
+        .strip_prefix('#')
+        .is_some_and(|n| !n.is_empty() && n.len() <= 7 && n.chars().all(|c| c.is_ascii_alphanumeric()));
+    named || numeric
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for (i, c) in text.char_indices() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' if !starts_entity(&text[i..]) => escaped.push_str("&amp;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn strip_tags(text: &str) -> String {
+    let lower = text.to_ascii_lowercase();
+    let mut stripped = String::with_capacity(text.len());
+    let mut pos = 0;
+    while let Some(offset) = lower[pos..].find('<') {
+        let open = pos + offset;
+        stripped.push_str(&text[pos..open]);
+        let Some(close) = lower[open..].find('>').map(|end| open + end + 1) else {
+            // An unterminated tag would swallow the rest of the text
+            pos = open;
+            break;
+        };
+        pos = close;
+        for element in ["script", "style"] {
+            let tag = &lower[open + 1..close - 1];
+            if tag.split(|c: char| c.is_whitespace() || c == '/').next() == Some(element) {
+                pos = lower[close..]
+                    .find(&format!("</{}", element))
+                    .and_then(|end| lower[close + end..].find('>').map(|gt| close + end + gt + 1))
+                    .unwrap_or(text.len());
+            }
+        }
+    }
+    stripped.push_str(&text[pos..]);
+    escape_html(&stripped)
+}
+
+fn mode() -> SanitizeMode {
+    get_settings().sanitize_mode.unwrap_or_default()
+}
+
+// Returns the text in the form that may be stored, or an error naming the field
+pub(crate) fn sanitize_text(field: &str, text: &str, multiline: bool) -> Result<String, Error> {
+    let cleaned = remove_controls(text, multiline);
+    match mode() {
+        SanitizeMode::Escape => Ok(escape_html(&cleaned)),
+        SanitizeMode::Strip => Ok(strip_tags(&cleaned)),
+        SanitizeMode::Reject => {
+            if cleaned != text || cleaned.contains(['<', '>']) {
+                return Err(Error::validation(format!(
+                    "{} must not contain markup, control or bidi override characters",
+                    field
+                ))
+                .with_detail("field", field));
+            }
+            Ok(cleaned)
+        }
+    }
+}
+
+// 2.41.1 set_sanitize_mode Function:
+// Applies to text submitted from now on; stored updates are not rewritten.
+#[ic_cdk::update(guard = "admit_update")]
+fn set_sanitize_mode(mode: SanitizeMode) -> Result<SanitizeMode, Error> {
+    crate::call_stats::count_call("set_sanitize_mode");
+    require_role(Role::Admin)?;
+    update_settings(|s| s.sanitize_mode = Some(mode))?;
+    Ok(mode)
+}
+
+// 2.41.2 get_sanitize_mode Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_sanitize_mode() -> SanitizeMode {
+    mode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn controls_and_bidi_overrides_are_removed() {
+        assert_eq!(remove_controls("a\u{0007}b\u{202E}c", false), "abc");
+        assert_eq!(remove_controls("one\ntwo\tthree\r", false), "one two three ");
+        assert_eq!(remove_controls("one\r\ntwo\tthree", true), "one\ntwo\tthree");
+    }
+
+    #[test]
+    fn escaping_leaves_existing_entities_alone() {
+        assert_eq!(escape_html("<b>salt & water</b>"), "&lt;b&gt;salt &amp; water&lt;/b&gt;");
+        assert_eq!(escape_html("salt &amp; water &#38; &x"), "salt &amp; water &#38; &amp;x");
+        assert_eq!(escape_html(&escape_html("a < b")), "a &lt; b");
+    }
+
+    #[test]
+    fn stripping_drops_tags_and_script_contents() {
+        assert_eq!(strip_tags("<p>Road <b>closed</b></p>"), "Road closed");
+        assert_eq!(strip_tags("before<script>alert(1)</script>after"), "beforeafter");
+        assert_eq!(strip_tags("a<style type=x>p {}</style >b"), "ab");
+        // An unterminated tag is escaped rather than swallowing the rest
+        assert_eq!(strip_tags("x < y"), "x &lt; y");
+    }
+}
@@ -0,0 +1,478 @@
+// A one-line query syntax over `CrisisUpdateFilter`, for power users and ops
+// scripts, e.g. `location:nairobi severity>=high "bridge collapsed" after:2024-03-01`.
+//
+//   location:NAME              primary location or an affected area
+//   status:active|resolved|archived|unconfirmed
+//   severity:P (or priority)   with :, >=, >, <= or < against low, normal,
+//                              high, critical
+//   after:DATE, before:DATE    YYYY-MM-DD in UTC or nanoseconds; after is
+//                              inclusive, before is exclusive
+//   time:created|updated|occurred   the timestamp dates apply to
+//   KEY:VALUE                  a metadata entry, for keys in the schema
+//   word, "a phrase"           must appear in the title or description,
+//                              compared by stem with stop words ignored
+//
+// Values containing spaces are quoted (`location:"Kisumu West"`). Everything
+// given must match. `ranked_search` also orders the matches by a relevance
+// score built from how often the words appear (title hits weigh more than
+// description hits), how recently the update changed, its severity and how
+// many people confirmed it.
+use crate::access_mode::admit_read;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::metadata::{schema, MetadataType, MetadataValue};
+use crate::response::{truncate_to_fit, QueryResult};
+use crate::text::tokenize;
+use crate::{CrisisPriority, CrisisStatus, CrisisUpdate, CrisisUpdateFilter, Error, TimestampField, CRISIS_STORAGE};
+use ic_cdk::api::time;
+
+const MAX_QUERY_CHARS: usize = 1_000;
+const MAX_TEXT_TERMS: usize = 20;
+
+const TITLE_WEIGHT: f64 = 3.0;
+const TEXT_WEIGHT: f64 = 2.0;
+const RECENCY_WEIGHT: f64 = 1.0;
+const SEVERITY_WEIGHT: f64 = 1.0;
+const CONFIRMATION_WEIGHT: f64 = 0.5;
+// The recency part halves for every week since the last change
+const RECENCY_HALF_LIFE_SECS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct RankedResult {
+    crisis_update: CrisisUpdate,
+    // Higher is more relevant; only comparable within one result set
+    score: f64,
+}
+
+enum Token {
+    // A bare word or a quoted phrase
+    Text(String),
+    Field { key: String, op: Op, value: String },
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ge,
+    Gt,
+    Le,
+    Lt,
+}
+
+impl Op {
+    const ALL: [(&'static str, Op); 6] = [
+        (">=", Op::Ge),
+        ("<=", Op::Le),
+        (">", Op::Gt),
+        ("<", Op::Lt),
+        (":", Op::Eq),
+        ("=", Op::Eq),
+    ];
+}
+
+// Reads up to the closing quote; the opening quote is already consumed
+fn read_quoted(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, Error> {
+    let mut text = String::new();
+    for c in chars.by_ref() {
+        if c == '"' {
+            return Ok(text);
+        }
+        text.push(c);
+    }
+    Err(Error::validation("the query has an unterminated quote"))
+}
+
+// Splits `key<op>value` when the token starts with a field name
+fn split_field(word: &str) -> Option<(String, Op, String)> {
+    let key_len = word
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(word.len());
+    let key = &word[..key_len];
+    if !key.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let rest = &word[key_len..];
+    let (symbol, op) = Op::ALL.iter().find(|(symbol, _)| rest.starts_with(symbol))?;
+    Some((key.to_ascii_lowercase(), *op, rest[symbol.len()..].to_string()))
+}
+
+fn tokenize_query(query: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        if c == '"' {
+            chars.next();
+            tokens.push(Token::Text(read_quoted(&mut chars)?));
+            continue;
+        }
+        let mut word = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                break;
+            }
+            chars.next();
+            if c == '"' {
+                word.push_str(&read_quoted(&mut chars)?);
+            } else {
+                word.push(c);
+            }
+        }
+        tokens.push(match split_field(&word) {
+            Some((key, op, value)) => Token::Field { key, op, value },
+            None => Token::Text(word),
+        });
+    }
+    Ok(tokens)
+}
+
+fn parse_status(value: &str) -> Result<CrisisStatus, Error> {
+    match value.to_ascii_lowercase().as_str() {
+        "active" => Ok(CrisisStatus::Active),
+        "resolved" => Ok(CrisisStatus::Resolved),
+        "archived" => Ok(CrisisStatus::Archived),
+        "unconfirmed" => Ok(CrisisStatus::Unconfirmed),
+        _ => Err(Error::validation(format!("unknown status '{}'", value)).with_detail("status", value)),
+    }
+}
+
+fn parse_priority(value: &str) -> Result<CrisisPriority, Error> {
+    CrisisPriority::ALL
+        .into_iter()
+        .find(|priority| format!("{:?}", priority).eq_ignore_ascii_case(value))
+        .ok_or_else(|| Error::validation(format!("unknown severity '{}'", value)).with_detail("severity", value))
+}
+
+fn parse_timestamp_field(value: &str) -> Result<TimestampField, Error> {
+    match value.to_ascii_lowercase().as_str() {
+        "created" => Ok(TimestampField::Created),
+        "updated" => Ok(TimestampField::Updated),
+        "occurred" => Ok(TimestampField::Occurred),
+        _ => Err(Error::validation(format!("unknown timestamp '{}'", value)).with_detail("time", value)),
+    }
+}
+
+// Days since 1970-01-01 for a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// Nanoseconds at the start of a YYYY-MM-DD day in UTC, or a raw nanosecond timestamp
+fn parse_date(key: &str, value: &str) -> Result<u64, Error> {
+    if let Ok(nanos) = value.parse::<u64>() {
+        return Ok(nanos);
+    }
+    let invalid = || Error::validation(format!("{} expects a YYYY-MM-DD date", key)).with_detail(key, value);
+    let parts: Vec<&str> = value.split('-').collect();
+    let [year, month, day] = parts.as_slice() else {
+        return Err(invalid());
+    };
+    let (Ok(year), Ok(month), Ok(day)) = (year.parse::<i64>(), month.parse::<i64>(), day.parse::<i64>()) else {
+        return Err(invalid());
+    };
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+    let days = days_from_civil(year, month, day);
+    Ok(days as u64 * 86_400 * NANOS_PER_SECOND)
+}
+
+fn parse_metadata_value(key: &str, value_type: MetadataType, value: &str) -> Result<MetadataValue, Error> {
+    let invalid = || Error::validation(format!("metadata key '{}' expects a {:?} value", key, value_type));
+    match value_type {
+        MetadataType::Text => Ok(MetadataValue::Text(value.to_string())),
+        MetadataType::Integer => value.parse().map(MetadataValue::Integer).map_err(|_| invalid()),
+        MetadataType::Number => value.parse().map(MetadataValue::Number).map_err(|_| invalid()),
+        MetadataType::Bool => value.parse().map(MetadataValue::Bool).map_err(|_| invalid()),
+    }
+}
+
+fn set_once<T>(slot: &mut Option<T>, key: &str, value: T) -> Result<(), Error> {
+    if slot.is_some() {
+        return Err(Error::validation(format!("{} is given more than once", key)).with_detail("key", key));
+    }
+    *slot = Some(value);
+    Ok(())
+}
+
+fn apply_severity(filter: &mut CrisisUpdateFilter, op: Op, value: &str) -> Result<(), Error> {
+    let priority = parse_priority(value)?;
+    let index = CrisisPriority::ALL
+        .iter()
+        .position(|p| *p == priority)
+        .unwrap_or_default();
+    let out_of_range = || {
+        Error::validation(format!(
+            "no severity is {} {:?}",
+            if op == Op::Gt { "above" } else { "below" },
+            priority
+        ))
+    };
+    let (min, max) = match op {
+        Op::Eq => (Some(priority), Some(priority)),
+        Op::Ge => (Some(priority), None),
+        Op::Le => (None, Some(priority)),
+        Op::Gt => (
+            Some(*CrisisPriority::ALL.get(index + 1).ok_or_else(out_of_range)?),
+            None,
+        ),
+        Op::Lt => (
+            None,
+            Some(CrisisPriority::ALL[index.checked_sub(1).ok_or_else(out_of_range)?]),
+        ),
+    };
+    if let Some(min) = min {
+        set_once(&mut filter.min_priority, "the minimum severity", min)?;
+    }
+    if let Some(max) = max {
+        set_once(&mut filter.max_priority, "the maximum severity", max)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn parse_query(query: &str) -> Result<CrisisUpdateFilter, Error> {
+    if query.chars().count() > MAX_QUERY_CHARS {
+        return Err(Error::validation(format!(
+            "queries must be at most {} characters",
+            MAX_QUERY_CHARS
+        )));
+    }
+    let mut filter = CrisisUpdateFilter::default();
+    let mut terms = Vec::new();
+    let schema = schema();
+    for token in tokenize_query(query)? {
+        let (key, op, value) = match token {
+            Token::Text(text) => {
+                let text = crate::normalize::normalize_line(&text);
+                // Words that are all stop words would match anything
+                if !tokenize(&text).is_empty() {
+                    terms.push(text);
+                }
+                continue;
+            }
+            Token::Field { key, op, value } => (key, op, value),
+        };
+        if value.is_empty() {
+            return Err(Error::validation(format!("{} needs a value", key)).with_detail("key", &key));
+        }
+        if key == "severity" || key == "priority" {
+            apply_severity(&mut filter, op, &value)?;
+            continue;
+        }
+        if op != Op::Eq {
+            return Err(Error::validation(format!("{} only takes ':'", key)).with_detail("key", &key));
+        }
+        match key.as_str() {
+            "location" => set_once(&mut filter.location, "location", value)?,
+            "status" => set_once(&mut filter.status, "status", parse_status(&value)?)?,
+            "after" => set_once(&mut filter.start_timestamp, "after", parse_date("after", &value)?)?,
+            "before" => {
+                let end = parse_date("before", &value)?.saturating_sub(1);
+                set_once(&mut filter.end_timestamp, "before", end)?
+            }
+            "time" => set_once(&mut filter.timestamp_field, "time", parse_timestamp_field(&value)?)?,
+            _ => {
+                let Some(field) = schema.iter().find(|field| field.key == key) else {
+                    return Err(Error::validation(format!("unknown search field '{}'", key)).with_detail("key", &key));
+                };
+                let value = parse_metadata_value(&key, field.value_type, &value)?;
+                filter.metadata.push((key, value));
+            }
+        }
+    }
+    if terms.len() > MAX_TEXT_TERMS {
+        return Err(Error::validation(format!(
+            "a query can contain at most {} words or phrases",
+            MAX_TEXT_TERMS
+        )));
+    }
+    if !terms.is_empty() {
+        filter.text = Some(terms);
+    }
+    Ok(filter)
+}
+
+// Times the phrase's tokens appear consecutively in the words
+fn occurrences(words: &[String], phrase: &[String]) -> usize {
+    if phrase.is_empty() {
+        return 0;
+    }
+    words.windows(phrase.len()).filter(|window| *window == phrase).count()
+}
+
+// True when every term appears in the title or the description
+pub(crate) fn contains_terms(update: &CrisisUpdate, terms: &[String]) -> bool {
+    let fields = [tokenize(&update.title), tokenize(&update.description)];
+    terms.iter().all(|term| {
+        let phrase = tokenize(term);
+        phrase.is_empty() || fields.iter().any(|words| occurrences(words, &phrase) > 0)
+    })
+}
+
+// 2.65.1 search Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn search(query: String) -> Result<QueryResult<CrisisUpdate>, Error> {
+    let filter = parse_query(&query)?;
+    crate::query_limits::cap_search_results(crate::present_updates(CRISIS_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, update)| update)
+            .filter(|update| filter.matches(update))
+            .collect()
+    })))
+}
+
+fn relevance(update: &CrisisUpdate, terms: &[String], now: u64) -> f64 {
+    let title = tokenize(&update.title);
+    let description = tokenize(&update.description);
+    let text: f64 = terms
+        .iter()
+        .map(|term| {
+            let phrase = tokenize(term);
+            let hits = TITLE_WEIGHT * occurrences(&title, &phrase) as f64 + occurrences(&description, &phrase) as f64;
+            (1.0 + hits).ln()
+        })
+        .sum();
+    let age_secs = now.saturating_sub(update.last_modified()) / NANOS_PER_SECOND;
+    let recency = 0.5f64.powf(age_secs as f64 / RECENCY_HALF_LIFE_SECS as f64);
+    let severity = update.priority as u8 as f64 / (CrisisPriority::ALL.len() - 1) as f64;
+    let confirmations = (1.0 + crate::reputation::confirmation_count(update.id) as f64).ln();
+    TEXT_WEIGHT * text + RECENCY_WEIGHT * recency + SEVERITY_WEIGHT * severity + CONFIRMATION_WEIGHT * confirmations
+}
+
+// 2.65.2 ranked_search Function:
+// Best matches first, up to `limit`; `total_count` counts every match.
+#[ic_cdk::query(guard = "admit_read")]
+fn ranked_search(query: String, limit: u64) -> Result<QueryResult<RankedResult>, Error> {
+    let filter = parse_query(&query)?;
+    let terms = filter.text.clone().unwrap_or_default();
+    let now = time();
+    let mut ranked: Vec<RankedResult> = CRISIS_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, update)| update)
+            .filter(|update| filter.matches(update))
+            .filter_map(crate::present_update)
+            .map(|update| RankedResult {
+                score: relevance(&update, &terms, now),
+                crisis_update: update,
+            })
+            .collect()
+    });
+    let total_count = ranked.len() as u64;
+    ranked.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.crisis_update.id.cmp(&b.crisis_update.id))
+    });
+    ranked.truncate(limit.clamp(1, crate::query_limits::query_limits().max_search_results) as usize);
+    Ok(QueryResult::limited(truncate_to_fit(ranked).0, total_count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 86_400 * NANOS_PER_SECOND;
+
+    #[test]
+    fn queries_parse_into_filters() {
+        let query = r#"location:"Kisumu West" status:active severity>=high "bridge collapsed""#;
+        let filter = parse_query(&format!("{} after:2024-03-01 before:2024-03-02", query)).unwrap();
+        assert_eq!(filter.location.as_deref(), Some("Kisumu West"));
+        assert_eq!(filter.status, Some(CrisisStatus::Active));
+        assert_eq!(filter.min_priority, Some(CrisisPriority::High));
+        assert_eq!(filter.max_priority, None);
+        let start = filter.start_timestamp.unwrap();
+        assert_eq!(start, 19_783 * DAY);
+        assert_eq!(filter.end_timestamp, Some(start + DAY - 1));
+        assert_eq!(filter.text, Some(vec!["bridge collapsed".to_string()]));
+    }
+
+    #[test]
+    fn strict_severity_bounds_step_to_the_next_level() {
+        let filter = parse_query("severity>normal").unwrap();
+        assert_eq!(filter.min_priority, Some(CrisisPriority::High));
+        let filter = parse_query("priority<normal").unwrap();
+        assert_eq!(filter.max_priority, Some(CrisisPriority::Low));
+        assert!(parse_query("severity>critical").is_err());
+        assert!(parse_query("severity<low").is_err());
+    }
+
+    #[test]
+    fn invalid_queries_are_rejected() {
+        for query in [
+            "status:active status:resolved",
+            "status:open",
+            "location:",
+            "location>nairobi",
+            "after:2024-13-01",
+            "unknownfield:x",
+            "\"unterminated",
+        ] {
+            assert!(parse_query(query).is_err(), "{} was accepted", query);
+        }
+    }
+
+    #[test]
+    fn dates_are_utc_days_or_raw_nanoseconds() {
+        assert_eq!(parse_date("after", "1970-01-02").unwrap(), DAY);
+        assert_eq!(parse_date("after", "2000-03-01").unwrap(), 11_017 * DAY);
+        assert_eq!(parse_date("after", "12345").unwrap(), 12345);
+        assert!(parse_date("after", "1969-12-31").is_err());
+    }
+
+    #[test]
+    fn filters_match_on_every_given_field() {
+        let update = CrisisUpdate {
+            title: "Bridge collapsed".to_string(),
+            description: "The river bridge into the market is gone".to_string(),
+            location: "Kisumu West".to_string(),
+            priority: CrisisPriority::High,
+            created_at: 5 * DAY,
+            ..Default::default()
+        };
+        let matching = CrisisUpdateFilter {
+            location: Some(" kisumu   west ".to_string()),
+            status: Some(CrisisStatus::Active),
+            min_priority: Some(CrisisPriority::Normal),
+            start_timestamp: Some(5 * DAY),
+            end_timestamp: Some(6 * DAY),
+            text: Some(vec!["river bridge".to_string()]),
+            include_expired: Some(true),
+            ..Default::default()
+        };
+        assert!(matching.matches(&update));
+        let misses = [
+            CrisisUpdateFilter {
+                location: Some("Kisumu".to_string()),
+                ..matching.clone()
+            },
+            CrisisUpdateFilter {
+                max_priority: Some(CrisisPriority::Normal),
+                ..matching.clone()
+            },
+            CrisisUpdateFilter {
+                start_timestamp: Some(5 * DAY + 1),
+                ..matching.clone()
+            },
+            CrisisUpdateFilter {
+                text: Some(vec!["bridge river".to_string()]),
+                ..matching.clone()
+            },
+        ];
+        for filter in misses {
+            assert!(!filter.matches(&update));
+        }
+    }
+}
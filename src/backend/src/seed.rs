@@ -0,0 +1,197 @@
+// Demo data for drills, training and frontend development on a fresh canister.
+// Generated records go through the normal storage paths, so indexes, SLAs and
+// the change feed see them like any other data. Titles carry a "[Demo]" prefix
+// so they are easy to tell apart from real reports.
+use crate::access::{require_role, Role};
+use crate::maintenance::admit_update;
+use crate::regions::Region;
+use crate::resources::{
+    do_insert_resource_offer, do_insert_resource_request, ResourceOffer, ResourceOfferStatus, ResourceRequest,
+    ResourceRequestStatus, ResourceType, Urgency, OFFER_ID_COUNTER, RESOURCE_ID_COUNTER,
+};
+use crate::{CrisisPriority, CrisisStatus, CrisisUpdate, Error};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::api::time;
+
+const MAX_SEED_COUNT: u64 = 200;
+const HOUR_NANOS: u64 = 60 * 60 * 1_000_000_000;
+
+// (title, description, resource most likely needed)
+const INCIDENTS: &[(&str, &str, ResourceType)] = &[
+    (
+        "Flooding on main roads",
+        "Water levels rising after heavy rain. Several streets impassable, residents moving to upper floors.",
+        ResourceType::Transport,
+    ),
+    (
+        "Building collapse",
+        "Partial collapse of a residential block. Search and rescue requested, number of trapped people unknown.",
+        ResourceType::Equipment,
+    ),
+    (
+        "Wildfire approaching homes",
+        "Fire front visible from the ridge, wind pushing it towards houses. Smoke affecting visibility.",
+        ResourceType::Shelter,
+    ),
+    (
+        "Drinking water contaminated",
+        "Tap water discoloured and smelling of fuel. Families need bottled water until tests come back.",
+        ResourceType::Water,
+    ),
+    (
+        "Power outage across district",
+        "No electricity since last night. Elderly residents on home oxygen at risk.",
+        ResourceType::Medical,
+    ),
+    (
+        "Landslide blocks access road",
+        "Mud and rocks cover the only road into the area. Villages beyond it are cut off.",
+        ResourceType::Food,
+    ),
+    (
+        "Injured people at the market",
+        "Several people injured after a roof gave way. First aid on site, ambulances delayed.",
+        ResourceType::Medical,
+    ),
+    (
+        "Displaced families need shelter",
+        "Around forty families sleeping outdoors after evacuating their homes.",
+        ResourceType::Clothing,
+    ),
+];
+
+const NEIGHBOURHOODS: &[&str] = &[
+    "Riverside",
+    "Old Town",
+    "Market Quarter",
+    "Harbour",
+    "Hillside",
+    "North District",
+    "Airport Road",
+    "University Area",
+];
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct SeedReport {
+    crisis_update_ids: Vec<u64>,
+    resource_request_ids: Vec<u64>,
+    resource_offer_ids: Vec<u64>,
+}
+
+// xorshift64*; demo data only needs variety, not unpredictability
+struct DemoRng(u64);
+
+impl DemoRng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound.max(1)
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len() as u64) as usize]
+    }
+}
+
+async fn demo_rng() -> Result<DemoRng, Error> {
+    let (bytes,) = raw_rand()
+        .await
+        .map_err(|(code, msg)| Error::external_call_failed(format!("raw_rand failed: {:?} {}", code, msg)))?;
+    let seed = bytes.iter().take(8).fold(0u64, |acc, b| (acc << 8) | u64::from(*b));
+    // xorshift never leaves the all-zero state
+    Ok(DemoRng(seed | 1))
+}
+
+// 2.48.1 seed_demo_data Function:
+// Creates `count` crisis updates in the region, with resource requests and offers
+// for some of them. Updates are published directly, bypassing moderation.
+#[ic_cdk::update(guard = "admit_update")]
+async fn seed_demo_data(count: u64, region: Region) -> Result<SeedReport, Error> {
+    crate::call_stats::count_call("seed_demo_data");
+    let admin = require_role(Role::Admin)?;
+    if count == 0 || count > MAX_SEED_COUNT {
+        return Err(Error::validation(format!(
+            "count must be between 1 and {}",
+            MAX_SEED_COUNT
+        )));
+    }
+    let mut region = Some(region);
+    crate::regions::validate_region(&mut region)?;
+    let area = region.as_ref().map(Region::label).unwrap_or_default();
+    let mut rng = demo_rng().await?;
+    let now = time();
+    let mut report = SeedReport {
+        crisis_update_ids: Vec::new(),
+        resource_request_ids: Vec::new(),
+        resource_offer_ids: Vec::new(),
+    };
+    for _ in 0..count {
+        let (title, description, needed) = *rng.pick(INCIDENTS);
+        let location = format!("{}, {}", rng.pick(NEIGHBOURHOODS), area);
+        let created_at = now.saturating_sub(rng.below(7 * 24) * HOUR_NANOS);
+        let id = crate::ids::next_id(&crate::CRISIS_ID_COUNTER)?;
+        let update = CrisisUpdate {
+            id,
+            title: format!("[Demo] {}", title),
+            description: description.to_string(),
+            location: location.clone(),
+            created_at,
+            occurred_at: Some(created_at.saturating_sub(rng.below(6) * HOUR_NANOS)),
+            status: if rng.below(4) == 0 {
+                CrisisStatus::Resolved
+            } else {
+                CrisisStatus::Active
+            },
+            priority: *rng.pick(&CrisisPriority::ALL),
+            author: Some(admin),
+            region: region.clone(),
+            ..Default::default()
+        };
+        crate::store_new_crisis_update(&update).await?;
+        report.crisis_update_ids.push(id);
+
+        if rng.below(2) == 0 {
+            let request_id = crate::ids::next_id(&RESOURCE_ID_COUNTER)?;
+            do_insert_resource_request(&ResourceRequest {
+                id: request_id,
+                crisis_update_id: id,
+                requester: admin,
+                resource_type: needed,
+                description: format!("[Demo] Supplies needed for: {}", title),
+                quantity: 10 + rng.below(190),
+                fulfilled_quantity: 0,
+                urgency: *rng.pick(&[Urgency::Low, Urgency::Medium, Urgency::High, Urgency::Critical]),
+                status: ResourceRequestStatus::Open,
+                location: location.clone(),
+                coordinates: None,
+                created_at,
+                updated_at: None,
+                contact: None,
+            });
+            report.resource_request_ids.push(request_id);
+        }
+        if rng.below(3) == 0 {
+            let offer_id = crate::ids::next_id(&OFFER_ID_COUNTER)?;
+            do_insert_resource_offer(&ResourceOffer {
+                id: offer_id,
+                donor: admin,
+                resource_type: needed,
+                description: "[Demo] Donated supplies ready for pickup".to_string(),
+                quantity: 5 + rng.below(95),
+                committed_quantity: 0,
+                status: ResourceOfferStatus::Available,
+                location,
+                coordinates: None,
+                created_at,
+                updated_at: None,
+            });
+            report.resource_offer_ids.push(offer_id);
+        }
+    }
+    Ok(report)
+}
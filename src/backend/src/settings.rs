@@ -0,0 +1,132 @@
+use crate::access::{require_role, Role};
+use crate::access_mode::AccessMode;
+use crate::access_mode::admit_read;
+use crate::cycles::CyclesAlertTarget;
+use crate::inspect::IngressLimits;
+use crate::metadata::MetadataField;
+use crate::maintenance::{admit_update, MaintenanceMode};
+use crate::privacy::AnonymizationPolicy;
+use crate::query_limits::QueryLimits;
+use crate::sanitize::SanitizeMode;
+use crate::sharding::ShardStrategy;
+use crate::staleness::StalenessPolicy;
+use crate::uniqueness::UniquenessConstraint;
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{Cell, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Canister-wide configuration. New fields must be optional so that settings
+// written by earlier versions keep decoding after an upgrade.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct Settings {
+    pub(crate) donation_ledger: Option<Principal>,
+    pub(crate) cycles_alert_threshold: Option<u128>,
+    pub(crate) cycles_alert_target: Option<CyclesAlertTarget>,
+    pub(crate) cycles_check_interval_secs: Option<u64>,
+    pub(crate) archive_canister: Option<Principal>,
+    // Number of active crisis updates above which archiving kicks in
+    pub(crate) archive_threshold: Option<u64>,
+    // None keeps every new crisis update in this canister
+    pub(crate) shard_strategy: Option<ShardStrategy>,
+    // How long deletions stay visible to delta sync
+    pub(crate) tombstone_retention_secs: Option<u64>,
+    // Holds updates from untrusted reporters for review when true
+    pub(crate) moderation_mode: Option<bool>,
+    // Metadata keys updates may carry, with their value types
+    pub(crate) metadata_schema: Option<Vec<MetadataField>>,
+    // Hosts (and their subdomains) that attached links may point to
+    pub(crate) link_host_allowlist: Option<Vec<String>>,
+    // How markup in submitted text is handled; None escapes it
+    pub(crate) sanitize_mode: Option<SanitizeMode>,
+    // Follower canister that receives every crisis update change
+    pub(crate) replica_canister: Option<Principal>,
+    // Checked when ingress calls are inspected; None uses the defaults
+    pub(crate) ingress_limits: Option<IngressLimits>,
+    // Retention policy for personal data in old updates; None keeps it
+    pub(crate) anonymization_policy: Option<AnonymizationPolicy>,
+    // Set while the canister is read-only for maintenance
+    pub(crate) maintenance_mode: Option<MaintenanceMode>,
+    // Words left out of text search; None uses the built-in English list
+    pub(crate) search_stop_words: Option<Vec<String>>,
+    // How often situation reports are generated; None uses the daily default
+    pub(crate) sitrep_interval_secs: Option<u64>,
+    // Who may call query methods; None is public
+    pub(crate) access_mode: Option<AccessMode>,
+    // Resolves active updates once their expiry passes when true
+    pub(crate) auto_resolve_expired: Option<bool>,
+    // Canister that confirms credentials for Responder and VerifiedOrg roles
+    pub(crate) credentials_canister: Option<Principal>,
+    // Rejects new updates that clash with a stored one; None allows them
+    pub(crate) uniqueness_constraint: Option<UniquenessConstraint>,
+    // Page, search, export and attachment limits; None uses the defaults
+    pub(crate) query_limits: Option<QueryLimits>,
+    // Global canister that receives public updates; None publishes nothing
+    pub(crate) aggregator_canister: Option<Principal>,
+    // When to prompt about stale reports and mark them Unconfirmed; None turns that off
+    pub(crate) staleness_policy: Option<StalenessPolicy>,
+}
+
+impl Storable for Settings {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    static SETTINGS: RefCell<Cell<Settings, Memory>> = RefCell::new(
+        Cell::init(CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25))), Settings::default())
+            .expect("Cannot create the settings cell")
+    );
+}
+
+pub(crate) fn get_settings() -> Settings {
+    SETTINGS.with(|s| s.borrow().get().clone())
+}
+
+// Applies a change to the stored settings and returns the new value
+pub(crate) fn update_settings(change: impl FnOnce(&mut Settings)) -> Result<Settings, Error> {
+    SETTINGS.with(|s| {
+        let mut settings = s.borrow().get().clone();
+        change(&mut settings);
+        s.borrow_mut()
+            .set(settings.clone())
+            .map_err(|err| Error::internal(format!("cannot persist settings: {:?}", err)))?;
+        Ok(settings)
+    })
+}
+
+// Options for a fresh install; each may be left out
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct InitArgs {
+    access_mode: Option<AccessMode>,
+}
+
+#[ic_cdk::init]
+fn init(args: Option<InitArgs>) {
+    let args = args.unwrap_or_default();
+    if let Err(err) = update_settings(|s| s.access_mode = args.access_mode) {
+        ic_cdk::trap(&err.msg);
+    }
+    crate::realtime::init_websocket();
+}
+
+// 2.19.1 get_canister_settings Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_canister_settings() -> Result<Settings, Error> {
+    require_role(Role::Admin)?;
+    Ok(get_settings())
+}
+
+// 2.19.2 set_donation_ledger Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn set_donation_ledger(ledger: Option<Principal>) -> Result<Settings, Error> {
+    crate::call_stats::count_call("set_donation_ledger");
+    require_role(Role::Admin)?;
+    update_settings(|s| s.donation_ledger = ledger)
+}
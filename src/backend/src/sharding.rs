@@ -0,0 +1,303 @@
+// Router side of horizontal sharding. When a shard strategy is configured, new
+// crisis updates are placed on storage canisters by region or id range; this
+// canister keeps the shard map plus an id -> shard pointer and fans list
+// queries out to every shard.
+//
+// Storage canisters are expected to expose:
+//   shard_insert_crisis_update : (CrisisUpdate) -> ();
+//   shard_remove_crisis_update : (nat64) -> (opt CrisisUpdate);
+//   shard_get_crisis_update : (nat64) -> (opt CrisisUpdate) query;
+//   shard_list_crisis_updates : (opt nat64, nat64) -> (vec CrisisUpdate) query;
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::ids::IdCounter;
+use crate::maintenance::admit_update;
+use crate::response::{fit, truncate_to_fit, PagedResult, QueryResult};
+use crate::settings::{get_settings, update_settings, Settings};
+use crate::{CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use candid::{Decode, Encode, Principal};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum ShardStrategy {
+    ByRegion,
+    ByIdRange,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Shard {
+    id: u64,
+    canister_id: Principal,
+    // Locations routed to this shard when sharding by region
+    regions: Vec<String>,
+    // Inclusive id range routed to this shard when sharding by id range
+    id_range: Option<(u64, u64)>,
+}
+
+// Implementing Storable and BoundedStorable traits for Shard
+impl Storable for Shard {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Shard {
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ShardPayload {
+    canister_id: Principal,
+    regions: Vec<String>,
+    id_range: Option<(u64, u64)>,
+}
+
+thread_local! {
+    static SHARD_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(34, "shards"));
+
+    static SHARD_STORAGE: RefCell<StableBTreeMap<u64, Shard, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(35)))
+    ));
+
+    // crisis update id -> shard id, for updates stored on a shard
+    static SHARD_INDEX: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(36)))
+    ));
+}
+
+fn call_error(canister: Principal, method: &str, err: (ic_cdk::api::call::RejectionCode, String)) -> Error {
+    Error::external_call_failed(format!("shard {} rejected {}: {:?} {}", canister, method, err.0, err.1))
+}
+
+pub(crate) fn list_shards() -> Vec<Shard> {
+    SHARD_STORAGE.with(|s| s.borrow().iter().map(|(_, shard)| shard).collect())
+}
+
+pub(crate) fn shard_canisters() -> Vec<Principal> {
+    list_shards().into_iter().map(|shard| shard.canister_id).collect()
+}
+
+// Picks the shard a new update belongs on; None keeps it in local storage
+pub(crate) fn shard_for_new_update(update: &CrisisUpdate) -> Option<Principal> {
+    let strategy = get_settings().shard_strategy?;
+    list_shards()
+        .into_iter()
+        .find(|shard| match strategy {
+            ShardStrategy::ByRegion => shard.regions.iter().any(|r| r.eq_ignore_ascii_case(&update.location)),
+            ShardStrategy::ByIdRange => shard
+                .id_range
+                .is_some_and(|(start, end)| (start..=end).contains(&update.id)),
+        })
+        .map(|shard| shard.canister_id)
+}
+
+// The storage canister holding a previously forwarded update
+pub(crate) fn sharded_location(id: u64) -> Option<Principal> {
+    let shard_id = SHARD_INDEX.with(|index| index.borrow().get(&id))?;
+    SHARD_STORAGE
+        .with(|s| s.borrow().get(&shard_id))
+        .map(|shard| shard.canister_id)
+}
+
+fn shard_id_of(canister: &Principal) -> Option<u64> {
+    list_shards()
+        .into_iter()
+        .find(|shard| shard.canister_id == *canister)
+        .map(|shard| shard.id)
+}
+
+pub(crate) async fn forward_insert(canister: Principal, update: &CrisisUpdate) -> Result<(), Error> {
+    let shard_id = shard_id_of(&canister)
+        .ok_or_else(|| Error::not_found(format!("canister {} is not a registered shard", canister)))?;
+    let _: () = ic_cdk::call(canister, "shard_insert_crisis_update", (update.clone(),))
+        .await
+        .map_err(|e| call_error(canister, "shard_insert_crisis_update", e))?;
+    SHARD_INDEX.with(|index| index.borrow_mut().insert(update.id, shard_id));
+    Ok(())
+}
+
+pub(crate) async fn forward_remove(canister: Principal, id: u64) -> Result<Option<CrisisUpdate>, Error> {
+    let (removed,): (Option<CrisisUpdate>,) = ic_cdk::call(canister, "shard_remove_crisis_update", (id,))
+        .await
+        .map_err(|e| call_error(canister, "shard_remove_crisis_update", e))?;
+    SHARD_INDEX.with(|index| index.borrow_mut().remove(&id));
+    Ok(removed)
+}
+
+pub(crate) async fn fetch_from_shard(id: u64) -> Result<Option<CrisisUpdate>, Error> {
+    let Some(canister) = sharded_location(id) else {
+        return Ok(None);
+    };
+    let (update,): (Option<CrisisUpdate>,) = ic_cdk::call(canister, "shard_get_crisis_update", (id,))
+        .await
+        .map_err(|e| call_error(canister, "shard_get_crisis_update", e))?;
+    Ok(update)
+}
+
+// 2.24.1 register_shard Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn register_shard(payload: ShardPayload) -> Result<Shard, Error> {
+    crate::call_stats::count_call("register_shard");
+    require_role(Role::Admin)?;
+    if list_shards().iter().any(|s| s.canister_id == payload.canister_id) {
+        return Err(Error::conflict(format!(
+            "canister {} is already a shard",
+            payload.canister_id
+        )));
+    }
+    if payload.id_range.is_some_and(|(start, end)| start > end) {
+        return Err(Error::validation("id range start must not exceed its end"));
+    }
+    let id = crate::ids::next_id(&SHARD_ID_COUNTER)?;
+    let shard = Shard {
+        id,
+        canister_id: payload.canister_id,
+        regions: payload.regions,
+        id_range: payload.id_range,
+    };
+    SHARD_STORAGE.with(|s| s.borrow_mut().insert(id, shard.clone()));
+    Ok(shard)
+}
+
+// 2.24.2 remove_shard Function:
+// Only shards that no longer hold any update can be removed.
+#[ic_cdk::update(guard = "admit_update")]
+fn remove_shard(id: u64) -> Result<Shard, Error> {
+    crate::call_stats::count_call("remove_shard");
+    require_role(Role::Admin)?;
+    if SHARD_INDEX.with(|index| index.borrow().iter().any(|(_, shard_id)| shard_id == id)) {
+        return Err(Error::conflict(format!(
+            "shard with id={} still holds crisis updates",
+            id
+        )));
+    }
+    match SHARD_STORAGE.with(|s| s.borrow_mut().remove(&id)) {
+        Some(shard) => Ok(shard),
+        None => Err(Error::not_found(format!("a shard with id={} not found", id))),
+    }
+}
+
+// 2.24.3 get_shards Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_shards() -> Result<QueryResult<Shard>, Error> {
+    require_role(Role::Admin)?;
+    fit(list_shards())
+}
+
+// 2.24.4 set_shard_strategy Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn set_shard_strategy(strategy: Option<ShardStrategy>) -> Result<Settings, Error> {
+    crate::call_stats::count_call("set_shard_strategy");
+    require_role(Role::Admin)?;
+    update_settings(|s| s.shard_strategy = strategy)
+}
+
+// Orders the rows from every source by id, drops repeats and keeps the first
+// `limit`; the flag is true when more follow
+fn merge_page(mut items: Vec<CrisisUpdate>, limit: u64) -> (Vec<CrisisUpdate>, bool) {
+    items.sort_by_key(|update| update.id);
+    items.dedup_by_key(|update| update.id);
+    let has_more = items.len() as u64 > limit;
+    items.truncate(limit as usize);
+    (items, has_more)
+}
+
+// A reply cut short to fit resumes after its last item; otherwise the page
+// resumes after its last row, returned or hidden
+fn next_cursor(last_id: Option<u64>, has_more: bool, last_returned: Option<u64>, truncated: bool) -> Option<u64> {
+    if truncated {
+        last_returned
+    } else if has_more {
+        last_id
+    } else {
+        None
+    }
+}
+
+// 2.24.5 list_crisis_updates_page Function:
+// Merges local storage and every shard by id, so pages are stable across shards.
+// Each source is asked for one row past the limit to tell whether more follow.
+#[ic_cdk::query(composite = true, guard = "admit_read")]
+async fn list_crisis_updates_page(start_after: Option<u64>, limit: u64) -> Result<PagedResult<CrisisUpdate>, Error> {
+    let limit = crate::query_limits::page_size(limit);
+    let start = start_after.map_or(0, |id| id.saturating_add(1));
+    let mut items: Vec<CrisisUpdate> = CRISIS_STORAGE.with(|s| {
+        s.borrow()
+            .range(start..)
+            .take(limit as usize + 1)
+            .map(|(_, update)| update)
+            .collect()
+    });
+    for shard in list_shards() {
+        let (page,): (Vec<CrisisUpdate>,) =
+            ic_cdk::call(shard.canister_id, "shard_list_crisis_updates", (start_after, limit + 1))
+                .await
+                .map_err(|e| call_error(shard.canister_id, "shard_list_crisis_updates", e))?;
+        items.extend(page);
+    }
+    let (items, has_more) = merge_page(items, limit);
+    // Hidden and expired updates still move the cursor, so a page of them doesn't end the listing
+    let last_id = items.last().map(|u| u.id);
+    let now = ic_cdk::api::time();
+    let unexpired = items.into_iter().filter(|update| !update.is_expired(now)).collect();
+    let (items, truncated) = truncate_to_fit(crate::present_updates(unexpired));
+    let next_cursor = next_cursor(last_id, has_more, items.last().map(|u| u.id), truncated);
+    // Every sharded update is indexed here, so the total needs no shard calls
+    let total_count = CRISIS_STORAGE.with(|s| s.borrow().len()) + SHARD_INDEX.with(|index| index.borrow().len());
+    Ok(PagedResult {
+        items,
+        total_count,
+        next_cursor,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(ids: &[u64]) -> Vec<CrisisUpdate> {
+        ids.iter()
+            .map(|id| CrisisUpdate {
+                id: *id,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn ids(items: &[CrisisUpdate]) -> Vec<u64> {
+        items.iter().map(|update| update.id).collect()
+    }
+
+    #[test]
+    fn pages_merge_sources_in_id_order() {
+        // Local rows, then two shards, one of which also returned a row held locally
+        let (page, has_more) = merge_page(rows(&[2, 5, 9, 4, 5, 7, 1]), 4);
+        assert_eq!(ids(&page), vec![1, 2, 4, 5]);
+        assert!(has_more);
+        let (page, has_more) = merge_page(rows(&[3, 1, 3]), 2);
+        assert_eq!(ids(&page), vec![1, 3]);
+        assert!(!has_more);
+    }
+
+    #[test]
+    fn cursors_resume_after_the_right_row() {
+        // More rows follow, and the last two on the page were hidden from the caller
+        assert_eq!(next_cursor(Some(9), true, Some(5), false), Some(9));
+        // The reply was cut short to fit, so the next page starts after what was returned
+        assert_eq!(next_cursor(Some(9), true, Some(3), true), Some(3));
+        assert_eq!(next_cursor(Some(9), false, Some(3), true), Some(3));
+        // The last page
+        assert_eq!(next_cursor(Some(9), false, Some(9), false), None);
+        assert_eq!(next_cursor(None, false, None, false), None);
+    }
+}
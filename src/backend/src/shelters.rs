@@ -0,0 +1,329 @@
+use crate::access::{has_role, require_role, Role};
+use crate::access_mode::admit_read;
+use crate::geo::Coordinates;
+use crate::ids::IdCounter;
+use crate::maintenance::admit_update;
+use crate::normalize::normalize_line;
+use crate::response::{fit, QueryResult};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_AMENITIES: usize = 20;
+const MAX_NEARBY_SHELTERS: usize = 20;
+const MAX_NAME_CHARS: usize = 200;
+const MAX_LOCATION_CHARS: usize = 200;
+const MAX_CONTACT_CHARS: usize = 200;
+const MAX_AMENITY_CHARS: usize = 50;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub(crate) enum ShelterStatus {
+    #[default]
+    Open,
+    Closed,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Shelter {
+    id: u64,
+    name: String,
+    location: String,
+    coordinates: Coordinates,
+    total_capacity: u64,
+    current_occupancy: u64,
+    amenities: Vec<String>,
+    contact: String,
+    status: ShelterStatus,
+    managed_by: Principal,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+// Implementing Storable and BoundedStorable traits for Shelter
+impl Storable for Shelter {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Shelter {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Shelter {
+    fn available_capacity(&self) -> u64 {
+        self.total_capacity.saturating_sub(self.current_occupancy)
+    }
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct ShelterPayload {
+    name: String,
+    location: String,
+    coordinates: Coordinates,
+    total_capacity: u64,
+    amenities: Vec<String>,
+    contact: String,
+    status: ShelterStatus,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct NearbyShelter {
+    shelter: Shelter,
+    distance_km: f64,
+    available_capacity: u64,
+}
+
+thread_local! {
+    static SHELTER_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(6, "shelters"));
+
+    static SHELTER_STORAGE: RefCell<StableBTreeMap<u64, Shelter, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7)))
+    ));
+}
+
+// Shelter openings for the calendar feed; closed shelters show as cancelled
+pub(crate) fn shelter_calendar_events() -> Vec<crate::calendar::CalendarEvent> {
+    SHELTER_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, shelter)| crate::calendar::CalendarEvent {
+                uid: format!("shelter-{}", shelter.id),
+                start: shelter.created_at,
+                stamp: shelter.updated_at.unwrap_or(shelter.created_at),
+                summary: format!("Shelter open: {}", shelter.name),
+                location: shelter.location.clone(),
+                description: format!(
+                    "{} of {} places available. Contact: {}",
+                    shelter.available_capacity(),
+                    shelter.total_capacity,
+                    shelter.contact
+                ),
+                coordinates: Some(shelter.coordinates),
+                cancelled: shelter.status == ShelterStatus::Closed,
+            })
+            .collect()
+    })
+}
+
+// GeoJSON point features for every shelter whose location contains `location`
+pub(crate) fn shelter_geojson_features(location: Option<&str>) -> Vec<serde_json::Value> {
+    let location = location.map(normalize_line);
+    SHELTER_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, shelter)| location.as_ref().is_none_or(|l| normalize_line(&shelter.location).contains(l)))
+            .map(|(_, shelter)| {
+                serde_json::json!({
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "Point",
+                        "coordinates": [shelter.coordinates.longitude, shelter.coordinates.latitude],
+                    },
+                    "properties": {
+                        "id": shelter.id,
+                        "name": shelter.name,
+                        "location": shelter.location,
+                        "available_capacity": shelter.available_capacity(),
+                        "open": shelter.status == ShelterStatus::Open,
+                    },
+                })
+            })
+            .collect()
+    })
+}
+
+// Helper method to perform insert for Shelter
+fn do_insert_shelter(shelter: &Shelter) -> Result<(), Error> {
+    crate::ensure_storable(shelter, "shelter")?;
+    SHELTER_STORAGE.with(|service| service.borrow_mut().insert(shelter.id, shelter.clone()));
+    Ok(())
+}
+
+// Normalizes the text fields in place before checking them
+fn validate_shelter_payload(payload: &mut ShelterPayload) -> Result<(), Error> {
+    payload.name = normalize_line(&payload.name);
+    payload.location = normalize_line(&payload.location);
+    payload.contact = normalize_line(&payload.contact);
+    for amenity in payload.amenities.iter_mut() {
+        *amenity = normalize_line(amenity);
+    }
+    if payload.name.is_empty() {
+        return Err(Error::validation("shelter name must not be empty"));
+    }
+    if payload.total_capacity == 0 {
+        return Err(Error::validation("total capacity must be greater than zero"));
+    }
+    if payload.amenities.len() > MAX_AMENITIES {
+        return Err(Error::validation(format!(
+            "a shelter can list at most {} amenities",
+            MAX_AMENITIES
+        )));
+    }
+    for (field, value, max) in [
+        ("name", &payload.name, MAX_NAME_CHARS),
+        ("location", &payload.location, MAX_LOCATION_CHARS),
+        ("contact", &payload.contact, MAX_CONTACT_CHARS),
+    ]
+    .into_iter()
+    .chain(payload.amenities.iter().map(|amenity| ("amenity", amenity, MAX_AMENITY_CHARS)))
+    {
+        if value.chars().count() > max {
+            return Err(Error::validation(format!("{} must be at most {} characters", field, max))
+                .with_detail("field", field));
+        }
+    }
+    payload.coordinates.validate()
+}
+
+pub(crate) fn shelter_exists(id: u64) -> bool {
+    SHELTER_STORAGE.with(|s| s.borrow().contains_key(&id))
+}
+
+// Loads a shelter and checks that the caller is the managing organization or an admin
+fn get_managed_shelter(id: u64) -> Result<Shelter, Error> {
+    let shelter = get_shelter(id)?;
+    let caller = require_role(Role::VerifiedOrg)?;
+    if shelter.managed_by != caller && !has_role(&caller, Role::Admin) {
+        return Err(Error::unauthorized(format!(
+            "caller does not manage the shelter with id={}",
+            id
+        )));
+    }
+    Ok(shelter)
+}
+
+// 2.11.1 get_shelter Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_shelter(id: u64) -> Result<Shelter, Error> {
+    match SHELTER_STORAGE.with(|s| s.borrow().get(&id)) {
+        Some(shelter) => Ok(shelter),
+        None => Err(Error::not_found(format!("a shelter with id={} not found", id))),
+    }
+}
+
+// 2.11.2 add_shelter Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn add_shelter(mut payload: ShelterPayload) -> Result<Shelter, Error> {
+    crate::call_stats::count_call("add_shelter");
+    let caller = require_role(Role::VerifiedOrg)?;
+    validate_shelter_payload(&mut payload)?;
+    let id = crate::ids::next_id(&SHELTER_ID_COUNTER)?;
+    let shelter = Shelter {
+        id,
+        name: payload.name,
+        location: payload.location,
+        coordinates: payload.coordinates,
+        total_capacity: payload.total_capacity,
+        current_occupancy: 0,
+        amenities: payload.amenities,
+        contact: payload.contact,
+        status: payload.status,
+        managed_by: caller,
+        created_at: time(),
+        updated_at: None,
+    };
+    do_insert_shelter(&shelter)?;
+    Ok(shelter)
+}
+
+// 2.11.3 update_shelter Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn update_shelter(id: u64, mut payload: ShelterPayload) -> Result<Shelter, Error> {
+    crate::call_stats::count_call("update_shelter");
+    validate_shelter_payload(&mut payload)?;
+    let mut shelter = get_managed_shelter(id)?;
+    if payload.total_capacity < shelter.current_occupancy {
+        return Err(Error::validation(format!(
+            "total capacity cannot be lower than the current occupancy of {}",
+            shelter.current_occupancy
+        )));
+    }
+    shelter.name = payload.name;
+    shelter.location = payload.location;
+    shelter.coordinates = payload.coordinates;
+    shelter.total_capacity = payload.total_capacity;
+    shelter.amenities = payload.amenities;
+    shelter.contact = payload.contact;
+    shelter.status = payload.status;
+    shelter.updated_at = Some(time());
+    do_insert_shelter(&shelter)?;
+    Ok(shelter)
+}
+
+// 2.11.4 update_shelter_occupancy Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn update_shelter_occupancy(id: u64, current_occupancy: u64) -> Result<Shelter, Error> {
+    crate::call_stats::count_call("update_shelter_occupancy");
+    let mut shelter = get_managed_shelter(id)?;
+    if current_occupancy > shelter.total_capacity {
+        return Err(Error::validation(format!(
+            "occupancy of {} exceeds the total capacity of {}",
+            current_occupancy, shelter.total_capacity
+        )));
+    }
+    shelter.current_occupancy = current_occupancy;
+    shelter.updated_at = Some(time());
+    do_insert_shelter(&shelter)?;
+    Ok(shelter)
+}
+
+// 2.11.5 delete_shelter Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn delete_shelter(id: u64) -> Result<Shelter, Error> {
+    crate::call_stats::count_call("delete_shelter");
+    get_managed_shelter(id)?;
+    match SHELTER_STORAGE.with(|service| service.borrow_mut().remove(&id)) {
+        Some(shelter) => Ok(shelter),
+        None => Err(Error::not_found(format!(
+            "couldn't delete a shelter with id={}. shelter not found.",
+            id
+        ))),
+    }
+}
+
+// 2.11.6 list_all_shelters Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn list_all_shelters() -> Result<QueryResult<Shelter>, Error> {
+    fit(SHELTER_STORAGE.with(|service| service.borrow().iter().map(|(_, shelter)| shelter).collect()))
+}
+
+// 2.11.7 find_shelters_near Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn find_shelters_near(
+    latitude: f64,
+    longitude: f64,
+    needed_capacity: u64,
+) -> Result<QueryResult<NearbyShelter>, Error> {
+    let origin = Coordinates { latitude, longitude };
+    origin.validate()?;
+    let mut shelters: Vec<NearbyShelter> = SHELTER_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, shelter)| {
+                shelter.status == ShelterStatus::Open && shelter.available_capacity() >= needed_capacity.max(1)
+            })
+            .map(|(_, shelter)| NearbyShelter {
+                distance_km: origin.distance_km(&shelter.coordinates),
+                available_capacity: shelter.available_capacity(),
+                shelter,
+            })
+            .collect()
+    });
+    shelters.sort_by(|a, b| a.distance_km.total_cmp(&b.distance_km));
+    let total_count = shelters.len() as u64;
+    shelters.truncate(MAX_NEARBY_SHELTERS);
+    Ok(QueryResult::limited(shelters, total_count))
+}
@@ -0,0 +1,223 @@
+// Situation reports: a periodic digest of what changed since the previous
+// report, so leadership does not have to compile one by hand. The heartbeat
+// generates a report once the interval has passed since the last one ended,
+// and coordinators can generate one on demand. Each report is kept and sent as
+// a notification to every coordinator and admin. Counts cover locally stored
+// updates; those moved to shards or the archive are only counted if they were
+// resolved or escalated here.
+use crate::access::{holders_of, require_role, Role};
+use crate::access_mode::admit_read;
+use crate::areas::{location_key, LocationKey};
+use crate::ids::IdCounter;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::maintenance::admit_update;
+use crate::notifications::{try_notify, NotificationKind};
+use crate::response::{fit, QueryResult};
+use crate::settings::{get_settings, update_settings, Settings};
+use crate::{CrisisPriority, Error, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::collections::BTreeMap;
+use std::{borrow::Cow, cell::RefCell};
+
+pub(crate) const SITREP_CHECK_INTERVAL_SECS: u64 = 10 * 60;
+const DEFAULT_INTERVAL_SECS: u64 = 24 * 60 * 60;
+const MIN_INTERVAL_SECS: u64 = 60 * 60;
+const MAX_INTERVAL_SECS: u64 = 7 * 24 * 60 * 60;
+const MAX_SITREPS: u64 = 1000;
+const TOP_LOCATIONS: usize = 5;
+const MAX_LOCATION_CHARS: usize = 200;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct LocationCount {
+    location: String,
+    new_updates: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SitRep {
+    id: u64,
+    // The report covers [period_start, period_end)
+    period_start: u64,
+    period_end: u64,
+    new_updates: u64,
+    new_critical: u64,
+    escalations: u64,
+    resolved: u64,
+    // Active updates when the report was generated
+    active: u64,
+    // Locations with the most new updates, most first
+    top_locations: Vec<LocationCount>,
+}
+
+// Implementing Storable and BoundedStorable traits for SitRep
+impl Storable for SitRep {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for SitRep {
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static SITREP_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(92, "situation reports"));
+
+    static SITREP_STORAGE: RefCell<StableBTreeMap<u64, SitRep, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(93)))
+    ));
+}
+
+fn interval_nanos() -> u64 {
+    get_settings()
+        .sitrep_interval_secs
+        .unwrap_or(DEFAULT_INTERVAL_SECS)
+        .saturating_mul(NANOS_PER_SECOND)
+}
+
+fn last_period_end() -> Option<u64> {
+    SITREP_STORAGE.with(|s| s.borrow().iter().last().map(|(_, report)| report.period_end))
+}
+
+// Whether a scheduled report is due; the first one is generated right away
+pub(crate) fn sitrep_due(now: u64) -> bool {
+    last_period_end().is_none_or(|end| now.saturating_sub(end) >= interval_nanos())
+}
+
+fn top_locations(counts: BTreeMap<LocationKey, (String, u64)>) -> Vec<LocationCount> {
+    let mut top: Vec<(String, u64)> = counts.into_values().collect();
+    top.sort_by(|(a_name, a_count), (b_name, b_count)| b_count.cmp(a_count).then(a_name.cmp(b_name)));
+    top.truncate(TOP_LOCATIONS);
+    top.into_iter()
+        .map(|(location, new_updates)| LocationCount {
+            location: location.chars().take(MAX_LOCATION_CHARS).collect(),
+            new_updates,
+        })
+        .collect()
+}
+
+// Compiles and stores the report for the time since the previous one, then
+// notifies coordinators and admins
+pub(crate) fn generate_sitrep(now: u64) -> Result<SitRep, Error> {
+    let period_start = last_period_end().unwrap_or_else(|| now.saturating_sub(interval_nanos()));
+    let period = period_start..now;
+    let mut new_updates = 0;
+    let mut new_critical = 0;
+    let mut active = 0;
+    let mut locations: BTreeMap<LocationKey, (String, u64)> = BTreeMap::new();
+    CRISIS_STORAGE.with(|s| {
+        for (_, update) in s.borrow().iter() {
+            if update.status.is_open() {
+                active += 1;
+            }
+            if !period.contains(&update.created_at) {
+                continue;
+            }
+            new_updates += 1;
+            if update.priority == CrisisPriority::Critical {
+                new_critical += 1;
+            }
+            if !update.location.trim().is_empty() {
+                let key = location_key(&update.location);
+                locations.entry(key).or_insert_with(|| (update.location.clone(), 0)).1 += 1;
+            }
+        }
+    });
+    let report = SitRep {
+        id: crate::ids::next_id(&SITREP_ID_COUNTER)?,
+        period_start,
+        period_end: now,
+        new_updates,
+        new_critical,
+        escalations: crate::escalation::escalations_between(period_start, now),
+        resolved: crate::sla::resolved_between(period_start, now),
+        active,
+        top_locations: top_locations(locations),
+    };
+    crate::ensure_storable(&report, "situation report")?;
+    SITREP_STORAGE.with(|s| {
+        let mut s = s.borrow_mut();
+        s.insert(report.id, report.clone());
+        while s.len() > MAX_SITREPS {
+            match s.iter().next() {
+                Some((oldest, _)) => s.remove(&oldest),
+                None => break,
+            };
+        }
+    });
+    let mut message = format!(
+        "Situation report #{}: {} new updates ({} critical), {} escalations, {} resolved, {} active",
+        report.id, report.new_updates, report.new_critical, report.escalations, report.resolved, report.active
+    );
+    if !report.top_locations.is_empty() {
+        let places: Vec<String> = report
+            .top_locations
+            .iter()
+            .map(|place| format!("{} ({})", place.location, place.new_updates))
+            .collect();
+        message.push_str(&format!("; most reports from {}", places.join(", ")));
+    }
+    for recipient in holders_of(&[Role::Coordinator]) {
+        try_notify(recipient, NotificationKind::SitRep, message.clone(), Some(report.id));
+    }
+    Ok(report)
+}
+
+// 2.69.1 set_sitrep_interval Function:
+// None restores the daily default.
+#[ic_cdk::update(guard = "admit_update")]
+fn set_sitrep_interval(interval_secs: Option<u64>) -> Result<Settings, Error> {
+    crate::call_stats::count_call("set_sitrep_interval");
+    require_role(Role::Admin)?;
+    if interval_secs.is_some_and(|secs| !(MIN_INTERVAL_SECS..=MAX_INTERVAL_SECS).contains(&secs)) {
+        return Err(Error::validation(format!(
+            "situation report intervals must be between {} and {} seconds",
+            MIN_INTERVAL_SECS, MAX_INTERVAL_SECS
+        )));
+    }
+    update_settings(|s| s.sitrep_interval_secs = interval_secs)
+}
+
+// 2.69.2 generate_sitrep_now Function:
+// Reports on the time since the previous report without waiting for the schedule.
+#[ic_cdk::update(guard = "admit_update")]
+fn generate_sitrep_now() -> Result<SitRep, Error> {
+    crate::call_stats::count_call("generate_sitrep_now");
+    require_role(Role::Coordinator)?;
+    generate_sitrep(time())
+}
+
+// 2.69.3 list_sitreps Function:
+// Reports whose period overlaps [from, to), newest first.
+#[ic_cdk::query(guard = "admit_read")]
+fn list_sitreps(from: Option<u64>, to: Option<u64>) -> Result<QueryResult<SitRep>, Error> {
+    require_role(Role::Coordinator)?;
+    let mut reports: Vec<SitRep> = SITREP_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, report)| report)
+            .filter(|report| from.is_none_or(|from| report.period_end > from))
+            .filter(|report| to.is_none_or(|to| report.period_start < to))
+            .collect()
+    });
+    reports.reverse();
+    fit(reports)
+}
+
+// 2.69.4 get_sitrep Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_sitrep(id: u64) -> Result<SitRep, Error> {
+    require_role(Role::Coordinator)?;
+    SITREP_STORAGE
+        .with(|s| s.borrow().get(&id))
+        .ok_or_else(|| Error::not_found(format!("situation report {} not found", id)))
+}
@@ -0,0 +1,326 @@
+// Response and resolution SLAs. Targets are configured per priority tier, which
+// serves as the update's severity; each update gets a record of when it was
+// opened, first responded to (assigned) and resolved, and its SLA state is
+// derived from that record at query time.
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::maintenance::admit_update;
+use crate::response::{fit, QueryResult};
+use crate::{CrisisPriority, CrisisStatus, CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::collections::BTreeMap;
+use std::{borrow::Cow, cell::RefCell};
+
+// Share of a target that may elapse before an unmet SLA counts as at risk
+const AT_RISK_PERCENT: u64 = 75;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub(crate) enum SlaState {
+    OnTrack,
+    AtRisk,
+    Breached,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct SlaTarget {
+    response_secs: u64,
+    resolution_secs: u64,
+}
+
+// Implementing Storable and BoundedStorable traits for SlaTarget
+impl Storable for SlaTarget {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for SlaTarget {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+fn default_target(priority: CrisisPriority) -> SlaTarget {
+    const HOUR: u64 = 60 * 60;
+    let (response_secs, resolution_secs) = match priority {
+        CrisisPriority::Critical => (15 * 60, 12 * HOUR),
+        CrisisPriority::High => (HOUR, 24 * HOUR),
+        CrisisPriority::Normal => (4 * HOUR, 72 * HOUR),
+        CrisisPriority::Low => (24 * HOUR, 7 * 24 * HOUR),
+    };
+    SlaTarget {
+        response_secs,
+        resolution_secs,
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct SlaRecord {
+    priority: CrisisPriority,
+    opened_at: u64,
+    first_response_at: Option<u64>,
+    resolved_at: Option<u64>,
+}
+
+// Implementing Storable and BoundedStorable traits for SlaRecord
+impl Storable for SlaRecord {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for SlaRecord {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct UpdateSla {
+    pub(crate) crisis_update_id: u64,
+    pub(crate) priority: CrisisPriority,
+    pub(crate) opened_at: u64,
+    pub(crate) first_response_at: Option<u64>,
+    pub(crate) resolved_at: Option<u64>,
+    pub(crate) response_state: SlaState,
+    pub(crate) resolution_state: SlaState,
+    // The worse of the two states
+    pub(crate) state: SlaState,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct TeamSlaCompliance {
+    team_id: u64,
+    assigned_updates: u64,
+    breached_updates: u64,
+    compliance_percent: u64,
+}
+
+thread_local! {
+    static SLA_TARGETS: RefCell<StableBTreeMap<u8, SlaTarget, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(43)))
+    ));
+
+    static SLA_RECORDS: RefCell<StableBTreeMap<u64, SlaRecord, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(44)))
+    ));
+}
+
+fn target_for(priority: CrisisPriority) -> SlaTarget {
+    SLA_TARGETS
+        .with(|t| t.borrow().get(&(priority as u8)))
+        .unwrap_or_else(|| default_target(priority))
+}
+
+fn modify_record(id: u64, change: impl FnOnce(&mut SlaRecord)) {
+    SLA_RECORDS.with(|records| {
+        let mut records = records.borrow_mut();
+        if let Some(mut record) = records.get(&id) {
+            change(&mut record);
+            records.insert(id, record);
+        }
+    });
+}
+
+// Starts the SLA clock for a new update
+pub(crate) fn open_sla(update: &CrisisUpdate) {
+    let record = SlaRecord {
+        priority: update.priority,
+        opened_at: update.created_at,
+        first_response_at: None,
+        resolved_at: None,
+    };
+    SLA_RECORDS.with(|records| records.borrow_mut().insert(update.id, record));
+}
+
+// Keeps the record in step with the update's priority and status
+pub(crate) fn sync_sla(update: &CrisisUpdate) {
+    let now = time();
+    modify_record(update.id, |record| {
+        record.priority = update.priority;
+        match update.status {
+            CrisisStatus::Active | CrisisStatus::Unconfirmed => record.resolved_at = None,
+            CrisisStatus::Resolved | CrisisStatus::Archived => {
+                record.resolved_at.get_or_insert(now);
+            }
+        }
+    });
+}
+
+// The first assignment counts as the response
+pub(crate) fn record_first_response(id: u64, at: u64) {
+    modify_record(id, |record| {
+        record.first_response_at.get_or_insert(at);
+    });
+}
+
+// Drops SLA records of updates that no longer exist; returns how many were removed
+pub(crate) fn remove_orphaned_slas(is_live: &dyn Fn(u64) -> bool) -> u64 {
+    let ids: Vec<u64> = SLA_RECORDS.with(|records| {
+        records
+            .borrow()
+            .iter()
+            .map(|(id, _)| id)
+            .filter(|id| !is_live(*id))
+            .collect()
+    });
+    SLA_RECORDS.with(|records| {
+        let mut records = records.borrow_mut();
+        for id in &ids {
+            records.remove(id);
+        }
+    });
+    ids.len() as u64
+}
+
+// Updates resolved or archived within [from, to)
+pub(crate) fn resolved_between(from: u64, to: u64) -> u64 {
+    SLA_RECORDS.with(|records| {
+        records
+            .borrow()
+            .iter()
+            .filter(|(_, record)| record.resolved_at.is_some_and(|at| (from..to).contains(&at)))
+            .count() as u64
+    })
+}
+
+// Opens a record for an update that lacks one, then syncs it; used when updates are restored
+pub(crate) fn ensure_sla(update: &CrisisUpdate) {
+    if !SLA_RECORDS.with(|records| records.borrow().contains_key(&update.id)) {
+        open_sla(update);
+    }
+    sync_sla(update);
+}
+
+pub(crate) fn remove_sla(id: u64) {
+    SLA_RECORDS.with(|records| records.borrow_mut().remove(&id));
+}
+
+fn state_for(opened_at: u64, met_at: Option<u64>, target_secs: u64, now: u64) -> SlaState {
+    let budget = target_secs.saturating_mul(NANOS_PER_SECOND);
+    let elapsed = met_at.unwrap_or(now).saturating_sub(opened_at);
+    if elapsed > budget {
+        SlaState::Breached
+    } else if met_at.is_none() && elapsed.saturating_mul(100) > budget.saturating_mul(AT_RISK_PERCENT) {
+        SlaState::AtRisk
+    } else {
+        SlaState::OnTrack
+    }
+}
+
+fn evaluate(id: u64, record: &SlaRecord, now: u64) -> UpdateSla {
+    let target = target_for(record.priority);
+    let response_state = state_for(record.opened_at, record.first_response_at, target.response_secs, now);
+    let resolution_state = state_for(record.opened_at, record.resolved_at, target.resolution_secs, now);
+    UpdateSla {
+        crisis_update_id: id,
+        priority: record.priority,
+        opened_at: record.opened_at,
+        first_response_at: record.first_response_at,
+        resolved_at: record.resolved_at,
+        response_state,
+        resolution_state,
+        state: response_state.max(resolution_state),
+    }
+}
+
+pub(crate) fn sla_of(id: u64, now: u64) -> Option<UpdateSla> {
+    SLA_RECORDS
+        .with(|records| records.borrow().get(&id))
+        .map(|record| evaluate(id, &record, now))
+}
+
+fn evaluate_all(now: u64) -> Vec<UpdateSla> {
+    SLA_RECORDS.with(|records| {
+        records
+            .borrow()
+            .iter()
+            .map(|(id, record)| evaluate(id, &record, now))
+            .collect()
+    })
+}
+
+// 2.30.1 set_sla_target Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn set_sla_target(priority: CrisisPriority, response_secs: u64, resolution_secs: u64) -> Result<SlaTarget, Error> {
+    crate::call_stats::count_call("set_sla_target");
+    require_role(Role::Admin)?;
+    if response_secs == 0 || resolution_secs < response_secs {
+        return Err(Error::validation(
+            "the response target must be positive and no longer than the resolution target",
+        ));
+    }
+    let target = SlaTarget {
+        response_secs,
+        resolution_secs,
+    };
+    SLA_TARGETS.with(|t| t.borrow_mut().insert(priority as u8, target));
+    Ok(target)
+}
+
+// 2.30.2 get_sla_targets Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_sla_targets() -> Vec<(CrisisPriority, SlaTarget)> {
+    CrisisPriority::ALL
+        .into_iter()
+        .map(|priority| (priority, target_for(priority)))
+        .collect()
+}
+
+// 2.30.3 get_sla_status Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_sla_status(crisis_update_id: u64) -> Result<UpdateSla, Error> {
+    sla_of(crisis_update_id, time()).ok_or_else(|| {
+        Error::not_found(format!(
+            "no SLA record for a crisis update with id={}",
+            crisis_update_id
+        ))
+    })
+}
+
+// 2.30.4 list_sla_breached_updates Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn list_sla_breached_updates() -> Result<QueryResult<UpdateSla>, Error> {
+    fit(evaluate_all(time())
+        .into_iter()
+        .filter(|sla| sla.state == SlaState::Breached)
+        .collect())
+}
+
+// 2.30.5 get_team_sla_compliance Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_team_sla_compliance() -> Result<QueryResult<TeamSlaCompliance>, Error> {
+    let now = time();
+    let mut stats: BTreeMap<u64, (u64, u64)> = BTreeMap::new();
+    for (team_id, update_id) in crate::assignments::team_assignments() {
+        let Some(record) = SLA_RECORDS.with(|records| records.borrow().get(&update_id)) else {
+            continue;
+        };
+        let entry = stats.entry(team_id).or_default();
+        entry.0 += 1;
+        if evaluate(update_id, &record, now).state == SlaState::Breached {
+            entry.1 += 1;
+        }
+    }
+    fit(stats
+        .into_iter()
+        .map(|(team_id, (assigned_updates, breached_updates))| TeamSlaCompliance {
+            team_id,
+            assigned_updates,
+            breached_updates,
+            compliance_percent: (assigned_updates - breached_updates) * 100 / assigned_updates,
+        })
+        .collect())
+}
@@ -0,0 +1,232 @@
+// Point-in-time snapshots of the locally stored crisis updates, for recovering
+// from a bad bulk operation. A snapshot is taken in a single message, so it is
+// consistent. Derived indexes (priority queue, locations, SLA records) are not
+// copied; restoring rebuilds them from the restored updates. Updates held on
+// shard canisters are outside the snapshot.
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::changes::{record_change, record_deletion, ChangeKind};
+use crate::ids::IdCounter;
+use crate::maintenance::admit_update;
+use crate::normalize::normalize_line;
+use crate::response::{fit, QueryResult};
+use crate::{CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::collections::BTreeSet;
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_SNAPSHOTS: usize = 10;
+const MAX_LABEL_CHARS: usize = 100;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SnapshotInfo {
+    id: u64,
+    label: String,
+    created_by: Principal,
+    created_at: u64,
+    crisis_update_count: u64,
+}
+
+// Implementing Storable and BoundedStorable traits for SnapshotInfo
+impl Storable for SnapshotInfo {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for SnapshotInfo {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct RestoreReport {
+    snapshot_id: u64,
+    restored: u64,
+    // Updates created after the snapshot, removed by the restore
+    removed: u64,
+}
+
+thread_local! {
+    static SNAPSHOT_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(62, "snapshots"));
+
+    static SNAPSHOTS: RefCell<StableBTreeMap<u64, SnapshotInfo, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(63)))
+    ));
+
+    // (snapshot id, crisis update id) -> the update as it was
+    pub(crate) static SNAPSHOT_UPDATES: RefCell<StableBTreeMap<(u64, u64), CrisisUpdate, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(132)))
+    ));
+}
+
+fn snapshot_updates(snapshot_id: u64) -> Vec<CrisisUpdate> {
+    SNAPSHOT_UPDATES.with(|s| {
+        s.borrow()
+            .range((snapshot_id, 0)..=(snapshot_id, u64::MAX))
+            .map(|(_, update)| update)
+            .collect()
+    })
+}
+
+pub(crate) fn find_snapshot(id: u64) -> Result<SnapshotInfo, Error> {
+    SNAPSHOTS
+        .with(|s| s.borrow().get(&id))
+        .ok_or_else(|| Error::not_found(format!("couldn't find a snapshot with id={}", id)).with_detail("id", id))
+}
+
+// Removes the local copy of an update and everything derived from it, without
+// recording a deletion; also used when an update moves to the archive
+pub(crate) fn remove_local_copy(id: u64) {
+    CRISIS_STORAGE.with(|s| s.borrow_mut().remove(&id));
+    crate::cache::forget(id);
+    crate::pins::unpin_deleted(id);
+    crate::links::remove_links_for_update(id);
+    crate::areas::unindex_update(id);
+    crate::field_search::unindex_update(id);
+    crate::priority::remove_from_priority_queue(id);
+    crate::sla::remove_sla(id);
+}
+
+// Removes the local update and everything derived from it, as a deletion would
+pub(crate) fn remove_local_update(id: u64, admin: Principal) -> Result<(), Error> {
+    remove_local_copy(id);
+    record_deletion(id, admin)?;
+    Ok(())
+}
+
+fn restore_local_update(update: &CrisisUpdate, existed: bool) -> Result<(), Error> {
+    crate::do_insert_crisis_update(update)?;
+    crate::areas::unindex_update(update.id);
+    crate::areas::index_update(update);
+    crate::field_search::index_update(update);
+    crate::priority::sync_priority_queue(update);
+    crate::sla::ensure_sla(update);
+    let kind = if existed {
+        ChangeKind::Updated
+    } else {
+        ChangeKind::Created
+    };
+    record_change(update.id, kind)?;
+    Ok(())
+}
+
+// 2.51.1 create_snapshot Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn create_snapshot(label: String) -> Result<SnapshotInfo, Error> {
+    crate::call_stats::count_call("create_snapshot");
+    let admin = require_role(Role::Admin)?;
+    let label = normalize_line(&label);
+    if label.is_empty() || label.chars().count() > MAX_LABEL_CHARS {
+        return Err(Error::validation(format!(
+            "snapshot labels must be between 1 and {} characters",
+            MAX_LABEL_CHARS
+        )));
+    }
+    if SNAPSHOTS.with(|s| s.borrow().len()) as usize >= MAX_SNAPSHOTS {
+        return Err(Error::conflict(format!(
+            "at most {} snapshots can be kept; delete one first",
+            MAX_SNAPSHOTS
+        )));
+    }
+    let id = crate::ids::next_id(&SNAPSHOT_ID_COUNTER)?;
+    let mut count = 0;
+    CRISIS_STORAGE.with(|storage| {
+        SNAPSHOT_UPDATES.with(|snapshot| {
+            let mut snapshot = snapshot.borrow_mut();
+            for (update_id, update) in storage.borrow().iter() {
+                snapshot.insert((id, update_id), update);
+                count += 1;
+            }
+        })
+    });
+    let info = SnapshotInfo {
+        id,
+        label,
+        created_by: admin,
+        created_at: time(),
+        crisis_update_count: count,
+    };
+    SNAPSHOTS.with(|s| s.borrow_mut().insert(id, info.clone()));
+    Ok(info)
+}
+
+// 2.51.2 list_snapshots Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn list_snapshots() -> Result<QueryResult<SnapshotInfo>, Error> {
+    require_role(Role::Admin)?;
+    fit(SNAPSHOTS.with(|s| s.borrow().iter().map(|(_, info)| info).collect()))
+}
+
+// 2.51.3 delete_snapshot Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn delete_snapshot(id: u64) -> Result<SnapshotInfo, Error> {
+    crate::call_stats::count_call("delete_snapshot");
+    require_role(Role::Admin)?;
+    let info = find_snapshot(id)?;
+    let keys: Vec<(u64, u64)> =
+        SNAPSHOT_UPDATES.with(|s| s.borrow().range((id, 0)..=(id, u64::MAX)).map(|(key, _)| key).collect());
+    SNAPSHOT_UPDATES.with(|s| {
+        let mut s = s.borrow_mut();
+        for key in keys {
+            s.remove(&key);
+        }
+    });
+    SNAPSHOTS.with(|s| s.borrow_mut().remove(&id));
+    Ok(info)
+}
+
+// The snapshot's updates, once everything that could refuse the restore has been
+// checked. Updates moved to the archive or a shard since the snapshot are left
+// where they are, so no update is stored twice.
+fn restorable_updates(snapshot_id: u64) -> Result<Vec<CrisisUpdate>, Error> {
+    find_snapshot(snapshot_id)?;
+    let updates: Vec<CrisisUpdate> = snapshot_updates(snapshot_id)
+        .into_iter()
+        .filter(|update| {
+            crate::archive::archived_location(update.id).is_none()
+                && crate::sharding::sharded_location(update.id).is_none()
+        })
+        .collect();
+    for update in &updates {
+        crate::ensure_storable(update, "crisis update")?;
+    }
+    Ok(updates)
+}
+
+pub(crate) fn check_restore(snapshot_id: u64) -> Result<(), Error> {
+    restorable_updates(snapshot_id).map(|_| ())
+}
+
+// Replaces the local crisis updates with the snapshot's. Updates created since the
+// snapshot are deleted, and every change goes through the change feed. Runs once
+// a second admin approves a restore proposal; every check comes before the first
+// write, and approve_proposal traps if a write fails so nothing is half restored.
+pub(crate) fn restore_snapshot(snapshot_id: u64, admin: Principal) -> Result<RestoreReport, Error> {
+    let updates = restorable_updates(snapshot_id)?;
+    let kept: BTreeSet<u64> = updates.iter().map(|update| update.id).collect();
+    let current: BTreeSet<u64> = CRISIS_STORAGE.with(|s| s.borrow().iter().map(|(id, _)| id).collect());
+    let mut report = RestoreReport {
+        snapshot_id,
+        restored: 0,
+        removed: 0,
+    };
+    for id in current.difference(&kept) {
+        remove_local_update(*id, admin)?;
+        report.removed += 1;
+    }
+    for update in &updates {
+        restore_local_update(update, current.contains(&update.id))?;
+        report.restored += 1;
+    }
+    Ok(report)
+}
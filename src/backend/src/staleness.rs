@@ -0,0 +1,364 @@
+// Prompts for reports that may have gone stale. With a policy set, a heartbeat
+// job finds active updates nobody has edited or confirmed for
+// `prompt_after_hours` and asks the author and assignees whether the report is
+// still accurate. If nobody answers within `unconfirm_after_hours` of the
+// prompt, the update becomes Unconfirmed so dashboards can play it down. An
+// edit, a confirmation or `confirm_still_accurate` answers the prompt, and
+// brings an Unconfirmed update back to Active. Only updates stored in this
+// canister are checked.
+use crate::access::{has_role, Role};
+use crate::jobs::NANOS_PER_SECOND;
+use crate::logs::{log, LogLevel};
+use crate::maintenance::admit_update;
+use crate::notifications::{try_notify, NotificationKind};
+use crate::settings::{get_settings, update_settings};
+use crate::{CrisisStatus, CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use candid::{Decode, Encode};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+pub(crate) const STALENESS_CHECK_INTERVAL_SECS: u64 = 15 * 60;
+// Keeps a run within the heartbeat's instruction limit; the rest wait for the next run
+const MAX_ACTIONS_PER_RUN: usize = 200;
+const SECS_PER_HOUR: u64 = 60 * 60;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct StalenessPolicy {
+    // Active updates with no edit or confirmation for this long get a prompt
+    prompt_after_hours: u64,
+    // Prompted updates with no answer for this long after the prompt become Unconfirmed
+    unconfirm_after_hours: u64,
+}
+
+impl StalenessPolicy {
+    fn prompt_after(&self) -> u64 {
+        self.prompt_after_hours.saturating_mul(SECS_PER_HOUR * NANOS_PER_SECOND)
+    }
+
+    fn unconfirm_after(&self) -> u64 {
+        self.unconfirm_after_hours
+            .saturating_mul(SECS_PER_HOUR * NANOS_PER_SECOND)
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default, PartialEq, Debug)]
+struct FreshnessRecord {
+    // Last confirmation of the update, or answer that it is still accurate
+    confirmed_at: Option<u64>,
+    // Set while a prompt is waiting for an answer
+    prompted_at: Option<u64>,
+    // When the check marked the update Unconfirmed
+    unconfirmed_at: Option<u64>,
+}
+
+// Implementing Storable and BoundedStorable traits for FreshnessRecord
+impl Storable for FreshnessRecord {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for FreshnessRecord {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static FRESHNESS: RefCell<StableBTreeMap<u64, FreshnessRecord, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(127)))
+    ));
+}
+
+fn record_of(id: u64) -> FreshnessRecord {
+    FRESHNESS.with(|s| s.borrow().get(&id)).unwrap_or_default()
+}
+
+fn save(id: u64, record: FreshnessRecord) {
+    FRESHNESS.with(|s| s.borrow_mut().insert(id, record));
+}
+
+// Called when someone confirms the update
+pub(crate) fn record_confirmation(id: u64, now: u64) {
+    let mut record = record_of(id);
+    record.confirmed_at = Some(now);
+    save(id, record);
+}
+
+pub(crate) fn remove_orphaned_freshness_records(is_live: &dyn Fn(u64) -> bool) -> u64 {
+    let orphaned: Vec<u64> =
+        FRESHNESS.with(|s| s.borrow().iter().map(|(id, _)| id).filter(|id| !is_live(*id)).collect());
+    FRESHNESS.with(|s| {
+        let mut s = s.borrow_mut();
+        for id in &orphaned {
+            s.remove(id);
+        }
+    });
+    orphaned.len() as u64
+}
+
+fn last_activity(update: &CrisisUpdate, record: &FreshnessRecord) -> u64 {
+    update.last_modified().max(record.confirmed_at.unwrap_or_default())
+}
+
+fn prompt(update: &CrisisUpdate, policy: &StalenessPolicy) {
+    let mut recipients = crate::assignments::assigned_principals(update.id);
+    recipients.extend(update.author);
+    recipients.sort();
+    recipients.dedup();
+    let title: String = update.title.chars().take(200).collect();
+    let message = format!(
+        "Is crisis update #{} still accurate? Edit it or confirm it within {} hours, or it will be marked \
+         Unconfirmed: {}",
+        update.id, policy.unconfirm_after_hours, title
+    );
+    for recipient in recipients {
+        if crate::moderation::visible_to(update, &recipient) {
+            try_notify(
+                recipient,
+                NotificationKind::StaleReportCheck,
+                message.clone(),
+                Some(update.id),
+            );
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+enum Step {
+    Wait,
+    Prompt,
+    Unconfirm,
+    Reactivate,
+}
+
+// What the check does with an open update; `record` is brought up to date to match
+fn next_step(update: &CrisisUpdate, record: &mut FreshnessRecord, policy: &StalenessPolicy, now: u64) -> Step {
+    let activity = last_activity(update, record);
+    if update.status == CrisisStatus::Unconfirmed {
+        // Set by hand when unconfirmed_at is None; then only a confirmation counts
+        if activity > record.unconfirmed_at.unwrap_or(update.last_modified()) {
+            record.unconfirmed_at = None;
+            return Step::Reactivate;
+        }
+        return Step::Wait;
+    }
+    // Reactivated by hand, or answered since the prompt
+    let answered = record.prompted_at.is_some_and(|at| activity > at);
+    if record.unconfirmed_at.is_some() || answered {
+        record.unconfirmed_at = None;
+        record.prompted_at = None;
+    }
+    match record.prompted_at {
+        Some(at) if now.saturating_sub(at) >= policy.unconfirm_after() => {
+            record.prompted_at = None;
+            record.unconfirmed_at = Some(now);
+            Step::Unconfirm
+        }
+        None if now.saturating_sub(activity) >= policy.prompt_after() => {
+            record.prompted_at = Some(now);
+            Step::Prompt
+        }
+        _ => Step::Wait,
+    }
+}
+
+// Prompts for stale active updates, marks unanswered ones Unconfirmed and
+// reactivates Unconfirmed updates that have been edited or confirmed since
+pub(crate) fn check_stale_updates(now: u64) -> Result<(), Error> {
+    let Some(policy) = get_settings().staleness_policy else {
+        return Ok(());
+    };
+    let open: Vec<CrisisUpdate> = CRISIS_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, update)| update)
+            .filter(|update| update.status.is_open())
+            .collect()
+    });
+    let canister = ic_cdk::api::id();
+    let (mut prompted, mut unconfirmed, mut reactivated) = (0, 0, 0);
+    for update in open {
+        if prompted + unconfirmed + reactivated >= MAX_ACTIONS_PER_RUN {
+            break;
+        }
+        let mut record = record_of(update.id);
+        let before = record.clone();
+        let step = next_step(&update, &mut record, &policy, now);
+        if record != before {
+            save(update.id, record);
+        }
+        match step {
+            Step::Wait => {}
+            Step::Prompt => {
+                prompt(&update, &policy);
+                prompted += 1;
+            }
+            Step::Unconfirm => {
+                crate::store_status(update, CrisisStatus::Unconfirmed, canister)?;
+                unconfirmed += 1;
+            }
+            Step::Reactivate => {
+                crate::store_status(update, CrisisStatus::Active, canister)?;
+                reactivated += 1;
+            }
+        }
+    }
+    if prompted + unconfirmed + reactivated > 0 {
+        log(
+            LogLevel::Info,
+            Some("stale_report_check"),
+            &format!(
+                "prompted {} stale updates, marked {} unconfirmed, reactivated {}",
+                prompted, unconfirmed, reactivated
+            ),
+        );
+    }
+    Ok(())
+}
+
+// 2.89.1 set_staleness_policy Function:
+// None turns the prompts off; updates already Unconfirmed stay so.
+#[ic_cdk::update(guard = "admit_update")]
+fn set_staleness_policy(policy: Option<StalenessPolicy>) -> Result<Option<StalenessPolicy>, Error> {
+    crate::call_stats::count_call("set_staleness_policy");
+    crate::access::require_role(Role::Admin)?;
+    if let Some(policy) = policy.as_ref() {
+        if policy.prompt_after_hours == 0 || policy.unconfirm_after_hours == 0 {
+            return Err(Error::validation(
+                "prompt_after_hours and unconfirm_after_hours must be at least 1",
+            ));
+        }
+    }
+    update_settings(|s| s.staleness_policy = policy.clone())?;
+    Ok(policy)
+}
+
+// 2.89.2 confirm_still_accurate Function:
+// Answers a prompt; the author, an assignee, a coordinator or a moderator can
+// confirm, and an Unconfirmed update becomes Active again.
+#[ic_cdk::update(guard = "admit_update")]
+fn confirm_still_accurate(id: u64) -> Result<CrisisUpdate, Error> {
+    crate::call_stats::count_call("confirm_still_accurate");
+    let caller = caller();
+    let update = crate::_get_crisis_update(&id)
+        .ok_or_else(|| Error::not_found(format!("a crisis update with id={} not found", id)).with_detail("id", id))?;
+    let allowed = update.author == Some(caller)
+        || crate::assignments::assigned_principals(id).contains(&caller)
+        || has_role(&caller, Role::Coordinator)
+        || has_role(&caller, Role::Moderator);
+    if !allowed {
+        return Err(Error::unauthorized(
+            "only the author, an assignee, a coordinator or a moderator can confirm an update is still accurate",
+        ));
+    }
+    if !update.status.is_open() {
+        return Err(Error::validation(format!("the crisis update with id={} is closed", id)).with_detail("id", id));
+    }
+    let now = time();
+    save(
+        id,
+        FreshnessRecord {
+            confirmed_at: Some(now),
+            prompted_at: None,
+            unconfirmed_at: None,
+        },
+    );
+    let update = if update.status == CrisisStatus::Unconfirmed {
+        crate::store_status(update, CrisisStatus::Active, caller)?
+    } else {
+        update
+    };
+    Ok(crate::redaction::redact_update_for_caller(update))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = SECS_PER_HOUR * NANOS_PER_SECOND;
+
+    fn policy() -> StalenessPolicy {
+        StalenessPolicy {
+            prompt_after_hours: 24,
+            unconfirm_after_hours: 6,
+        }
+    }
+
+    fn active(created_at: u64) -> CrisisUpdate {
+        CrisisUpdate {
+            created_at,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn quiet_updates_are_prompted_then_unconfirmed() {
+        let update = active(0);
+        let mut record = FreshnessRecord::default();
+        assert_eq!(next_step(&update, &mut record, &policy(), 23 * HOUR), Step::Wait);
+        assert_eq!(next_step(&update, &mut record, &policy(), 24 * HOUR), Step::Prompt);
+        assert_eq!(record.prompted_at, Some(24 * HOUR));
+        assert_eq!(next_step(&update, &mut record, &policy(), 29 * HOUR), Step::Wait);
+        assert_eq!(next_step(&update, &mut record, &policy(), 30 * HOUR), Step::Unconfirm);
+        assert_eq!(record.prompted_at, None);
+        assert_eq!(record.unconfirmed_at, Some(30 * HOUR));
+    }
+
+    #[test]
+    fn an_answer_clears_the_prompt() {
+        let update = active(0);
+        let mut record = FreshnessRecord {
+            confirmed_at: Some(25 * HOUR),
+            prompted_at: Some(24 * HOUR),
+            unconfirmed_at: None,
+        };
+        assert_eq!(next_step(&update, &mut record, &policy(), 31 * HOUR), Step::Wait);
+        assert_eq!(record.prompted_at, None);
+        // The next prompt counts from the answer
+        assert_eq!(next_step(&update, &mut record, &policy(), 49 * HOUR), Step::Prompt);
+    }
+
+    #[test]
+    fn unconfirmed_updates_come_back_after_new_activity() {
+        let unconfirmed = CrisisUpdate {
+            status: CrisisStatus::Unconfirmed,
+            updated_at: Some(30 * HOUR),
+            ..active(0)
+        };
+        let mut record = FreshnessRecord {
+            unconfirmed_at: Some(30 * HOUR),
+            ..Default::default()
+        };
+        assert_eq!(next_step(&unconfirmed, &mut record, &policy(), 40 * HOUR), Step::Wait);
+        let edited = CrisisUpdate {
+            updated_at: Some(35 * HOUR),
+            ..unconfirmed.clone()
+        };
+        assert_eq!(next_step(&edited, &mut record, &policy(), 40 * HOUR), Step::Reactivate);
+        assert_eq!(record.unconfirmed_at, None);
+        // Marked Unconfirmed by hand, where only a confirmation counts
+        let mut record = FreshnessRecord::default();
+        assert_eq!(next_step(&unconfirmed, &mut record, &policy(), 40 * HOUR), Step::Wait);
+        record.confirmed_at = Some(31 * HOUR);
+        assert_eq!(next_step(&unconfirmed, &mut record, &policy(), 40 * HOUR), Step::Reactivate);
+    }
+
+    #[test]
+    fn updates_reactivated_by_hand_start_over() {
+        let update = active(0);
+        let mut record = FreshnessRecord {
+            unconfirmed_at: Some(30 * HOUR),
+            ..Default::default()
+        };
+        assert_eq!(next_step(&update, &mut record, &policy(), 40 * HOUR), Step::Prompt);
+        assert_eq!(record.unconfirmed_at, None);
+        assert_eq!(record.prompted_at, Some(40 * HOUR));
+    }
+}
@@ -0,0 +1,244 @@
+use crate::access::{has_role, require_any_role, Role};
+use crate::access_mode::admit_read;
+use crate::ids::IdCounter;
+use crate::maintenance::admit_update;
+use crate::response::{fit, QueryResult};
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_TEAM_MEMBERS: usize = 50;
+const MAX_TEAM_SPECIALTIES: usize = 20;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Team {
+    pub(crate) id: u64,
+    pub(crate) name: String,
+    // The org lead who created the team and may manage it
+    pub(crate) lead: Principal,
+    pub(crate) members: Vec<Principal>,
+    pub(crate) coverage_region: String,
+    pub(crate) specialties: Vec<String>,
+    pub(crate) created_at: u64,
+    pub(crate) updated_at: Option<u64>,
+}
+
+// Implementing Storable and BoundedStorable traits for Team
+impl Storable for Team {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Team {
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct TeamPayload {
+    name: String,
+    members: Vec<Principal>,
+    coverage_region: String,
+    specialties: Vec<String>,
+}
+
+thread_local! {
+    static TEAM_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(41, "teams"));
+
+    static TEAM_STORAGE: RefCell<StableBTreeMap<u64, Team, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(42)))
+    ));
+}
+
+// Helper method to perform insert for Team
+fn do_insert_team(team: &Team) {
+    TEAM_STORAGE.with(|service| service.borrow_mut().insert(team.id, team.clone()));
+}
+
+pub(crate) fn find_team(id: u64) -> Option<Team> {
+    TEAM_STORAGE.with(|service| service.borrow().get(&id))
+}
+
+pub(crate) fn is_team_member(team_id: u64, principal: &Principal) -> bool {
+    find_team(team_id).is_some_and(|team| team.members.contains(principal))
+}
+
+pub(crate) fn teams_of(principal: &Principal) -> Vec<Team> {
+    TEAM_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, team)| team)
+            .filter(|team| team.members.contains(principal))
+            .collect()
+    })
+}
+
+fn validate_team_payload(payload: &mut TeamPayload) -> Result<(), Error> {
+    payload.name = crate::normalize::normalize_line(&payload.name);
+    if payload.name.is_empty() {
+        return Err(Error::validation("team name must not be empty"));
+    }
+    payload.members.sort();
+    payload.members.dedup();
+    if payload.members.len() > MAX_TEAM_MEMBERS {
+        return Err(Error::validation(format!(
+            "a team can have at most {} members",
+            MAX_TEAM_MEMBERS
+        )));
+    }
+    if payload.specialties.len() > MAX_TEAM_SPECIALTIES {
+        return Err(Error::validation(format!(
+            "a team can list at most {} specialties",
+            MAX_TEAM_SPECIALTIES
+        )));
+    }
+    Ok(())
+}
+
+// Loads a team and checks that the caller leads it or is an admin
+fn get_managed_team(id: u64) -> Result<Team, Error> {
+    let caller = require_any_role(&[Role::Admin, Role::OrgLead])?;
+    let team = get_team(id)?;
+    if team.lead != caller && !has_role(&caller, Role::Admin) {
+        return Err(Error::unauthorized(format!(
+            "caller does not lead the team with id={}",
+            id
+        )));
+    }
+    Ok(team)
+}
+
+// 2.29.1 get_team Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_team(id: u64) -> Result<Team, Error> {
+    match find_team(id) {
+        Some(team) => Ok(team),
+        None => Err(Error::not_found(format!("a team with id={} not found", id))),
+    }
+}
+
+// 2.29.2 create_team Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn create_team(mut payload: TeamPayload) -> Result<Team, Error> {
+    crate::call_stats::count_call("create_team");
+    let lead = require_any_role(&[Role::Admin, Role::OrgLead])?;
+    validate_team_payload(&mut payload)?;
+    let id = crate::ids::next_id(&TEAM_ID_COUNTER)?;
+    let team = Team {
+        id,
+        name: payload.name,
+        lead,
+        members: payload.members,
+        coverage_region: payload.coverage_region,
+        specialties: payload.specialties,
+        created_at: time(),
+        updated_at: None,
+    };
+    do_insert_team(&team);
+    Ok(team)
+}
+
+// 2.29.3 update_team Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn update_team(id: u64, mut payload: TeamPayload) -> Result<Team, Error> {
+    crate::call_stats::count_call("update_team");
+    let mut team = get_managed_team(id)?;
+    validate_team_payload(&mut payload)?;
+    team.name = payload.name;
+    team.members = payload.members;
+    team.coverage_region = payload.coverage_region;
+    team.specialties = payload.specialties;
+    team.updated_at = Some(time());
+    do_insert_team(&team);
+    Ok(team)
+}
+
+// 2.29.4 delete_team Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn delete_team(id: u64) -> Result<Team, Error> {
+    crate::call_stats::count_call("delete_team");
+    get_managed_team(id)?;
+    match TEAM_STORAGE.with(|service| service.borrow_mut().remove(&id)) {
+        Some(team) => Ok(team),
+        None => Err(Error::not_found(format!(
+            "couldn't delete a team with id={}. team not found.",
+            id
+        ))),
+    }
+}
+
+// 2.29.5 add_team_member Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn add_team_member(id: u64, member: Principal) -> Result<Team, Error> {
+    crate::call_stats::count_call("add_team_member");
+    let mut team = get_managed_team(id)?;
+    if team.members.contains(&member) {
+        return Ok(team);
+    }
+    if team.members.len() >= MAX_TEAM_MEMBERS {
+        return Err(Error::validation(format!(
+            "a team can have at most {} members",
+            MAX_TEAM_MEMBERS
+        )));
+    }
+    team.members.push(member);
+    team.updated_at = Some(time());
+    do_insert_team(&team);
+    Ok(team)
+}
+
+// 2.29.6 remove_team_member Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn remove_team_member(id: u64, member: Principal) -> Result<Team, Error> {
+    crate::call_stats::count_call("remove_team_member");
+    let mut team = get_managed_team(id)?;
+    let Some(position) = team.members.iter().position(|m| *m == member) else {
+        return Err(Error::not_found(format!(
+            "principal {} is not a member of the team with id={}",
+            member, id
+        )));
+    };
+    team.members.remove(position);
+    team.updated_at = Some(time());
+    do_insert_team(&team);
+    Ok(team)
+}
+
+// 2.29.7 list_teams Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn list_teams(region: Option<String>) -> Result<QueryResult<Team>, Error> {
+    fit(TEAM_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, team)| team)
+            .filter(|team| {
+                region
+                    .as_ref()
+                    .is_none_or(|r| team.coverage_region.eq_ignore_ascii_case(r))
+            })
+            .collect()
+    }))
+}
+
+// 2.29.8 get_teams_for_member Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_teams_for_member(member: Principal) -> Result<QueryResult<Team>, Error> {
+    fit(teams_of(&member))
+}
+
+// 2.29.9 get_my_teams Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_my_teams() -> Result<QueryResult<Team>, Error> {
+    fit(teams_of(&caller()))
+}
@@ -0,0 +1,179 @@
+// Several agencies sharing one canister. Each principal belongs to at most one
+// tenant; crisis updates record the tenant of their author when created, and a
+// tenant's updates are visible only to its members, their author, admins and
+// the tenants an admin has shared the update with. Updates without a tenant
+// (reports from the public, or created before tenants existed) stay visible to
+// everyone.
+use crate::access::{has_role, principal_key, require_role, PrincipalKey, Role};
+use crate::access_mode::admit_read;
+use crate::ids::IdCounter;
+use crate::maintenance::admit_update;
+use crate::normalize::normalize_line;
+use crate::response::{fit, QueryResult};
+use crate::{CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_TENANT_NAME_CHARS: usize = 100;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Tenant {
+    id: u64,
+    name: String,
+    created_at: u64,
+}
+
+// Implementing Storable and BoundedStorable traits for Tenant
+impl Storable for Tenant {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Tenant {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static TENANT_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(76, "tenants"));
+
+    static TENANT_STORAGE: RefCell<StableBTreeMap<u64, Tenant, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(77)))
+    ));
+
+    // principal -> tenant id
+    static TENANT_MEMBERS: RefCell<StableBTreeMap<PrincipalKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(78)))
+    ));
+
+    // (crisis update id, tenant id) for updates shared beyond their own tenant
+    static SHARED_UPDATES: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(79)))
+    ));
+}
+
+pub(crate) fn tenant_of(principal: &Principal) -> Option<u64> {
+    TENANT_MEMBERS.with(|s| s.borrow().get(&principal_key(principal)))
+}
+
+pub(crate) fn find_tenant(id: u64) -> Result<Tenant, Error> {
+    TENANT_STORAGE
+        .with(|s| s.borrow().get(&id))
+        .ok_or_else(|| Error::not_found(format!("a tenant with id={} not found", id)).with_detail("id", id))
+}
+
+fn is_shared_with(crisis_update_id: u64, tenant_id: u64) -> bool {
+    SHARED_UPDATES.with(|s| s.borrow().contains_key(&(crisis_update_id, tenant_id)))
+}
+
+pub(crate) fn can_see(update: &CrisisUpdate, viewer: &Principal) -> bool {
+    let Some(owner) = update.tenant else {
+        return true;
+    };
+    if update.author.as_ref() == Some(viewer) || has_role(viewer, Role::Admin) {
+        return true;
+    }
+    match tenant_of(viewer) {
+        Some(tenant) => tenant == owner || is_shared_with(update.id, tenant),
+        None => false,
+    }
+}
+
+// 2.57.1 create_tenant Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn create_tenant(name: String) -> Result<Tenant, Error> {
+    crate::call_stats::count_call("create_tenant");
+    require_role(Role::Admin)?;
+    let name = normalize_line(&name);
+    if name.is_empty() || name.chars().count() > MAX_TENANT_NAME_CHARS {
+        return Err(Error::validation(format!(
+            "tenant names must be between 1 and {} characters",
+            MAX_TENANT_NAME_CHARS
+        )));
+    }
+    let tenant = Tenant {
+        id: crate::ids::next_id(&TENANT_ID_COUNTER)?,
+        name,
+        created_at: time(),
+    };
+    TENANT_STORAGE.with(|s| s.borrow_mut().insert(tenant.id, tenant.clone()));
+    Ok(tenant)
+}
+
+// 2.57.2 list_tenants Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn list_tenants() -> Result<QueryResult<Tenant>, Error> {
+    fit(TENANT_STORAGE.with(|s| s.borrow().iter().map(|(_, tenant)| tenant).collect()))
+}
+
+// 2.57.3 set_tenant_membership Function:
+// Moves the principal into the tenant, or out of any tenant with None. Updates
+// the principal already created keep their tenant.
+#[ic_cdk::update(guard = "admit_update")]
+fn set_tenant_membership(principal: Principal, tenant_id: Option<u64>) -> Result<Option<u64>, Error> {
+    crate::call_stats::count_call("set_tenant_membership");
+    require_role(Role::Admin)?;
+    let key = principal_key(&principal);
+    match tenant_id {
+        Some(id) => {
+            find_tenant(id)?;
+            TENANT_MEMBERS.with(|s| s.borrow_mut().insert(key, id));
+        }
+        None => {
+            TENANT_MEMBERS.with(|s| s.borrow_mut().remove(&key));
+        }
+    }
+    Ok(tenant_id)
+}
+
+// 2.57.4 get_my_tenant Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_my_tenant() -> Option<Tenant> {
+    tenant_of(&caller()).and_then(|id| find_tenant(id).ok())
+}
+
+// 2.57.5 share_crisis_update_with_tenant Function:
+#[ic_cdk::update(guard = "admit_update")]
+async fn share_crisis_update_with_tenant(crisis_update_id: u64, tenant_id: u64) -> Result<Vec<u64>, Error> {
+    crate::call_stats::count_call("share_crisis_update_with_tenant");
+    require_role(Role::Admin)?;
+    find_tenant(tenant_id)?;
+    if crate::_find_crisis_update(crisis_update_id).await?.is_none() {
+        return Err(Error::not_found(format!(
+            "a crisis update with id={} not found",
+            crisis_update_id
+        )));
+    }
+    SHARED_UPDATES.with(|s| s.borrow_mut().insert((crisis_update_id, tenant_id), ()));
+    Ok(shared_tenants(crisis_update_id))
+}
+
+// 2.57.6 unshare_crisis_update_with_tenant Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn unshare_crisis_update_with_tenant(crisis_update_id: u64, tenant_id: u64) -> Result<Vec<u64>, Error> {
+    crate::call_stats::count_call("unshare_crisis_update_with_tenant");
+    require_role(Role::Admin)?;
+    SHARED_UPDATES.with(|s| s.borrow_mut().remove(&(crisis_update_id, tenant_id)));
+    Ok(shared_tenants(crisis_update_id))
+}
+
+// Tenants the update is shared with, besides its own
+fn shared_tenants(crisis_update_id: u64) -> Vec<u64> {
+    SHARED_UPDATES.with(|s| {
+        s.borrow()
+            .range((crisis_update_id, 0)..=(crisis_update_id, u64::MAX))
+            .map(|((_, tenant), _)| tenant)
+            .collect()
+    })
+}
@@ -0,0 +1,162 @@
+// Word tokens for full-text search. Text is lowercased, split on anything that
+// is not a letter or digit, stripped of stop words and reduced to a rough stem,
+// so "flooding", "flooded" and "floods" all become "flood". The stemmer only
+// knows common English suffixes. Stop words are configurable by admins and kept
+// on the heap once loaded.
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::maintenance::admit_update;
+use crate::settings::{get_settings, update_settings};
+use crate::Error;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+const MAX_STOP_WORDS: usize = 200;
+const MAX_STOP_WORD_CHARS: usize = 30;
+// Stems shorter than this are left alone, so "bus" does not become "bu"
+const MIN_STEM_CHARS: usize = 3;
+
+const DEFAULT_STOP_WORDS: [&str; 25] = [
+    "a", "an", "and", "are", "as", "at", "be", "by", "for", "from", "has", "have", "in", "is", "it", "of", "on", "or",
+    "that", "the", "this", "to", "was", "were", "with",
+];
+
+thread_local! {
+    static STOP_WORDS: RefCell<Option<BTreeSet<String>>> = const { RefCell::new(None) };
+}
+
+fn stop_words() -> Vec<String> {
+    get_settings()
+        .search_stop_words
+        .unwrap_or_else(|| DEFAULT_STOP_WORDS.iter().map(|word| word.to_string()).collect())
+}
+
+fn is_stop_word(word: &str) -> bool {
+    STOP_WORDS.with(|s| {
+        s.borrow_mut()
+            .get_or_insert_with(|| stop_words().into_iter().collect())
+            .contains(word)
+    })
+}
+
+fn strip_suffix<'a>(word: &'a str, suffix: &str) -> Option<&'a str> {
+    word.strip_suffix(suffix)
+        .filter(|stem| stem.chars().count() >= MIN_STEM_CHARS)
+}
+
+// "running" -> "runn" -> "run", keeping the doubled letters English words end in
+fn undouble(stem: &str) -> String {
+    let mut chars: Vec<char> = stem.chars().collect();
+    if let [.., a, b] = chars.as_slice() {
+        if a == b && !matches!(a, 'l' | 's' | 'z') && a.is_alphabetic() && !"aeiou".contains(*a) {
+            chars.pop();
+        }
+    }
+    chars.into_iter().collect()
+}
+
+// Strips plural and verb endings and a final "e", so "damage" and "damaged"
+// share the stem "damag"
+fn stem(word: &str) -> String {
+    let mut word = if let Some(stem) = strip_suffix(word, "ies") {
+        format!("{}y", stem)
+    } else if word.ends_with("sses") {
+        word[..word.len() - 2].to_string()
+    } else if word.ends_with('s') && !word.ends_with("ss") && !word.ends_with("us") {
+        strip_suffix(word, "s").unwrap_or(word).to_string()
+    } else {
+        word.to_string()
+    };
+    // "speed" and "need" are not past tenses
+    let suffixes: &[&str] = if word.ends_with("eed") {
+        &["ing"]
+    } else {
+        &["ing", "ed"]
+    };
+    for suffix in suffixes {
+        if let Some(stem) = strip_suffix(&word, suffix) {
+            word = undouble(stem);
+            break;
+        }
+    }
+    if word.chars().count() > MIN_STEM_CHARS + 1 && word.ends_with('e') {
+        word.pop();
+    }
+    word
+}
+
+// The stemmed words of the text, stop words removed, in order
+pub(crate) fn tokenize(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty() && !is_stop_word(word))
+        .map(stem)
+        .collect()
+}
+
+// 2.66.1 set_search_stop_words Function:
+// None restores the built-in English list; an empty list turns stop words off.
+#[ic_cdk::update(guard = "admit_update")]
+fn set_search_stop_words(words: Option<Vec<String>>) -> Result<Vec<String>, Error> {
+    crate::call_stats::count_call("set_search_stop_words");
+    require_role(Role::Admin)?;
+    let words = match words {
+        Some(words) => {
+            if words.len() > MAX_STOP_WORDS {
+                return Err(Error::validation(format!(
+                    "at most {} stop words can be set",
+                    MAX_STOP_WORDS
+                )));
+            }
+            let mut words: Vec<String> = words.iter().map(|word| word.trim().to_lowercase()).collect();
+            if let Some(word) = words.iter().find(|word| {
+                word.is_empty()
+                    || word.chars().count() > MAX_STOP_WORD_CHARS
+                    || !word.chars().all(char::is_alphanumeric)
+            }) {
+                return Err(Error::validation(format!(
+                    "stop words must be single words of at most {} letters or digits",
+                    MAX_STOP_WORD_CHARS
+                ))
+                .with_detail("word", word));
+            }
+            words.sort();
+            words.dedup();
+            Some(words)
+        }
+        None => None,
+    };
+    update_settings(|s| s.search_stop_words = words)?;
+    STOP_WORDS.with(|s| *s.borrow_mut() = None);
+    Ok(stop_words())
+}
+
+// 2.66.2 get_search_stop_words Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_search_stop_words() -> Vec<String> {
+    stop_words()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_forms_share_a_stem() {
+        for word in ["flood", "floods", "flooded", "flooding"] {
+            assert_eq!(stem(word), "flood", "{}", word);
+        }
+        assert_eq!(stem("running"), "run");
+        assert_eq!(stem("damaged"), stem("damage"));
+        assert_eq!(stem("supplies"), "supply");
+        assert_eq!(stem("speed"), "speed");
+        assert_eq!(stem("bus"), "bus");
+        assert_eq!(stem("status"), "status");
+    }
+
+    #[test]
+    fn tokens_skip_stop_words() {
+        assert_eq!(tokenize("The bridge, at the RIVER, was flooded!"), vec!["bridg", "river", "flood"]);
+        assert!(tokenize("it is on the").is_empty());
+    }
+}
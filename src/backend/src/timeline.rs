@@ -0,0 +1,282 @@
+// One chronological stream of everything that happened around a crisis update,
+// for situation rooms and after-action review. Most events are derived from the
+// records other modules keep (assignments, links, resource requests, escalations,
+// alerts, quick updates); status changes, manual priority changes and review decisions leave
+// no other trace, so they are recorded here when they happen.
+use crate::access_mode::admit_read;
+use crate::alerts::AlertSeverity;
+use crate::assignments::Assignee;
+use crate::ids::IdCounter;
+use crate::logs::{log, LogLevel};
+use crate::resources::{ResourceType, RESOURCE_REQUEST_STORAGE};
+use crate::response::{truncate_to_fit, PagedResult};
+use crate::{CrisisPriority, CrisisStatus, Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) enum TimelineEventKind {
+    Reported {
+        title: String,
+    },
+    StatusChanged {
+        from: CrisisStatus,
+        to: CrisisStatus,
+    },
+    PriorityChanged {
+        from: CrisisPriority,
+        to: CrisisPriority,
+    },
+    // A moderator approved or rejected an update held for review
+    Reviewed {
+        approved: bool,
+    },
+    Escalated {
+        from: CrisisPriority,
+        to: CrisisPriority,
+        rule_name: String,
+        reason: String,
+    },
+    Assigned {
+        assignee: Assignee,
+    },
+    Unassigned {
+        assignee: Assignee,
+    },
+    LinkAttached {
+        url: String,
+    },
+    ResourceRequested {
+        request_id: u64,
+        resource_type: ResourceType,
+        quantity: u64,
+    },
+    AlertIssued {
+        alert_id: u64,
+        title: String,
+        severity: AlertSeverity,
+    },
+    QuickUpdatePosted {
+        quick_update_id: u64,
+        text: String,
+    },
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct TimelineEvent {
+    pub(crate) at: u64,
+    // Who caused the event, when known; the canister itself for automatic changes
+    pub(crate) actor: Option<Principal>,
+    pub(crate) kind: TimelineEventKind,
+}
+
+// Implementing Storable and BoundedStorable traits for TimelineEvent
+impl Storable for TimelineEvent {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for TimelineEvent {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static TIMELINE_SEQ_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(74, "timeline events"));
+
+    // (crisis update id, seq) -> status change, priority change or review decision
+    static RECORDED_EVENTS: RefCell<StableBTreeMap<(u64, u64), TimelineEvent, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(75)))
+    ));
+}
+
+// The change is already stored, so a failure to record it is logged rather than returned
+fn record(crisis_update_id: u64, actor: Principal, kind: TimelineEventKind) {
+    match crate::ids::next_id(&TIMELINE_SEQ_COUNTER) {
+        Ok(seq) => {
+            let event = TimelineEvent {
+                at: time(),
+                actor: Some(actor),
+                kind,
+            };
+            RECORDED_EVENTS.with(|s| s.borrow_mut().insert((crisis_update_id, seq), event));
+        }
+        Err(err) => log(
+            LogLevel::Error,
+            Some("timeline"),
+            &format!("failed to record a timeline event: {:?}", err),
+        ),
+    }
+}
+
+pub(crate) fn record_status_change(crisis_update_id: u64, from: CrisisStatus, to: CrisisStatus, actor: Principal) {
+    record(crisis_update_id, actor, TimelineEventKind::StatusChanged { from, to });
+}
+
+pub(crate) fn record_priority_change(
+    crisis_update_id: u64,
+    from: CrisisPriority,
+    to: CrisisPriority,
+    actor: Principal,
+) {
+    record(crisis_update_id, actor, TimelineEventKind::PriorityChanged { from, to });
+}
+
+pub(crate) fn record_review(crisis_update_id: u64, approved: bool, actor: Principal) {
+    record(crisis_update_id, actor, TimelineEventKind::Reviewed { approved });
+}
+
+// Status changes, priority changes and review decisions, oldest first
+pub(crate) fn recorded_events(crisis_update_id: u64) -> Vec<TimelineEvent> {
+    RECORDED_EVENTS.with(|s| {
+        s.borrow()
+            .range((crisis_update_id, 0)..=(crisis_update_id, u64::MAX))
+            .map(|(_, event)| event)
+            .collect()
+    })
+}
+
+fn event(at: u64, actor: Option<Principal>, kind: TimelineEventKind) -> TimelineEvent {
+    TimelineEvent { at, actor, kind }
+}
+
+pub(crate) fn collect_events(update: &crate::CrisisUpdate) -> Vec<TimelineEvent> {
+    let id = update.id;
+    let mut events = vec![event(
+        update.created_at,
+        update.author,
+        TimelineEventKind::Reported {
+            title: update.title.clone(),
+        },
+    )];
+    events.extend(recorded_events(id));
+    events.extend(crate::escalation::escalations_of(id).into_iter().map(|entry| {
+        event(
+            entry.at,
+            Some(ic_cdk::id()),
+            TimelineEventKind::Escalated {
+                from: entry.from,
+                to: entry.to,
+                rule_name: entry.rule_name,
+                reason: entry.reason,
+            },
+        )
+    }));
+    for assignment in crate::assignments::assignment_history(id) {
+        events.push(event(
+            assignment.assigned_at,
+            Some(assignment.assigned_by),
+            TimelineEventKind::Assigned {
+                assignee: assignment.assignee,
+            },
+        ));
+        if let Some(at) = assignment.unassigned_at {
+            events.push(event(
+                at,
+                None,
+                TimelineEventKind::Unassigned {
+                    assignee: assignment.assignee,
+                },
+            ));
+        }
+    }
+    events.extend(crate::links::links_for_update(id).into_iter().map(|link| {
+        event(
+            link.added_at,
+            Some(link.added_by),
+            TimelineEventKind::LinkAttached { url: link.url },
+        )
+    }));
+    RESOURCE_REQUEST_STORAGE.with(|s| {
+        events.extend(
+            s.borrow()
+                .iter()
+                .map(|(_, request)| request)
+                .filter(|request| request.crisis_update_id == id)
+                .map(|request| {
+                    event(
+                        request.created_at,
+                        Some(request.requester),
+                        TimelineEventKind::ResourceRequested {
+                            request_id: request.id,
+                            resource_type: request.resource_type,
+                            quantity: request.quantity,
+                        },
+                    )
+                }),
+        )
+    });
+    let places: Vec<String> = std::iter::once(update.location.clone())
+        .chain(update.affected_areas.iter().map(|area| area.name.clone()))
+        .collect();
+    events.extend(
+        crate::alerts::alerts_covering(&places)
+            .into_iter()
+            .filter(|alert| alert.issued_at >= update.created_at)
+            .map(|alert| {
+                event(
+                    alert.issued_at,
+                    Some(alert.issued_by),
+                    TimelineEventKind::AlertIssued {
+                        alert_id: alert.id,
+                        title: alert.title,
+                        severity: alert.severity,
+                    },
+                )
+            }),
+    );
+    events.extend(crate::quick_updates::quick_updates_of(id).into_iter().map(|quick_update| {
+        event(
+            quick_update.created_at,
+            Some(quick_update.author),
+            TimelineEventKind::QuickUpdatePosted {
+                quick_update_id: quick_update.id,
+                text: quick_update.text,
+            },
+        )
+    }));
+    // Stable, so events at the same instant keep the order they were gathered in
+    events.sort_by_key(|event| event.at);
+    events
+}
+
+// 2.55.1 get_crisis_timeline Function:
+// Oldest first. The cursor is the position of the last event returned.
+#[ic_cdk::query(composite = true, guard = "admit_read")]
+async fn get_crisis_timeline(
+    crisis_id: u64,
+    start_after: Option<u64>,
+    limit: u64,
+) -> Result<PagedResult<TimelineEvent>, Error> {
+    let Some(update) = crate::_find_crisis_update(crisis_id)
+        .await?
+        .filter(crate::moderation::visible_to_caller)
+    else {
+        return Err(
+            Error::not_found(format!("a crisis update with id={} not found", crisis_id)).with_detail("id", crisis_id),
+        );
+    };
+    let events = collect_events(&update);
+    let total_count = events.len() as u64;
+    let start = start_after.map_or(0, |position| position.saturating_add(1)) as usize;
+    let limit = crate::query_limits::page_size(limit) as usize;
+    let page: Vec<TimelineEvent> = events.into_iter().skip(start).take(limit).collect();
+    let (items, _) = truncate_to_fit(page);
+    let last = (start + items.len()) as u64;
+    let next_cursor = (last < total_count && !items.is_empty()).then(|| last - 1);
+    Ok(PagedResult {
+        items,
+        total_count,
+        next_cursor,
+    })
+}
@@ -0,0 +1,163 @@
+// Uniqueness constraint for new crisis updates, so the same report is not
+// opened twice, e.g. by one author with the same title at the same location
+// while the first is still active. Unlike duplicate detection, which quietly
+// returns the original for an exact resubmission on the same day, a clash is
+// rejected with a Duplicate error naming the existing update, so the client
+// can edit that one instead. Candidates come from the location index when the
+// constraint includes the location. Only updates stored in this canister are
+// checked; the constraint is off until an admin sets one.
+use crate::access::{require_role, Role};
+use crate::areas::same_place;
+use crate::maintenance::admit_update;
+use crate::settings::{get_settings, update_settings, Settings};
+use crate::{CrisisUpdate, Error, CRISIS_STORAGE};
+use candid::Principal;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum UniqueField {
+    Author,
+    Title,
+    Description,
+    Location,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct UniquenessConstraint {
+    // A new update clashes with one that matches on every field
+    fields: Vec<UniqueField>,
+    // Only active updates count when true
+    unresolved_only: bool,
+}
+
+// Text fields compare ignoring case and surrounding whitespace
+fn same_text(a: &str, b: &str) -> bool {
+    a.trim().to_lowercase() == b.trim().to_lowercase()
+}
+
+fn clashes(constraint: &UniquenessConstraint, existing: &CrisisUpdate, new: &NewUpdate) -> bool {
+    if constraint.unresolved_only && !existing.status.is_open() {
+        return false;
+    }
+    constraint.fields.iter().all(|field| match field {
+        UniqueField::Author => existing.author.as_ref() == Some(new.author),
+        UniqueField::Title => same_text(&existing.title, new.title),
+        UniqueField::Description => same_text(&existing.description, new.description),
+        UniqueField::Location => same_place(&existing.location, new.location),
+    })
+}
+
+// The fields of a submission the constraint can look at
+pub(crate) struct NewUpdate<'a> {
+    pub(crate) author: &'a Principal,
+    pub(crate) title: &'a str,
+    pub(crate) description: &'a str,
+    pub(crate) location: &'a str,
+}
+
+// Fails with a Duplicate error when a stored update clashes with the submission
+pub(crate) fn check_unique(new: &NewUpdate) -> Result<(), Error> {
+    let Some(constraint) = get_settings().uniqueness_constraint else {
+        return Ok(());
+    };
+    let existing = if constraint.fields.contains(&UniqueField::Location) {
+        crate::areas::updates_at(new.location)
+            .into_iter()
+            .filter_map(|id| crate::_get_crisis_update(&id))
+            .find(|update| clashes(&constraint, update, new))
+    } else {
+        CRISIS_STORAGE.with(|s| {
+            s.borrow()
+                .iter()
+                .map(|(_, update)| update)
+                .find(|update| clashes(&constraint, update, new))
+        })
+    };
+    match existing {
+        Some(existing) => Err(Error::duplicate(
+            format!(
+                "crisis update with id={} already matches on {:?}",
+                existing.id, constraint.fields
+            ),
+            existing.id,
+        )),
+        None => Ok(()),
+    }
+}
+
+// 2.80.1 set_uniqueness_constraint Function:
+// None turns the constraint off.
+#[ic_cdk::update(guard = "admit_update")]
+fn set_uniqueness_constraint(constraint: Option<UniquenessConstraint>) -> Result<Settings, Error> {
+    crate::call_stats::count_call("set_uniqueness_constraint");
+    require_role(Role::Admin)?;
+    if constraint.as_ref().is_some_and(|c| c.fields.is_empty()) {
+        return Err(Error::validation("a uniqueness constraint needs at least one field"));
+    }
+    update_settings(|s| s.uniqueness_constraint = constraint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CrisisStatus;
+
+    #[test]
+    fn clashes_need_every_constrained_field_to_match() {
+        let author = Principal::from_slice(&[1]);
+        let existing = CrisisUpdate {
+            author: Some(author),
+            title: "Bridge collapsed".to_string(),
+            description: "The river bridge is gone".to_string(),
+            location: "Kisumu  West".to_string(),
+            ..Default::default()
+        };
+        let new = NewUpdate {
+            author: &author,
+            title: " bridge COLLAPSED ",
+            description: "Another account of it",
+            location: "kisumu west",
+        };
+        let constraint = UniquenessConstraint {
+            fields: vec![UniqueField::Author, UniqueField::Title, UniqueField::Location],
+            unresolved_only: true,
+        };
+        assert!(clashes(&constraint, &existing, &new));
+        let with_description = UniquenessConstraint {
+            fields: vec![UniqueField::Title, UniqueField::Description],
+            unresolved_only: true,
+        };
+        assert!(!clashes(&with_description, &existing, &new));
+        let other_author = Principal::from_slice(&[2]);
+        assert!(!clashes(
+            &constraint,
+            &existing,
+            &NewUpdate {
+                author: &other_author,
+                ..new
+            }
+        ));
+    }
+
+    #[test]
+    fn resolved_updates_only_clash_when_all_updates_count() {
+        let author = Principal::from_slice(&[1]);
+        let existing = CrisisUpdate {
+            title: "Road closed".to_string(),
+            status: CrisisStatus::Resolved,
+            ..Default::default()
+        };
+        let new = NewUpdate {
+            author: &author,
+            title: "Road closed",
+            description: "",
+            location: "",
+        };
+        let mut constraint = UniquenessConstraint {
+            fields: vec![UniqueField::Title],
+            unresolved_only: true,
+        };
+        assert!(!clashes(&constraint, &existing, &new));
+        constraint.unresolved_only = false;
+        assert!(clashes(&constraint, &existing, &new));
+    }
+}
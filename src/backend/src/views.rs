@@ -0,0 +1,138 @@
+// Read counts per crisis update, so coordinators can see which incidents the
+// public is following most closely. Queries cannot write state, so clients
+// buffer the updates they display and report them in batches through an update
+// call, which anonymous visitors may also make. Counts are kept per day for the
+// last 30 days and as an all-time total; a day's bucket for an update is
+// dropped the next time that update is viewed after the bucket ages out.
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::gc::update_exists;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::leaderboard::LeaderboardWindow;
+use crate::maintenance::admit_update;
+use crate::response::{truncate_to_fit, QueryResult};
+use crate::{CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+const MAX_VIEWS_PER_CALL: usize = 100;
+const MAX_MOST_VIEWED: u64 = 100;
+const DAY_NANOS: u64 = 24 * 60 * 60 * NANOS_PER_SECOND;
+const DAYS_KEPT: u64 = 30;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ViewedUpdate {
+    crisis_update: CrisisUpdate,
+    views: u64,
+}
+
+thread_local! {
+    // (crisis update id, day since the epoch) -> views that day
+    static DAILY_VIEWS: RefCell<StableBTreeMap<(u64, u64), u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(90)))
+    ));
+
+    // crisis update id -> views since counting began
+    static TOTAL_VIEWS: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(91)))
+    ));
+}
+
+fn day_of(timestamp: u64) -> u64 {
+    timestamp / DAY_NANOS
+}
+
+fn record_view(id: u64, today: u64) {
+    let oldest_kept = today.saturating_sub(DAYS_KEPT - 1);
+    DAILY_VIEWS.with(|s| {
+        let mut s = s.borrow_mut();
+        let expired: Vec<(u64, u64)> = s.range((id, 0)..(id, oldest_kept)).map(|(key, _)| key).collect();
+        for key in expired {
+            s.remove(&key);
+        }
+        let views = s.get(&(id, today)).unwrap_or_default();
+        s.insert((id, today), views.saturating_add(1));
+    });
+    TOTAL_VIEWS.with(|s| {
+        let mut s = s.borrow_mut();
+        let views = s.get(&id).unwrap_or_default();
+        s.insert(id, views.saturating_add(1));
+    });
+}
+
+// View counts per update within the window
+fn views_since(window: LeaderboardWindow, now: u64) -> BTreeMap<u64, u64> {
+    if window == LeaderboardWindow::AllTime {
+        return TOTAL_VIEWS.with(|s| s.borrow().iter().collect());
+    }
+    let first_day = day_of(window.cutoff(now));
+    let mut counts = BTreeMap::new();
+    DAILY_VIEWS.with(|s| {
+        for ((id, day), views) in s.borrow().iter() {
+            if day >= first_day {
+                *counts.entry(id).or_insert(0) += views;
+            }
+        }
+    });
+    counts
+}
+
+// Drops the counts of updates that no longer exist; returns how many updates were affected
+pub(crate) fn remove_orphaned_views(is_live: &dyn Fn(u64) -> bool) -> u64 {
+    let ids: Vec<u64> = TOTAL_VIEWS.with(|s| s.borrow().iter().map(|(id, _)| id).filter(|id| !is_live(*id)).collect());
+    for id in &ids {
+        TOTAL_VIEWS.with(|s| s.borrow_mut().remove(id));
+        DAILY_VIEWS.with(|s| {
+            let mut s = s.borrow_mut();
+            let keys: Vec<(u64, u64)> = s.range((*id, 0)..=(*id, u64::MAX)).map(|(key, _)| key).collect();
+            for key in keys {
+                s.remove(&key);
+            }
+        });
+    }
+    ids.len() as u64
+}
+
+// 2.68.1 record_crisis_update_views Function:
+// Counts one view of each listed update; returns how many were counted.
+#[ic_cdk::update(guard = "admit_update")]
+fn record_crisis_update_views(ids: Vec<u64>) -> Result<u64, Error> {
+    crate::call_stats::count_call("record_crisis_update_views");
+    if ids.len() > MAX_VIEWS_PER_CALL {
+        return Err(Error::validation(format!(
+            "at most {} views can be recorded per call",
+            MAX_VIEWS_PER_CALL
+        )));
+    }
+    let today = day_of(time());
+    let mut recorded = 0;
+    for id in ids.into_iter().filter(|id| update_exists(*id)) {
+        record_view(id, today);
+        recorded += 1;
+    }
+    Ok(recorded)
+}
+
+// 2.68.2 get_most_viewed_crisis_updates Function:
+// Locally stored updates only, most viewed first.
+#[ic_cdk::query(guard = "admit_read")]
+fn get_most_viewed_crisis_updates(window: LeaderboardWindow, limit: u64) -> Result<QueryResult<ViewedUpdate>, Error> {
+    require_role(Role::Coordinator)?;
+    let mut ranked: Vec<(u64, u64)> = views_since(window, time()).into_iter().collect();
+    ranked.sort_by(|(a_id, a_views), (b_id, b_views)| b_views.cmp(a_views).then(a_id.cmp(b_id)));
+    let mut viewed: Vec<ViewedUpdate> = ranked
+        .into_iter()
+        .filter_map(|(id, views)| {
+            let crisis_update = crate::_get_crisis_update(&id).and_then(crate::present_update)?;
+            Some(ViewedUpdate { crisis_update, views })
+        })
+        .collect();
+    let total_count = viewed.len() as u64;
+    viewed.truncate(limit.clamp(1, MAX_MOST_VIEWED) as usize);
+    Ok(QueryResult::limited(truncate_to_fit(viewed).0, total_count))
+}
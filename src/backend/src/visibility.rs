@@ -0,0 +1,72 @@
+// Who may see a crisis update. Sensitive reports, such as security incidents or
+// where vulnerable people are sheltering, can be limited to verified
+// organizations or to named principals while still being coordinated here.
+// Enforced through `moderation::visible_to`, which every query path goes through.
+use crate::access::{has_role, is_staff, Role};
+use crate::maintenance::admit_update;
+use crate::{CrisisUpdate, Error};
+use candid::Principal;
+use ic_cdk::api::caller;
+
+const MAX_ALLOWED_PRINCIPALS: usize = 10;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, Default, Debug)]
+pub(crate) enum Visibility {
+    #[default]
+    Public,
+    // Verified organizations and staff
+    OrgOnly,
+    // The listed principals, plus the author, assignees and staff
+    Restricted {
+        allowed: Vec<Principal>,
+    },
+}
+
+pub(crate) fn validate_visibility(visibility: &mut Visibility) -> Result<(), Error> {
+    if let Visibility::Restricted { allowed } = visibility {
+        allowed.sort();
+        allowed.dedup();
+        if allowed.len() > MAX_ALLOWED_PRINCIPALS {
+            return Err(Error::validation(format!(
+                "a restricted update can name at most {} principals",
+                MAX_ALLOWED_PRINCIPALS
+            )));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn can_see(update: &CrisisUpdate, viewer: &Principal) -> bool {
+    match &update.visibility {
+        Visibility::Public => true,
+        Visibility::OrgOnly => has_role(viewer, Role::VerifiedOrg) || is_staff(viewer),
+        Visibility::Restricted { allowed } => {
+            allowed.contains(viewer)
+                || update.author.as_ref() == Some(viewer)
+                || is_staff(viewer)
+                || crate::assignments::assigned_principals(update.id).contains(viewer)
+        }
+    }
+}
+
+// 2.56.1 set_crisis_update_visibility Function:
+// The author or a coordinator can widen or narrow who sees an update.
+#[ic_cdk::update(guard = "admit_update")]
+async fn set_crisis_update_visibility(id: u64, mut visibility: Visibility) -> Result<CrisisUpdate, Error> {
+    crate::call_stats::count_call("set_crisis_update_visibility");
+    let caller = caller();
+    validate_visibility(&mut visibility)?;
+    let Some(mut update) = crate::_find_crisis_update(id).await? else {
+        return Err(Error::not_found(format!("a crisis update with id={} not found", id)).with_detail("id", id));
+    };
+    if update.author != Some(caller) && !has_role(&caller, Role::Coordinator) {
+        return Err(Error::unauthorized(format!(
+            "caller may not change the visibility of the crisis update with id={}",
+            id
+        )));
+    }
+    update.visibility = visibility;
+    update.updated_at = Some(ic_cdk::api::time());
+    crate::store_edited_crisis_update(&update).await?;
+    Ok(update)
+}
@@ -0,0 +1,396 @@
+use crate::access::{has_role, principal_key, require_role, PrincipalKey, Role};
+use crate::access_mode::admit_read;
+use crate::ids::IdCounter;
+use crate::maintenance::admit_update;
+use crate::normalize::{normalize_line, normalize_multiline};
+use crate::response::{fit, QueryResult};
+use crate::teams::is_team_member;
+use crate::{Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_SKILLS: usize = 20;
+const MAX_SKILL_CHARS: usize = 50;
+const MAX_NAME_CHARS: usize = 100;
+const MAX_LOCATION_CHARS: usize = 200;
+const MAX_TITLE_CHARS: usize = 200;
+const MAX_DESCRIPTION_CHARS: usize = 1_000;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub(crate) enum Availability {
+    #[default]
+    Available,
+    Limited,
+    Unavailable,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Volunteer {
+    principal: Principal,
+    name: String,
+    skills: Vec<String>,
+    availability: Availability,
+    location: String,
+    registered_at: u64,
+    updated_at: Option<u64>,
+}
+
+// Implementing Storable and BoundedStorable traits for Volunteer
+impl Storable for Volunteer {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Volunteer {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct VolunteerPayload {
+    name: String,
+    skills: Vec<String>,
+    availability: Availability,
+    location: String,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub(crate) enum TaskStatus {
+    #[default]
+    Open,
+    Assigned,
+    InProgress,
+    Completed,
+    Cancelled,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Task {
+    pub(crate) id: u64,
+    pub(crate) crisis_update_id: u64,
+    pub(crate) title: String,
+    pub(crate) description: String,
+    pub(crate) location: String,
+    pub(crate) required_skills: Vec<String>,
+    pub(crate) assigned_to: Option<Principal>,
+    pub(crate) assigned_team: Option<u64>,
+    pub(crate) status: TaskStatus,
+    pub(crate) created_by: Principal,
+    pub(crate) created_at: u64,
+    pub(crate) updated_at: Option<u64>,
+}
+
+// Implementing Storable and BoundedStorable traits for Task
+impl Storable for Task {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Task {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct TaskPayload {
+    crisis_update_id: u64,
+    title: String,
+    description: String,
+    // Defaults to the location of the referenced crisis update when omitted
+    location: Option<String>,
+    required_skills: Vec<String>,
+}
+
+thread_local! {
+    static VOLUNTEER_STORAGE: RefCell<StableBTreeMap<PrincipalKey, Volunteer, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11)))
+    ));
+
+    static TASK_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(12, "tasks"));
+
+    pub(crate) static TASK_STORAGE: RefCell<StableBTreeMap<u64, Task, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13)))
+    ));
+}
+
+// Helper method to perform insert for Task
+pub(crate) fn do_insert_task(task: &Task) {
+    TASK_STORAGE.with(|service| service.borrow_mut().insert(task.id, task.clone()));
+}
+
+pub(crate) fn is_registered_volunteer(principal: &Principal) -> bool {
+    VOLUNTEER_STORAGE.with(|s| s.borrow().contains_key(&principal_key(principal)))
+}
+
+// Normalizes the skills in place before checking them
+fn validate_skills(skills: &mut [String]) -> Result<(), Error> {
+    for skill in skills.iter_mut() {
+        *skill = normalize_line(skill);
+    }
+    if skills.len() > MAX_SKILLS {
+        return Err(Error::validation(format!(
+            "at most {} skills can be listed",
+            MAX_SKILLS
+        )));
+    }
+    if skills.iter().any(|skill| skill.chars().count() > MAX_SKILL_CHARS) {
+        return Err(Error::validation(format!(
+            "skills must be at most {} characters",
+            MAX_SKILL_CHARS
+        )));
+    }
+    Ok(())
+}
+
+fn validate_length(field: &str, value: &str, max: usize) -> Result<(), Error> {
+    if value.chars().count() > max {
+        return Err(Error::validation(format!("{} must be at most {} characters", field, max))
+            .with_detail("field", field));
+    }
+    Ok(())
+}
+
+// Tasks assigned directly to the principal that are not yet closed
+pub(crate) fn open_tasks_of(principal: &Principal) -> Vec<Task> {
+    TASK_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, task)| task)
+            .filter(|task| {
+                task.assigned_to == Some(*principal)
+                    && !matches!(task.status, TaskStatus::Completed | TaskStatus::Cancelled)
+            })
+            .collect()
+    })
+}
+
+// Direct assignees and members of the assigned team both count
+fn is_task_assignee(task: &Task, principal: &Principal) -> bool {
+    task.assigned_to == Some(*principal) || task.assigned_team.is_some_and(|team| is_team_member(team, principal))
+}
+
+// 2.13.1 register_volunteer Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn register_volunteer(mut payload: VolunteerPayload) -> Result<Volunteer, Error> {
+    crate::call_stats::count_call("register_volunteer");
+    payload.name = normalize_line(&payload.name);
+    payload.location = normalize_line(&payload.location);
+    if payload.name.is_empty() {
+        return Err(Error::validation("volunteer name must not be empty"));
+    }
+    validate_length("name", &payload.name, MAX_NAME_CHARS)?;
+    validate_length("location", &payload.location, MAX_LOCATION_CHARS)?;
+    validate_skills(&mut payload.skills)?;
+    let principal = caller();
+    let key = principal_key(&principal);
+    let volunteer = match VOLUNTEER_STORAGE.with(|s| s.borrow().get(&key)) {
+        Some(existing) => Volunteer {
+            name: payload.name,
+            skills: payload.skills,
+            availability: payload.availability,
+            location: payload.location,
+            updated_at: Some(time()),
+            ..existing
+        },
+        None => Volunteer {
+            principal,
+            name: payload.name,
+            skills: payload.skills,
+            availability: payload.availability,
+            location: payload.location,
+            registered_at: time(),
+            updated_at: None,
+        },
+    };
+    crate::ensure_storable(&volunteer, "volunteer")?;
+    VOLUNTEER_STORAGE.with(|s| s.borrow_mut().insert(key, volunteer.clone()));
+    Ok(volunteer)
+}
+
+pub(crate) fn volunteer_of(principal: &Principal) -> Option<Volunteer> {
+    VOLUNTEER_STORAGE.with(|s| s.borrow().get(&principal_key(principal)))
+}
+
+pub(crate) fn remove_volunteer(principal: &Principal) -> bool {
+    VOLUNTEER_STORAGE.with(|s| s.borrow_mut().remove(&principal_key(principal)).is_some())
+}
+
+// 2.13.2 get_volunteer Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_volunteer(principal: Principal) -> Result<Volunteer, Error> {
+    match VOLUNTEER_STORAGE.with(|s| s.borrow().get(&principal_key(&principal))) {
+        Some(volunteer) => Ok(volunteer),
+        None => Err(Error::not_found(format!(
+            "a volunteer with principal={} not found",
+            principal
+        ))),
+    }
+}
+
+// 2.13.3 list_available_volunteers Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn list_available_volunteers(skill: Option<String>) -> Result<QueryResult<Volunteer>, Error> {
+    require_role(Role::Coordinator)?;
+    let skill = skill.as_deref().map(normalize_line);
+    fit(VOLUNTEER_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, volunteer)| {
+                volunteer.availability != Availability::Unavailable
+                    && skill.as_ref().is_none_or(|s| volunteer.skills.iter().any(|v| normalize_line(v) == *s))
+            })
+            .map(|(_, volunteer)| volunteer)
+            .collect()
+    }))
+}
+
+// 2.13.4 get_task Function:
+#[ic_cdk::query(guard = "admit_read")]
+pub(crate) fn get_task(id: u64) -> Result<Task, Error> {
+    match TASK_STORAGE.with(|s| s.borrow().get(&id)) {
+        Some(task) => Ok(task),
+        None => Err(Error::not_found(format!("a task with id={} not found", id))),
+    }
+}
+
+// 2.13.5 create_task Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn create_task(mut payload: TaskPayload) -> Result<Task, Error> {
+    crate::call_stats::count_call("create_task");
+    let coordinator = require_role(Role::Coordinator)?;
+    payload.title = normalize_line(&payload.title);
+    payload.description = normalize_multiline(&payload.description);
+    payload.location = payload.location.as_deref().map(normalize_line);
+    if payload.title.is_empty() {
+        return Err(Error::validation("task title must not be empty"));
+    }
+    validate_length("title", &payload.title, MAX_TITLE_CHARS)?;
+    validate_length("description", &payload.description, MAX_DESCRIPTION_CHARS)?;
+    if let Some(location) = payload.location.as_ref() {
+        validate_length("location", location, MAX_LOCATION_CHARS)?;
+    }
+    validate_skills(&mut payload.required_skills)?;
+    let update = crate::moderation::visible_parent(payload.crisis_update_id)?;
+    let id = crate::ids::next_id(&TASK_ID_COUNTER)?;
+    let task = Task {
+        id,
+        crisis_update_id: payload.crisis_update_id,
+        title: payload.title,
+        description: payload.description,
+        location: payload.location.unwrap_or(update.location),
+        required_skills: payload.required_skills,
+        assigned_to: None,
+        assigned_team: None,
+        status: TaskStatus::Open,
+        created_by: coordinator,
+        created_at: time(),
+        updated_at: None,
+    };
+    crate::ensure_storable(&task, "task")?;
+    do_insert_task(&task);
+    Ok(task)
+}
+
+// 2.13.6 assign_task Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn assign_task(id: u64, volunteer: Principal) -> Result<Task, Error> {
+    crate::call_stats::count_call("assign_task");
+    require_role(Role::Coordinator)?;
+    let mut task = get_task(id)?;
+    if !is_registered_volunteer(&volunteer) {
+        return Err(Error::not_found(format!(
+            "a volunteer with principal={} not found",
+            volunteer
+        )));
+    }
+    if matches!(task.status, TaskStatus::Completed | TaskStatus::Cancelled) {
+        return Err(Error::conflict(format!("task with id={} is already closed", id)));
+    }
+    task.assigned_to = Some(volunteer);
+    task.status = TaskStatus::Assigned;
+    task.updated_at = Some(time());
+    do_insert_task(&task);
+    Ok(task)
+}
+
+// 2.13.7 update_task_status Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn update_task_status(id: u64, status: TaskStatus) -> Result<Task, Error> {
+    crate::call_stats::count_call("update_task_status");
+    let mut task = get_task(id)?;
+    let caller = caller();
+    if !is_task_assignee(&task, &caller) && !has_role(&caller, Role::Coordinator) {
+        return Err(Error::unauthorized(format!(
+            "only the assignee or a coordinator can update task id={}",
+            id
+        )));
+    }
+    task.status = status;
+    task.updated_at = Some(time());
+    do_insert_task(&task);
+    Ok(task)
+}
+
+// 2.13.8 get_my_tasks Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn get_my_tasks() -> Result<QueryResult<Task>, Error> {
+    let caller = caller();
+    fit(TASK_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, task)| is_task_assignee(task, &caller))
+            .map(|(_, task)| task)
+            .collect()
+    }))
+}
+
+// 2.13.9 list_open_tasks Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn list_open_tasks(location: String) -> Result<QueryResult<Task>, Error> {
+    let location = normalize_line(&location);
+    fit(TASK_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, task)| task.status == TaskStatus::Open && normalize_line(&task.location) == location)
+            .map(|(_, task)| task)
+            .collect()
+    }))
+}
+
+// 2.13.10 assign_task_to_team Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn assign_task_to_team(id: u64, team_id: u64) -> Result<Task, Error> {
+    crate::call_stats::count_call("assign_task_to_team");
+    require_role(Role::Coordinator)?;
+    let mut task = get_task(id)?;
+    if crate::teams::find_team(team_id).is_none() {
+        return Err(Error::not_found(format!("a team with id={} not found", team_id)));
+    }
+    if matches!(task.status, TaskStatus::Completed | TaskStatus::Cancelled) {
+        return Err(Error::conflict(format!("task with id={} is already closed", id)));
+    }
+    task.assigned_team = Some(team_id);
+    task.status = TaskStatus::Assigned;
+    task.updated_at = Some(time());
+    do_insert_task(&task);
+    Ok(task)
+}
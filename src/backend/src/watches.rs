@@ -0,0 +1,174 @@
+// Following a single crisis update, for people who care about one report
+// rather than a whole location. Watchers are notified when the update is
+// edited and, alongside the author and assignees, when its status or priority
+// changes. Once an update is no longer active its watches lapse after a grace
+// period, so resolved reports stop filling notification lists; the heartbeat
+// sweeps them.
+use crate::access::{principal_key, PrincipalKey};
+use crate::access_mode::admit_read;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::maintenance::admit_update;
+use crate::notifications::{try_notify, NotificationKind};
+use crate::response::QueryResult;
+use crate::{CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER, CRISIS_STORAGE};
+use candid::Principal;
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+pub(crate) const WATCH_EXPIRY_INTERVAL_SECS: u64 = 60 * 60;
+const UNWATCH_GRACE_SECS: u64 = 3 * 24 * 60 * 60;
+const MAX_WATCHES_PER_PRINCIPAL: usize = 100;
+
+thread_local! {
+    // (crisis update id, watcher) -> watched at
+    static WATCHERS: RefCell<StableBTreeMap<(u64, PrincipalKey), u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(110)))
+    ));
+
+    // (watcher, crisis update id), so a principal's watches can be listed
+    static WATCHED_BY: RefCell<StableBTreeMap<(PrincipalKey, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(111)))
+    ));
+}
+
+fn watched_ids(watcher: &Principal) -> Vec<u64> {
+    let key = principal_key(watcher);
+    WATCHED_BY.with(|s| {
+        s.borrow()
+            .range((key, 0)..=(key, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    })
+}
+
+fn unwatch(crisis_update_id: u64, watcher: PrincipalKey) -> bool {
+    WATCHED_BY.with(|s| s.borrow_mut().remove(&(watcher, crisis_update_id)));
+    WATCHERS
+        .with(|s| s.borrow_mut().remove(&(crisis_update_id, watcher)))
+        .is_some()
+}
+
+fn remove_watches_where(drop: impl Fn(u64) -> bool) -> u64 {
+    let keys: Vec<(u64, PrincipalKey)> = WATCHERS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|(id, _)| drop(*id))
+            .collect()
+    });
+    for (id, watcher) in &keys {
+        unwatch(*id, *watcher);
+    }
+    keys.len() as u64
+}
+
+pub(crate) fn watchers_of(crisis_update_id: u64) -> Vec<Principal> {
+    let min_key = (crisis_update_id, PrincipalKey::default());
+    WATCHERS.with(|s| {
+        s.borrow()
+            .range(min_key..)
+            .take_while(|((id, _), _)| *id == crisis_update_id)
+            .map(|((_, watcher), _)| Principal::from_slice(watcher.as_slice()))
+            .collect()
+    })
+}
+
+// Tells watchers other than the editor that the update's content changed
+pub(crate) fn notify_watchers_of_edit(update: &CrisisUpdate, edited_by: Principal) {
+    let title: String = update.title.chars().take(200).collect();
+    let message = format!("Crisis update #{} you watch was edited: {}", update.id, title);
+    for watcher in watchers_of(update.id) {
+        if watcher != edited_by && crate::moderation::visible_to(update, &watcher) {
+            try_notify(
+                watcher,
+                NotificationKind::WatchedUpdateEdited,
+                message.clone(),
+                Some(update.id),
+            );
+        }
+    }
+}
+
+// Drops watches on updates that were closed more than the grace period ago
+pub(crate) fn expire_watches(now: u64) {
+    let grace = UNWATCH_GRACE_SECS * NANOS_PER_SECOND;
+    remove_watches_where(|id| {
+        CRISIS_STORAGE.with(|s| s.borrow().get(&id)).is_some_and(|update| {
+            !update.status.is_open()
+                && now.saturating_sub(update.updated_at.unwrap_or(update.created_at)) >= grace
+        })
+    });
+}
+
+pub(crate) fn remove_orphaned_watches(is_live: &dyn Fn(u64) -> bool) -> u64 {
+    remove_watches_where(|id| !is_live(id))
+}
+
+// Drops every watch of the principal; returns how many were removed
+pub(crate) fn remove_watches_of(watcher: &Principal) -> u64 {
+    let key = principal_key(watcher);
+    watched_ids(watcher).into_iter().filter(|id| unwatch(*id, key)).count() as u64
+}
+
+// 2.81.1 watch_crisis_update Function:
+// Watching an update that is already watched is not an error.
+#[ic_cdk::update(guard = "admit_update")]
+async fn watch_crisis_update(crisis_update_id: u64) -> Result<(), Error> {
+    crate::call_stats::count_call("watch_crisis_update");
+    let watcher = caller();
+    if watcher == Principal::anonymous() {
+        return Err(Error::unauthorized("watching an update requires an identity"));
+    }
+    let visible = crate::_find_crisis_update(crisis_update_id)
+        .await?
+        .is_some_and(|update| crate::moderation::visible_to(&update, &watcher));
+    if !visible {
+        return Err(
+            Error::not_found(format!("a crisis update with id={} not found", crisis_update_id))
+                .with_detail("crisis_update_id", crisis_update_id),
+        );
+    }
+    let key = principal_key(&watcher);
+    if WATCHERS.with(|s| s.borrow().contains_key(&(crisis_update_id, key))) {
+        return Ok(());
+    }
+    if watched_ids(&watcher).len() >= MAX_WATCHES_PER_PRINCIPAL {
+        return Err(Error::validation(format!(
+            "at most {} updates can be watched at once",
+            MAX_WATCHES_PER_PRINCIPAL
+        )));
+    }
+    WATCHERS.with(|s| s.borrow_mut().insert((crisis_update_id, key), time()));
+    WATCHED_BY.with(|s| s.borrow_mut().insert((key, crisis_update_id), ()));
+    Ok(())
+}
+
+// 2.81.2 unwatch_crisis_update Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn unwatch_crisis_update(crisis_update_id: u64) -> Result<(), Error> {
+    crate::call_stats::count_call("unwatch_crisis_update");
+    if !unwatch(crisis_update_id, principal_key(&caller())) {
+        return Err(Error::not_found(format!(
+            "caller does not watch the crisis update with id={}",
+            crisis_update_id
+        ))
+        .with_detail("crisis_update_id", crisis_update_id));
+    }
+    Ok(())
+}
+
+// 2.81.3 list_my_watched_updates Function:
+// Watched updates stored in this canister, in id order.
+#[ic_cdk::query(guard = "admit_read")]
+fn list_my_watched_updates() -> Result<QueryResult<CrisisUpdate>, Error> {
+    crate::fit_updates(
+        watched_ids(&caller())
+            .into_iter()
+            .filter_map(|id| crate::_get_crisis_update(&id))
+            .collect(),
+    )
+}
@@ -0,0 +1,688 @@
+// Webhooks for integrators: admins register https endpoints, each with a filter
+// on the events it wants (e.g. only Critical updates created, only changes to
+// Resolved, only in one region) and an optional payload template. Matching
+// events are queued in a stable outbox and POSTed by the heartbeat through
+// HTTPS outcalls, retried with exponential backoff and marked failed after the
+// last attempt; failed deliveries can be replayed. Only updates anyone could
+// see produce events, with their public redaction applied. Every replica makes
+// the outcall, so receivers should deduplicate on the Idempotency-Key header.
+//
+// Templates may use the placeholders {{event}}, {{id}}, {{title}},
+// {{description}}, {{location}}, {{region}}, {{status}}, {{priority}},
+// {{from}}, {{to}} and {{timestamp}}; values are JSON-escaped. Without a
+// template the body is a JSON object with the same fields.
+use crate::access::{require_role, Role};
+use crate::access_mode::admit_read;
+use crate::ids::IdCounter;
+use crate::jobs::NANOS_PER_SECOND;
+use crate::logs::LogLevel;
+use crate::maintenance::admit_update;
+use crate::response::{fit, QueryResult};
+use crate::{CrisisPriority, CrisisStatus, CrisisUpdate, Error, Memory, CRISIS_MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs, TransformContext,
+};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+pub(crate) const WEBHOOK_DELIVERY_INTERVAL_SECS: u64 = 5;
+const MAX_ENDPOINTS: u64 = 20;
+const MAX_TEMPLATE_CHARS: usize = 2_000;
+const MAX_FILTER_VALUES: usize = 20;
+const MAX_BODY_BYTES: usize = 8 * 1024;
+const MAX_OUTBOX_DELIVERIES: u64 = 10_000;
+const DELIVERY_BATCH_SIZE: usize = 10;
+const MAX_ATTEMPTS: u32 = 6;
+const RETRY_BASE_SECS: u64 = 30;
+const MAX_RETRY_DELAY_SECS: u64 = 60 * 60;
+// Delivered and failed deliveries are kept this long for inspection and replay
+const DELIVERY_RETENTION_SECS: u64 = 7 * 24 * 60 * 60;
+const WEBHOOK_MAX_RESPONSE_BYTES: u64 = 1024;
+// Comfortably above the outcall price for an 8 KiB request; unused cycles are refunded
+const WEBHOOK_OUTCALL_CYCLES: u128 = 1_000_000_000;
+
+const PLACEHOLDERS: [&str; 11] = [
+    "event",
+    "id",
+    "title",
+    "description",
+    "location",
+    "region",
+    "status",
+    "priority",
+    "from",
+    "to",
+    "timestamp",
+];
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum WebhookEventKind {
+    Created,
+    Updated,
+    Deleted,
+    StatusChanged,
+    PriorityChanged,
+}
+
+// What happened, with the transition for status and priority changes
+#[derive(Clone, Copy)]
+pub(crate) enum WebhookEvent {
+    Created,
+    Updated,
+    Deleted,
+    StatusChanged { from: CrisisStatus, to: CrisisStatus },
+    PriorityChanged { from: CrisisPriority, to: CrisisPriority },
+}
+
+impl WebhookEvent {
+    fn kind(self) -> WebhookEventKind {
+        match self {
+            WebhookEvent::Created => WebhookEventKind::Created,
+            WebhookEvent::Updated => WebhookEventKind::Updated,
+            WebhookEvent::Deleted => WebhookEventKind::Deleted,
+            WebhookEvent::StatusChanged { .. } => WebhookEventKind::StatusChanged,
+            WebhookEvent::PriorityChanged { .. } => WebhookEventKind::PriorityChanged,
+        }
+    }
+
+    fn transition(self) -> (String, String) {
+        match self {
+            WebhookEvent::StatusChanged { from, to } => (format!("{:?}", from), format!("{:?}", to)),
+            WebhookEvent::PriorityChanged { from, to } => (format!("{:?}", from), format!("{:?}", to)),
+            _ => (String::new(), String::new()),
+        }
+    }
+}
+
+// Every listed condition must hold; empty lists and None match anything
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct WebhookFilter {
+    event_kinds: Vec<WebhookEventKind>,
+    priorities: Vec<CrisisPriority>,
+    // The new status of a status change; other events do not match when set
+    to_statuses: Vec<CrisisStatus>,
+    // Country code, admin area or city of the update's region
+    region: Option<String>,
+}
+
+impl WebhookFilter {
+    fn constrains_update(&self) -> bool {
+        !self.priorities.is_empty() || !self.to_statuses.is_empty() || self.region.is_some()
+    }
+
+    fn matches(&self, event: WebhookEvent, update: Option<&CrisisUpdate>) -> bool {
+        if !self.event_kinds.is_empty() && !self.event_kinds.contains(&event.kind()) {
+            return false;
+        }
+        if !self.to_statuses.is_empty() {
+            let WebhookEvent::StatusChanged { to, .. } = event else {
+                return false;
+            };
+            if !self.to_statuses.contains(&to) {
+                return false;
+            }
+        }
+        let Some(update) = update else {
+            // A deleted update can no longer be checked against the other conditions
+            return !self.constrains_update();
+        };
+        (self.priorities.is_empty() || self.priorities.contains(&update.priority))
+            && self
+                .region
+                .as_ref()
+                .is_none_or(|name| update.region.as_ref().is_some_and(|r| r.has_name(name)))
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct WebhookEndpointPayload {
+    url: String,
+    filter: WebhookFilter,
+    // None sends the default JSON body
+    template: Option<String>,
+    active: bool,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct WebhookEndpoint {
+    id: u64,
+    url: String,
+    filter: WebhookFilter,
+    template: Option<String>,
+    // Inactive endpoints queue nothing; queued deliveries are still sent
+    active: bool,
+    created_by: Principal,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+// Implementing Storable and BoundedStorable traits for WebhookEndpoint
+impl Storable for WebhookEndpoint {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for WebhookEndpoint {
+    const MAX_SIZE: u32 = 8192;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum WebhookDeliveryStatus {
+    Pending,
+    Delivered,
+    // Every attempt failed; see replay_failed_deliveries
+    Failed,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct WebhookDelivery {
+    id: u64,
+    endpoint_id: u64,
+    event_kind: WebhookEventKind,
+    crisis_update_id: u64,
+    body: String,
+    created_at: u64,
+    status: WebhookDeliveryStatus,
+    attempts: u32,
+    // When the next attempt is due while pending
+    next_attempt_at: u64,
+    // When the delivery was last attempted
+    last_attempt_at: Option<u64>,
+    last_error: Option<String>,
+}
+
+// Implementing Storable and BoundedStorable traits for WebhookDelivery
+impl Storable for WebhookDelivery {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for WebhookDelivery {
+    const MAX_SIZE: u32 = 10 * 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static WEBHOOK_ENDPOINT_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(117, "webhook endpoints"));
+
+    static WEBHOOK_ENDPOINTS: RefCell<StableBTreeMap<u64, WebhookEndpoint, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(118)))
+    ));
+
+    static WEBHOOK_DELIVERY_ID_COUNTER: RefCell<IdCounter> = RefCell::new(IdCounter::init(119, "webhook deliveries"));
+
+    static WEBHOOK_DELIVERIES: RefCell<StableBTreeMap<u64, WebhookDelivery, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            CRISIS_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(120)))
+    ));
+
+    static DELIVERY_IN_PROGRESS: RefCell<bool> = const { RefCell::new(false) };
+}
+
+struct DeliveryRunGuard;
+
+impl DeliveryRunGuard {
+    fn acquire() -> Result<Self, Error> {
+        if DELIVERY_IN_PROGRESS.with(|r| r.replace(true)) {
+            return Err(Error::conflict("webhook delivery is already in progress"));
+        }
+        Ok(DeliveryRunGuard)
+    }
+}
+
+impl Drop for DeliveryRunGuard {
+    fn drop(&mut self) {
+        DELIVERY_IN_PROGRESS.with(|r| *r.borrow_mut() = false);
+    }
+}
+
+fn json_escaped(value: &str) -> String {
+    let quoted = serde_json::Value::String(value.to_string()).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+fn event_fields(
+    event: WebhookEvent,
+    crisis_update_id: u64,
+    update: Option<&CrisisUpdate>,
+) -> Vec<(&'static str, String)> {
+    let (from, to) = event.transition();
+    let text = |f: fn(&CrisisUpdate) -> String| update.map(f).unwrap_or_default();
+    vec![
+        ("event", format!("{:?}", event.kind())),
+        ("id", crisis_update_id.to_string()),
+        ("title", text(|u| u.title.clone())),
+        ("description", text(|u| u.description.clone())),
+        ("location", text(|u| u.location.clone())),
+        (
+            "region",
+            text(|u| u.region.as_ref().map(|r| r.label()).unwrap_or_default()),
+        ),
+        ("status", text(|u| format!("{:?}", u.status))),
+        ("priority", text(|u| format!("{:?}", u.priority))),
+        ("from", from),
+        ("to", to),
+        ("timestamp", time().to_string()),
+    ]
+}
+
+fn render_body(template: Option<&str>, fields: &[(&'static str, String)]) -> String {
+    match template {
+        Some(template) => fields.iter().fold(template.to_string(), |body, (name, value)| {
+            body.replace(&format!("{{{{{}}}}}", name), &json_escaped(value))
+        }),
+        None => {
+            let object: serde_json::Map<String, serde_json::Value> = fields
+                .iter()
+                .map(|(name, value)| (name.to_string(), serde_json::Value::String(value.clone())))
+                .collect();
+            serde_json::Value::Object(object).to_string()
+        }
+    }
+}
+
+fn validate_template(template: &str) -> Result<(), Error> {
+    if template.chars().count() > MAX_TEMPLATE_CHARS {
+        return Err(Error::validation(format!(
+            "a template must be at most {} characters",
+            MAX_TEMPLATE_CHARS
+        )));
+    }
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            return Err(Error::validation("a template placeholder is not closed with }}"));
+        };
+        let name = &after[..end];
+        if !PLACEHOLDERS.contains(&name) {
+            return Err(
+                Error::validation(format!("unknown template placeholder {{{{{}}}}}", name))
+                    .with_detail("placeholder", name),
+            );
+        }
+        rest = &after[end + 2..];
+    }
+    Ok(())
+}
+
+fn validate_payload(payload: &mut WebhookEndpointPayload) -> Result<(), Error> {
+    payload.url = crate::links::validate_allowed_url(&payload.url)?.0;
+    if let Some(template) = &payload.template {
+        validate_template(template)?;
+    }
+    let filter = &payload.filter;
+    if filter.event_kinds.len() > MAX_FILTER_VALUES
+        || filter.priorities.len() > MAX_FILTER_VALUES
+        || filter.to_statuses.len() > MAX_FILTER_VALUES
+    {
+        return Err(Error::validation(format!(
+            "a filter list can have at most {} values",
+            MAX_FILTER_VALUES
+        )));
+    }
+    payload.filter.region = payload
+        .filter
+        .region
+        .take()
+        .map(|r| r.trim().to_string())
+        .filter(|r| !r.is_empty());
+    Ok(())
+}
+
+fn find_endpoint(id: u64) -> Result<WebhookEndpoint, Error> {
+    WEBHOOK_ENDPOINTS
+        .with(|s| s.borrow().get(&id))
+        .ok_or_else(|| Error::not_found(format!("a webhook endpoint with id={} not found", id)).with_detail("id", id))
+}
+
+fn queue_delivery(endpoint: &WebhookEndpoint, event: WebhookEvent, crisis_update_id: u64, body: String) {
+    if body.len() > MAX_BODY_BYTES {
+        crate::logs::log(
+            LogLevel::Warn,
+            Some("webhooks"),
+            &format!(
+                "webhook body for endpoint {} is {} bytes, over the {} byte limit; event dropped",
+                endpoint.id,
+                body.len(),
+                MAX_BODY_BYTES
+            ),
+        );
+        return;
+    }
+    if WEBHOOK_DELIVERIES.with(|s| s.borrow().len()) >= MAX_OUTBOX_DELIVERIES {
+        crate::logs::log(
+            LogLevel::Warn,
+            Some("webhooks"),
+            &format!("the webhook outbox is full; event for endpoint {} dropped", endpoint.id),
+        );
+        return;
+    }
+    let id = match crate::ids::next_id(&WEBHOOK_DELIVERY_ID_COUNTER) {
+        Ok(id) => id,
+        Err(err) => {
+            crate::logs::log(
+                LogLevel::Error,
+                Some("webhooks"),
+                &format!("failed to queue a webhook delivery: {:?}", err),
+            );
+            return;
+        }
+    };
+    let now = time();
+    let delivery = WebhookDelivery {
+        id,
+        endpoint_id: endpoint.id,
+        event_kind: event.kind(),
+        crisis_update_id,
+        body,
+        created_at: now,
+        status: WebhookDeliveryStatus::Pending,
+        attempts: 0,
+        next_attempt_at: now,
+        last_attempt_at: None,
+        last_error: None,
+    };
+    WEBHOOK_DELIVERIES.with(|s| s.borrow_mut().insert(id, delivery));
+}
+
+// Queues the event for every active endpoint whose filter matches; the change
+// is already stored, so nothing here fails the caller
+pub(crate) fn enqueue(event: WebhookEvent, crisis_update_id: u64) {
+    let update = match event {
+        WebhookEvent::Deleted => None,
+        _ => match crate::_get_crisis_update(&crisis_update_id) {
+            Some(update) if crate::moderation::visible_to(&update, &Principal::anonymous()) => {
+                Some(crate::redaction::redact_update(update, &Principal::anonymous()))
+            }
+            // Not public, or held on a shard this canister cannot read synchronously
+            _ => return,
+        },
+    };
+    let endpoints: Vec<WebhookEndpoint> =
+        WEBHOOK_ENDPOINTS.with(|s| s.borrow().iter().map(|(_, e)| e).filter(|e| e.active).collect());
+    if endpoints.is_empty() {
+        return;
+    }
+    let fields = event_fields(event, crisis_update_id, update.as_ref());
+    for endpoint in endpoints {
+        if endpoint.filter.matches(event, update.as_ref()) {
+            let body = render_body(endpoint.template.as_deref(), &fields);
+            queue_delivery(&endpoint, event, crisis_update_id, body);
+        }
+    }
+}
+
+fn retry_delay_secs(attempts: u32) -> u64 {
+    RETRY_BASE_SECS
+        .saturating_mul(1u64 << attempts.saturating_sub(1).min(16))
+        .min(MAX_RETRY_DELAY_SECS)
+}
+
+// Drops delivered and failed deliveries past the retention period
+fn prune_deliveries(now: u64) {
+    let cutoff = now.saturating_sub(DELIVERY_RETENTION_SECS * NANOS_PER_SECOND);
+    let expired: Vec<u64> = WEBHOOK_DELIVERIES.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, d)| {
+                d.status != WebhookDeliveryStatus::Pending && d.last_attempt_at.unwrap_or(d.created_at) < cutoff
+            })
+            .map(|(id, _)| id)
+            .collect()
+    });
+    WEBHOOK_DELIVERIES.with(|s| {
+        let mut s = s.borrow_mut();
+        for id in expired {
+            s.remove(&id);
+        }
+    });
+}
+
+fn due_deliveries(now: u64) -> Vec<WebhookDelivery> {
+    WEBHOOK_DELIVERIES.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, d)| d)
+            .filter(|d| d.status == WebhookDeliveryStatus::Pending && d.next_attempt_at <= now)
+            .take(DELIVERY_BATCH_SIZE)
+            .collect()
+    })
+}
+
+// True when a delivery is due and no run is in progress; checked by the heartbeat
+pub(crate) fn deliveries_due(now: u64) -> bool {
+    !DELIVERY_IN_PROGRESS.with(|r| *r.borrow()) && !due_deliveries(now).is_empty()
+}
+
+// Replicas must agree on the outcall response, so only the status is kept.
+// Called by the system rather than a member, so it has no read guard.
+#[ic_cdk::query]
+fn transform_webhook_response(args: TransformArgs) -> HttpResponse {
+    HttpResponse {
+        status: args.response.status,
+        headers: Vec::new(),
+        body: Vec::new(),
+    }
+}
+
+async fn post(url: &str, delivery: &WebhookDelivery) -> Result<(), Error> {
+    let request = CanisterHttpRequestArgument {
+        url: url.to_string(),
+        max_response_bytes: Some(WEBHOOK_MAX_RESPONSE_BYTES),
+        method: HttpMethod::POST,
+        headers: vec![
+            HttpHeader {
+                name: "Content-Type".to_string(),
+                value: "application/json".to_string(),
+            },
+            HttpHeader {
+                name: "Idempotency-Key".to_string(),
+                value: format!("{}-{}", ic_cdk::id(), delivery.id),
+            },
+        ],
+        body: Some(delivery.body.clone().into_bytes()),
+        transform: Some(TransformContext::from_name(
+            "transform_webhook_response".to_string(),
+            Vec::new(),
+        )),
+    };
+    let (response,) = http_request(request, WEBHOOK_OUTCALL_CYCLES)
+        .await
+        .map_err(|(code, msg)| Error::external_call_failed(format!("webhook POST failed: {:?} {}", code, msg)))?;
+    if !(200u64..300u64).contains(&response.status) {
+        return Err(Error::external_call_failed(format!(
+            "the endpoint answered with HTTP status {}",
+            response.status
+        )));
+    }
+    Ok(())
+}
+
+// Attempts a batch of due deliveries; run from the heartbeat
+pub(crate) async fn deliver_webhooks(now: u64) {
+    let Ok(_guard) = DeliveryRunGuard::acquire() else {
+        return;
+    };
+    prune_deliveries(now);
+    for mut delivery in due_deliveries(now) {
+        let result = match WEBHOOK_ENDPOINTS.with(|s| s.borrow().get(&delivery.endpoint_id)) {
+            Some(endpoint) => post(&endpoint.url, &delivery).await,
+            None => Err(Error::not_found("the webhook endpoint was removed")),
+        };
+        let attempted_at = time();
+        delivery.attempts += 1;
+        delivery.last_attempt_at = Some(attempted_at);
+        match result {
+            Ok(()) => {
+                delivery.status = WebhookDeliveryStatus::Delivered;
+                delivery.last_error = None;
+            }
+            Err(err) => {
+                delivery.last_error = Some(err.msg);
+                if delivery.attempts >= MAX_ATTEMPTS {
+                    delivery.status = WebhookDeliveryStatus::Failed;
+                } else {
+                    delivery.next_attempt_at =
+                        attempted_at.saturating_add(retry_delay_secs(delivery.attempts) * NANOS_PER_SECOND);
+                }
+            }
+        }
+        // The endpoint may have been removed, with its deliveries, while the call was out
+        if WEBHOOK_DELIVERIES.with(|s| s.borrow().contains_key(&delivery.id)) {
+            WEBHOOK_DELIVERIES.with(|s| s.borrow_mut().insert(delivery.id, delivery));
+        }
+    }
+}
+
+// 2.86.1 add_webhook_endpoint Function:
+// The URL must be https on a host in the link allowlist.
+#[ic_cdk::update(guard = "admit_update")]
+fn add_webhook_endpoint(mut payload: WebhookEndpointPayload) -> Result<WebhookEndpoint, Error> {
+    crate::call_stats::count_call("add_webhook_endpoint");
+    let admin = require_role(Role::Admin)?;
+    validate_payload(&mut payload)?;
+    if WEBHOOK_ENDPOINTS.with(|s| s.borrow().len()) >= MAX_ENDPOINTS {
+        return Err(Error::validation(format!(
+            "at most {} webhook endpoints can be registered",
+            MAX_ENDPOINTS
+        )));
+    }
+    let endpoint = WebhookEndpoint {
+        id: crate::ids::next_id(&WEBHOOK_ENDPOINT_ID_COUNTER)?,
+        url: payload.url,
+        filter: payload.filter,
+        template: payload.template,
+        active: payload.active,
+        created_by: admin,
+        created_at: time(),
+        updated_at: None,
+    };
+    crate::ensure_storable(&endpoint, "webhook endpoint")?;
+    WEBHOOK_ENDPOINTS.with(|s| s.borrow_mut().insert(endpoint.id, endpoint.clone()));
+    Ok(endpoint)
+}
+
+// 2.86.2 update_webhook_endpoint Function:
+#[ic_cdk::update(guard = "admit_update")]
+fn update_webhook_endpoint(id: u64, mut payload: WebhookEndpointPayload) -> Result<WebhookEndpoint, Error> {
+    crate::call_stats::count_call("update_webhook_endpoint");
+    require_role(Role::Admin)?;
+    let mut endpoint = find_endpoint(id)?;
+    validate_payload(&mut payload)?;
+    endpoint.url = payload.url;
+    endpoint.filter = payload.filter;
+    endpoint.template = payload.template;
+    endpoint.active = payload.active;
+    endpoint.updated_at = Some(time());
+    crate::ensure_storable(&endpoint, "webhook endpoint")?;
+    WEBHOOK_ENDPOINTS.with(|s| s.borrow_mut().insert(id, endpoint.clone()));
+    Ok(endpoint)
+}
+
+// 2.86.3 remove_webhook_endpoint Function:
+// Queued deliveries to the endpoint are dropped with it.
+#[ic_cdk::update(guard = "admit_update")]
+fn remove_webhook_endpoint(id: u64) -> Result<WebhookEndpoint, Error> {
+    crate::call_stats::count_call("remove_webhook_endpoint");
+    require_role(Role::Admin)?;
+    let endpoint = find_endpoint(id)?;
+    WEBHOOK_ENDPOINTS.with(|s| s.borrow_mut().remove(&id));
+    WEBHOOK_DELIVERIES.with(|s| {
+        let mut s = s.borrow_mut();
+        let queued: Vec<u64> = s
+            .iter()
+            .filter(|(_, d)| d.endpoint_id == id)
+            .map(|(id, _)| id)
+            .collect();
+        for delivery_id in queued {
+            s.remove(&delivery_id);
+        }
+    });
+    Ok(endpoint)
+}
+
+// 2.86.4 list_webhook_endpoints Function:
+#[ic_cdk::query(guard = "admit_read")]
+fn list_webhook_endpoints() -> Result<QueryResult<WebhookEndpoint>, Error> {
+    require_role(Role::Admin)?;
+    fit(WEBHOOK_ENDPOINTS.with(|s| s.borrow().iter().map(|(_, e)| e).collect()))
+}
+
+// 2.86.5 list_webhook_deliveries Function:
+// Newest first, up to the page size.
+#[ic_cdk::query(guard = "admit_read")]
+fn list_webhook_deliveries(
+    endpoint_id: u64,
+    status: Option<WebhookDeliveryStatus>,
+) -> Result<QueryResult<WebhookDelivery>, Error> {
+    require_role(Role::Admin)?;
+    find_endpoint(endpoint_id)?;
+    let mut matching: Vec<WebhookDelivery> = WEBHOOK_DELIVERIES.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, d)| d)
+            .filter(|d| d.endpoint_id == endpoint_id && status.is_none_or(|st| d.status == st))
+            .collect()
+    });
+    let total = matching.len() as u64;
+    matching.reverse();
+    let page = matching
+        .into_iter()
+        .take(crate::query_limits::page_size(u64::MAX) as usize)
+        .collect();
+    Ok(QueryResult::limited(fit(page)?.items, total))
+}
+
+// 2.86.6 replay_failed_deliveries Function:
+// Queues every failed delivery to the endpoint again with fresh attempts; returns how many.
+#[ic_cdk::update(guard = "admit_update")]
+fn replay_failed_deliveries(endpoint_id: u64) -> Result<u64, Error> {
+    crate::call_stats::count_call("replay_failed_deliveries");
+    require_role(Role::Admin)?;
+    find_endpoint(endpoint_id)?;
+    let now = time();
+    let failed: Vec<WebhookDelivery> = WEBHOOK_DELIVERIES.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, d)| d)
+            .filter(|d| d.endpoint_id == endpoint_id && d.status == WebhookDeliveryStatus::Failed)
+            .collect()
+    });
+    let replayed = failed.len() as u64;
+    WEBHOOK_DELIVERIES.with(|s| {
+        let mut s = s.borrow_mut();
+        for mut delivery in failed {
+            delivery.status = WebhookDeliveryStatus::Pending;
+            delivery.attempts = 0;
+            delivery.next_attempt_at = now;
+            s.insert(delivery.id, delivery);
+        }
+    });
+    crate::logs::log(
+        LogLevel::Info,
+        Some("replay_failed_deliveries"),
+        &format!(
+            "{} replayed {} failed deliveries to webhook endpoint {}",
+            caller(),
+            replayed,
+            endpoint_id
+        ),
+    );
+    Ok(replayed)
+}